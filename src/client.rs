@@ -1,59 +1,394 @@
+use crate::config::{
+    JUPITER_V6_PROGRAM_ID, PUMP_FUN_PROGRAM_ID, RAYDIUM_CLMM_PROGRAM_ID, RAYDIUM_CPMM_PROGRAM_ID,
+};
 use crate::decoder;
+use crate::gen_engine::Engine;
 use crate::target_list::TargetList;
+use solana_sdk::account::Account;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, RwLock};
+use tokio::sync::{Mutex, mpsc};
 use yellowstone_grpc_client::ClientTlsConfig;
-use yellowstone_grpc_proto::geyser::SubscribeRequestFilterTransactions;
+use yellowstone_grpc_proto::geyser::{
+    SubscribeRequestFilterAccounts, SubscribeRequestFilterTransactions,
+};
 use {
     futures::{sink::SinkExt, stream::StreamExt},
-    log::info,
-    tokio::time::{Duration, interval},
+    log::{info, warn},
+    tokio::time::{Duration, interval, sleep},
     yellowstone_grpc_client::GeyserGrpcClient,
     yellowstone_grpc_proto::prelude::{
-        CommitmentLevel, SubscribeRequest, SubscribeRequestPing, SubscribeUpdatePong,
-        SubscribeUpdateSlot, subscribe_update::UpdateOneof,
+        CommitmentLevel, SubscribeRequest, SubscribeRequestPing, SubscribeUpdateAccount,
+        SubscribeUpdatePong, SubscribeUpdateSlot, subscribe_update::UpdateOneof,
     },
 };
 
+/// Gets the updates of the accounts it was registered for. Runs on the stream's task, so it
+/// should hand slow work off.
+pub trait AccountHandler: Send + Sync {
+    fn on_account(&self, key: Pubkey, account: &Account, slot: u64);
+}
+
+impl<F: Fn(Pubkey, &Account, u64) + Send + Sync> AccountHandler for F {
+    fn on_account(&self, key: Pubkey, account: &Account, slot: u64) {
+        self(key, account, slot)
+    }
+}
+
+/// Which account updates a handler gets
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AccountFilter {
+    Account(Pubkey),
+    /// Every account owned by the program
+    Owner(Pubkey),
+}
+
+#[derive(Default)]
+struct Handlers {
+    next_id: u64,
+    handlers: HashMap<u64, (Vec<AccountFilter>, Arc<dyn AccountHandler>)>,
+}
+
+/// Account subscriptions sharing one geyser stream, the `SolGrpcClient`'s or one of their own,
+/// added and removed while it runs.
+#[derive(Clone)]
+pub struct AccountSubscriptions {
+    handlers: Arc<RwLock<Handlers>>,
+    resubscribe: mpsc::UnboundedSender<()>,
+    /// Whoever runs the stream sends a new request when this fires
+    resubscribe_rx: Arc<Mutex<mpsc::UnboundedReceiver<()>>>,
+}
+
+impl AccountSubscriptions {
+    pub fn new() -> Self {
+        let (resubscribe, resubscribe_rx) = mpsc::unbounded_channel();
+        Self {
+            handlers: Arc::new(RwLock::new(Handlers::default())),
+            resubscribe,
+            resubscribe_rx: Arc::new(Mutex::new(resubscribe_rx)),
+        }
+    }
+
+    /// Streams the subscribed accounts on a connection of their own, for commands that don't
+    /// run a `SolGrpcClient`. Reconnects on failure, updates sent while disconnected are missed.
+    pub fn start(&self, endpoint: String) {
+        let subscriptions = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let mut resubscribe = subscriptions.resubscribe_rx.lock().await;
+                let request = || SubscribeRequest {
+                    accounts: subscriptions.filters(),
+                    commitment: Some(CommitmentLevel::Processed as i32),
+                    ..Default::default()
+                };
+                if let Err(e) = stream_accounts(
+                    &endpoint,
+                    request,
+                    Some(&mut resubscribe),
+                    |key, account, slot| {
+                        subscriptions.dispatch(key, &account, slot);
+                        true
+                    },
+                )
+                .await
+                {
+                    warn!("Account subscription failed: {}", e);
+                }
+                drop(resubscribe);
+                sleep(Duration::from_secs(1)).await;
+            }
+        });
+    }
+
+    /// Registers `handler` for updates matching `filter` until the returned guard is dropped
+    pub fn subscribe(
+        &self,
+        filter: AccountFilter,
+        handler: impl AccountHandler + 'static,
+    ) -> AccountSubscription {
+        self.subscribe_all([filter], handler)
+    }
+
+    /// Registers `handler` for updates matching any of `filters` with a single new request
+    pub fn subscribe_all(
+        &self,
+        filters: impl IntoIterator<Item = AccountFilter>,
+        handler: impl AccountHandler + 'static,
+    ) -> AccountSubscription {
+        let mut handlers = self.handlers.write().unwrap();
+        let id = handlers.next_id;
+        handlers.next_id += 1;
+        handlers
+            .handlers
+            .insert(id, (filters.into_iter().collect(), Arc::new(handler)));
+        drop(handlers);
+        let _ = self.resubscribe.send(());
+        AccountSubscription {
+            id,
+            subscriptions: self.clone(),
+        }
+    }
+
+    fn unsubscribe(&self, id: u64) {
+        self.handlers.write().unwrap().handlers.remove(&id);
+        let _ = self.resubscribe.send(());
+    }
+
+    /// Account filters covering every handler. A filter matches on all of its lists, so
+    /// accounts and owners go in separate ones, and an empty one would match everything.
+    fn filters(&self) -> HashMap<String, SubscribeRequestFilterAccounts> {
+        let handlers = self.handlers.read().unwrap();
+        let (mut accounts, mut owners) = (vec![], vec![]);
+        for filter in handlers.handlers.values().flat_map(|(filters, _)| filters) {
+            match filter {
+                AccountFilter::Account(key) => accounts.push(key.to_string()),
+                AccountFilter::Owner(owner) => owners.push(owner.to_string()),
+            }
+        }
+        // pools share accounts like the clock, one entry each is enough
+        accounts.sort_unstable();
+        accounts.dedup();
+        owners.sort_unstable();
+        owners.dedup();
+        let mut filters = HashMap::new();
+        for (name, account, owner) in [("accounts", accounts, vec![]), ("owners", vec![], owners)] {
+            if account.is_empty() && owner.is_empty() {
+                continue;
+            }
+            filters.insert(
+                name.to_owned(),
+                SubscribeRequestFilterAccounts {
+                    account,
+                    owner,
+                    filters: vec![],
+                    nonempty_txn_signature: None,
+                },
+            );
+        }
+        filters
+    }
+
+    /// Hands an update to the handlers of the account and of its owner
+    fn dispatch(&self, key: Pubkey, account: &Account, slot: u64) {
+        // handlers run unlocked, they may subscribe or drop subscriptions themselves
+        let handlers = self
+            .handlers
+            .read()
+            .unwrap()
+            .handlers
+            .values()
+            .filter(|(filters, _)| {
+                filters.iter().any(|filter| match filter {
+                    AccountFilter::Account(filter_key) => *filter_key == key,
+                    AccountFilter::Owner(owner) => *owner == account.owner,
+                })
+            })
+            .map(|(_, handler)| Arc::clone(handler))
+            .collect::<Vec<_>>();
+        for handler in handlers {
+            handler.on_account(key, account, slot);
+        }
+    }
+}
+
+/// Logs updates of the accounts in `WATCH_ACCOUNTS` and of those owned by the programs in
+/// `WATCH_OWNERS`, both comma separated. Updates stop once the returned guards are dropped.
+pub fn watch_from_env(accounts: &AccountSubscriptions) -> anyhow::Result<Vec<AccountSubscription>> {
+    let keys = |name: &str| -> anyhow::Result<Vec<Pubkey>> {
+        env::var(name)
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .map(|key| Ok(key.parse()?))
+            .collect()
+    };
+    let filters = keys("WATCH_ACCOUNTS")?
+        .into_iter()
+        .map(AccountFilter::Account)
+        .chain(keys("WATCH_OWNERS")?.into_iter().map(AccountFilter::Owner));
+    Ok(filters
+        .map(|filter| {
+            accounts.subscribe(filter, |key: Pubkey, account: &Account, slot| {
+                info!(
+                    "Account {} updated in slot {}: {} lamports, {} bytes, owner {}",
+                    key,
+                    slot,
+                    account.lamports,
+                    account.data.len(),
+                    account.owner
+                );
+            })
+        })
+        .collect())
+}
+
+/// An account update with its key and owner decoded. None, logged, when either isn't a pubkey,
+/// one bad update shouldn't end the stream.
+pub fn account_update(update: SubscribeUpdateAccount) -> Option<(Pubkey, Account, u64)> {
+    let account = update.account?;
+    let (Ok(key), Ok(owner)) = (
+        Pubkey::try_from(account.pubkey.as_slice()),
+        Pubkey::try_from(account.owner.as_slice()),
+    ) else {
+        warn!("Skipping account update with a malformed key or owner");
+        return None;
+    };
+    Some((
+        key,
+        Account {
+            lamports: account.lamports,
+            data: account.data,
+            owner,
+            executable: account.executable,
+            rent_epoch: account.rent_epoch,
+        },
+        update.slot,
+    ))
+}
+
+/// Runs one account-only geyser subscription. Sends `request()`, and again whenever
+/// `resubscribe` fires since every request replaces the filters, pings to keep the stream open
+/// and hands each update to `on_account`. Returns once `on_account` returns false, fails when
+/// the stream does.
+pub async fn stream_accounts(
+    endpoint: &str,
+    request: impl Fn() -> SubscribeRequest,
+    mut resubscribe: Option<&mut mpsc::UnboundedReceiver<()>>,
+    mut on_account: impl FnMut(Pubkey, Account, u64) -> bool,
+) -> anyhow::Result<()> {
+    let mut client = GeyserGrpcClient::build_from_shared(endpoint.to_string())?
+        .tls_config(ClientTlsConfig::new().with_native_roots())?
+        .connect()
+        .await?;
+    let (mut subscribe_tx, mut stream) = client.subscribe().await?;
+    subscribe_tx.send(request()).await?;
+
+    let mut timer = interval(Duration::from_secs(3));
+    let mut id = 0;
+    loop {
+        let resubscribed = async {
+            match resubscribe.as_deref_mut() {
+                Some(resubscribe) => resubscribe.recv().await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            _ = timer.tick() => {
+                id += 1;
+                subscribe_tx
+                    .send(SubscribeRequest {
+                        ping: Some(SubscribeRequestPing { id }),
+                        ..Default::default()
+                    })
+                    .await?;
+            }
+            Some(()) = resubscribed => {
+                subscribe_tx.send(request()).await?;
+            }
+            message = stream.next() => {
+                let Some(message) = message else {
+                    anyhow::bail!("stream closed");
+                };
+                let Some(UpdateOneof::Account(update)) = message?.update_oneof else {
+                    continue;
+                };
+                if let Some((key, account, slot)) = account_update(update)
+                    && !on_account(key, account, slot)
+                {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Keeps a handler registered, dropping it unsubscribes.
+pub struct AccountSubscription {
+    id: u64,
+    subscriptions: AccountSubscriptions,
+}
+
+impl Drop for AccountSubscription {
+    fn drop(&mut self) {
+        self.subscriptions.unsubscribe(self.id);
+    }
+}
+
 pub struct SolGrpcClient {
     endpoint: String,
+    engine: Engine,
+    accounts: AccountSubscriptions,
 }
 impl SolGrpcClient {
-    pub fn new(endpoint: String) -> Self {
-        Self { endpoint }
+    /// Streams the engine's account subscriptions along with the transactions
+    pub fn new(endpoint: String, engine: Engine) -> Self {
+        Self {
+            endpoint,
+            accounts: engine.accounts.clone(),
+            engine,
+        }
+    }
+
+    /// Handle to add and remove account subscriptions, before or after connecting
+    pub fn accounts(&self) -> AccountSubscriptions {
+        self.accounts.clone()
     }
+
+    /// Transactions of the programs we copy trades on, and the subscribed accounts
+    fn subscribe_request(&self) -> SubscribeRequest {
+        let raydium_account = "675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8".to_string();
+        let raydium_cpmm_account = RAYDIUM_CPMM_PROGRAM_ID.to_string();
+        let raydium_clmm_account = RAYDIUM_CLMM_PROGRAM_ID.to_string();
+        let pump_fun_account = PUMP_FUN_PROGRAM_ID.to_string();
+        let jupiter_account = JUPITER_V6_PROGRAM_ID.to_string();
+        SubscribeRequest {
+            transactions: maplit::hashmap! {
+                "".to_owned() => SubscribeRequestFilterTransactions {
+                    vote: None,failed: None,signature: None,account_include: vec![raydium_account, raydium_cpmm_account, raydium_clmm_account, pump_fun_account, jupiter_account] ,account_exclude: vec![],
+                account_required: vec![],}
+            },
+            accounts: self.accounts.filters(),
+            commitment: Some(CommitmentLevel::Processed as i32),
+            ..Default::default()
+        }
+    }
+
     pub async fn connect(&self) -> anyhow::Result<()> {
         let endpoint = self.endpoint.clone();
+        let engine = self.engine.clone();
+        let accounts = self.accounts.clone();
         let mut client = GeyserGrpcClient::build_from_shared(endpoint)?
             .tls_config(ClientTlsConfig::new().with_native_roots())?
             .connect()
             .await?;
         let (mut subscribe_tx, mut stream) = client.subscribe().await?;
+        let mut resubscribe = self.accounts.resubscribe_rx.lock().await;
 
         futures::try_join!(
-            async move {
-                let raydium_account = "675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8".to_string();
-                subscribe_tx
-                    .send(SubscribeRequest {
-                        transactions: maplit::hashmap! {
-                            "".to_owned() => SubscribeRequestFilterTransactions {
-                                vote: None,failed: None,signature: None,account_include: vec![raydium_account] ,account_exclude: vec![],
-                            account_required: vec![],}
-                        },
-                        commitment: Some(CommitmentLevel::Processed as i32),
-                        ..Default::default()
-                    })
-                    .await?;
+            async {
+                subscribe_tx.send(self.subscribe_request()).await?;
 
                 let mut timer = interval(Duration::from_secs(3));
                 let mut id = 0;
                 loop {
-                    timer.tick().await;
-                    id += 1;
-                    subscribe_tx
-                        .send(SubscribeRequest {
-                            ping: Some(SubscribeRequestPing { id }),
-                            ..Default::default()
-                        })
-                        .await?;
+                    tokio::select! {
+                        _ = timer.tick() => {
+                            id += 1;
+                            subscribe_tx
+                                .send(SubscribeRequest {
+                                    ping: Some(SubscribeRequestPing { id }),
+                                    ..Default::default()
+                                })
+                                .await?;
+                        }
+                        Some(()) = resubscribe.recv() => {
+                            // every request replaces the filters, so it carries all of them
+                            subscribe_tx.send(self.subscribe_request()).await?;
+                        }
+                    }
                 }
                 #[allow(unreachable_code)]
                 Ok::<(), anyhow::Error>(())
@@ -67,6 +402,7 @@ impl SolGrpcClient {
                         UpdateOneof::Transaction(transaction) => {
                             // info!("slot received: {slot}");
                             if decoder::decode_instruction(
+                                engine.clone(),
                                 target_list.clone(),
                                 token_list.clone(),
                                 transaction,
@@ -77,6 +413,12 @@ impl SolGrpcClient {
                                 // info!("Succesfully parsed tx");
                             }
                         }
+                        UpdateOneof::Account(update) => {
+                            let Some((key, account, slot)) = account_update(update) else {
+                                continue;
+                            };
+                            accounts.dispatch(key, &account, slot);
+                        }
                         UpdateOneof::Slot(SubscribeUpdateSlot { slot, .. }) => {
                             info!("slot received: {slot}");
                         }
@@ -96,3 +438,83 @@ impl SolGrpcClient {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{AccountFilter, AccountSubscriptions};
+    use solana_sdk::account::Account;
+    use solana_sdk::pubkey::Pubkey;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[test]
+    fn dispatches_until_unsubscribed() {
+        let subscriptions = AccountSubscriptions::new();
+        assert!(subscriptions.filters().is_empty());
+
+        let (key, owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let seen = Arc::new(AtomicU64::new(0));
+        let counter = |seen: &Arc<AtomicU64>| {
+            let seen = Arc::clone(seen);
+            move |_: Pubkey, _: &Account, slot: u64| {
+                seen.fetch_add(slot, Ordering::Relaxed);
+            }
+        };
+        let by_key = subscriptions.subscribe(AccountFilter::Account(key), counter(&seen));
+        let by_owner = subscriptions.subscribe(AccountFilter::Owner(owner), counter(&seen));
+        let filters = subscriptions.filters();
+        assert_eq!(filters["accounts"].account, [key.to_string()]);
+        assert_eq!(filters["owners"].owner, [owner.to_string()]);
+
+        let account = Account {
+            owner,
+            ..Default::default()
+        };
+        subscriptions.dispatch(key, &account, 1);
+        assert_eq!(seen.load(Ordering::Relaxed), 2);
+        drop(by_key);
+        subscriptions.dispatch(key, &account, 10);
+        assert_eq!(seen.load(Ordering::Relaxed), 12);
+        drop(by_owner);
+        assert!(subscriptions.filters().is_empty());
+        // every change asked for a new subscribe request
+        let mut resubscribe_rx = subscriptions.resubscribe_rx.try_lock().unwrap();
+        assert_eq!(
+            std::iter::from_fn(|| resubscribe_rx.try_recv().ok()).count(),
+            4
+        );
+    }
+
+    #[test]
+    fn shares_accounts_between_subscriptions() {
+        let subscriptions = AccountSubscriptions::new();
+        let (shared, own) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let seen = Arc::new(AtomicU64::new(0));
+        let counter = || {
+            let seen = Arc::clone(&seen);
+            move |_: Pubkey, _: &Account, _: u64| {
+                seen.fetch_add(1, Ordering::Relaxed);
+            }
+        };
+        let first = subscriptions.subscribe_all(
+            [AccountFilter::Account(shared), AccountFilter::Account(own)],
+            counter(),
+        );
+        let _second = subscriptions.subscribe(AccountFilter::Account(shared), counter());
+        assert_eq!(subscriptions.filters()["accounts"].account.len(), 2);
+
+        subscriptions.dispatch(shared, &Account::default(), 0);
+        assert_eq!(seen.load(Ordering::Relaxed), 2);
+        drop(first);
+        assert_eq!(
+            subscriptions.filters()["accounts"].account,
+            [shared.to_string()]
+        );
+        // the two filters went out in one request
+        let mut resubscribe_rx = subscriptions.resubscribe_rx.try_lock().unwrap();
+        assert_eq!(
+            std::iter::from_fn(|| resubscribe_rx.try_recv().ok()).count(),
+            3
+        );
+    }
+}
@@ -1,9 +1,23 @@
+use anyhow::anyhow;
+use log::warn;
 use std::env;
 use std::env::VarError;
+use std::str::FromStr;
 pub const WSOL: &str = "So11111111111111111111111111111111111111112";
+pub const USDC: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+pub const USDT: &str = "Es9vMFrzaCERmJrcqS1ZzUK2Lg7wxLqsXzUyzDsCrHtS";
+/// Mints tokens are priced in rather than traded
+pub const QUOTE_MINTS: [&str; 3] = [WSOL, USDC, USDT];
 pub const RAYDIUM_AUTHORITY_V4: &str = "5Q544fKrFoe6tsEbD7S8EmxGTJYAKtTVhAW5Q5pge4j1";
 pub const RAYDIUM_LIQUIDITY_POOL_V4_PROGRAM_ID: &str =
     "675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8";
+pub const RAYDIUM_CLMM_PROGRAM_ID: &str = "CAMMCzo5YL8w4VFF8KVHrK22GGUsp5VTaW7grrKgrWqK";
+pub const RAYDIUM_CPMM_PROGRAM_ID: &str = "CPMMoo8L3F4NbTegBCKVNunggL7H1ZpdTHKxQB5qKP1C";
+pub const PUMP_FUN_PROGRAM_ID: &str = "6EF8rrecthR5Dkzon8Nwu78hRvfCKubJ14M5uBEwF6P";
+pub const PUMP_AMM_PROGRAM_ID: &str = "pAMMBay6oceH9fJKBRHGP5D4bD4sWpmSwMn52FMfXEA";
+/// Keeps the fee tiers of pump.fun and the pump AMM
+pub const PUMP_FEE_PROGRAM_ID: &str = "pfeeUxB6jkeY1Hxd7CsFCAjcbHA9rWtchMGdZ6VojVZ";
+pub const JUPITER_V6_PROGRAM_ID: &str = "JUP6LkbZbjS1jKKwapdHNy74zcZ3tLUZoi5QNyVTaV4";
 
 pub struct Config {
     pub rpc_link: String,
@@ -22,3 +36,28 @@ impl Config {
         })
     }
 }
+
+/// Reads an optional setting from the environment, falling back to `default`
+/// when the variable is missing. A value that cannot be parsed is logged and falls back too.
+pub fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    match env_parse(key) {
+        Ok(value) => value.unwrap_or(default),
+        Err(e) => {
+            warn!("{}, using the default", e);
+            default
+        }
+    }
+}
+
+/// Reads an optional setting from the environment, failing on a value that cannot be parsed
+/// instead of falling back. For settings where a typo must not go unnoticed.
+pub fn env_parse<T: FromStr>(key: &str) -> anyhow::Result<Option<T>> {
+    match env::var(key) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| anyhow!("Invalid value {:?} for {}", value, key)),
+        Err(_) => Ok(None),
+    }
+}
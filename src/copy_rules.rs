@@ -0,0 +1,153 @@
+use crate::raydium::types::QuoteSummary;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+
+/// Filters applied to a copy before we buy. Unset fields don't filter anything.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CopyRules {
+    /// Minimum SOL held by the pool, in lamports
+    pub min_sol_liquidity: Option<u64>,
+    /// Skip pools opened less than this many seconds ago
+    pub min_pool_age_secs: Option<u64>,
+    /// Skip pools opened more than this many seconds ago
+    pub max_pool_age_secs: Option<u64>,
+    /// Skip when our own trade moves the price by more than this
+    pub max_price_impact_bps: Option<u64>,
+}
+
+impl CopyRules {
+    /// Fields set in `self` win, the rest come from `fallback`
+    fn or(&self, fallback: &CopyRules) -> CopyRules {
+        CopyRules {
+            min_sol_liquidity: self.min_sol_liquidity.or(fallback.min_sol_liquidity),
+            min_pool_age_secs: self.min_pool_age_secs.or(fallback.min_pool_age_secs),
+            max_pool_age_secs: self.max_pool_age_secs.or(fallback.max_pool_age_secs),
+            max_price_impact_bps: self.max_price_impact_bps.or(fallback.max_price_impact_bps),
+        }
+    }
+
+    /// `now` is a unix timestamp in seconds
+    pub fn evaluate(&self, quote: &QuoteSummary, now: u64) -> Result<(), SkipReason> {
+        if quote.pool_open_time > now {
+            return Err(SkipReason::PoolNotOpen {
+                opens_in_secs: quote.pool_open_time - now,
+            });
+        }
+        if let Some(min) = self.min_sol_liquidity {
+            let liquidity = quote.sol_reserve.unwrap_or_default();
+            if liquidity < min {
+                return Err(SkipReason::LowLiquidity { liquidity, min });
+            }
+        }
+        let age_secs = now - quote.pool_open_time;
+        if let Some(min) = self.min_pool_age_secs
+            && age_secs < min
+        {
+            return Err(SkipReason::PoolTooYoung { age_secs, min });
+        }
+        if let Some(max) = self.max_pool_age_secs
+            && age_secs > max
+        {
+            return Err(SkipReason::PoolTooOld { age_secs, max });
+        }
+        if let Some(max) = self.max_price_impact_bps {
+            let impact_bps = quote.price_impact_bps;
+            if impact_bps > max {
+                return Err(SkipReason::PriceImpact { impact_bps, max });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SkipReason {
+    PoolNotOpen { opens_in_secs: u64 },
+    LowLiquidity { liquidity: u64, min: u64 },
+    PoolTooYoung { age_secs: u64, min: u64 },
+    PoolTooOld { age_secs: u64, max: u64 },
+    PriceImpact { impact_bps: u64, max: u64 },
+}
+
+impl std::fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SkipReason::PoolNotOpen { opens_in_secs } => {
+                write!(f, "pool opens in {}s", opens_in_secs)
+            }
+            SkipReason::LowLiquidity { liquidity, min } => {
+                write!(f, "pool holds {} lamports, below {}", liquidity, min)
+            }
+            SkipReason::PoolTooYoung { age_secs, min } => {
+                write!(f, "pool is {}s old, younger than {}s", age_secs, min)
+            }
+            SkipReason::PoolTooOld { age_secs, max } => {
+                write!(f, "pool is {}s old, older than {}s", age_secs, max)
+            }
+            SkipReason::PriceImpact { impact_bps, max } => {
+                write!(f, "price impact {} bps above {} bps", impact_bps, max)
+            }
+        }
+    }
+}
+
+/// Rules for every target, loaded from a json file shaped like
+/// `{ "default": { ... }, "targets": { "<wallet>": { ... } } }`.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CopyRuleSet {
+    default: CopyRules,
+    targets: HashMap<String, CopyRules>,
+}
+
+impl CopyRuleSet {
+    /// A missing file means no rules at all
+    pub fn new(file_path: &str) -> anyhow::Result<Self> {
+        match fs::read_to_string(file_path) {
+            Ok(contents) => Ok(serde_json::from_str(&contents)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn for_target(&self, target: &str) -> CopyRules {
+        match self.targets.get(target) {
+            Some(rules) => rules.or(&self.default),
+            None => self.default.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CopyRuleSet, CopyRules};
+
+    #[test]
+    fn target_rules_fall_back_to_default() {
+        let rules: CopyRuleSet = serde_json::from_str(
+            r#"{
+                "default": { "min_sol_liquidity": 5000000000, "max_price_impact_bps": 300 },
+                "targets": { "wallet": { "max_price_impact_bps": 800 } }
+            }"#,
+        )
+        .unwrap();
+
+        let target = rules.for_target("wallet");
+        assert_eq!(target.min_sol_liquidity, Some(5_000_000_000));
+        assert_eq!(target.max_price_impact_bps, Some(800));
+
+        let other = rules.for_target("someone else");
+        assert_eq!(other.max_price_impact_bps, Some(300));
+        assert_eq!(other.min_pool_age_secs, None);
+    }
+
+    #[test]
+    fn empty_rules_filter_nothing() {
+        let rules: CopyRules = serde_json::from_str("{}").unwrap();
+        assert!(rules.min_sol_liquidity.is_none());
+        assert!(rules.max_pool_age_secs.is_none());
+    }
+}
@@ -1,54 +1,111 @@
-use crate::gen_engine;
+use crate::gen_engine::Engine;
+use crate::pump_fun::migrations;
+use crate::raydium::liquidity::{LiquidityEvent, liquidity_events};
 use crate::target_list::TargetList;
 use crate::trade_info::{TradeInfoFromToken, TradeType};
-use log::{debug, info};
+use log::{debug, info, warn};
 use yellowstone_grpc_proto::geyser::SubscribeUpdateTransaction;
 
 pub async fn decode_instruction(
+    engine: Engine,
     target_list: TargetList,
     token_list: TargetList,
     transaction: SubscribeUpdateTransaction,
 ) -> anyhow::Result<()> {
-    if let Some(log_messages) = transaction
-        .clone()
+    publish_liquidity_events(&engine, &token_list, &transaction);
+    follow_migrations(&engine, &transaction);
+    if transaction
         .transaction
-        .unwrap()
-        .meta
-        .map(|meta| meta.log_messages)
+        .as_ref()
+        .is_some_and(|info| info.meta.is_some())
     {
         let trade_info = TradeInfoFromToken::from_update(transaction.clone())?;
+        // only listed targets trading listed tokens are copied, and only their trades journaled
+        if !target_list.is_listed_on_target(&trade_info.target) {
+            return Ok(());
+        }
+        engine.journal.record_target_trade(&trade_info);
+        if !token_list.is_listed_on_target(&trade_info.mint) {
+            return Ok(());
+        }
         match trade_info.trade_type {
             TradeType::Buy => {
                 info!("Buy transaction detected: {:?}", trade_info.signature);
-                crate::raydium::swap_in(trade_info).await;
+                // sending waits for confirmation, don't hold up the stream
+                tokio::spawn(async move {
+                    let signature = trade_info.signature.clone();
+                    if let Err(e) = engine.buy_token(trade_info).await {
+                        warn!("Failed to copy buy {}: {:?}", signature, e);
+                    }
+                });
             }
             TradeType::Sell => {
                 debug!("Sell transaction detected: {:?}", trade_info.signature)
             }
             TradeType::Unknown => debug!("Unknown trade type: {:?}", trade_info.signature),
         }
-
-        // todo uncomment
-        // if target_list.is_listed_on_target(&trade_info.target)
-        //     && token_list.is_listed_on_target(&trade_info.mint)
-        // {
-        //     // todo make it configurable in env for example -> ONLY_BUY or ONLY_SELL
-        //     match trade_info.trade_type {
-        //         TradeType::Buy => {
-        //             info!("Buy transaction detected: {:?}", trade_info.signature);
-        //             Engine::buy_token(trade_info)
-        //         }
-        //         TradeType::Sell => {
-        //             debug!("Sell transaction detected: {:?}", trade_info.signature)
-        //         }
-        //         TradeType::Unknown => debug!("Unknown trade type: {:?}", trade_info.signature),
-        //     }
-        //     if let Some(_log) = log_messages.into_iter().next() {};
-        // }
     }
     Ok(())
 }
 
+/// Logs and broadcasts the amm v4 pool creations and liquidity changes in `transaction`,
+/// warming the pool cache for new pools of tokens on `token_list` or that we hold.
+fn publish_liquidity_events(
+    engine: &Engine,
+    token_list: &TargetList,
+    transaction: &SubscribeUpdateTransaction,
+) {
+    let Some(info) = &transaction.transaction else {
+        return;
+    };
+    let (Some(message), Some(meta)) = (
+        info.transaction.as_ref().and_then(|tx| tx.message.as_ref()),
+        &info.meta,
+    ) else {
+        return;
+    };
+    for event in liquidity_events(message, meta, transaction.slot) {
+        info!("Liquidity event: {}", event);
+        if let LiquidityEvent::PoolCreated(created) = &event {
+            let keys = &created.amm_keys;
+            if [keys.amm_coin_mint, keys.amm_pc_mint].iter().any(|mint| {
+                token_list.is_listed_on_target(&mint.to_string()) || engine.positions.holds(mint)
+            }) {
+                engine.warm_pool(created.amm_keys);
+            }
+        }
+        // nobody listening is fine
+        let _ = engine.liquidity.send(event);
+    }
+}
+
+/// Moves positions held on pump.fun curves that `transaction` migrated onto their pump AMM pool,
+/// so exits and sells trade there.
+fn follow_migrations(engine: &Engine, transaction: &SubscribeUpdateTransaction) {
+    let Some(info) = &transaction.transaction else {
+        return;
+    };
+    let (Some(message), Some(meta)) = (
+        info.transaction.as_ref().and_then(|tx| tx.message.as_ref()),
+        &info.meta,
+    ) else {
+        return;
+    };
+    for migration in migrations(message, meta) {
+        info!(
+            "Curve {} of {} migrated to {}",
+            migration.bonding_curve, migration.mint, migration.pool
+        );
+        if let Some(position) =
+            engine
+                .positions
+                .graduate(&migration.mint, &migration.bonding_curve, migration.pool)
+        {
+            info!("Position graduated: {}", position);
+        }
+    }
+}
+
 pub fn parse_logs(logs: Vec<String>) {
     for log in logs {
         if log.contains("swap") {
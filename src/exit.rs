@@ -0,0 +1,273 @@
+//! Emergency exits: positions are sold as soon as their pool is being drained of liquidity.
+
+use crate::config::{WSOL, env_or};
+use crate::gen_engine::Engine;
+use crate::positions::Position;
+use crate::raydium::amm::RaydiumAmm;
+use crate::raydium::api_v3::ApiV3Client;
+use crate::raydium::liquidity::{LiquidityChange, LiquidityEvent};
+use crate::raydium::types::{RaydiumAmmExecutorOpts, SwapExecutionMode, SwapInput};
+use crate::router::SwapVenue;
+use crate::sender::SendOutcome;
+use anyhow::bail;
+use log::{info, warn};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signer::Signer;
+use spl_associated_token_account_client::address::get_associated_token_address_with_program_id;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{Duration, Instant};
+
+/// Reason exits triggered by a liquidity drop are journaled under
+pub const RUG_EXITED: &str = "rug-exited";
+
+#[derive(Clone, Copy, Debug)]
+pub struct RugExitConfig {
+    pub enabled: bool,
+    /// Drop of a pool's quote side liquidity, in bps of its recent high, that triggers the exit
+    pub max_drop_bps: u64,
+    /// How far back the recent high is taken from
+    pub window: Duration,
+    /// Slippage the exit sells with, it has to land while the pool empties
+    pub slippage_bps: u64,
+    /// Sells tried before the position is left open for the next exit
+    pub max_attempts: u32,
+}
+
+impl RugExitConfig {
+    pub fn from_env() -> Self {
+        Self {
+            enabled: env_or("RUG_EXIT", true),
+            max_drop_bps: env_or("RUG_EXIT_MAX_DROP_BPS", 3_000),
+            window: Duration::from_secs(env_or("RUG_EXIT_WINDOW_SECS", 120)),
+            slippage_bps: env_or("RUG_EXIT_SLIPPAGE_BPS", 5_000),
+            max_attempts: env_or("RUG_EXIT_MAX_ATTEMPTS", 3).max(1),
+        }
+    }
+}
+
+/// Quote side liquidity each pool had recently, from the liquidity events seen.
+#[derive(Debug)]
+pub struct LiquidityWindow {
+    window: Duration,
+    reserves: HashMap<Pubkey, VecDeque<(Instant, u64)>>,
+}
+
+impl LiquidityWindow {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            reserves: HashMap::new(),
+        }
+    }
+
+    /// Records `change` and returns how far the pool's quote side now sits below its high
+    /// within the window, in bps.
+    pub fn observe(&mut self, change: &LiquidityChange, removed: bool, at: Instant) -> u64 {
+        let (amount, reserve) = quote_side(change);
+        let before = if removed {
+            reserve.saturating_add(amount)
+        } else {
+            reserve.saturating_sub(amount)
+        };
+        let reserves = self.reserves.entry(change.pool).or_default();
+        while reserves
+            .front()
+            .is_some_and(|(seen, _)| at.duration_since(*seen) > self.window)
+        {
+            reserves.pop_front();
+        }
+        // the reserve right before the change counts too, so one withdrawal draining the pool
+        // is caught without an earlier event
+        reserves.push_back((at, before));
+        reserves.push_back((at, reserve));
+        let high = reserves
+            .iter()
+            .map(|(_, reserve)| *reserve)
+            .max()
+            .unwrap_or(0);
+        if high == 0 {
+            return 0;
+        }
+        ((high - reserve) as u128 * 10_000 / high as u128) as u64
+    }
+}
+
+/// Amount moved and reserve left on the quote side: wsol when the pool trades against it,
+/// else pc
+fn quote_side(change: &LiquidityChange) -> (u64, u64) {
+    if change.coin_mint == Pubkey::from_str_const(WSOL) {
+        (change.coin_amount, change.coin_reserve)
+    } else {
+        (change.pc_amount, change.pc_reserve)
+    }
+}
+
+/// Watches the engine's liquidity events and sells open positions on pools losing liquidity.
+/// The exit skips the copy rules, safety and honeypot checks and slippage limits buys go
+/// through.
+pub struct RugExit;
+
+impl RugExit {
+    pub fn start(engine: Engine, config: RugExitConfig) {
+        let mut events = engine.liquidity.subscribe();
+        tokio::spawn(async move {
+            let mut window = LiquidityWindow::new(config.window);
+            loop {
+                let (change, removed) = match events.recv().await {
+                    Ok(LiquidityEvent::Added(change)) => (change, false),
+                    Ok(LiquidityEvent::Removed(change)) => (change, true),
+                    Ok(LiquidityEvent::PoolCreated(_)) => continue,
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!("Rug exit missed {} liquidity events", missed);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return,
+                };
+                let drop_bps = window.observe(&change, removed, Instant::now());
+                if drop_bps < config.max_drop_bps {
+                    continue;
+                }
+                for position in engine.positions.open_on(&change.pool) {
+                    let Some(position) = engine.positions.exit(&position.mint) else {
+                        continue;
+                    };
+                    warn!(
+                        "Liquidity of {} dropped {} bps, exiting {}",
+                        change.pool, drop_bps, position
+                    );
+                    let engine = engine.clone();
+                    tokio::spawn(async move {
+                        exit(&engine, &position, &config, drop_bps).await;
+                    });
+                }
+            }
+        });
+    }
+}
+
+/// Sells the position until a sell lands, journaling every attempt. Reopens the position if
+/// none did, so the next drop exits it again.
+async fn exit(engine: &Engine, position: &Position, config: &RugExitConfig, drop_bps: u64) {
+    for attempt in 1..=config.max_attempts {
+        match sell(engine, position, config.slippage_bps).await {
+            Ok(outcome) => {
+                info!(
+                    "Rug exit of {} attempt {}: {}",
+                    position.mint, attempt, outcome
+                );
+                engine
+                    .journal
+                    .record_exit(position, RUG_EXITED, drop_bps, Ok(&outcome));
+                if outcome.is_success() {
+                    return;
+                }
+            }
+            Err(e) => {
+                warn!(
+                    "Rug exit of {} attempt {} failed: {:?}",
+                    position.mint, attempt, e
+                );
+                engine
+                    .journal
+                    .record_exit(position, RUG_EXITED, drop_bps, Err(&e.to_string()));
+            }
+        }
+    }
+    warn!(
+        "No rug exit of {} landed in {} attempts, leaving it open",
+        position.mint, config.max_attempts
+    );
+    engine.positions.reopen(&position.mint);
+}
+
+/// Sells all we hold of the position's token on its pool for wsol
+async fn sell(
+    engine: &Engine,
+    position: &Position,
+    slippage_bps: u64,
+) -> anyhow::Result<SendOutcome> {
+    let user = engine.keypair.pubkey();
+    // token 2022 mints keep balances in accounts derived with their own program
+    let token_program = engine.client.get_account(&position.mint).await?.owner;
+    let token_account =
+        get_associated_token_address_with_program_id(&user, &position.mint, &token_program);
+    let balance: u64 = engine
+        .client
+        .get_token_account_balance(&token_account)
+        .await?
+        .amount
+        .parse()?;
+    if balance == 0 {
+        bail!("no {} left to sell", position.mint);
+    }
+
+    let amm = RaydiumAmm::new(
+        Arc::clone(&engine.client),
+        RaydiumAmmExecutorOpts {
+            as_legacy_transaction: Some(env_or("LEGACY_TRANSACTIONS", true)),
+            ..Default::default()
+        },
+        ApiV3Client::new(None),
+    )
+    .with_pool_cache(engine.pools.clone());
+    let swap_input = SwapInput {
+        input_token_mint: position.mint,
+        output_token_mint: Pubkey::from_str_const(WSOL),
+        slippage_bps: slippage_bps.min(10_000) as u16,
+        amount: balance,
+        mode: SwapExecutionMode::ExactIn,
+        market: Some(position.market),
+    };
+    let mut quote = SwapVenue::quote(&amm, &swap_input).await?;
+    quote.set_slippage(slippage_bps)?;
+    let instructions = SwapVenue::swap_instructions(&amm, user, &quote).await?;
+    let lookup_tables = SwapVenue::lookup_tables(&amm, &quote).await?;
+    let outcome = engine
+        .sender
+        .send(instructions, &lookup_tables, &engine.keypair)
+        .await?;
+    engine
+        .journal
+        .record_send(&position.target_signature, &quote.summary(), &outcome);
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LiquidityWindow;
+    use crate::config::WSOL;
+    use crate::raydium::liquidity::LiquidityChange;
+    use solana_sdk::pubkey::Pubkey;
+    use tokio::time::{Duration, Instant};
+
+    #[test]
+    fn measures_drops_from_the_recent_high() {
+        let change = |amount, reserve| LiquidityChange {
+            pool: Pubkey::default(),
+            coin_mint: Pubkey::new_unique(),
+            pc_mint: Pubkey::from_str_const(WSOL),
+            coin_amount: 0,
+            pc_amount: amount,
+            lp_amount: 0,
+            coin_reserve: 0,
+            pc_reserve: reserve,
+            slot: 0,
+        };
+        let mut window = LiquidityWindow::new(Duration::from_secs(60));
+        let start = Instant::now();
+        // a single withdrawal of most of the pool
+        assert_eq!(window.observe(&change(800, 200), true, start), 8_000);
+
+        let mut window = LiquidityWindow::new(Duration::from_secs(60));
+        assert_eq!(window.observe(&change(100, 1_000), false, start), 0);
+        // small pulls add up within the window
+        let later = start + Duration::from_secs(30);
+        assert_eq!(window.observe(&change(100, 900), true, later), 1_000);
+        assert_eq!(window.observe(&change(200, 700), true, later), 3_000);
+        // the high ages out
+        let much_later = start + Duration::from_secs(120);
+        assert_eq!(window.observe(&change(100, 600), true, much_later), 1_428);
+    }
+}
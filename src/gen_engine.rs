@@ -1,202 +1,93 @@
-use crate::keypair::from_bytes_to_key_pair;
+use crate::client::AccountSubscriptions;
+use crate::config::env_or;
+use crate::journal::Journal;
+use crate::keypair::load_keypair;
+use crate::positions::PositionBook;
+use crate::raydium::CopyConfig;
+use crate::raydium::amm::RaydiumAmm;
 use crate::raydium::api_v3::ApiV3Client;
-use crate::raydium::types::{SwapExecutionMode, SwapInput};
-use crate::trade_info::TradeInfoFromToken;
-use borsh::BorshDeserialize;
-use log::info;
-use solana_client::rpc_client::RpcClient;
-use solana_sdk::{
-    instruction::{AccountMeta, Instruction},
-    message::Message,
-    pubkey::Pubkey,
-    signature::{Keypair, Signer},
-    transaction::Transaction,
+use crate::raydium::liquidity::LiquidityEvent;
+use crate::raydium::pool_cache::PoolStateCache;
+use crate::raydium::types::{AmmKeys, RaydiumAmmExecutorOpts};
+use crate::sender::{
+    BlockhashCache, RpcSubmitter, SendPath, SenderConfig, TransactionSender, TransactionSubmitter,
 };
-use std::str::FromStr;
+use crate::tpu::TpuSubmitter;
+use crate::trade_info::TradeInfoFromToken;
+use log::debug;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::signature::Keypair;
 use std::sync::Arc;
+use tokio::sync::broadcast;
 
-// Constants (assumed to be defined in config.rs)
-const RAYDIUM_AMM_V4: &str = "675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8";
-const RAYDIUM_AUTHORITY_V4: &str = "5Q544fKrFoe6tsEbD7S8EmxGTJYAKtTVhAW5Q5pge4j1";
-const WSOL: &str = "So11111111111111111111111111111111111111112";
-const SERUM_PROGRAM_ID: &str = "srmqPvymJeFKQ4zGQed1GFppgkRHL9kaELCbyksJtPX";
-
-// Assume these are defined in your project
-struct Config {
-    rpc_link: String,
-    private_key: String,
+/// State shared by every copy: the rpc client, our wallet, the sender and the positions we hold.
+#[derive(Clone)]
+pub struct Engine {
+    pub client: Arc<RpcClient>,
+    pub keypair: Arc<Keypair>,
+    pub sender: TransactionSender,
+    pub positions: PositionBook,
+    pub pools: Option<PoolStateCache>,
+    /// Accounts followed on the geyser stream the `SolGrpcClient` runs
+    pub accounts: AccountSubscriptions,
+    pub journal: Journal,
+    /// Checks and rules copies go through
+    pub copy: Arc<CopyConfig>,
+    /// Pool creations and liquidity changes of amm v4 pools seen on the stream
+    pub liquidity: broadcast::Sender<LiquidityEvent>,
 }
 
-impl Config {
-    fn new() -> anyhow::Result<Self> {
-        // Implementation to load config
-        Ok(Config {
-            rpc_link: String::from("https://api.devnet.solana.com"),
-            private_key: String::from("YOUR_PRIVATE_KEY"),
+impl Engine {
+    pub async fn new(rpc_link: String, ws_link: &str, private_key: &str) -> anyhow::Result<Self> {
+        let client = Arc::new(RpcClient::new(rpc_link));
+        let config = SenderConfig::from_env()?;
+        let blockhashes =
+            BlockhashCache::start(Arc::clone(&client), config.blockhash_refresh_ms).await?;
+        let submitter: Arc<dyn TransactionSubmitter> = match config.send_path {
+            SendPath::Rpc => Arc::new(RpcSubmitter::new(&client, &config)),
+            SendPath::Tpu => Arc::new(TpuSubmitter::new(Arc::clone(&client), ws_link).await?),
+        };
+        let accounts = AccountSubscriptions::new();
+        let pools = env_or("POOL_CACHE", true)
+            .then(|| PoolStateCache::start(&accounts, Arc::clone(&client)));
+        Ok(Self {
+            sender: TransactionSender::new(Arc::clone(&client), submitter, blockhashes, config),
+            client,
+            keypair: Arc::new(load_keypair(private_key)?),
+            positions: PositionBook::new(),
+            pools,
+            accounts,
+            journal: Journal::open(&env_or("JOURNAL_PATH", "journal.db".to_string()))?,
+            copy: Arc::new(CopyConfig::from_env()?),
+            liquidity: broadcast::channel(1024).0,
         })
     }
-}
-
-// Pool state struct for deserialization
-#[derive(BorshDeserialize, Debug)]
-struct AmmInfo {
-    token_0_mint: Pubkey,
-    token_1_mint: Pubkey,
-    token_0_vault: Pubkey,
-    token_1_vault: Pubkey,
-    open_orders: Pubkey,
-    market: Pubkey,
-}
-
-// Pool keys struct
-#[derive(Clone, Debug)]
-struct PoolKeys {
-    id: Pubkey,
-    authority: Pubkey,
-    open_orders: Pubkey,
-    base_vault: Pubkey,
-    quote_vault: Pubkey,
-    market_id: Pubkey,
-    market_program_id: Pubkey,
-    market_bids: Pubkey,
-    market_asks: Pubkey,
-    market_event_queue: Pubkey,
-    market_base_vault: Pubkey,
-    market_quote_vault: Pubkey,
-    market_vault_signer: Pubkey,
-}
 
-pub struct Engine {}
+    /// Starts caching a freshly created pool in the background, so the first copy on it
+    /// needs no rpc
+    pub fn warm_pool(&self, amm_keys: AmmKeys) {
+        if self.pools.is_none() {
+            return;
+        }
+        let amm = RaydiumAmm::new(
+            Arc::clone(&self.client),
+            RaydiumAmmExecutorOpts::default(),
+            ApiV3Client::new(None),
+        )
+        .with_pool_cache(self.pools.clone());
+        tokio::spawn(async move {
+            if let Err(e) = amm.warm(amm_keys).await {
+                debug!("Failed to warm pool {}: {}", amm_keys.amm_pool, e);
+            }
+        });
+    }
 
-impl Engine {
-    pub async fn buy_token(trade_info: TradeInfoFromToken) -> anyhow::Result<()> {
-        let token_amount = trade_info.token_amount_list.token_post_amount
-            - trade_info.token_amount_list.token_pre_amount;
-        let sol_amount =
-            trade_info.sol_amount_list.sol_post_amount - trade_info.sol_amount_list.sol_pre_amount;
-        let Config { rpc_link, .. } = Config::new()?;
-        let payer = from_bytes_to_key_pair();
-        let token_mint = Pubkey::from_str(&trade_info.mint)?;
-        let pool_id = Pubkey::from_str(&trade_info.pool)?;
-        let raydium_program_id = Pubkey::from_str(RAYDIUM_AMM_V4)?;
-        let wsol_mint = Pubkey::from_str(WSOL)?;
-        info!("Token amount: {}, SOL amount: {}", token_amount, sol_amount);
-        Ok(())
+    pub async fn buy_token(&self, trade_info: TradeInfoFromToken) -> anyhow::Result<()> {
+        crate::raydium::swap_in(self, trade_info).await
     }
 
-    pub async fn sell_token(trade_info: TradeInfoFromToken) -> anyhow::Result<()> {
-        // let token_amount = trade_info.token_amount_list.token_post_amount
-        //     - trade_info.token_amount_list.token_pre_amount;
-        // let sol_amount =
-        //     trade_info.sol_amount_list.sol_post_amount - trade_info.sol_amount_list.sol_pre_amount;
-        // let Config { rpc_link, .. } = Config::new()?;
-        // let client = RpcClient::new(rpc_link);
-        // let payer = from_bytes_to_key_pair();
-        // let token_mint = Pubkey::from_str(&trade_info.mint)?;
-        // let pool_id = Pubkey::from_str(&trade_info.pool)?;
-        // let raydium_program_id = Pubkey::from_str(RAYDIUM_AMM_V4)?;
-        // let wsol_mint = Pubkey::from_str(WSOL)?;
-        //
-        // info!("Token amount: {}, SOL amount: {}", token_amount, sol_amount);
-        //
-        // // Fetch pool keys
-        // let pool_keys = get_pool_keys(&client, &pool_id).await?;
-        //
-        // // Determine base and quote vaults based on pool order
-        // let (base_vault, quote_vault, base_mint, quote_mint) = {
-        //     let account = client.get_account(&pool_id)?;
-        //     let pool_data = account.data;
-        //     let amm_info = AmmInfo::try_from_slice(&pool_data[8..])?;
-        //     if amm_info.token_0_mint == wsol_mint {
-        //         (
-        //             pool_keys.base_vault,
-        //             pool_keys.quote_vault,
-        //             amm_info.token_0_mint,
-        //             amm_info.token_1_mint,
-        //         )
-        //     } else {
-        //         (
-        //             pool_keys.quote_vault,
-        //             pool_keys.base_vault,
-        //             amm_info.token_1_mint,
-        //             amm_info.token_0_mint,
-        //         )
-        //     }
-        // };
-        //
-        // // Get user token accounts
-        // let user_source = get_associated_token_address(&payer.pubkey(), &token_mint); // Token
-        // let user_destination = get_associated_token_address(&payer.pubkey(), &wsol_mint); // SOL
-        //
-        // // Create associated token account if needed
-        // let create_ata_instruction = if client.get_account(&user_destination).is_err() {
-        //     Some(
-        //         spl_associated_token_account::instruction::create_associated_token_account(
-        //             &payer.pubkey(),
-        //             &payer.pubkey(),
-        //             &wsol_mint,
-        //             &spl_token::id(),
-        //         ),
-        //     )
-        // } else {
-        //     None
-        // };
-        //
-        // // Swap parameters
-        // let amount_in: u64 = 100_000_000; // Example: 100 tokens (adjust for decimals)
-        // let minimum_amount_out: u64 = 0; // Adjust for slippage
-        //
-        // // Construct swap instruction accounts
-        // let accounts = vec![
-        //     AccountMeta::new_readonly(spl_token::id(), false), // Token program
-        //     AccountMeta::new(pool_id, false),                  // AMM pool
-        //     AccountMeta::new_readonly(pool_keys.authority, false), // AMM authority
-        //     AccountMeta::new(pool_keys.open_orders, false),    // AMM open orders
-        //     AccountMeta::new(quote_vault, false),              // AMM base vault (Token)
-        //     AccountMeta::new(base_vault, false),               // AMM quote vault (SOL)
-        //     AccountMeta::new_readonly(pool_keys.market_program_id, false), // Serum program
-        //     AccountMeta::new(pool_keys.market_id, false),      // Serum market
-        //     AccountMeta::new(pool_keys.market_bids, false),    // Serum bids
-        //     AccountMeta::new(pool_keys.market_asks, false),    // Serum asks
-        //     AccountMeta::new(pool_keys.market_event_queue, false), // Serum event queue
-        //     AccountMeta::new(pool_keys.market_quote_vault, false), // Serum base vault
-        //     AccountMeta::new(pool_keys.market_base_vault, false), // Serum quote vault
-        //     AccountMeta::new_readonly(pool_keys.market_vault_signer, false), // Serum vault signer
-        //     AccountMeta::new(user_source, false),              // User source (Token)
-        //     AccountMeta::new(user_destination, false),         // User destination (SOL)
-        //     AccountMeta::new(payer.pubkey(), true),            // User owner
-        // ];
-        //
-        // // Swap instruction data
-        // let data = vec![
-        //     vec![9_u8], // SwapBaseIn instruction index (verify with Raydium program)
-        //     amount_in.to_le_bytes().to_vec(),
-        //     minimum_amount_out.to_le_bytes().to_vec(),
-        // ]
-        // .concat();
-        //
-        // let swap_instruction = Instruction {
-        //     program_id: raydium_program_id,
-        //     accounts,
-        //     data,
-        // };
-        //
-        // // Build transaction
-        // let mut instructions = vec![];
-        // if let Some(ata_instruction) = create_ata_instruction {
-        //     instructions.push(ata_instruction);
-        // }
-        // instructions.push(swap_instruction);
-        //
-        // let recent_blockhash = client.get_latest_blockhash()?;
-        // let message = Message::new(&instructions, Some(&payer.pubkey()));
-        // let mut transaction = Transaction::new_unsigned(message);
-        // transaction.sign(&[&payer], recent_blockhash);
-        //
-        // // Send transaction
-        // let signature = client.send_and_confirm_transaction(&transaction)?;
-        // info!("Sell transaction signature: {}", signature);
-        //
+    /// Sells of targets are not copied yet
+    pub async fn sell_token(&self, _trade_info: TradeInfoFromToken) -> anyhow::Result<()> {
         Ok(())
     }
 }
@@ -0,0 +1,257 @@
+use crate::config::{WSOL, env_or};
+use crate::lookup_table::compile_message;
+use crate::raydium::types::{SwapExecutionMode, SwapInput};
+use crate::router::{SwapVenue, VenueQuote};
+use anyhow::{Context, anyhow, bail};
+use log::debug;
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_config::{
+    RpcSimulateTransactionAccountsConfig, RpcSimulateTransactionConfig,
+};
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::compute_budget::{self, ComputeBudgetInstruction};
+use solana_sdk::hash::Hash;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::message::VersionedMessage;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::{TransactionError, VersionedTransaction};
+use spl_associated_token_account_client::address::{
+    get_associated_token_address, get_associated_token_address_with_program_id,
+};
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug)]
+pub struct HoneypotConfig {
+    pub enabled: bool,
+    /// Round trip loss on top of the pool fees, in bps of the amount spent, before we give up
+    pub max_loss_bps: u64,
+}
+
+impl HoneypotConfig {
+    pub fn from_env() -> Self {
+        Self {
+            enabled: env_or("HONEYPOT_CHECK", true),
+            max_loss_bps: env_or("HONEYPOT_MAX_LOSS_BPS", 500),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum RoundTrip {
+    /// Our buy would not land in the first place
+    BuyFailed(TransactionError),
+    /// The buy lands but selling the tokens back does not
+    SellFailed(TransactionError),
+    Sellable {
+        spent: u64,
+        returned: u64,
+        /// Loss beyond what two swaps worth of the venue's fees explain, in bps of `spent`
+        loss_bps: u64,
+    },
+}
+
+impl std::fmt::Display for RoundTrip {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RoundTrip::BuyFailed(err) => write!(f, "buy fails: {}", err),
+            RoundTrip::SellFailed(err) => write!(f, "sell fails: {}", err),
+            RoundTrip::Sellable {
+                spent,
+                returned,
+                loss_bps,
+            } => write!(
+                f,
+                "spent {} returned {} ({} bps beyond fees)",
+                spent, returned, loss_bps
+            ),
+        }
+    }
+}
+
+impl RoundTrip {
+    pub fn is_honeypot(&self, config: &HoneypotConfig) -> bool {
+        match self {
+            RoundTrip::BuyFailed(_) | RoundTrip::SellFailed(_) => true,
+            RoundTrip::Sellable { loss_bps, .. } => *loss_bps > config.max_loss_bps,
+        }
+    }
+}
+
+/// Simulates buying with `quote` on `venue` and selling the tokens back in the same transaction.
+///
+/// Both swaps run against the live pool state, so the sell sees exactly what the buy left behind.
+/// The sell is quoted before the buy moved the price, venues passing price dependent accounts
+/// (clmm tick arrays) may fail it when the buy leaves the current range.
+pub async fn simulate_round_trip(
+    client: &Arc<RpcClient>,
+    venue: &dyn SwapVenue,
+    user: Pubkey,
+    quote: &VenueQuote,
+) -> anyhow::Result<RoundTrip> {
+    let wsol = Pubkey::from_str_const(WSOL);
+    let buy = quote.summary();
+    if buy.input_mint != wsol || !buy.amount_specified_is_input {
+        return Err(anyhow!(
+            "Round trip is only simulated for exact-in SOL buys"
+        ));
+    }
+    // the buy is only sure to fill down to its minimum out, selling the quoted output would spend
+    // more than we hold whenever it fills below the quote
+    let sold = buy.minimum_out;
+    if sold == 0 {
+        bail!("Round trip needs a minimum out to sell back, set the quote's slippage first");
+    }
+    let mut sell = venue
+        .quote(&SwapInput {
+            input_token_mint: buy.output_mint,
+            output_token_mint: wsol,
+            slippage_bps: 10_000,
+            amount: sold,
+            mode: SwapExecutionMode::ExactIn,
+            market: Some(buy.market),
+        })
+        .await?;
+    sell.set_slippage(10_000)?;
+
+    // every lamport the round trip can move: the wallet, wrapped sol and the token account, rent
+    // included
+    let token_program = client.get_account(&buy.output_mint).await?.owner;
+    let watched = [
+        user,
+        get_associated_token_address(&user, &wsol),
+        get_associated_token_address_with_program_id(&user, &buy.output_mint, &token_program),
+    ];
+    let lamports_before: u64 = client
+        .get_multiple_accounts(&watched)
+        .await?
+        .iter()
+        .flatten()
+        .map(|account| account.lamports)
+        .sum();
+
+    // each leg brings its own compute budget, a transaction only takes one
+    let buy_instructions = venue.swap_instructions(user, quote).await?;
+    let sell_instructions = venue.swap_instructions(user, &sell).await?;
+    let not_budget = |instruction: &Instruction| instruction.program_id != compute_budget::id();
+    let mut instructions = vec![ComputeBudgetInstruction::set_compute_unit_limit(1_400_000)];
+    instructions.extend(buy_instructions.into_iter().filter(not_budget));
+    let sell_start = instructions.len() as u8;
+    instructions.extend(sell_instructions.into_iter().filter(not_budget));
+
+    let mut lookup_tables = venue.lookup_tables(quote).await?;
+    lookup_tables.extend(venue.lookup_tables(&sell).await?);
+    let message = compile_message(&user, &instructions, &lookup_tables, Hash::default())?;
+    let fee = match &message {
+        VersionedMessage::Legacy(message) => client.get_fee_for_message(message).await?,
+        VersionedMessage::V0(message) => client.get_fee_for_message(message).await?,
+    };
+    let transaction = VersionedTransaction {
+        signatures: vec![Signature::default(); message.header().num_required_signatures as usize],
+        message,
+    };
+    let result = client
+        .simulate_transaction_with_config(
+            &transaction,
+            RpcSimulateTransactionConfig {
+                sig_verify: false,
+                replace_recent_blockhash: true,
+                commitment: Some(CommitmentConfig::processed()),
+                accounts: Some(RpcSimulateTransactionAccountsConfig {
+                    encoding: Some(UiAccountEncoding::Base64),
+                    addresses: watched.iter().map(Pubkey::to_string).collect(),
+                }),
+                ..Default::default()
+            },
+        )
+        .await?
+        .value;
+    debug!("Round trip simulation logs: {:?}", result.logs);
+
+    if let Some(err) = result.err {
+        return Ok(match err {
+            TransactionError::InstructionError(index, _) if index >= sell_start => {
+                RoundTrip::SellFailed(err)
+            }
+            _ => RoundTrip::BuyFailed(err),
+        });
+    }
+
+    let lamports_after: u64 = result
+        .accounts
+        .context("Simulation did not return the watched accounts")?
+        .iter()
+        .flatten()
+        .map(|account| account.lamports)
+        .sum();
+    // the buy spent exactly its input, what came back on top of that is the sell's output
+    let returned = (lamports_after as i128 - lamports_before as i128
+        + fee as i128
+        + buy.amount as i128)
+        .max(0) as u64;
+
+    let loss_bps = loss_bps(
+        buy.amount,
+        returned,
+        buy.other_amount,
+        sold,
+        (buy.fee_amount, buy.amount),
+    );
+
+    Ok(RoundTrip::Sellable {
+        spent: buy.amount,
+        returned,
+        loss_bps,
+    })
+}
+
+/// Loss of a round trip beyond two swaps worth of `fee` (numerator, denominator), in bps of
+/// what the tokens sold back cost. Only `sold` of the `bought` tokens go back, so `spent` is
+/// scaled down to their share.
+fn loss_bps(spent: u64, returned: u64, bought: u64, sold: u64, fee: (u64, u64)) -> u64 {
+    let (fee_numerator, fee_denominator) = fee;
+    let spent = (spent as u128 * sold as u128 / (bought as u128).max(1)).max(1);
+    let fee_kept = fee_denominator.saturating_sub(fee_numerator) as u128;
+    let denominator = (fee_denominator as u128).max(1);
+    let expected = spent * fee_kept * fee_kept / (denominator * denominator);
+    (expected.saturating_sub(returned as u128) * 10_000 / spent) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HoneypotConfig, RoundTrip, loss_bps};
+    use solana_sdk::instruction::InstructionError;
+    use solana_sdk::transaction::TransactionError;
+
+    #[test]
+    fn loss_is_measured_beyond_fees_on_the_share_sold() {
+        // 0.25% per swap leaves 99.500625% after two
+        let fee = (25, 10_000);
+        assert_eq!(loss_bps(1_000_000, 995_006, 500, 500, fee), 0);
+        assert_eq!(loss_bps(1_000_000, 945_006, 500, 500, fee), 500);
+        // half sold back at the same price
+        assert_eq!(loss_bps(1_000_000, 497_503, 500, 250, fee), 0);
+        assert_eq!(loss_bps(1_000_000, 447_503, 500, 250, fee), 1_000);
+        // more than expected back is no loss
+        assert_eq!(loss_bps(1_000_000, 1_100_000, 500, 500, fee), 0);
+    }
+
+    #[test]
+    fn failed_legs_and_large_losses_are_honeypots() {
+        let config = HoneypotConfig {
+            enabled: true,
+            max_loss_bps: 500,
+        };
+        let err = TransactionError::InstructionError(3, InstructionError::Custom(1));
+        assert!(RoundTrip::BuyFailed(err.clone()).is_honeypot(&config));
+        assert!(RoundTrip::SellFailed(err).is_honeypot(&config));
+        let sellable = |loss_bps| RoundTrip::Sellable {
+            spent: 1_000_000,
+            returned: 0,
+            loss_bps,
+        };
+        assert!(!sellable(500).is_honeypot(&config));
+        assert!(sellable(501).is_honeypot(&config));
+    }
+}
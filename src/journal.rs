@@ -0,0 +1,378 @@
+use crate::positions::Position;
+use crate::raydium::types::QuoteSummary;
+use crate::sender::SendOutcome;
+use crate::trade_info::TradeInfoFromToken;
+use log::warn;
+use rusqlite::{Connection, ToSql, params_from_iter};
+use std::sync::mpsc::{Sender, channel, sync_channel};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Schema changes, applied in order. The database's `user_version` is the number applied so far,
+/// so only append here.
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE target_trades (
+        signature TEXT PRIMARY KEY,
+        slot INTEGER NOT NULL,
+        recent_blockhash TEXT NOT NULL,
+        target TEXT NOT NULL,
+        mint TEXT NOT NULL,
+        pool TEXT NOT NULL,
+        decimals INTEGER NOT NULL,
+        trade_type TEXT NOT NULL,
+        token_pre_amount REAL NOT NULL,
+        token_post_amount REAL NOT NULL,
+        sol_pre_amount REAL NOT NULL,
+        sol_post_amount REAL NOT NULL,
+        observed_at INTEGER NOT NULL
+    );
+    CREATE TABLE decisions (
+        id INTEGER PRIMARY KEY,
+        target_signature TEXT NOT NULL,
+        mint TEXT NOT NULL,
+        copied INTEGER NOT NULL,
+        skip_reason TEXT,
+        decided_at INTEGER NOT NULL
+    );
+    CREATE TABLE quotes (
+        id INTEGER PRIMARY KEY,
+        target_signature TEXT NOT NULL,
+        market TEXT NOT NULL,
+        input_mint TEXT NOT NULL,
+        output_mint TEXT NOT NULL,
+        amount INTEGER NOT NULL,
+        other_amount INTEGER NOT NULL,
+        minimum_out INTEGER NOT NULL,
+        amount_specified_is_input INTEGER NOT NULL,
+        input_mint_decimals INTEGER NOT NULL,
+        output_mint_decimals INTEGER NOT NULL,
+        spot_price REAL NOT NULL,
+        execution_price REAL NOT NULL,
+        price_impact_bps INTEGER NOT NULL,
+        fee_amount INTEGER NOT NULL,
+        slot INTEGER NOT NULL,
+        slots_behind INTEGER NOT NULL,
+        quoted_at INTEGER NOT NULL
+    );
+    CREATE TABLE sends (
+        id INTEGER PRIMARY KEY,
+        target_signature TEXT NOT NULL,
+        signature TEXT NOT NULL,
+        market TEXT NOT NULL,
+        input_mint TEXT NOT NULL,
+        output_mint TEXT NOT NULL,
+        amount_in INTEGER NOT NULL,
+        amount_out INTEGER NOT NULL,
+        landed INTEGER NOT NULL,
+        endpoint TEXT,
+        slot INTEGER,
+        err TEXT,
+        compute_units INTEGER,
+        fee INTEGER,
+        sent_at INTEGER NOT NULL
+    );
+    CREATE INDEX decisions_target_signature ON decisions (target_signature);
+    CREATE INDEX quotes_target_signature ON quotes (target_signature);
+    CREATE INDEX sends_target_signature ON sends (target_signature);
+",
+    "
+    CREATE TABLE exits (
+        id INTEGER PRIMARY KEY,
+        target_signature TEXT NOT NULL,
+        mint TEXT NOT NULL,
+        market TEXT NOT NULL,
+        reason TEXT NOT NULL,
+        liquidity_drop_bps INTEGER NOT NULL,
+        signature TEXT,
+        landed INTEGER NOT NULL,
+        err TEXT,
+        exited_at INTEGER NOT NULL
+    );
+",
+    "
+    ALTER TABLE sends ADD COLUMN landed_amount_in INTEGER;
+    ALTER TABLE sends ADD COLUMN landed_amount_out INTEGER;
+",
+];
+
+/// Work queued for the journal's writer thread
+type Write = Box<dyn FnOnce(&Connection) + Send>;
+
+/// Statement parameters owned by the write, which runs after the caller moved on
+macro_rules! owned_params {
+    ($($param:expr),* $(,)?) => {
+        vec![$(Box::new($param) as Box<dyn ToSql + Send>),*]
+    };
+}
+
+/// Everything the bot saw and did, in an embedded SQLite database.
+///
+/// Recording never fails the copy, errors are only logged. Writes go to a thread of their own so
+/// SQLite never blocks the async workers.
+#[derive(Clone)]
+pub struct Journal {
+    connection: Arc<Mutex<Connection>>,
+    writes: Sender<Write>,
+}
+
+impl Journal {
+    /// Opens or creates the database at `path` and brings its schema up to date
+    pub fn open(path: &str) -> anyhow::Result<Self> {
+        let mut connection = Connection::open(path)?;
+        migrate(&mut connection)?;
+        let connection = Arc::new(Mutex::new(connection));
+        let (writes, queued) = channel::<Write>();
+        let writer = Arc::clone(&connection);
+        // runs until every handle to the journal is dropped
+        thread::spawn(move || {
+            for write in queued {
+                write(&writer.lock().unwrap());
+            }
+        });
+        Ok(Self { connection, writes })
+    }
+
+    /// The connection, once the writes recorded so far went through. Blocks, keep it off async
+    /// workers.
+    pub fn connection(&self) -> MutexGuard<'_, Connection> {
+        let (done, flushed) = sync_channel(1);
+        let flush: Write = Box::new(move |_| {
+            let _ = done.send(());
+        });
+        if self.writes.send(flush).is_ok() {
+            let _ = flushed.recv();
+        }
+        self.connection.lock().unwrap()
+    }
+
+    pub fn record_target_trade(&self, trade: &TradeInfoFromToken) {
+        self.execute(
+            "INSERT OR IGNORE INTO target_trades (signature, slot, recent_blockhash, target, mint,
+                pool, decimals, trade_type, token_pre_amount, token_post_amount, sol_pre_amount,
+                sol_post_amount, observed_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            owned_params![
+                trade.signature.clone(),
+                trade.slot,
+                trade.recent_blockhash.to_string(),
+                trade.target.clone(),
+                trade.mint.clone(),
+                trade.pool.clone(),
+                trade.decimal,
+                trade.trade_type.to_string(),
+                trade.token_amount_list.token_pre_amount,
+                trade.token_amount_list.token_post_amount,
+                trade.sol_amount_list.sol_pre_amount,
+                trade.sol_amount_list.sol_post_amount,
+                now(),
+            ],
+        );
+    }
+
+    /// `skip_reason` is `None` when we went ahead with the copy
+    pub fn record_decision(&self, target_signature: &str, mint: &str, skip_reason: Option<&str>) {
+        self.execute(
+            "INSERT INTO decisions (target_signature, mint, copied, skip_reason, decided_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            owned_params![
+                target_signature.to_string(),
+                mint.to_string(),
+                skip_reason.is_none(),
+                skip_reason.map(str::to_string),
+                now()
+            ],
+        );
+    }
+
+    pub fn record_quote(&self, target_signature: &str, quote: &QuoteSummary) {
+        self.execute(
+            "INSERT INTO quotes (target_signature, market, input_mint, output_mint, amount,
+                other_amount, minimum_out, amount_specified_is_input, input_mint_decimals,
+                output_mint_decimals, spot_price, execution_price, price_impact_bps, fee_amount,
+                slot, slots_behind, quoted_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)",
+            owned_params![
+                target_signature.to_string(),
+                quote.market.to_string(),
+                quote.input_mint.to_string(),
+                quote.output_mint.to_string(),
+                quote.amount,
+                quote.other_amount,
+                quote.minimum_out,
+                quote.amount_specified_is_input,
+                quote.input_mint_decimals,
+                quote.output_mint_decimals,
+                quote.spot_price,
+                quote.execution_price,
+                quote.price_impact_bps,
+                quote.fee_amount,
+                quote.slot,
+                quote.slots_behind,
+                now(),
+            ],
+        );
+    }
+
+    /// Records a transaction built from `quote` and how it ended
+    pub fn record_send(&self, target_signature: &str, quote: &QuoteSummary, outcome: &SendOutcome) {
+        let (amount_in, amount_out) = if quote.amount_specified_is_input {
+            (quote.amount, quote.other_amount)
+        } else {
+            (quote.other_amount, quote.amount)
+        };
+        let (signature, endpoint, slot, err, compute_units, fee, landed_amounts) = match outcome {
+            SendOutcome::Landed {
+                signature,
+                endpoint,
+                slot,
+                err,
+                compute_units,
+                fee,
+                balance_changes,
+            } => (
+                signature,
+                endpoint.clone(),
+                Some(*slot),
+                err.as_ref().map(|e| e.to_string()),
+                *compute_units,
+                *fee,
+                balance_changes.as_ref().map(|changes| {
+                    (
+                        u64::try_from(-changes.of(&quote.input_mint)).ok(),
+                        u64::try_from(changes.of(&quote.output_mint)).ok(),
+                    )
+                }),
+            ),
+            SendOutcome::Expired { signature } => (signature, None, None, None, None, None, None),
+        };
+        // the side the quote fixed is exact, only the other one is taken from the balances
+        let (landed_amount_in, landed_amount_out) = match landed_amounts.filter(|_| err.is_none()) {
+            Some((_, out)) if quote.amount_specified_is_input => (Some(amount_in), out),
+            Some((input, _)) => (input, Some(amount_out)),
+            None => (None, None),
+        };
+        self.execute(
+            "INSERT INTO sends (target_signature, signature, market, input_mint, output_mint,
+                amount_in, amount_out, landed, endpoint, slot, err, compute_units, fee, sent_at,
+                landed_amount_in, landed_amount_out)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
+            owned_params![
+                target_signature.to_string(),
+                signature.to_string(),
+                quote.market.to_string(),
+                quote.input_mint.to_string(),
+                quote.output_mint.to_string(),
+                amount_in,
+                amount_out,
+                outcome.is_success(),
+                endpoint,
+                slot,
+                err,
+                compute_units,
+                fee,
+                now(),
+                landed_amount_in,
+                landed_amount_out,
+            ],
+        );
+    }
+
+    /// Records an emergency exit of `position` and the sell it sent, `Err` when no sell went out
+    pub fn record_exit(
+        &self,
+        position: &Position,
+        reason: &str,
+        liquidity_drop_bps: u64,
+        outcome: Result<&SendOutcome, &str>,
+    ) {
+        let (signature, landed, err) = match outcome {
+            Ok(outcome @ SendOutcome::Landed { signature, err, .. }) => (
+                Some(signature.to_string()),
+                outcome.is_success(),
+                err.as_ref().map(|e| e.to_string()),
+            ),
+            Ok(SendOutcome::Expired { signature }) => (Some(signature.to_string()), false, None),
+            Err(e) => (None, false, Some(e.to_string())),
+        };
+        self.execute(
+            "INSERT INTO exits (target_signature, mint, market, reason, liquidity_drop_bps,
+                signature, landed, err, exited_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            owned_params![
+                position.target_signature.clone(),
+                position.mint.to_string(),
+                position.market.to_string(),
+                reason.to_string(),
+                liquidity_drop_bps,
+                signature,
+                landed,
+                err,
+                now(),
+            ],
+        );
+    }
+
+    fn execute(&self, sql: &'static str, params: Vec<Box<dyn ToSql + Send>>) {
+        let write: Write = Box::new(move |connection| {
+            if let Err(e) = connection.execute(sql, params_from_iter(params.iter())) {
+                warn!("Failed to write to the journal: {}", e);
+            }
+        });
+        if self.writes.send(write).is_err() {
+            warn!("Journal writer is gone, dropping a write");
+        }
+    }
+}
+
+fn migrate(connection: &mut Connection) -> rusqlite::Result<()> {
+    let applied: usize = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(applied) {
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", version + 1)?;
+        transaction.commit()?;
+    }
+    Ok(())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Journal, MIGRATIONS, migrate};
+
+    #[test]
+    fn migrations_apply_once() {
+        let journal = Journal::open(":memory:").unwrap();
+        let mut connection = journal.connection();
+        // reopening an up to date database is a no-op
+        migrate(&mut connection).unwrap();
+        let version: usize = connection
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+    }
+
+    #[test]
+    fn records_skipped_decisions() {
+        let journal = Journal::open(":memory:").unwrap();
+        journal.record_decision("target-signature", "mint", Some("pool not open"));
+        journal.record_decision("target-signature", "mint", None);
+        let (copied, skipped): (u64, u64) = journal
+            .connection()
+            .query_row(
+                "SELECT SUM(copied), SUM(skip_reason IS NOT NULL) FROM decisions",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((copied, skipped), (1, 1));
+    }
+}
@@ -0,0 +1,207 @@
+//! Decoding of Jupiter v6 routes, so trades a target routes through the aggregator can be copied.
+
+use crate::config::{QUOTE_MINTS, WSOL};
+use borsh::BorshDeserialize;
+use solana_sdk::pubkey::Pubkey;
+
+/// Anchor discriminators of the Jupiter v6 instructions and events we read
+pub const ROUTE_DISCRIMINATOR: [u8; 8] = [229, 23, 203, 151, 122, 227, 173, 42];
+pub const SHARED_ACCOUNTS_ROUTE_DISCRIMINATOR: [u8; 8] = [193, 32, 155, 51, 65, 214, 156, 129];
+pub const SWAP_EVENT_DISCRIMINATOR: [u8; 8] = [64, 198, 205, 232, 38, 8, 113, 226];
+
+/// Prefix of the self-invoked instructions anchor emits events through
+pub const EVENT_IX_TAG: [u8; 8] = [228, 69, 165, 46, 81, 203, 154, 29];
+
+/// `in_amount`, `quoted_out_amount`, `slippage_bps` and `platform_fee_bps`, the fixed size tail
+/// of both routes' arguments
+const ROUTE_TAIL_LEN: usize = 8 + 8 + 2 + 1;
+
+/// A `route` or `shared_accounts_route` instruction found in a transaction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct JupiterRouteInstruction {
+    /// Only `shared_accounts_route` names the source mint
+    pub source_mint: Option<Pubkey>,
+    pub destination_mint: Pubkey,
+    pub in_amount: u64,
+    pub quoted_out_amount: u64,
+    pub slippage_bps: u16,
+}
+
+impl JupiterRouteInstruction {
+    /// `accounts` are the instruction's accounts in order. None if this isn't a route
+    pub fn decode(data: &[u8], accounts: &[Pubkey]) -> Option<Self> {
+        let (discriminator, args) = data.split_first_chunk::<8>()?;
+        // the route plan in front is variable length, the amounts are read from the end
+        let tail = args.get(args.len().checked_sub(ROUTE_TAIL_LEN)?..)?;
+        let (in_amount, tail) = tail.split_first_chunk::<8>()?;
+        let (quoted_out_amount, tail) = tail.split_first_chunk::<8>()?;
+        let (slippage_bps, _) = tail.split_first_chunk::<2>()?;
+        let (source_mint, destination_mint) = match *discriminator {
+            // token program, user transfer authority, user source and destination token
+            // accounts, destination token account, destination mint, ...
+            ROUTE_DISCRIMINATOR => (None, *accounts.get(5)?),
+            // token program, program authority, user transfer authority, source token account,
+            // program source and destination token accounts, destination token account,
+            // source mint, destination mint, ...
+            SHARED_ACCOUNTS_ROUTE_DISCRIMINATOR => (Some(*accounts.get(7)?), *accounts.get(8)?),
+            _ => return None,
+        };
+        Some(Self {
+            source_mint,
+            destination_mint,
+            in_amount: u64::from_le_bytes(*in_amount),
+            quoted_out_amount: u64::from_le_bytes(*quoted_out_amount),
+            slippage_bps: u16::from_le_bytes(*slippage_bps),
+        })
+    }
+}
+
+/// `SwapEvent` Jupiter emits for every hop of a route
+#[derive(Clone, Copy, Debug, PartialEq, BorshDeserialize)]
+pub struct SwapEvent {
+    pub amm: Pubkey,
+    pub input_mint: Pubkey,
+    pub input_amount: u64,
+    pub output_mint: Pubkey,
+    pub output_amount: u64,
+}
+
+impl SwapEvent {
+    /// Reads the event from the data of a self-invoked event instruction. None for other events
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let body = data
+            .strip_prefix(&EVENT_IX_TAG)?
+            .strip_prefix(&SWAP_EVENT_DISCRIMINATOR)?;
+        Self::deserialize(&mut &body[..]).ok()
+    }
+}
+
+/// What a route traded end to end, intermediate hops netted out
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NetTrade {
+    pub input_mint: Pubkey,
+    pub input_amount: u64,
+    pub output_mint: Pubkey,
+    pub output_amount: u64,
+}
+
+impl NetTrade {
+    /// Sums the hops spending the route's input and those paying out its output, split routes
+    /// included. Falls back to the instruction's amounts when no event shows up.
+    /// None for circular routes.
+    pub fn from_route(route: &JupiterRouteInstruction, events: &[SwapEvent]) -> Option<Self> {
+        let input_mint = route
+            .source_mint
+            .or_else(|| events.first().map(|event| event.input_mint))?;
+        let output_mint = route.destination_mint;
+        if input_mint == output_mint {
+            return None;
+        }
+        let input_amount: u64 = events
+            .iter()
+            .filter(|event| event.input_mint == input_mint)
+            .map(|event| event.input_amount)
+            .sum();
+        let output_amount: u64 = events
+            .iter()
+            .filter(|event| event.output_mint == output_mint)
+            .map(|event| event.output_amount)
+            .sum();
+        Some(Self {
+            input_mint,
+            input_amount: if input_amount == 0 {
+                route.in_amount
+            } else {
+                input_amount
+            },
+            output_mint,
+            output_amount: if output_amount == 0 {
+                route.quoted_out_amount
+            } else {
+                output_amount
+            },
+        })
+    }
+}
+
+impl NetTrade {
+    /// The token traded against SOL and whether it was bought. None when the route isn't
+    /// quoted in SOL, or trades SOL for another quote mint.
+    pub fn sol_trade(&self) -> Option<(Pubkey, bool)> {
+        let is_quote = |mint: &Pubkey| QUOTE_MINTS.contains(&mint.to_string().as_str());
+        let wsol = Pubkey::from_str_const(WSOL);
+        if self.input_mint == wsol && !is_quote(&self.output_mint) {
+            Some((self.output_mint, true))
+        } else if self.output_mint == wsol && !is_quote(&self.input_mint) {
+            Some((self.input_mint, false))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{JupiterRouteInstruction, NetTrade, SwapEvent};
+    use crate::config::{USDC, WSOL};
+    use solana_sdk::pubkey::Pubkey;
+
+    #[test]
+    fn nets_out_intermediate_hops() {
+        let (sol, usdc, token) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let route = JupiterRouteInstruction {
+            source_mint: None,
+            destination_mint: token,
+            in_amount: 3_000,
+            quoted_out_amount: 1,
+            slippage_bps: 50,
+        };
+        let hop = |input_mint, input_amount, output_mint, output_amount| SwapEvent {
+            amm: Pubkey::new_unique(),
+            input_mint,
+            input_amount,
+            output_mint,
+            output_amount,
+        };
+        // sol split over two pools into usdc, then usdc into the token
+        let events = [
+            hop(sol, 1_000, usdc, 10),
+            hop(sol, 2_000, usdc, 20),
+            hop(usdc, 30, token, 500),
+        ];
+        assert_eq!(
+            NetTrade::from_route(&route, &events),
+            Some(NetTrade {
+                input_mint: sol,
+                input_amount: 3_000,
+                output_mint: token,
+                output_amount: 500,
+            })
+        );
+    }
+
+    #[test]
+    fn only_sol_quoted_routes_trade_a_token() {
+        let (sol, usdc, token) = (
+            Pubkey::from_str_const(WSOL),
+            Pubkey::from_str_const(USDC),
+            Pubkey::new_unique(),
+        );
+        let trade = |input_mint, output_mint| NetTrade {
+            input_mint,
+            input_amount: 1,
+            output_mint,
+            output_amount: 1,
+        };
+        assert_eq!(trade(sol, token).sol_trade(), Some((token, true)));
+        assert_eq!(trade(token, sol).sol_trade(), Some((token, false)));
+        // bought with usdc, fill prices are in sol
+        assert_eq!(trade(usdc, token).sol_trade(), None);
+        assert_eq!(trade(sol, usdc).sol_trade(), None);
+        assert_eq!(trade(usdc, sol).sol_trade(), None);
+    }
+}
@@ -5,6 +5,20 @@ pub fn get_keypair(private_key: &str) -> Keypair {
     Keypair::from_base58_string(private_key)
 }
 
+/// Accepts the key either as a base58 string or as a `[1, 2, ...]` byte array.
+pub fn load_keypair(private_key: &str) -> anyhow::Result<Keypair> {
+    let private_key = private_key.trim();
+    if !private_key.starts_with('[') {
+        return Ok(get_keypair(private_key));
+    }
+    let bytes = private_key
+        .trim_matches(&['[', ']'][..])
+        .split(',')
+        .map(|s| s.trim().parse::<u8>())
+        .collect::<Result<Vec<u8>, _>>()?;
+    Ok(Keypair::from_bytes(&bytes)?)
+}
+
 // todo move to config
 pub fn from_bytes_to_key_pair() -> Keypair {
     let pk_bytes = env::var("PK_SOLANA").expect("PK bytes is not found");
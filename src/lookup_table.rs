@@ -0,0 +1,89 @@
+use crate::config::{RAYDIUM_AUTHORITY_V4, RAYDIUM_LIQUIDITY_POOL_V4_PROGRAM_ID, WSOL};
+use log::{info, warn};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::address_lookup_table::state::AddressLookupTable;
+use solana_sdk::address_lookup_table::{AddressLookupTableAccount, instruction};
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::hash::Hash;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::message::{Message, VersionedMessage, v0};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::transaction::Transaction;
+use solana_sdk::{compute_budget, system_program};
+use spl_associated_token_account_client::address::get_associated_token_address;
+
+/// Legacy message when there are no lookup tables, v0 otherwise.
+pub fn compile_message(
+    payer: &Pubkey,
+    instructions: &[Instruction],
+    lookup_tables: &[AddressLookupTableAccount],
+    blockhash: Hash,
+) -> anyhow::Result<VersionedMessage> {
+    if lookup_tables.is_empty() {
+        return Ok(VersionedMessage::Legacy(Message::new_with_blockhash(
+            instructions,
+            Some(payer),
+            &blockhash,
+        )));
+    }
+    Ok(VersionedMessage::V0(v0::Message::try_compile(
+        payer,
+        instructions,
+        lookup_tables,
+        blockhash,
+    )?))
+}
+
+/// Loads lookup tables, skipping the ones that don't exist or don't parse.
+pub async fn fetch_lookup_tables(
+    client: &RpcClient,
+    addresses: &[Pubkey],
+) -> anyhow::Result<Vec<AddressLookupTableAccount>> {
+    let accounts = client.get_multiple_accounts(addresses).await?;
+    let mut tables = vec![];
+    for (key, account) in addresses.iter().zip(accounts) {
+        let Some(account) = account else {
+            warn!("Lookup table {} not found", key);
+            continue;
+        };
+        match AddressLookupTable::deserialize(&account.data) {
+            Ok(table) => tables.push(AddressLookupTableAccount {
+                key: *key,
+                addresses: table.addresses.to_vec(),
+            }),
+            Err(e) => warn!("Failed to parse lookup table {}: {}", key, e),
+        }
+    }
+    Ok(tables)
+}
+
+/// Creates a lookup table owned by `payer` with its WSOL account and the programs every swap uses.
+pub async fn create_lookup_table(client: &RpcClient, payer: &Keypair) -> anyhow::Result<Pubkey> {
+    let user = payer.pubkey();
+    let recent_slot = client
+        .get_slot_with_commitment(CommitmentConfig::finalized())
+        .await?;
+    let (create, lookup_table) = instruction::create_lookup_table(user, user, recent_slot);
+    let extend = instruction::extend_lookup_table(
+        lookup_table,
+        user,
+        Some(user),
+        vec![
+            get_associated_token_address(&user, &Pubkey::from_str_const(WSOL)),
+            Pubkey::from_str_const(WSOL),
+            spl_token::id(),
+            spl_associated_token_account_client::program::id(),
+            system_program::id(),
+            compute_budget::id(),
+            Pubkey::from_str_const(RAYDIUM_LIQUIDITY_POOL_V4_PROGRAM_ID),
+            Pubkey::from_str_const(RAYDIUM_AUTHORITY_V4),
+        ],
+    );
+    let blockhash = client.get_latest_blockhash().await?;
+    let transaction =
+        Transaction::new_signed_with_payer(&[create, extend], Some(&user), &[payer], blockhash);
+    let signature = client.send_and_confirm_transaction(&transaction).await?;
+    info!("Created lookup table {} in {}", lookup_table, signature);
+    Ok(lookup_table)
+}
@@ -1,14 +1,41 @@
 mod client;
 mod config;
+mod copy_rules;
 pub mod decoder;
+mod exit;
 mod gen_engine;
+mod honeypot;
+mod journal;
+mod jupiter;
 pub mod keypair;
+mod lookup_table;
+mod nonce;
+mod positions;
+mod pump_fun;
 pub mod raydium;
+mod report;
+mod router;
+mod sender;
+mod slippage;
 mod target_list;
+mod token_safety;
+mod tpu;
 mod trade_info;
 
-use crate::client::SolGrpcClient;
-use crate::config::Config;
+use crate::client::{AccountSubscriptions, SolGrpcClient, watch_from_env};
+use crate::config::{Config, env_or};
+use crate::exit::{RugExit, RugExitConfig};
+use crate::gen_engine::Engine;
+use crate::journal::Journal;
+use crate::keypair::load_keypair;
+use crate::lookup_table::create_lookup_table;
+use crate::nonce::create_nonce_account;
+use crate::raydium::event_queue::FillStream;
+use crate::report::{Report, ReportFormat};
+use anyhow::bail;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use std::env;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -21,7 +48,69 @@ async fn main() -> anyhow::Result<()> {
         private_key,
     } = Config::new()?;
 
-    let client = SolGrpcClient::new(grpc_link);
+    let mut args = env::args().skip(1);
+    match args.next().as_deref() {
+        // create-nonce [lamports] [count]
+        Some("create-nonce") => {
+            let lamports = args.next().map(|x| x.parse()).transpose()?.unwrap_or(0);
+            let count = args.next().map(|x| x.parse()).transpose()?.unwrap_or(1);
+            let client = RpcClient::new(rpc_link);
+            let payer = load_keypair(&private_key)?;
+            let mut nonce_accounts = vec![];
+            for _ in 0..count {
+                nonce_accounts.push(
+                    create_nonce_account(&client, &payer, lamports)
+                        .await?
+                        .to_string(),
+                );
+            }
+            println!("NONCE_ACCOUNTS={}", nonce_accounts.join(","));
+            return Ok(());
+        }
+        Some("create-lookup-table") => {
+            let client = RpcClient::new(rpc_link);
+            let lookup_table = create_lookup_table(&client, &load_keypair(&private_key)?).await?;
+            println!("LOOKUP_TABLE={}", lookup_table);
+            return Ok(());
+        }
+        // report [table|csv|json]
+        Some("report") => {
+            let format = args
+                .next()
+                .map(|format| format.parse())
+                .transpose()?
+                .unwrap_or(ReportFormat::Table);
+            let journal = Journal::open(&env_or("JOURNAL_PATH", "journal.db".to_string()))?;
+            print!("{}", Report::from_journal(&journal)?.render(format)?);
+            return Ok(());
+        }
+        // fills <event queue>...
+        Some("fills") => {
+            let event_queues = args
+                .map(|key| key.parse::<Pubkey>())
+                .collect::<Result<Vec<_>, _>>()?;
+            if event_queues.is_empty() {
+                bail!("Usage: fills <event queue>...");
+            }
+            let accounts = AccountSubscriptions::new();
+            accounts.start(grpc_link);
+            let mut fills = FillStream::start(&accounts, &event_queues);
+            while let Some((event_queue, event)) = fills.recv().await {
+                println!("{} {}", event_queue, event);
+            }
+            return Ok(());
+        }
+        Some(command) => bail!("Unknown command {}", command),
+        None => {}
+    }
+
+    let engine = Engine::new(rpc_link, &ws_link, &private_key).await?;
+    let rug_exit = RugExitConfig::from_env();
+    if rug_exit.enabled {
+        RugExit::start(engine.clone(), rug_exit);
+    }
+    let client = SolGrpcClient::new(grpc_link, engine);
+    let _watches = watch_from_env(&client.accounts())?;
     client.connect().await?;
     Ok(())
 }
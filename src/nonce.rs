@@ -0,0 +1,37 @@
+use log::info;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::nonce::State;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::system_instruction;
+use solana_sdk::transaction::Transaction;
+
+/// Creates a durable nonce account funded and controlled by `payer`.
+///
+/// `lamports` below the rent exempt minimum is raised to it.
+pub async fn create_nonce_account(
+    client: &RpcClient,
+    payer: &Keypair,
+    lamports: u64,
+) -> anyhow::Result<Pubkey> {
+    let nonce = Keypair::new();
+    let rent = client
+        .get_minimum_balance_for_rent_exemption(State::size())
+        .await?;
+    let instructions = system_instruction::create_nonce_account(
+        &payer.pubkey(),
+        &nonce.pubkey(),
+        &payer.pubkey(),
+        lamports.max(rent),
+    );
+    let blockhash = client.get_latest_blockhash().await?;
+    let transaction = Transaction::new_signed_with_payer(
+        &instructions,
+        Some(&payer.pubkey()),
+        &[payer, &nonce],
+        blockhash,
+    );
+    let signature = client.send_and_confirm_transaction(&transaction).await?;
+    info!("Created nonce account {} in {}", nonce.pubkey(), signature);
+    Ok(nonce.pubkey())
+}
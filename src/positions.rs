@@ -0,0 +1,134 @@
+use crate::raydium::types::QuoteSummary;
+use crate::sender::SendOutcome;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Debug)]
+pub struct Position {
+    pub mint: Pubkey,
+    pub market: Pubkey,
+    /// Signature of the target's trade we copied
+    pub target_signature: String,
+    /// Input spent by the buy that landed, in lamports
+    pub spent: u64,
+    /// Tokens the landed buy was quoted for
+    pub expected_tokens: u64,
+    /// Every send made for this position, latest last
+    pub sends: Vec<SendOutcome>,
+    /// Set while an emergency exit sells the position and once it sold, cleared if it failed
+    pub exited: bool,
+}
+
+impl Position {
+    pub fn is_open(&self) -> bool {
+        !self.exited && self.sends.iter().any(SendOutcome::is_success)
+    }
+}
+
+impl std::fmt::Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} on {} ({}): spent {} for {} tokens over {} sends, copied from {}",
+            self.mint,
+            self.market,
+            if self.exited {
+                "exited"
+            } else if self.is_open() {
+                "open"
+            } else {
+                "not filled"
+            },
+            self.spent,
+            self.expected_tokens,
+            self.sends.len(),
+            self.target_signature
+        )
+    }
+}
+
+/// Positions the bot holds, keyed by mint.
+#[derive(Clone, Default)]
+pub struct PositionBook {
+    positions: Arc<Mutex<HashMap<Pubkey, Position>>>,
+}
+
+impl PositionBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the outcome of sending a buy built from `quote` and returns the updated position.
+    pub fn record_buy(
+        &self,
+        quote: &QuoteSummary,
+        target_signature: &str,
+        outcome: SendOutcome,
+    ) -> Position {
+        let mut positions = self.positions.lock().unwrap();
+        let position = positions
+            .entry(quote.output_mint)
+            .or_insert_with(|| Position {
+                mint: quote.output_mint,
+                market: quote.market,
+                target_signature: target_signature.to_string(),
+                spent: 0,
+                expected_tokens: 0,
+                sends: vec![],
+                exited: false,
+            });
+        if outcome.is_success() {
+            position.spent += quote.amount;
+            position.expected_tokens += quote.other_amount;
+        }
+        position.sends.push(outcome);
+        position.clone()
+    }
+
+    /// Moves the position in `mint` off its bonding curve onto the pool the token migrated to.
+    /// None if we hold no position in it on `curve`, one already moved stays where it is.
+    pub fn graduate(&self, mint: &Pubkey, curve: &Pubkey, pool: Pubkey) -> Option<Position> {
+        let mut positions = self.positions.lock().unwrap();
+        let position = positions
+            .get_mut(mint)
+            .filter(|position| position.market == *curve)?;
+        position.market = pool;
+        Some(position.clone())
+    }
+
+    /// Whether we hold an open position in `mint`
+    pub fn holds(&self, mint: &Pubkey) -> bool {
+        let positions = self.positions.lock().unwrap();
+        positions.get(mint).is_some_and(Position::is_open)
+    }
+
+    /// Open positions bought on `market`
+    pub fn open_on(&self, market: &Pubkey) -> Vec<Position> {
+        let positions = self.positions.lock().unwrap();
+        positions
+            .values()
+            .filter(|position| position.market == *market && position.is_open())
+            .cloned()
+            .collect()
+    }
+
+    /// Marks the position in `mint` as exited. None if it isn't open, so a position is only
+    /// exited once.
+    pub fn exit(&self, mint: &Pubkey) -> Option<Position> {
+        let mut positions = self.positions.lock().unwrap();
+        let position = positions
+            .get_mut(mint)
+            .filter(|position| position.is_open())?;
+        position.exited = true;
+        Some(position.clone())
+    }
+
+    /// Clears the exit of the position in `mint` after its sell failed, so a later exit can
+    /// try again
+    pub fn reopen(&self, mint: &Pubkey) {
+        if let Some(position) = self.positions.lock().unwrap().get_mut(mint) {
+            position.exited = false;
+        }
+    }
+}
@@ -0,0 +1,517 @@
+use crate::config::{PUMP_AMM_PROGRAM_ID, PUMP_FEE_PROGRAM_ID, WSOL};
+use crate::lookup_table::fetch_lookup_tables;
+use crate::pump_fun::amm_types::{
+    CREATOR_VAULT_SEED, GLOBAL_CONFIG_DISCRIMINATOR, GLOBAL_CONFIG_SEED, GlobalConfig,
+    POOL_DISCRIMINATOR, POOL_SEED, PumpAmmPool, PumpAmmQuote, PumpAmmReserves,
+};
+use crate::pump_fun::types::{
+    BUY_DISCRIMINATOR, EVENT_AUTHORITY_SEED, FEE_CONFIG_DISCRIMINATOR, FeeConfig,
+    SELL_DISCRIMINATOR,
+};
+use crate::pump_fun::{
+    fee_config_address, global_volume_accumulator_address, pool_authority_address,
+    user_volume_accumulator_address,
+};
+use crate::raydium::amm::{
+    compute_budget_instructions, price_impact_bps, ui_price, wrap_sol_instructions,
+};
+use crate::raydium::cpmm_types::decode_anchor_account;
+use crate::raydium::quote_error::QuoteError;
+use crate::raydium::types::{
+    QuoteSummary, RaydiumAmmExecutorOpts, SwapConfig, SwapConfigOverrides, SwapInput,
+};
+use crate::raydium::utils::get_multiple_account_data;
+use crate::router::{Quote, SwapVenue, VenueQuote};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use log::debug;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::address_lookup_table::AddressLookupTableAccount;
+use solana_sdk::clock::Clock;
+use solana_sdk::instruction::{AccountMeta, Instruction};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::{system_program, sysvar};
+use spl_associated_token_account_client::address::get_associated_token_address_with_program_id;
+use spl_associated_token_account_client::instruction::create_associated_token_account_idempotent;
+use spl_token_2022::extension::StateWithExtensions;
+use std::sync::Arc;
+
+/// Quotes and builds buys and sells on pump AMM pools, where completed pump.fun curves migrate.
+///
+/// Only pools quoted in WSOL are traded, the SOL side is wrapped and unwrapped around the swap.
+#[derive(Clone)]
+pub struct PumpAmm {
+    client: Arc<RpcClient>,
+    config: SwapConfig,
+}
+
+impl PumpAmm {
+    pub fn new(client: Arc<RpcClient>, config: RaydiumAmmExecutorOpts) -> Self {
+        let RaydiumAmmExecutorOpts {
+            priority_fee,
+            cu_limits,
+            as_legacy_transaction,
+            lookup_table,
+            ..
+        } = config;
+        Self {
+            client,
+            config: SwapConfig {
+                priority_fee,
+                cu_limits,
+                wrap_and_unwrap_sol: Some(true),
+                as_legacy_transaction: as_legacy_transaction.or(Some(true)),
+                lookup_table,
+            },
+        }
+    }
+
+    /// Quotes a buy when the input is WSOL, a sell when the output is. Without a market the
+    /// pool the token's curve migrated to is used.
+    pub async fn quote(&self, swap_input: &SwapInput) -> Result<PumpAmmQuote, QuoteError> {
+        let wsol = Pubkey::from_str_const(WSOL);
+        let pool_not_found = || QuoteError::PoolNotFound {
+            input_mint: swap_input.input_token_mint,
+            output_mint: swap_input.output_token_mint,
+        };
+        let (mint, is_buy) = if swap_input.input_token_mint == swap_input.output_token_mint {
+            return Err(QuoteError::SameMint(swap_input.input_token_mint));
+        } else if swap_input.input_token_mint == wsol {
+            (swap_input.output_token_mint, true)
+        } else if swap_input.output_token_mint == wsol {
+            (swap_input.input_token_mint, false)
+        } else {
+            return Err(pool_not_found());
+        };
+        let amount_specified_is_input = swap_input.mode.amount_specified_is_input();
+        if !is_buy && !amount_specified_is_input {
+            return Err(anyhow!("pump AMM sells take an exact token amount").into());
+        }
+
+        let pool_id = swap_input
+            .market
+            .unwrap_or_else(|| canonical_pool_address(&mint));
+        let load_pubkeys = [
+            pool_id,
+            global_config_address(),
+            mint,
+            sysvar::clock::id(),
+            fee_config_address(&program_id()),
+        ];
+        let mut accounts = get_multiple_account_data(&self.client, &load_pubkeys).await?;
+        // fees come from the global config until the fee program configures them
+        let fee_config = accounts
+            .pop()
+            .flatten()
+            .map(|account| {
+                decode_anchor_account::<FeeConfig>(&account.data, &FEE_CONFIG_DISCRIMINATOR)
+            })
+            .transpose()
+            .map_err(|e| QuoteError::bad_layout(&load_pubkeys[4], e))?;
+        let Some(pool_account) = accounts.remove(0) else {
+            return Err(pool_not_found());
+        };
+        let [config_account, mint_account, clock_account] = <[_; 3]>::try_from(
+            load_pubkeys[1..4]
+                .iter()
+                .zip(accounts)
+                .map(|(key, account)| account.ok_or(QuoteError::AccountMissing(*key)))
+                .collect::<Result<Vec<_>, _>>()?,
+        )
+        .map_err(|_| QuoteError::AccountMissing(pool_id))?;
+        let pool: PumpAmmPool = decode_anchor_account(&pool_account.data, &POOL_DISCRIMINATOR)
+            .map_err(|e| QuoteError::bad_layout(&pool_id, e))?;
+        if pool.base_mint != mint || pool.quote_mint != wsol {
+            return Err(pool_not_found());
+        }
+        let global: GlobalConfig =
+            decode_anchor_account(&config_account.data, &GLOBAL_CONFIG_DISCRIMINATOR)
+                .map_err(|e| QuoteError::bad_layout(&load_pubkeys[1], e))?;
+        let mint_state =
+            StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_account.data)
+                .map_err(|e| QuoteError::bad_layout(&mint, e))?;
+        let (decimals, supply) = (mint_state.base.decimals, mint_state.base.supply);
+        let clock: Clock = bincode::deserialize(&clock_account.data)
+            .map_err(|e| QuoteError::bad_layout(&sysvar::clock::id(), e))?;
+        debug!("Pump AMM pool {:?}", pool);
+
+        if !global.trade_enabled(is_buy) {
+            return Err(QuoteError::SwapNotAllowed {
+                pool: pool_id,
+                status: global.disable_flags as u64,
+            });
+        }
+        let protocol_fee_recipient = global
+            .protocol_fee_recipient()
+            .ok_or(QuoteError::AccountMissing(load_pubkeys[1]))?;
+
+        let vault_keys = [pool.pool_base_token_account, pool.pool_quote_token_account];
+        let vaults = get_multiple_account_data(&self.client, &vault_keys)
+            .await?
+            .into_iter()
+            .zip(vault_keys)
+            .map(|(account, key)| {
+                let account = account.ok_or(QuoteError::AccountMissing(key))?;
+                StateWithExtensions::<spl_token_2022::state::Account>::unpack(&account.data)
+                    .map(|vault| vault.base.amount)
+                    .map_err(|e| QuoteError::bad_layout(&key, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let reserves = PumpAmmReserves {
+            base: vaults[0],
+            quote: vaults[1],
+        };
+
+        // pools of migrated curves pay the tier of their market cap, the others a flat fee
+        let fees = match &fee_config {
+            Some(fee_config) if pool.creator == pool_authority_address(&mint) => {
+                fee_config.tiered_fees(reserves.market_cap(supply))
+            }
+            Some(fee_config) => fee_config.flat_fees,
+            None => global.fees(),
+        };
+        let has_creator = pool.coin_creator != Pubkey::default();
+
+        let (token_amount, sol_amount, fee_amount, spot_price, execution_price, price_impact) =
+            if is_buy {
+                let token_amount = if amount_specified_is_input {
+                    reserves.buy_base(swap_input.amount, &fees, has_creator)?
+                } else {
+                    swap_input.amount
+                };
+                let (sol_amount, fee_amount) =
+                    reserves.buy_cost(token_amount, &fees, has_creator)?;
+                (
+                    token_amount,
+                    sol_amount,
+                    fee_amount,
+                    ui_price(reserves.quote, 9, reserves.base, decimals),
+                    ui_price(sol_amount, 9, token_amount, decimals),
+                    price_impact_bps(
+                        sol_amount - fee_amount,
+                        token_amount,
+                        reserves.quote,
+                        reserves.base,
+                    ),
+                )
+            } else {
+                let (sol_amount, fee_amount) =
+                    reserves.sell_proceeds(swap_input.amount, &fees, has_creator)?;
+                (
+                    swap_input.amount,
+                    sol_amount,
+                    fee_amount,
+                    ui_price(reserves.base, decimals, reserves.quote, 9),
+                    ui_price(swap_input.amount, decimals, sol_amount, 9),
+                    price_impact_bps(
+                        swap_input.amount,
+                        sol_amount + fee_amount,
+                        reserves.base,
+                        reserves.quote,
+                    ),
+                )
+            };
+
+        let mut quote = PumpAmmQuote {
+            pool: pool_id,
+            mint,
+            pool_base_token_account: pool.pool_base_token_account,
+            pool_quote_token_account: pool.pool_quote_token_account,
+            token_program: mint_account.owner,
+            protocol_fee_recipient,
+            coin_creator: pool.coin_creator,
+            is_buy,
+            token_amount,
+            sol_amount,
+            sol_threshold: sol_amount,
+            amount: swap_input.amount,
+            amount_specified_is_input,
+            decimals,
+            sol_reserve: reserves.quote,
+            spot_price,
+            execution_price,
+            price_impact_bps: price_impact,
+            fee_amount,
+            slot: clock.slot,
+        };
+        quote.set_slippage(swap_input.slippage_bps as u64)?;
+        Ok(quote)
+    }
+
+    pub async fn swap_instructions(
+        &self,
+        user: Pubkey,
+        quote: &PumpAmmQuote,
+        overrides: Option<&SwapConfigOverrides>,
+    ) -> anyhow::Result<Vec<Instruction>> {
+        let wrap_and_unwrap_sol = overrides
+            .and_then(|o| o.wrap_and_unwrap_sol)
+            .or(self.config.wrap_and_unwrap_sol)
+            .unwrap_or(true);
+        let mut instructions =
+            compute_budget_instructions(&self.client, &self.config, overrides, &quote.pool, &user)
+                .await?;
+        let wsol = Pubkey::from_str_const(WSOL);
+        let user_token_account = overrides
+            .and_then(|o| o.destination_token_account)
+            .filter(|_| quote.is_buy)
+            .unwrap_or_else(|| {
+                get_associated_token_address_with_program_id(
+                    &user,
+                    &quote.mint,
+                    &quote.token_program,
+                )
+            });
+
+        if wrap_and_unwrap_sol {
+            if quote.is_buy {
+                instructions.extend(wrap_sol_instructions(&user, quote.sol_threshold)?);
+            } else {
+                instructions.push(create_associated_token_account_idempotent(
+                    &user,
+                    &user,
+                    &wsol,
+                    &spl_token::id(),
+                ));
+            }
+        }
+        if quote.is_buy
+            && overrides
+                .and_then(|o| o.destination_token_account)
+                .is_none()
+        {
+            instructions.push(create_associated_token_account_idempotent(
+                &user,
+                &user,
+                &quote.mint,
+                &quote.token_program,
+            ));
+        }
+        instructions.push(trade_instruction(quote, &user, user_token_account));
+        if wrap_and_unwrap_sol {
+            let wsol_account =
+                get_associated_token_address_with_program_id(&user, &wsol, &spl_token::id());
+            instructions.push(spl_token::instruction::close_account(
+                &spl_token::id(),
+                &wsol_account,
+                &user,
+                &user,
+                &[],
+            )?);
+        }
+        Ok(instructions)
+    }
+
+    /// Our own lookup table for v0 transactions. Empty when building legacy transactions.
+    pub async fn lookup_tables(
+        &self,
+        overrides: Option<&SwapConfigOverrides>,
+    ) -> anyhow::Result<Vec<AddressLookupTableAccount>> {
+        let as_legacy_transaction = overrides
+            .and_then(|o| o.as_legacy_transaction)
+            .or(self.config.as_legacy_transaction)
+            .unwrap_or(true);
+        match self.config.lookup_table {
+            Some(lookup_table) if !as_legacy_transaction => {
+                fetch_lookup_tables(&self.client, &[lookup_table]).await
+            }
+            _ => Ok(vec![]),
+        }
+    }
+}
+
+fn program_id() -> Pubkey {
+    Pubkey::from_str_const(PUMP_AMM_PROGRAM_ID)
+}
+
+pub fn global_config_address() -> Pubkey {
+    Pubkey::find_program_address(&[GLOBAL_CONFIG_SEED], &program_id()).0
+}
+
+/// Pool a completed pump.fun curve of `mint` migrates to
+pub fn canonical_pool_address(mint: &Pubkey) -> Pubkey {
+    let wsol = Pubkey::from_str_const(WSOL);
+    Pubkey::find_program_address(
+        &[
+            POOL_SEED,
+            &0u16.to_le_bytes(),
+            pool_authority_address(mint).as_ref(),
+            mint.as_ref(),
+            wsol.as_ref(),
+        ],
+        &program_id(),
+    )
+    .0
+}
+
+pub fn coin_creator_vault_authority(coin_creator: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[CREATOR_VAULT_SEED, coin_creator.as_ref()], &program_id()).0
+}
+
+pub fn event_authority() -> Pubkey {
+    Pubkey::find_program_address(&[EVENT_AUTHORITY_SEED], &program_id()).0
+}
+
+/// `buy` with `max_quote_amount_in` or `sell` with `min_quote_amount_out` for a quote
+pub fn trade_instruction(
+    quote: &PumpAmmQuote,
+    user: &Pubkey,
+    user_token_account: Pubkey,
+) -> Instruction {
+    let wsol = Pubkey::from_str_const(WSOL);
+    let creator_vault_authority = coin_creator_vault_authority(&quote.coin_creator);
+    let mut accounts = vec![
+        AccountMeta::new(quote.pool, false),
+        AccountMeta::new(*user, true),
+        AccountMeta::new_readonly(global_config_address(), false),
+        AccountMeta::new_readonly(quote.mint, false),
+        AccountMeta::new_readonly(wsol, false),
+        AccountMeta::new(user_token_account, false),
+        AccountMeta::new(
+            get_associated_token_address_with_program_id(user, &wsol, &spl_token::id()),
+            false,
+        ),
+        AccountMeta::new(quote.pool_base_token_account, false),
+        AccountMeta::new(quote.pool_quote_token_account, false),
+        AccountMeta::new_readonly(quote.protocol_fee_recipient, false),
+        AccountMeta::new(
+            get_associated_token_address_with_program_id(
+                &quote.protocol_fee_recipient,
+                &wsol,
+                &spl_token::id(),
+            ),
+            false,
+        ),
+        AccountMeta::new_readonly(quote.token_program, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(spl_associated_token_account_client::program::id(), false),
+        AccountMeta::new_readonly(event_authority(), false),
+        AccountMeta::new_readonly(program_id(), false),
+        AccountMeta::new(
+            get_associated_token_address_with_program_id(
+                &creator_vault_authority,
+                &wsol,
+                &spl_token::id(),
+            ),
+            false,
+        ),
+        AccountMeta::new_readonly(creator_vault_authority, false),
+    ];
+    // buys count towards the trading volume rewards
+    if quote.is_buy {
+        accounts.extend([
+            AccountMeta::new_readonly(global_volume_accumulator_address(&program_id()), false),
+            AccountMeta::new(user_volume_accumulator_address(&program_id(), user), false),
+        ]);
+    }
+    accounts.extend([
+        AccountMeta::new_readonly(fee_config_address(&program_id()), false),
+        AccountMeta::new_readonly(Pubkey::from_str_const(PUMP_FEE_PROGRAM_ID), false),
+    ]);
+    let mut data = [
+        if quote.is_buy {
+            BUY_DISCRIMINATOR
+        } else {
+            SELL_DISCRIMINATOR
+        }
+        .to_vec(),
+        quote.token_amount.to_le_bytes().to_vec(),
+        quote.sol_threshold.to_le_bytes().to_vec(),
+    ]
+    .concat();
+    if quote.is_buy {
+        // `track_volume`, an optional bool
+        data.push(0);
+    }
+    Instruction {
+        program_id: program_id(),
+        accounts,
+        data,
+    }
+}
+
+impl Quote for PumpAmmQuote {
+    fn summary(&self) -> QuoteSummary {
+        PumpAmmQuote::summary(self)
+    }
+
+    fn set_slippage(&mut self, slippage_bps: u64) -> anyhow::Result<()> {
+        PumpAmmQuote::set_slippage(self, slippage_bps)
+    }
+}
+
+#[async_trait]
+impl SwapVenue for PumpAmm {
+    fn name(&self) -> &'static str {
+        "pump amm"
+    }
+
+    /// Only pools quoted in SOL are traded
+    fn supports(&self, input_mint: &Pubkey, output_mint: &Pubkey) -> bool {
+        let wsol = Pubkey::from_str_const(WSOL);
+        input_mint != output_mint && (*input_mint == wsol || *output_mint == wsol)
+    }
+
+    async fn quote(&self, swap_input: &SwapInput) -> Result<VenueQuote, QuoteError> {
+        Ok(VenueQuote::new(PumpAmm::quote(self, swap_input).await?))
+    }
+
+    async fn swap_instructions(
+        &self,
+        user: Pubkey,
+        quote: &VenueQuote,
+    ) -> anyhow::Result<Vec<Instruction>> {
+        let Some(quote) = quote.downcast_ref::<PumpAmmQuote>() else {
+            anyhow::bail!("Quote is not for a pump AMM pool");
+        };
+        PumpAmm::swap_instructions(self, user, quote, None).await
+    }
+
+    fn required_accounts(&self, quote: &VenueQuote) -> Vec<Pubkey> {
+        let Some(quote) = quote.downcast_ref::<PumpAmmQuote>() else {
+            return vec![];
+        };
+        vec![
+            quote.pool,
+            quote.pool_base_token_account,
+            quote.pool_quote_token_account,
+        ]
+    }
+
+    async fn lookup_tables(
+        &self,
+        _quote: &VenueQuote,
+    ) -> anyhow::Result<Vec<AddressLookupTableAccount>> {
+        PumpAmm::lookup_tables(self, None).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{canonical_pool_address, event_authority, global_config_address, program_id};
+    use crate::pump_fun::{fee_config_address, global_volume_accumulator_address};
+    use solana_sdk::pubkey::Pubkey;
+
+    #[test]
+    fn derives_the_mainnet_accounts() {
+        assert_eq!(
+            global_config_address(),
+            Pubkey::from_str_const("ADyA8hdefvWN2dbGGWFotbzWxrAvLW83WG6QCVXvJKqw")
+        );
+        assert_eq!(
+            event_authority(),
+            Pubkey::from_str_const("GS4CU59F31iL7aR2Q8zVS8DRrcRnXX1yjQ66TqNVQnaR")
+        );
+        assert_eq!(
+            global_volume_accumulator_address(&program_id()),
+            Pubkey::from_str_const("C2aFPdENg4A2HQsmrd5rTw5TaYBX5Ku887cWjbFKtZpw")
+        );
+        assert_eq!(
+            fee_config_address(&program_id()),
+            Pubkey::from_str_const("5PHirr8joyTMp9JMm6nW7hNDVyEYdkzDqazxPD7RaTjx")
+        );
+        let mint = Pubkey::new_unique();
+        assert_ne!(canonical_pool_address(&mint), Pubkey::default());
+    }
+}
@@ -0,0 +1,282 @@
+use crate::config::WSOL;
+use crate::pump_fun::types::{FEE_BASIS_POINTS_DENOMINATOR, Fees};
+use crate::raydium::amm::amount_with_slippage;
+use crate::raydium::quote_error::QuoteError;
+use crate::raydium::types::QuoteSummary;
+use borsh::BorshDeserialize;
+use solana_sdk::pubkey::Pubkey;
+
+/// Anchor discriminators of the pump AMM accounts we read. Its `buy` and `sell` share
+/// pump.fun's.
+pub const POOL_DISCRIMINATOR: [u8; 8] = [241, 154, 109, 4, 17, 177, 109, 188];
+pub const GLOBAL_CONFIG_DISCRIMINATOR: [u8; 8] = [149, 8, 156, 202, 160, 252, 176, 217];
+
+pub const POOL_SEED: &[u8] = b"pool";
+pub const GLOBAL_CONFIG_SEED: &[u8] = b"global_config";
+pub const CREATOR_VAULT_SEED: &[u8] = b"creator_vault";
+
+/// Bits of `GlobalConfig::disable_flags` that stop buys and sells
+const BUY_DISABLED_BIT: u8 = 1 << 3;
+const SELL_DISABLED_BIT: u8 = 1 << 4;
+
+/// On-chain `Pool` of the pump AMM, without the discriminator. Later fields are not read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshDeserialize)]
+pub struct PumpAmmPool {
+    pub pool_bump: u8,
+    pub index: u16,
+    /// The pool authority of pump.fun for pools a curve migrated to
+    pub creator: Pubkey,
+    pub base_mint: Pubkey,
+    pub quote_mint: Pubkey,
+    pub lp_mint: Pubkey,
+    pub pool_base_token_account: Pubkey,
+    pub pool_quote_token_account: Pubkey,
+    pub lp_supply: u64,
+    /// Gets the creator fee, none is charged when unset
+    pub coin_creator: Pubkey,
+}
+
+/// On-chain `GlobalConfig` of the pump AMM, without the discriminator. Later fields are not read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshDeserialize)]
+pub struct GlobalConfig {
+    pub admin: Pubkey,
+    pub lp_fee_basis_points: u64,
+    pub protocol_fee_basis_points: u64,
+    pub disable_flags: u8,
+    pub protocol_fee_recipients: [Pubkey; 8],
+    pub coin_creator_fee_basis_points: u64,
+    pub admin_set_coin_creator_authority: Pubkey,
+}
+
+impl GlobalConfig {
+    /// Fees charged before the fee program configures any
+    pub fn fees(&self) -> Fees {
+        Fees {
+            lp_fee_bps: self.lp_fee_basis_points,
+            protocol_fee_bps: self.protocol_fee_basis_points,
+            creator_fee_bps: self.coin_creator_fee_basis_points,
+        }
+    }
+
+    pub fn trade_enabled(&self, is_buy: bool) -> bool {
+        let bit = if is_buy {
+            BUY_DISABLED_BIT
+        } else {
+            SELL_DISABLED_BIT
+        };
+        self.disable_flags & bit == 0
+    }
+
+    /// Any of the configured recipients takes the protocol fee
+    pub fn protocol_fee_recipient(&self) -> Option<Pubkey> {
+        self.protocol_fee_recipients
+            .into_iter()
+            .find(|recipient| *recipient != Pubkey::default())
+    }
+}
+
+/// Token and SOL held by a pool, in raw units
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PumpAmmReserves {
+    pub base: u64,
+    pub quote: u64,
+}
+
+impl PumpAmmReserves {
+    /// Lamports, fees included, and the fees charged for `base_out`, rounded like the program
+    pub fn buy_cost(
+        &self,
+        base_out: u64,
+        fees: &Fees,
+        has_creator: bool,
+    ) -> Result<(u64, u64), QuoteError> {
+        if base_out >= self.base {
+            return Err(QuoteError::InsufficientLiquidity);
+        }
+        let cost = (self.quote as u128 * base_out as u128).div_ceil((self.base - base_out) as u128);
+        let cost = u64::try_from(cost).map_err(|_| QuoteError::MathOverflow)?;
+        let fee_amount = fees.charged_on(cost, has_creator)?;
+        Ok((
+            cost.checked_add(fee_amount)
+                .ok_or(QuoteError::MathOverflow)?,
+            fee_amount,
+        ))
+    }
+
+    /// Tokens `budget` lamports, fees included, buy
+    pub fn buy_base(&self, budget: u64, fees: &Fees, has_creator: bool) -> Result<u64, QuoteError> {
+        // a few lamports less to absorb the rounding of `buy_cost` and its three fees
+        let budget = budget as u128 * FEE_BASIS_POINTS_DENOMINATOR as u128
+            / (FEE_BASIS_POINTS_DENOMINATOR + fees.total_bps(has_creator)) as u128;
+        let budget = budget.saturating_sub(4);
+        let base = self.base as u128 * budget / (self.quote as u128 + budget).max(1);
+        u64::try_from(base).map_err(|_| QuoteError::MathOverflow)
+    }
+
+    /// Lamports, fees taken out, and the fees charged for selling `base_in`
+    pub fn sell_proceeds(
+        &self,
+        base_in: u64,
+        fees: &Fees,
+        has_creator: bool,
+    ) -> Result<(u64, u64), QuoteError> {
+        let proceeds =
+            self.quote as u128 * base_in as u128 / (self.base as u128 + base_in as u128).max(1);
+        let proceeds = u64::try_from(proceeds).map_err(|_| QuoteError::MathOverflow)?;
+        let fee_amount = fees.charged_on(proceeds, has_creator)?;
+        Ok((
+            proceeds
+                .checked_sub(fee_amount)
+                .ok_or(QuoteError::InsufficientLiquidity)?,
+            fee_amount,
+        ))
+    }
+
+    /// Value of `supply` at the pool's price, in lamports
+    pub fn market_cap(&self, supply: u64) -> u128 {
+        self.quote as u128 * supply as u128 / (self.base as u128).max(1)
+    }
+}
+
+/// Quote against a pump AMM pool trading a token for WSOL, with the accounts its `buy` / `sell`
+/// instructions need.
+///
+/// Like pump.fun, both instructions take an exact token amount and bound the sol side with
+/// `sol_threshold`.
+#[derive(Clone, Debug)]
+pub struct PumpAmmQuote {
+    pub pool: Pubkey,
+    pub mint: Pubkey,
+    pub pool_base_token_account: Pubkey,
+    pub pool_quote_token_account: Pubkey,
+    /// Owner of the mint, spl token or token 2022
+    pub token_program: Pubkey,
+    pub protocol_fee_recipient: Pubkey,
+    pub coin_creator: Pubkey,
+    pub is_buy: bool,
+    /// Tokens bought or sold
+    pub token_amount: u64,
+    /// Lamports paid for a buy or received for a sell, fees included
+    pub sol_amount: u64,
+    /// `max_quote_amount_in` for buys, `min_quote_amount_out` for sells
+    pub sol_threshold: u64,
+    /// The amount specified
+    pub amount: u64,
+    pub amount_specified_is_input: bool,
+    pub decimals: u8,
+    /// Lamports the pool holds
+    pub sol_reserve: u64,
+    /// Output per input before the trade, in ui units
+    pub spot_price: f64,
+    /// Output per input we actually get, fees included, in ui units
+    pub execution_price: f64,
+    /// How much worse our fill is than the spot price, fees excluded
+    pub price_impact_bps: u64,
+    /// Fees paid, in lamports
+    pub fee_amount: u64,
+    /// Slot the pool was read at
+    pub slot: u64,
+}
+
+impl PumpAmmQuote {
+    /// Re-applies `slippage_bps` to the sol side of the quote
+    pub fn set_slippage(&mut self, slippage_bps: u64) -> anyhow::Result<()> {
+        self.sol_threshold = amount_with_slippage(self.sol_amount, slippage_bps, self.is_buy)?;
+        Ok(())
+    }
+
+    pub fn other_amount(&self) -> u64 {
+        if self.is_buy == self.amount_specified_is_input {
+            self.token_amount
+        } else {
+            self.sol_amount
+        }
+    }
+
+    pub fn summary(&self) -> QuoteSummary {
+        let wsol = Pubkey::from_str_const(WSOL);
+        let (input_mint, output_mint, input_mint_decimals, output_mint_decimals) = if self.is_buy {
+            (wsol, self.mint, 9, self.decimals)
+        } else {
+            (self.mint, wsol, self.decimals, 9)
+        };
+        QuoteSummary {
+            market: self.pool,
+            input_mint,
+            output_mint,
+            amount: self.amount,
+            other_amount: self.other_amount(),
+            amount_specified_is_input: self.amount_specified_is_input,
+            input_mint_decimals,
+            output_mint_decimals,
+            spot_price: self.spot_price,
+            execution_price: self.execution_price,
+            price_impact_bps: self.price_impact_bps,
+            fee_amount: self.fee_amount,
+            minimum_out: if self.is_buy {
+                self.token_amount
+            } else {
+                self.sol_threshold
+            },
+            pool_open_time: 0,
+            sol_reserve: Some(self.sol_reserve),
+            slot: self.slot,
+            slots_behind: 0,
+        }
+    }
+}
+
+impl std::fmt::Display for PumpAmmQuote {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} tokens of {} for {} lamports (limit {}) on pump pool {}: spot {:.12}, execution {:.12}, impact {} bps, fee {}, slot {}",
+            if self.is_buy { "buy" } else { "sell" },
+            self.token_amount,
+            self.mint,
+            self.sol_amount,
+            self.sol_threshold,
+            self.pool,
+            self.spot_price,
+            self.execution_price,
+            self.price_impact_bps,
+            self.fee_amount,
+            self.slot
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PumpAmmReserves;
+    use crate::pump_fun::types::Fees;
+
+    #[test]
+    fn buys_stay_within_the_budget_and_sells_pay_the_fees() {
+        // a freshly migrated pool
+        let reserves = PumpAmmReserves {
+            base: 206_900_000_000_000,
+            quote: 84_990_359_679,
+        };
+        let fees = Fees {
+            lp_fee_bps: 20,
+            protocol_fee_bps: 5,
+            creator_fee_bps: 5,
+        };
+        for budget in [1_000, 1_000_000, 1_000_000_000, 50_000_000_000] {
+            let base = reserves.buy_base(budget, &fees, true).unwrap();
+            let (cost, fee) = reserves.buy_cost(base, &fees, true).unwrap();
+            assert!(cost <= budget, "{} over {}", cost, budget);
+            // a few lamports of rounding at most
+            assert!(cost + 10 + budget / 10_000 >= budget);
+            assert!(fee >= (cost - fee) * 30 / 10_000);
+        }
+        let (proceeds, fee) = reserves.sell_proceeds(1_000_000_000, &fees, false).unwrap();
+        let (without_fees, _) = reserves
+            .sell_proceeds(1_000_000_000, &Fees::default(), false)
+            .unwrap();
+        assert_eq!(proceeds + fee, without_fees);
+        assert_eq!(fee, without_fees * 25 / 10_000 + 2);
+        assert!(reserves.buy_cost(reserves.base, &fees, true).is_err());
+    }
+}
@@ -0,0 +1,587 @@
+use crate::config::{PUMP_FEE_PROGRAM_ID, PUMP_FUN_PROGRAM_ID, WSOL};
+use crate::lookup_table::fetch_lookup_tables;
+use crate::pump_fun::types::{
+    BONDING_CURVE_DISCRIMINATOR, BONDING_CURVE_SEED, BUY_DISCRIMINATOR, BondingCurve,
+    CREATOR_VAULT_SEED, EVENT_AUTHORITY_SEED, FEE_BASIS_POINTS_DENOMINATOR,
+    FEE_CONFIG_DISCRIMINATOR, FEE_CONFIG_SEED, FeeConfig, GLOBAL_DISCRIMINATOR, GLOBAL_SEED,
+    GLOBAL_VOLUME_ACCUMULATOR_SEED, Global, POOL_AUTHORITY_SEED, PumpFunMigrateInstruction,
+    PumpFunQuote, SELL_DISCRIMINATOR, USER_VOLUME_ACCUMULATOR_SEED, fee,
+};
+use crate::raydium::amm::{compute_budget_instructions, price_impact_bps, ui_price};
+use crate::raydium::cpmm_types::decode_anchor_account;
+use crate::raydium::quote_error::QuoteError;
+use crate::raydium::types::{
+    QuoteSummary, RaydiumAmmExecutorOpts, SwapConfig, SwapConfigOverrides, SwapInput,
+};
+use crate::raydium::utils::get_multiple_account_data;
+use crate::router::{Quote, SwapVenue, VenueQuote};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use log::debug;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::address_lookup_table::AddressLookupTableAccount;
+use solana_sdk::clock::Clock;
+use solana_sdk::instruction::{AccountMeta, Instruction};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::{system_program, sysvar};
+use spl_associated_token_account_client::address::get_associated_token_address_with_program_id;
+use spl_associated_token_account_client::instruction::create_associated_token_account_idempotent;
+use spl_token_2022::extension::StateWithExtensions;
+use std::sync::Arc;
+use yellowstone_grpc_proto::prelude::{Message, TransactionStatusMeta};
+
+pub mod amm;
+pub mod amm_types;
+pub mod types;
+
+/// Quotes and builds buys and sells on pump.fun bonding curves.
+///
+/// Curves trade against native SOL, no WSOL account is involved.
+#[derive(Clone)]
+pub struct PumpFun {
+    client: Arc<RpcClient>,
+    config: SwapConfig,
+}
+
+impl PumpFun {
+    pub fn new(client: Arc<RpcClient>, config: RaydiumAmmExecutorOpts) -> Self {
+        let RaydiumAmmExecutorOpts {
+            priority_fee,
+            cu_limits,
+            as_legacy_transaction,
+            lookup_table,
+            ..
+        } = config;
+        Self {
+            client,
+            config: SwapConfig {
+                priority_fee,
+                cu_limits,
+                wrap_and_unwrap_sol: Some(false),
+                as_legacy_transaction: as_legacy_transaction.or(Some(true)),
+                lookup_table,
+            },
+        }
+    }
+
+    /// Quotes a buy when the input is WSOL, a sell when the output is.
+    ///
+    /// Fails with `QuoteError::CurveComplete` once the token migrated off its curve.
+    pub async fn quote(&self, swap_input: &SwapInput) -> Result<PumpFunQuote, QuoteError> {
+        let wsol = Pubkey::from_str_const(WSOL);
+        let (mint, is_buy) = if swap_input.input_token_mint == swap_input.output_token_mint {
+            return Err(QuoteError::SameMint(swap_input.input_token_mint));
+        } else if swap_input.input_token_mint == wsol {
+            (swap_input.output_token_mint, true)
+        } else if swap_input.output_token_mint == wsol {
+            (swap_input.input_token_mint, false)
+        } else {
+            return Err(QuoteError::PoolNotFound {
+                input_mint: swap_input.input_token_mint,
+                output_mint: swap_input.output_token_mint,
+            });
+        };
+        let amount_specified_is_input = swap_input.mode.amount_specified_is_input();
+        if !is_buy && !amount_specified_is_input {
+            return Err(anyhow!("pump.fun sells take an exact token amount").into());
+        }
+
+        let bonding_curve = swap_input
+            .market
+            .unwrap_or_else(|| bonding_curve_address(&mint));
+        let load_pubkeys = [
+            global_address(),
+            bonding_curve,
+            mint,
+            sysvar::clock::id(),
+            fee_config_address(&program_id()),
+        ];
+        let mut accounts = get_multiple_account_data(&self.client, &load_pubkeys).await?;
+        // fees come from the global account until the fee program configures them
+        let fee_config = accounts
+            .pop()
+            .flatten()
+            .map(|account| {
+                decode_anchor_account::<FeeConfig>(&account.data, &FEE_CONFIG_DISCRIMINATOR)
+            })
+            .transpose()
+            .map_err(|e| QuoteError::bad_layout(&load_pubkeys[4], e))?;
+        let [global_account, curve_account, mint_account, clock_account] = <[_; 4]>::try_from(
+            load_pubkeys
+                .iter()
+                .zip(accounts)
+                .map(|(key, account)| account.ok_or(QuoteError::AccountMissing(*key)))
+                .collect::<Result<Vec<_>, _>>()?,
+        )
+        .map_err(|_| QuoteError::AccountMissing(bonding_curve))?;
+        let global: Global = decode_anchor_account(&global_account.data, &GLOBAL_DISCRIMINATOR)
+            .map_err(|e| QuoteError::bad_layout(&load_pubkeys[0], e))?;
+        let curve: BondingCurve =
+            decode_anchor_account(&curve_account.data, &BONDING_CURVE_DISCRIMINATOR)
+                .map_err(|e| QuoteError::bad_layout(&bonding_curve, e))?;
+        let decimals =
+            StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_account.data)
+                .map(|mint| mint.base.decimals)
+                .map_err(|e| QuoteError::bad_layout(&mint, e))?;
+        let clock: Clock = bincode::deserialize(&clock_account.data)
+            .map_err(|e| QuoteError::bad_layout(&sysvar::clock::id(), e))?;
+        debug!("Bonding curve {:?}", curve);
+
+        if curve.complete {
+            return Err(QuoteError::CurveComplete(mint));
+        }
+        let (protocol_fee_bps, creator_fee_bps) = match &fee_config {
+            Some(fee_config) => {
+                let fees = fee_config.tiered_fees(curve.market_cap());
+                (fees.protocol_fee_bps, fees.creator_fee_bps)
+            }
+            None => (global.fee_basis_points, global.creator_fee_basis_points),
+        };
+        let fee_basis_points = if curve.creator == Pubkey::default() {
+            protocol_fee_bps
+        } else {
+            protocol_fee_bps + creator_fee_bps
+        };
+
+        let (token_amount, sol_amount, fee_amount, spot_price, execution_price, price_impact) =
+            if is_buy {
+                let token_amount = if amount_specified_is_input {
+                    // the part of the budget left once fees are paid goes into the curve
+                    let sol_for_curve = swap_input.amount as u128
+                        * FEE_BASIS_POINTS_DENOMINATOR as u128
+                        / (FEE_BASIS_POINTS_DENOMINATOR + fee_basis_points) as u128;
+                    curve.buy_tokens(sol_for_curve as u64)?
+                } else {
+                    swap_input.amount
+                };
+                let cost = curve.buy_cost(token_amount)?;
+                let fee_amount = fee(cost, fee_basis_points)?;
+                let sol_amount = cost
+                    .checked_add(fee_amount)
+                    .ok_or(QuoteError::MathOverflow)?;
+                (
+                    token_amount,
+                    sol_amount,
+                    fee_amount,
+                    ui_price(
+                        curve.virtual_sol_reserves,
+                        9,
+                        curve.virtual_token_reserves,
+                        decimals,
+                    ),
+                    ui_price(sol_amount, 9, token_amount, decimals),
+                    price_impact_bps(
+                        cost,
+                        token_amount,
+                        curve.virtual_sol_reserves,
+                        curve.virtual_token_reserves,
+                    ),
+                )
+            } else {
+                let proceeds = curve.sell_proceeds(swap_input.amount)?;
+                let fee_amount = fee(proceeds, fee_basis_points)?;
+                let sol_amount = proceeds.saturating_sub(fee_amount);
+                (
+                    swap_input.amount,
+                    sol_amount,
+                    fee_amount,
+                    ui_price(
+                        curve.virtual_token_reserves,
+                        decimals,
+                        curve.virtual_sol_reserves,
+                        9,
+                    ),
+                    ui_price(swap_input.amount, decimals, sol_amount, 9),
+                    price_impact_bps(
+                        swap_input.amount,
+                        proceeds,
+                        curve.virtual_token_reserves,
+                        curve.virtual_sol_reserves,
+                    ),
+                )
+            };
+
+        let mut quote = PumpFunQuote {
+            mint,
+            bonding_curve,
+            associated_bonding_curve: get_associated_token_address_with_program_id(
+                &bonding_curve,
+                &mint,
+                &mint_account.owner,
+            ),
+            fee_recipient: global.fee_recipient,
+            creator: curve.creator,
+            token_program: mint_account.owner,
+            is_buy,
+            token_amount,
+            sol_amount,
+            sol_threshold: sol_amount,
+            amount: swap_input.amount,
+            amount_specified_is_input,
+            decimals,
+            real_sol_reserves: curve.real_sol_reserves,
+            spot_price,
+            execution_price,
+            price_impact_bps: price_impact,
+            fee_amount,
+            slot: clock.slot,
+        };
+        quote.set_slippage(swap_input.slippage_bps as u64)?;
+        Ok(quote)
+    }
+
+    pub async fn swap_instructions(
+        &self,
+        user: Pubkey,
+        quote: &PumpFunQuote,
+        overrides: Option<&SwapConfigOverrides>,
+    ) -> anyhow::Result<Vec<Instruction>> {
+        let mut instructions = compute_budget_instructions(
+            &self.client,
+            &self.config,
+            overrides,
+            &quote.bonding_curve,
+            &user,
+        )
+        .await?;
+        let user_token_account = overrides
+            .and_then(|o| o.destination_token_account)
+            .filter(|_| quote.is_buy)
+            .unwrap_or_else(|| {
+                get_associated_token_address_with_program_id(
+                    &user,
+                    &quote.mint,
+                    &quote.token_program,
+                )
+            });
+        if quote.is_buy
+            && overrides
+                .and_then(|o| o.destination_token_account)
+                .is_none()
+        {
+            instructions.push(create_associated_token_account_idempotent(
+                &user,
+                &user,
+                &quote.mint,
+                &quote.token_program,
+            ));
+        }
+        instructions.push(trade_instruction(quote, &user, user_token_account));
+        Ok(instructions)
+    }
+
+    /// Our own lookup table for v0 transactions. Empty when building legacy transactions.
+    pub async fn lookup_tables(
+        &self,
+        overrides: Option<&SwapConfigOverrides>,
+    ) -> anyhow::Result<Vec<AddressLookupTableAccount>> {
+        let as_legacy_transaction = overrides
+            .and_then(|o| o.as_legacy_transaction)
+            .or(self.config.as_legacy_transaction)
+            .unwrap_or(true);
+        match self.config.lookup_table {
+            Some(lookup_table) if !as_legacy_transaction => {
+                fetch_lookup_tables(&self.client, &[lookup_table]).await
+            }
+            _ => Ok(vec![]),
+        }
+    }
+}
+
+fn program_id() -> Pubkey {
+    Pubkey::from_str_const(PUMP_FUN_PROGRAM_ID)
+}
+
+pub fn global_address() -> Pubkey {
+    Pubkey::find_program_address(&[GLOBAL_SEED], &program_id()).0
+}
+
+pub fn bonding_curve_address(mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[BONDING_CURVE_SEED, mint.as_ref()], &program_id()).0
+}
+
+pub fn creator_vault_address(creator: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[CREATOR_VAULT_SEED, creator.as_ref()], &program_id()).0
+}
+
+pub fn event_authority() -> Pubkey {
+    Pubkey::find_program_address(&[EVENT_AUTHORITY_SEED], &program_id()).0
+}
+
+pub fn global_volume_accumulator_address(program_id: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[GLOBAL_VOLUME_ACCUMULATOR_SEED], program_id).0
+}
+
+pub fn user_volume_accumulator_address(program_id: &Pubkey, user: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[USER_VOLUME_ACCUMULATOR_SEED, user.as_ref()], program_id).0
+}
+
+/// Fee tiers of `program_id`, pump.fun or the pump AMM, kept by the fee program
+pub fn fee_config_address(program_id: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[FEE_CONFIG_SEED, program_id.as_ref()],
+        &Pubkey::from_str_const(PUMP_FEE_PROGRAM_ID),
+    )
+    .0
+}
+
+/// Creator of the pump AMM pool a completed curve of `mint` migrates to
+pub fn pool_authority_address(mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[POOL_AUTHORITY_SEED, mint.as_ref()], &program_id()).0
+}
+
+/// `buy` with `max_sol_cost` or `sell` with `min_sol_output` for a quote
+pub fn trade_instruction(
+    quote: &PumpFunQuote,
+    user: &Pubkey,
+    user_token_account: Pubkey,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(global_address(), false),
+        AccountMeta::new(quote.fee_recipient, false),
+        AccountMeta::new_readonly(quote.mint, false),
+        AccountMeta::new(quote.bonding_curve, false),
+        AccountMeta::new(quote.associated_bonding_curve, false),
+        AccountMeta::new(user_token_account, false),
+        AccountMeta::new(*user, true),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+    let creator_vault = AccountMeta::new(creator_vault_address(&quote.creator), false);
+    let token_program = AccountMeta::new_readonly(quote.token_program, false);
+    // buy and sell order the creator vault and token program differently
+    if quote.is_buy {
+        accounts.extend([token_program, creator_vault]);
+    } else {
+        accounts.extend([creator_vault, token_program]);
+    }
+    accounts.extend([
+        AccountMeta::new_readonly(event_authority(), false),
+        AccountMeta::new_readonly(program_id(), false),
+    ]);
+    // buys count towards the trading volume rewards
+    if quote.is_buy {
+        accounts.extend([
+            AccountMeta::new_readonly(global_volume_accumulator_address(&program_id()), false),
+            AccountMeta::new(user_volume_accumulator_address(&program_id(), user), false),
+        ]);
+    }
+    accounts.extend([
+        AccountMeta::new_readonly(fee_config_address(&program_id()), false),
+        AccountMeta::new_readonly(Pubkey::from_str_const(PUMP_FEE_PROGRAM_ID), false),
+    ]);
+    let mut data = [
+        if quote.is_buy {
+            BUY_DISCRIMINATOR
+        } else {
+            SELL_DISCRIMINATOR
+        }
+        .to_vec(),
+        quote.token_amount.to_le_bytes().to_vec(),
+        quote.sol_threshold.to_le_bytes().to_vec(),
+    ]
+    .concat();
+    if quote.is_buy {
+        // `track_volume`, an optional bool
+        data.push(0);
+    }
+    Instruction {
+        program_id: program_id(),
+        accounts,
+        data,
+    }
+}
+
+/// Curves the successful `message` migrated onto pump AMM pools, from its top level and inner
+/// `migrate` instructions
+pub fn migrations(
+    message: &Message,
+    meta: &TransactionStatusMeta,
+) -> Vec<PumpFunMigrateInstruction> {
+    if meta.err.is_some() {
+        return vec![];
+    }
+    // static keys first, then the ones loaded from lookup tables
+    let Some(keys) = message
+        .account_keys
+        .iter()
+        .chain(&meta.loaded_writable_addresses)
+        .chain(&meta.loaded_readonly_addresses)
+        .map(|key| Pubkey::try_from(key.as_slice()).ok())
+        .collect::<Option<Vec<_>>>()
+    else {
+        return vec![];
+    };
+    let program_id = program_id();
+    message
+        .instructions
+        .iter()
+        .map(|ix| (ix.program_id_index, &ix.accounts, &ix.data))
+        .chain(
+            meta.inner_instructions
+                .iter()
+                .flat_map(|inner| &inner.instructions)
+                .map(|ix| (ix.program_id_index, &ix.accounts, &ix.data)),
+        )
+        .filter(|(program_id_index, _, _)| {
+            keys.get(*program_id_index as usize) == Some(&program_id)
+        })
+        .filter_map(|(_, accounts, data)| {
+            let accounts = accounts
+                .iter()
+                .map(|index| keys.get(*index as usize).copied())
+                .collect::<Option<Vec<_>>>()?;
+            PumpFunMigrateInstruction::decode(data, &accounts)
+        })
+        .collect()
+}
+
+impl Quote for PumpFunQuote {
+    fn summary(&self) -> QuoteSummary {
+        PumpFunQuote::summary(self)
+    }
+
+    fn set_slippage(&mut self, slippage_bps: u64) -> anyhow::Result<()> {
+        PumpFunQuote::set_slippage(self, slippage_bps)
+    }
+}
+
+#[async_trait]
+impl SwapVenue for PumpFun {
+    fn name(&self) -> &'static str {
+        "pump.fun"
+    }
+
+    /// Curves only trade against SOL
+    fn supports(&self, input_mint: &Pubkey, output_mint: &Pubkey) -> bool {
+        let wsol = Pubkey::from_str_const(WSOL);
+        input_mint != output_mint && (*input_mint == wsol || *output_mint == wsol)
+    }
+
+    async fn quote(&self, swap_input: &SwapInput) -> Result<VenueQuote, QuoteError> {
+        Ok(VenueQuote::new(PumpFun::quote(self, swap_input).await?))
+    }
+
+    async fn swap_instructions(
+        &self,
+        user: Pubkey,
+        quote: &VenueQuote,
+    ) -> anyhow::Result<Vec<Instruction>> {
+        let Some(quote) = quote.downcast_ref::<PumpFunQuote>() else {
+            anyhow::bail!("Quote is not for a pump.fun bonding curve");
+        };
+        PumpFun::swap_instructions(self, user, quote, None).await
+    }
+
+    fn required_accounts(&self, quote: &VenueQuote) -> Vec<Pubkey> {
+        let Some(quote) = quote.downcast_ref::<PumpFunQuote>() else {
+            return vec![];
+        };
+        vec![
+            quote.bonding_curve,
+            quote.associated_bonding_curve,
+            quote.fee_recipient,
+            creator_vault_address(&quote.creator),
+        ]
+    }
+
+    async fn lookup_tables(
+        &self,
+        _quote: &VenueQuote,
+    ) -> anyhow::Result<Vec<AddressLookupTableAccount>> {
+        PumpFun::lookup_tables(self, None).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        event_authority, fee_config_address, global_address, global_volume_accumulator_address,
+        program_id, trade_instruction,
+    };
+    use crate::pump_fun::types::{PumpFunQuote, PumpFunTradeInstruction};
+    use solana_sdk::pubkey::Pubkey;
+
+    #[test]
+    fn derives_the_mainnet_accounts() {
+        assert_eq!(
+            global_address(),
+            Pubkey::from_str_const("4wTV1YmiEkRvAtNtsSGPtUrqRYQMe5SKy2uB4Jjaxnjf")
+        );
+        assert_eq!(
+            event_authority(),
+            Pubkey::from_str_const("Ce6TQqeHC9p8KetsN6JsjHK7UTZk7nasjjnr7XxXp9F1")
+        );
+        assert_eq!(
+            global_volume_accumulator_address(&program_id()),
+            Pubkey::from_str_const("Hq2wp8uJ9jCPsYgNHex8RtqdvMPfVGoYwjvF1ATiwn2Y")
+        );
+        assert_eq!(
+            fee_config_address(&program_id()),
+            Pubkey::from_str_const("8Wf5TiAheLUqBrKXeYg2JtAFFMWtKdG2BSFgqUcPVwTt")
+        );
+    }
+
+    #[test]
+    fn decodes_and_rebuilds_trade_instructions() {
+        let user = Pubkey::new_unique();
+        for is_buy in [true, false] {
+            let quote = PumpFunQuote {
+                mint: Pubkey::new_unique(),
+                bonding_curve: Pubkey::new_unique(),
+                associated_bonding_curve: Pubkey::new_unique(),
+                fee_recipient: Pubkey::new_unique(),
+                creator: Pubkey::new_unique(),
+                token_program: spl_token::id(),
+                is_buy,
+                token_amount: 35_000_000_000,
+                sol_amount: 1_000_000,
+                sol_threshold: 1_100_000,
+                amount: 1_000_000,
+                amount_specified_is_input: true,
+                decimals: 6,
+                real_sol_reserves: 0,
+                spot_price: 0.,
+                execution_price: 0.,
+                price_impact_bps: 0,
+                fee_amount: 0,
+                slot: 0,
+            };
+            let user_token_account = Pubkey::new_unique();
+            let instruction = trade_instruction(&quote, &user, user_token_account);
+            let accounts: Vec<_> = instruction.accounts.iter().map(|a| a.pubkey).collect();
+            let decoded = PumpFunTradeInstruction::decode(&instruction.data, &accounts).unwrap();
+            assert_eq!(
+                decoded,
+                PumpFunTradeInstruction {
+                    mint: quote.mint,
+                    bonding_curve: quote.bonding_curve,
+                    token_amount: quote.token_amount,
+                    sol_threshold: quote.sol_threshold,
+                    is_buy,
+                }
+            );
+            // buys carry the volume accumulators and `track_volume`, both end with the fee
+            // config and fee program
+            let (accounts_len, data_len) = if is_buy { (16, 25) } else { (14, 24) };
+            assert_eq!(accounts.len(), accounts_len);
+            assert_eq!(instruction.data.len(), data_len);
+            assert_eq!(accounts[5], user_token_account);
+            assert_eq!(accounts[6], user);
+            assert!(instruction.accounts[6].is_signer);
+            assert_eq!(
+                accounts[accounts_len - 2],
+                fee_config_address(&program_id())
+            );
+            if is_buy {
+                assert_eq!(accounts[8], spl_token::id());
+                assert_eq!(
+                    accounts[12],
+                    global_volume_accumulator_address(&program_id())
+                );
+                assert!(instruction.accounts[13].is_writable);
+            } else {
+                assert_eq!(accounts[9], spl_token::id());
+            }
+        }
+    }
+}
@@ -0,0 +1,411 @@
+use crate::config::WSOL;
+use crate::raydium::amm::amount_with_slippage;
+use crate::raydium::quote_error::QuoteError;
+use crate::raydium::types::QuoteSummary;
+use borsh::BorshDeserialize;
+use solana_sdk::pubkey::Pubkey;
+
+/// Anchor discriminators of the pump.fun accounts and instructions we read
+pub const GLOBAL_DISCRIMINATOR: [u8; 8] = [167, 232, 232, 177, 200, 108, 114, 127];
+pub const BONDING_CURVE_DISCRIMINATOR: [u8; 8] = [23, 183, 248, 55, 96, 216, 172, 96];
+pub const BUY_DISCRIMINATOR: [u8; 8] = [102, 6, 61, 18, 1, 218, 235, 234];
+pub const SELL_DISCRIMINATOR: [u8; 8] = [51, 230, 133, 164, 1, 127, 131, 173];
+pub const MIGRATE_DISCRIMINATOR: [u8; 8] = [155, 234, 231, 146, 236, 158, 162, 30];
+/// `FeeConfig` of the fee program, shared by pump.fun and the pump AMM
+pub const FEE_CONFIG_DISCRIMINATOR: [u8; 8] = [143, 52, 146, 187, 219, 123, 76, 155];
+
+pub const GLOBAL_SEED: &[u8] = b"global";
+pub const BONDING_CURVE_SEED: &[u8] = b"bonding-curve";
+pub const CREATOR_VAULT_SEED: &[u8] = b"creator-vault";
+pub const EVENT_AUTHORITY_SEED: &[u8] = b"__event_authority";
+pub const GLOBAL_VOLUME_ACCUMULATOR_SEED: &[u8] = b"global_volume_accumulator";
+pub const USER_VOLUME_ACCUMULATOR_SEED: &[u8] = b"user_volume_accumulator";
+pub const FEE_CONFIG_SEED: &[u8] = b"fee_config";
+pub const POOL_AUTHORITY_SEED: &[u8] = b"pool-authority";
+
+/// Fees are basis points of the sol side
+pub const FEE_BASIS_POINTS_DENOMINATOR: u64 = 10_000;
+
+/// On-chain `Global` of the pump.fun program, without the discriminator.
+/// Later fields are not read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshDeserialize)]
+pub struct Global {
+    pub initialized: bool,
+    pub authority: Pubkey,
+    pub fee_recipient: Pubkey,
+    pub initial_virtual_token_reserves: u64,
+    pub initial_virtual_sol_reserves: u64,
+    pub initial_real_token_reserves: u64,
+    pub token_total_supply: u64,
+    /// Protocol fee, charged on the sol side
+    pub fee_basis_points: u64,
+    pub withdraw_authority: Pubkey,
+    pub enable_migrate: bool,
+    pub pool_migration_fee: u64,
+    /// Creator fee, charged on the sol side when the curve has a creator
+    pub creator_fee_basis_points: u64,
+}
+
+/// On-chain `BondingCurve` of the pump.fun program, without the discriminator
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshDeserialize)]
+pub struct BondingCurve {
+    pub virtual_token_reserves: u64,
+    pub virtual_sol_reserves: u64,
+    /// Tokens left to sell on the curve
+    pub real_token_reserves: u64,
+    /// Lamports paid into the curve so far
+    pub real_sol_reserves: u64,
+    pub token_total_supply: u64,
+    /// Set once the curve sold out, the token then trades on the pool it migrated to
+    pub complete: bool,
+    pub creator: Pubkey,
+}
+
+/// Fee rates in basis points
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshDeserialize)]
+pub struct Fees {
+    pub lp_fee_bps: u64,
+    pub protocol_fee_bps: u64,
+    pub creator_fee_bps: u64,
+}
+
+impl Fees {
+    /// Fees on `amount`, each rounded up. The creator fee only applies to pools and curves with
+    /// a creator.
+    pub fn charged_on(&self, amount: u64, has_creator: bool) -> Result<u64, QuoteError> {
+        let creator_fee = if has_creator {
+            fee(amount, self.creator_fee_bps)?
+        } else {
+            0
+        };
+        fee(amount, self.lp_fee_bps)?
+            .checked_add(fee(amount, self.protocol_fee_bps)?)
+            .and_then(|fees| fees.checked_add(creator_fee))
+            .ok_or(QuoteError::MathOverflow)
+    }
+
+    pub fn total_bps(&self, has_creator: bool) -> u64 {
+        self.lp_fee_bps + self.protocol_fee_bps + if has_creator { self.creator_fee_bps } else { 0 }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshDeserialize)]
+pub struct FeeTier {
+    /// Market cap from which the tier applies
+    pub market_cap_lamports_threshold: u128,
+    pub fees: Fees,
+}
+
+/// On-chain `FeeConfig` the fee program keeps per program, without the discriminator
+#[derive(Clone, Debug, Default, PartialEq, BorshDeserialize)]
+pub struct FeeConfig {
+    pub bump: u8,
+    pub admin: Pubkey,
+    /// Fees of pools that don't take tiered fees
+    pub flat_fees: Fees,
+    /// Ascending by threshold
+    pub fee_tiers: Vec<FeeTier>,
+}
+
+impl FeeConfig {
+    /// Fees of the highest tier `market_cap_lamports` reaches, the first tier below them all
+    pub fn tiered_fees(&self, market_cap_lamports: u128) -> Fees {
+        self.fee_tiers
+            .iter()
+            .rev()
+            .find(|tier| market_cap_lamports >= tier.market_cap_lamports_threshold)
+            .or(self.fee_tiers.first())
+            .map_or(self.flat_fees, |tier| tier.fees)
+    }
+}
+
+impl BondingCurve {
+    /// Value of the whole supply at the curve's price, in lamports
+    pub fn market_cap(&self) -> u128 {
+        self.virtual_sol_reserves as u128 * self.token_total_supply as u128
+            / (self.virtual_token_reserves as u128).max(1)
+    }
+
+    /// Lamports charged for `token_amount`, fees excluded, rounded like the program
+    pub fn buy_cost(&self, token_amount: u64) -> Result<u64, QuoteError> {
+        if token_amount > self.real_token_reserves {
+            return Err(QuoteError::InsufficientLiquidity);
+        }
+        let remaining = self
+            .virtual_token_reserves
+            .checked_sub(token_amount)
+            .filter(|remaining| *remaining > 0)
+            .ok_or(QuoteError::InsufficientLiquidity)?;
+        let cost = token_amount as u128 * self.virtual_sol_reserves as u128 / remaining as u128 + 1;
+        u64::try_from(cost).map_err(|_| QuoteError::MathOverflow)
+    }
+
+    /// Tokens `sol_amount`, fees excluded, buys. Capped at what is left on the curve
+    pub fn buy_tokens(&self, sol_amount: u64) -> Result<u64, QuoteError> {
+        // one lamport less to absorb the rounding `buy_cost` adds
+        let sol_amount = sol_amount.saturating_sub(1) as u128;
+        let tokens = self.virtual_token_reserves as u128 * sol_amount
+            / (self.virtual_sol_reserves as u128 + sol_amount).max(1);
+        Ok(u64::try_from(tokens)
+            .map_err(|_| QuoteError::MathOverflow)?
+            .min(self.real_token_reserves))
+    }
+
+    /// Lamports `token_amount` sells for, fees excluded
+    pub fn sell_proceeds(&self, token_amount: u64) -> Result<u64, QuoteError> {
+        let proceeds = token_amount as u128 * self.virtual_sol_reserves as u128
+            / (self.virtual_token_reserves as u128 + token_amount as u128).max(1);
+        let proceeds = u64::try_from(proceeds).map_err(|_| QuoteError::MathOverflow)?;
+        if proceeds > self.real_sol_reserves {
+            return Err(QuoteError::InsufficientLiquidity);
+        }
+        Ok(proceeds)
+    }
+}
+
+/// Fee on `sol_amount` at `fee_basis_points`, rounded up
+pub fn fee(sol_amount: u64, fee_basis_points: u64) -> Result<u64, QuoteError> {
+    let fee = (sol_amount as u128 * fee_basis_points as u128)
+        .div_ceil(FEE_BASIS_POINTS_DENOMINATOR as u128);
+    u64::try_from(fee).map_err(|_| QuoteError::MathOverflow)
+}
+
+/// A `buy` or `sell` instruction found in a transaction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PumpFunTradeInstruction {
+    pub mint: Pubkey,
+    pub bonding_curve: Pubkey,
+    pub token_amount: u64,
+    /// `max_sol_cost` for buys, `min_sol_output` for sells
+    pub sol_threshold: u64,
+    pub is_buy: bool,
+}
+
+impl PumpFunTradeInstruction {
+    /// `accounts` are the instruction's accounts in order. None if this isn't a buy or sell
+    pub fn decode(data: &[u8], accounts: &[Pubkey]) -> Option<Self> {
+        let (discriminator, args) = data.split_first_chunk::<8>()?;
+        let is_buy = match *discriminator {
+            BUY_DISCRIMINATOR => true,
+            SELL_DISCRIMINATOR => false,
+            _ => return None,
+        };
+        let (token_amount, rest) = args.split_first_chunk::<8>()?;
+        let (sol_threshold, _) = rest.split_first_chunk::<8>()?;
+        // global, fee recipient, mint, bonding curve, its token account, user token account, user
+        if accounts.len() < 7 {
+            return None;
+        }
+        Some(Self {
+            mint: accounts[2],
+            bonding_curve: accounts[3],
+            token_amount: u64::from_le_bytes(*token_amount),
+            sol_threshold: u64::from_le_bytes(*sol_threshold),
+            is_buy,
+        })
+    }
+}
+
+/// A `migrate` instruction, moving a completed curve's liquidity to its pump AMM pool
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PumpFunMigrateInstruction {
+    pub mint: Pubkey,
+    pub bonding_curve: Pubkey,
+    pub pool: Pubkey,
+}
+
+impl PumpFunMigrateInstruction {
+    /// `accounts` are the instruction's accounts in order. None if this isn't a migrate
+    pub fn decode(data: &[u8], accounts: &[Pubkey]) -> Option<Self> {
+        if data.get(..8)? != MIGRATE_DISCRIMINATOR {
+            return None;
+        }
+        // global, withdraw authority, mint, bonding curve, its token account, user, system
+        // program, token program, pump amm, pool
+        if accounts.len() < 10 {
+            return None;
+        }
+        Some(Self {
+            mint: accounts[2],
+            bonding_curve: accounts[3],
+            pool: accounts[9],
+        })
+    }
+}
+
+/// Quote against a pump.fun bonding curve, with the accounts its `buy` / `sell` instructions need.
+///
+/// Both instructions take an exact token amount, the sol side is bounded by `sol_threshold`.
+#[derive(Clone, Debug)]
+pub struct PumpFunQuote {
+    pub mint: Pubkey,
+    pub bonding_curve: Pubkey,
+    pub associated_bonding_curve: Pubkey,
+    pub fee_recipient: Pubkey,
+    pub creator: Pubkey,
+    /// Owner of the mint, spl token or token 2022
+    pub token_program: Pubkey,
+    pub is_buy: bool,
+    /// Tokens bought or sold
+    pub token_amount: u64,
+    /// Lamports paid for a buy or received for a sell, fees included
+    pub sol_amount: u64,
+    /// `max_sol_cost` for buys, `min_sol_output` for sells
+    pub sol_threshold: u64,
+    /// The amount specified
+    pub amount: u64,
+    pub amount_specified_is_input: bool,
+    pub decimals: u8,
+    /// Lamports paid into the curve so far
+    pub real_sol_reserves: u64,
+    /// Output per input before the trade, in ui units
+    pub spot_price: f64,
+    /// Output per input we actually get, fees included, in ui units
+    pub execution_price: f64,
+    /// How much worse our fill is than the spot price, fees excluded
+    pub price_impact_bps: u64,
+    /// Fees paid, in lamports
+    pub fee_amount: u64,
+    /// Slot the curve was read at
+    pub slot: u64,
+}
+
+impl PumpFunQuote {
+    /// Re-applies `slippage_bps` to the sol side of the quote
+    pub fn set_slippage(&mut self, slippage_bps: u64) -> anyhow::Result<()> {
+        self.sol_threshold = amount_with_slippage(self.sol_amount, slippage_bps, self.is_buy)?;
+        Ok(())
+    }
+
+    pub fn other_amount(&self) -> u64 {
+        if self.is_buy == self.amount_specified_is_input {
+            self.token_amount
+        } else {
+            self.sol_amount
+        }
+    }
+
+    pub fn summary(&self) -> QuoteSummary {
+        let wsol = Pubkey::from_str_const(WSOL);
+        let (input_mint, output_mint, input_mint_decimals, output_mint_decimals) = if self.is_buy {
+            (wsol, self.mint, 9, self.decimals)
+        } else {
+            (self.mint, wsol, self.decimals, 9)
+        };
+        QuoteSummary {
+            market: self.bonding_curve,
+            input_mint,
+            output_mint,
+            amount: self.amount,
+            other_amount: self.other_amount(),
+            amount_specified_is_input: self.amount_specified_is_input,
+            input_mint_decimals,
+            output_mint_decimals,
+            spot_price: self.spot_price,
+            execution_price: self.execution_price,
+            price_impact_bps: self.price_impact_bps,
+            fee_amount: self.fee_amount,
+            minimum_out: if self.is_buy {
+                self.token_amount
+            } else {
+                self.sol_threshold
+            },
+            pool_open_time: 0,
+            sol_reserve: Some(self.real_sol_reserves),
+            slot: self.slot,
+            slots_behind: 0,
+        }
+    }
+}
+
+impl std::fmt::Display for PumpFunQuote {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} tokens of {} for {} lamports (limit {}) on curve {}: spot {:.12}, execution {:.12}, impact {} bps, fee {}, slot {}",
+            if self.is_buy { "buy" } else { "sell" },
+            self.token_amount,
+            self.mint,
+            self.sol_amount,
+            self.sol_threshold,
+            self.bonding_curve,
+            self.spot_price,
+            self.execution_price,
+            self.price_impact_bps,
+            self.fee_amount,
+            self.slot
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BondingCurve, FeeConfig, FeeTier, Fees, MIGRATE_DISCRIMINATOR, PumpFunMigrateInstruction,
+    };
+    use solana_sdk::pubkey::Pubkey;
+
+    #[test]
+    fn buy_tokens_stay_within_the_budget() {
+        // a fresh curve
+        let curve = BondingCurve {
+            virtual_token_reserves: 1_073_000_000_000_000,
+            virtual_sol_reserves: 30_000_000_000,
+            real_token_reserves: 793_100_000_000_000,
+            real_sol_reserves: 0,
+            token_total_supply: 1_000_000_000_000_000,
+            ..Default::default()
+        };
+        for budget in [1, 1_000_000, 1_000_000_000, 50_000_000_000] {
+            let tokens = curve.buy_tokens(budget).unwrap();
+            assert!(curve.buy_cost(tokens).unwrap() <= budget.max(1));
+        }
+        // the whole curve can't be bought past its real reserves
+        assert_eq!(
+            curve.buy_tokens(u64::MAX / 2).unwrap(),
+            curve.real_token_reserves
+        );
+        assert!(curve.buy_cost(curve.real_token_reserves + 1).is_err());
+    }
+
+    #[test]
+    fn fees_come_from_the_tier_the_market_cap_reached() {
+        let fees = |bps| Fees {
+            lp_fee_bps: 0,
+            protocol_fee_bps: bps,
+            creator_fee_bps: 0,
+        };
+        let tier = |threshold, bps| FeeTier {
+            market_cap_lamports_threshold: threshold,
+            fees: fees(bps),
+        };
+        let mut config = FeeConfig {
+            flat_fees: fees(100),
+            ..Default::default()
+        };
+        assert_eq!(config.tiered_fees(1_000), fees(100));
+        config.fee_tiers = vec![tier(500, 95), tier(2_000, 50), tier(10_000, 20)];
+        assert_eq!(config.tiered_fees(0), fees(95));
+        assert_eq!(config.tiered_fees(1_999), fees(95));
+        assert_eq!(config.tiered_fees(2_000), fees(50));
+        assert_eq!(config.tiered_fees(u128::MAX), fees(20));
+    }
+
+    #[test]
+    fn decodes_migrations() {
+        let accounts: Vec<_> = (0..24).map(|_| Pubkey::new_unique()).collect();
+        assert_eq!(
+            PumpFunMigrateInstruction::decode(&MIGRATE_DISCRIMINATOR, &accounts),
+            Some(PumpFunMigrateInstruction {
+                mint: accounts[2],
+                bonding_curve: accounts[3],
+                pool: accounts[9],
+            })
+        );
+        assert_eq!(
+            PumpFunMigrateInstruction::decode(&MIGRATE_DISCRIMINATOR, &accounts[..9]),
+            None
+        );
+        assert_eq!(PumpFunMigrateInstruction::decode(&[0; 8], &accounts), None);
+    }
+}
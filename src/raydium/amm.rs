@@ -1,30 +1,55 @@
-use crate::config::{RAYDIUM_AUTHORITY_V4, RAYDIUM_LIQUIDITY_POOL_V4_PROGRAM_ID};
-use crate::raydium::amm_types::{LiquidityStateV4, RaydiumAmmInfo, RaydiumStatus};
+use crate::config::{RAYDIUM_AUTHORITY_V4, RAYDIUM_LIQUIDITY_POOL_V4_PROGRAM_ID, WSOL};
+use crate::lookup_table::{compile_message, fetch_lookup_tables};
+use crate::raydium::amm_types::{LiquidityStateV4, RaydiumAmmInfo, RaydiumFees, RaydiumStatus};
 use crate::raydium::api_v3::response::{ApiV3PoolsPage, ApiV3StandardPool, ApiV3StandardPoolKeys};
 use crate::raydium::api_v3::{ApiV3Client, PoolFetchParams, PoolSort, PoolSortOrder, PoolType};
+use crate::raydium::math::{CheckedCeilDiv, SwapDirection, U128};
+use crate::raydium::orderbook::{
+    BookSummary, OrderBook, decode_slab, market_keys, market_lot_sizes,
+};
+use crate::raydium::pool_cache::{PoolState, PoolStateCache, pool_account_keys};
+use crate::raydium::quote_error::QuoteError;
 use crate::raydium::serum::load_serum_market_order;
+use crate::raydium::serum_types::Side;
 use crate::raydium::types::{
-    AmmInfo, AmmKeys, MarketKeys, RaydiumAmmExecutorOpts, SwapConfig, SwapInput,
+    AmmKeys, ComputeUnitLimits, MarketKeys, PriorityFeeConfig, QuoteSummary,
+    RaydiumAmmExecutorOpts, RaydiumAmmQuote, SwapConfig, SwapConfigOverrides, SwapInput,
 };
-use anyhow::{Context, anyhow};
-use arrayref::array_ref;
+use crate::router::{Quote, SwapVenue, VenueQuote};
+use async_trait::async_trait;
 use borsh::BorshDeserialize;
-use log::info;
+use log::{debug, info, warn};
 use safe_transmute::{transmute_one_pedantic, transmute_to_bytes};
 use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_program::pubkey::Pubkey;
+use solana_sdk::account::Account;
 use solana_sdk::account_info::{AccountInfo, IntoAccountInfo};
+use solana_sdk::address_lookup_table::AddressLookupTableAccount;
+use solana_sdk::clock::Clock;
+use solana_sdk::compute_budget::ComputeBudgetInstruction;
+use solana_sdk::hash::Hash;
+use solana_sdk::instruction::{AccountMeta, Instruction};
+use solana_sdk::signature::Signature;
+use solana_sdk::system_instruction;
+use solana_sdk::sysvar;
+use solana_sdk::transaction::VersionedTransaction;
+use spl_associated_token_account_client::address::get_associated_token_address;
+use spl_associated_token_account_client::instruction::create_associated_token_account_idempotent;
 use spl_token::solana_program;
 use spl_token::solana_program::program_pack::Pack;
-use std::str::FromStr;
 use std::sync::Arc;
 
+/// One of the Jito tip accounts, used for `PriorityFeeConfig::JitoTip`
+const JITO_TIP_ACCOUNT: &str = "96gYZGLnJYVFmbjzopPSU6QiEV5fGqZNyN9nmNhvrZU5";
+
 #[derive(Clone)]
 pub struct RaydiumAmm {
     client: Arc<RpcClient>,
     api: ApiV3Client,
     config: SwapConfig,
     load_keys_by_api: bool,
+    pools: Option<PoolStateCache>,
+    order_book: bool,
 }
 impl RaydiumAmm {
     pub fn new(client: Arc<RpcClient>, config: RaydiumAmmExecutorOpts, api: ApiV3Client) -> Self {
@@ -33,29 +58,49 @@ impl RaydiumAmm {
             cu_limits,
             wrap_and_unwrap_sol,
             load_keys_by_api,
+            as_legacy_transaction,
+            lookup_table,
         } = config;
         Self {
             client,
             api,
             load_keys_by_api: load_keys_by_api.unwrap_or(true),
+            pools: None,
+            order_book: false,
             config: SwapConfig {
                 priority_fee,
                 cu_limits,
                 wrap_and_unwrap_sol,
-                as_legacy_transaction: Some(true),
+                as_legacy_transaction: as_legacy_transaction.or(Some(true)),
+                lookup_table,
             },
         }
     }
 
-    pub async fn quote(&self, swap_input: &SwapInput) -> anyhow::Result<()> {
+    /// Quotes from `pools` when it holds fresh state for the pool, and starts tracking the
+    /// pools it had to load over rpc.
+    pub fn with_pool_cache(mut self, pools: Option<PoolStateCache>) -> Self {
+        self.pools = pools;
+        self
+    }
+
+    /// Attaches the market's book to quotes on pools sharing liquidity with it. Costs an rpc
+    /// round trip per quote, cached pools included.
+    pub fn with_order_book(mut self, order_book: bool) -> Self {
+        self.order_book = order_book;
+        self
+    }
+
+    pub async fn quote(&self, swap_input: &SwapInput) -> Result<RaydiumAmmQuote, QuoteError> {
         if swap_input.input_token_mint == swap_input.output_token_mint {
-            return Err(anyhow!(
-                "Input token cannot equal output token {}",
-                swap_input.input_token_mint
-            ));
+            return Err(QuoteError::SameMint(swap_input.input_token_mint));
         }
 
-        let mut pool_id = swap_input.market;
+        let mut pool_id = swap_input.market.or_else(|| {
+            self.pools.as_ref().and_then(|pools| {
+                pools.pool_for(&swap_input.input_token_mint, &swap_input.output_token_mint)
+            })
+        });
         if pool_id.is_none() {
             let response: ApiV3PoolsPage<ApiV3StandardPool> = self
                 .api
@@ -72,12 +117,12 @@ impl RaydiumAmm {
                 )
                 .await?;
             pool_id = response.pools.into_iter().find_map(|pool| {
-                if pool.mint_a.address == swap_input.input_token_mint
+                if (pool.mint_a.address == swap_input.input_token_mint
                     && pool.mint_b.address == swap_input.output_token_mint
                     || pool.mint_a.address == swap_input.output_token_mint
-                        && pool.mint_b.address == swap_input.input_token_mint
-                        && pool.program_id
-                            == Pubkey::from_str_const(RAYDIUM_LIQUIDITY_POOL_V4_PROGRAM_ID)
+                        && pool.mint_b.address == swap_input.input_token_mint)
+                    && pool.program_id
+                        == Pubkey::from_str_const(RAYDIUM_LIQUIDITY_POOL_V4_PROGRAM_ID)
                 {
                     Some(pool.id)
                 } else {
@@ -87,191 +132,914 @@ impl RaydiumAmm {
         }
 
         let Some(pool_id) = pool_id else {
-            return Err(anyhow!("Failed to get market for swap"));
+            return Err(QuoteError::PoolNotFound {
+                input_mint: swap_input.input_token_mint,
+                output_mint: swap_input.output_token_mint,
+            });
         };
 
+        if let Some(pools) = &self.pools
+            && let Some((pool, clock)) = pools.get(&pool_id)
+        {
+            return Ok(self
+                .with_book(quote_pool(swap_input, &pool, &clock)?, &pool)
+                .await);
+        }
+
         let response = self
             .api
             .fetch_pool_keys_by_ids::<ApiV3StandardPoolKeys>(
                 [&pool_id].into_iter().map(|id| id.to_string()).collect(),
             )
             .await?;
-        let keys = response.first().context(format!(
-            "Failed to get pool keys for raydium standard pool {}",
-            pool_id
-        ))?;
+        let keys = response
+            .first()
+            .ok_or(QuoteError::PoolKeysNotFound(pool_id))?;
 
         let (amm_keys, market_keys) = (AmmKeys::try_from(keys)?, MarketKeys::try_from(keys)?);
-        info!("{:?}, {:?}", amm_keys, market_keys);
+        debug!("{:?}, {:?}", amm_keys, market_keys);
 
+        let (pool, clock) = self
+            .load_pool(
+                pool_id,
+                amm_keys,
+                market_keys,
+                keys.lookup_table_account,
+                false,
+            )
+            .await?;
+        Ok(self
+            .with_book(quote_pool(swap_input, &pool, &clock)?, &pool)
+            .await)
+    }
+
+    /// Loads a pool's accounts over rpc, decodes them and tracks the pool in the cache,
+    /// `warmed` when no quote asked for it.
+    async fn load_pool(
+        &self,
+        pool_id: Pubkey,
+        amm_keys: AmmKeys,
+        market_keys: MarketKeys,
+        lookup_table: Option<Pubkey>,
+        warmed: bool,
+    ) -> Result<(PoolState, Clock), QuoteError> {
         // reload accounts data to calculate amm pool vault amount
         // get multiple accounts at the same time to ensure data consistency
-        let load_pubkeys = vec![
-            pool_id,
-            amm_keys.amm_target,
-            amm_keys.amm_pc_vault,
-            amm_keys.amm_coin_vault,
-            amm_keys.amm_open_order,
-            amm_keys.market,
-            market_keys.event_queue,
-        ];
+        let mut load_pubkeys = pool_account_keys(&pool_id, &amm_keys, &market_keys).to_vec();
+        load_pubkeys.push(sysvar::clock::id());
         let rsps =
             crate::raydium::utils::get_multiple_account_data(&self.client, &load_pubkeys).await?;
-        info!("{:?}", rsps);
-        let accounts = array_ref![rsps, 0, 7];
-        let [
-            amm_account,
-            amm_target_account,
-            amm_pc_vault_account,
-            amm_coin_vault_account,
-            amm_open_orders_account,
-            market_account,
-            market_event_q_account,
-        ] = accounts;
-        let data = &amm_account.as_ref().unwrap().data;
-        info!("Account data length: {}", data.len());
-
-        info!(
-            "Expected AmmInfo size: {}",
-            std::mem::size_of::<LiquidityStateV4>()
-        );
+        debug!("{:?}", rsps);
+        let mut accounts = load_pubkeys
+            .iter()
+            .zip(rsps)
+            .map(|(key, account)| account.ok_or(QuoteError::AccountMissing(*key)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let clock_account = accounts
+            .pop()
+            .ok_or(QuoteError::AccountMissing(sysvar::clock::id()))?;
+        let clock: Clock = bincode::deserialize(&clock_account.data)
+            .map_err(|e| QuoteError::bad_layout(&sysvar::clock::id(), e))?;
+        let mut accounts: [Account; 7] = accounts
+            .try_into()
+            .map_err(|_| QuoteError::AccountMissing(pool_id))?;
 
-        let amm: RaydiumAmmInfo =
-            transmute_one_pedantic::<LiquidityStateV4>(transmute_to_bytes(data))
-                .map_err(|e| e.without_src())
-                .unwrap()
-                .into();
-        info!("AMM {:?}", amm);
+        let pool = decode_pool_state(
+            pool_id,
+            amm_keys,
+            market_keys,
+            lookup_table,
+            &mut accounts,
+            clock.slot,
+        )?;
+        if let Some(pools) = &self.pools {
+            pools.track(pool, accounts, warmed);
+        }
+        Ok((pool, clock))
+    }
 
-        let _amm_target: crate::raydium::amm_types::RaydiumTargetOrders =
-            transmute_one_pedantic::<crate::raydium::amm_types::RaydiumTargetOrders>(
-                transmute_to_bytes(&amm_target_account.as_ref().unwrap().clone().data),
-            )
-            .map_err(|e| e.without_src())?;
-        let amm_pc_vault =
-            spl_token::state::Account::unpack(&amm_pc_vault_account.as_ref().unwrap().clone().data)
-                .unwrap();
-        let amm_coin_vault = spl_token::state::Account::unpack(
-            &amm_coin_vault_account.as_ref().unwrap().clone().data,
-        )
-        .unwrap();
-        let (amm_pool_pc_vault_amount, amm_pool_coin_vault_amount) =
-            crate::raydium::math::Calculator::calc_total_without_take_pnl_no_orderbook(
-                amm_pc_vault.amount,
-                amm_coin_vault.amount,
-                &amm,
-            )
-            .unwrap();
-        let (a, b) = (amm_pool_pc_vault_amount, amm_pool_coin_vault_amount);
-        info!("{}", a);
-
-        let (amm_pool_pc_vault_amount, amm_pool_coin_vault_amount) =
-            if RaydiumStatus::from_u64(amm.status).orderbook_permission() {
-                let amm_open_orders_account =
-                    &mut amm_open_orders_account.as_ref().unwrap().clone();
-                let market_account = &mut market_account.as_ref().unwrap().clone();
-                let market_event_q_account = &mut market_event_q_account.as_ref().unwrap().clone();
-                let amm_open_orders_info =
-                    (&amm.open_orders, amm_open_orders_account).into_account_info();
-                let market_account_info = (&amm.market, market_account).into_account_info();
-                let market_event_queue_info =
-                    (&(market_keys.event_queue), market_event_q_account).into_account_info();
-                let liquidity_pool_pub_key =
-                    Pubkey::from_str(RAYDIUM_LIQUIDITY_POOL_V4_PROGRAM_ID).unwrap();
-                let amm_authority = Pubkey::find_program_address(
-                    &[RAYDIUM_AUTHORITY_V4.as_ref()],
-                    &liquidity_pool_pub_key,
-                )
-                .0;
-                let lamports = &mut 0;
-                let data = &mut [0u8];
-                let owner = Pubkey::default();
-                let amm_authority_info = AccountInfo::new(
-                    &amm_authority,
-                    false,
-                    false,
-                    lamports,
-                    data,
-                    &owner,
-                    false,
-                    0,
+    /// Starts caching a pool no quote asked for yet, from keys read off its `initialize2`.
+    /// Does nothing without a pool cache.
+    pub async fn warm(&self, amm_keys: AmmKeys) -> Result<(), QuoteError> {
+        if self.pools.is_none() {
+            return Ok(());
+        }
+        let market = self.client.get_account(&amm_keys.market).await?;
+        let market_keys = market_keys(&market.data, &amm_keys.market, &amm_keys.market_program)
+            .ok_or_else(|| QuoteError::bad_layout(&amm_keys.market, "not a serum market"))?;
+        self.load_pool(amm_keys.amm_pool, amm_keys, market_keys, None, true)
+            .await?;
+        Ok(())
+    }
+
+    /// Adds the market's book to a quote on a pool sharing liquidity with it, when enabled.
+    /// The quote goes out without it when the book can't be read.
+    async fn with_book(&self, mut quote: RaydiumAmmQuote, pool: &PoolState) -> RaydiumAmmQuote {
+        if !self.order_book
+            || !RaydiumStatus::try_from_u64(pool.amm.status)
+                .is_some_and(|status| status.orderbook_permission())
+        {
+            return quote;
+        }
+        match self.order_book(&pool.amm_keys, &pool.market_keys).await {
+            Ok(book) => {
+                let (coin_decimals, pc_decimals) =
+                    (pool.amm.coin_decimals as u8, pool.amm.pc_decimals as u8);
+                let book = BookSummary::new(
+                    &book,
+                    coin_decimals,
+                    pc_decimals,
+                    ui_price(
+                        quote.coin_vault_amount,
+                        coin_decimals,
+                        quote.pc_vault_amount,
+                        pc_decimals,
+                    ),
+                    quote.input_mint == pool.amm_keys.amm_coin_mint,
+                    quote.amount_specified_is_input.then_some(quote.amount),
                 );
-                let (market_state, open_orders) = load_serum_market_order(
-                    &market_account_info,
-                    &amm_open_orders_info,
-                    &amm_authority_info,
-                    &amm,
-                    false,
-                )?;
-                let (amm_pool_pc_vault_amount, amm_pool_coin_vault_amount) =
-                    crate::raydium::math::Calculator::calc_total_without_take_pnl(
-                        amm_pc_vault.amount,
-                        amm_coin_vault.amount,
-                        &open_orders,
-                        &amm,
-                        &market_state,
-                        &market_event_queue_info,
-                        &amm_open_orders_info,
-                    )?;
-                (amm_pool_pc_vault_amount, amm_pool_coin_vault_amount)
+                info!("Book of {}: {}", pool.amm_keys.market, book);
+                quote.book = Some(book);
+            }
+            Err(e) => warn!("No book for market {}: {}", pool.amm_keys.market, e),
+        }
+        quote
+    }
+
+    /// Bids and asks of the serum / OpenBook market behind a pool
+    pub async fn order_book(
+        &self,
+        amm_keys: &AmmKeys,
+        market_keys: &MarketKeys,
+    ) -> Result<OrderBook, QuoteError> {
+        let load_pubkeys = [amm_keys.market, market_keys.bids, market_keys.asks];
+        let accounts =
+            crate::raydium::utils::get_multiple_account_data(&self.client, &load_pubkeys).await?;
+        let [market_account, bids_account, asks_account] = <[_; 3]>::try_from(
+            load_pubkeys
+                .iter()
+                .zip(accounts)
+                .map(|(key, account)| account.ok_or(QuoteError::AccountMissing(*key)))
+                .collect::<Result<Vec<_>, _>>()?,
+        )
+        .map_err(|_| QuoteError::AccountMissing(amm_keys.market))?;
+        let (coin_lot_size, pc_lot_size) = market_lot_sizes(&market_account.data)
+            .ok_or_else(|| QuoteError::bad_layout(&amm_keys.market, "market account too short"))?;
+        Ok(OrderBook {
+            bids: decode_slab(&bids_account.data, Side::Bid)
+                .map_err(|e| QuoteError::bad_layout(&market_keys.bids, e))?,
+            asks: decode_slab(&asks_account.data, Side::Ask)
+                .map_err(|e| QuoteError::bad_layout(&market_keys.asks, e))?,
+            coin_lot_size,
+            pc_lot_size,
+        })
+    }
+
+    /// Builds the compute budget, account setup and swap instructions for a quote.
+    pub async fn swap_instructions(
+        &self,
+        user: Pubkey,
+        quote: &RaydiumAmmQuote,
+        overrides: Option<&SwapConfigOverrides>,
+    ) -> anyhow::Result<Vec<Instruction>> {
+        let wrap_and_unwrap_sol = overrides
+            .and_then(|o| o.wrap_and_unwrap_sol)
+            .or(self.config.wrap_and_unwrap_sol)
+            .unwrap_or(true);
+
+        let mut instructions = compute_budget_instructions(
+            &self.client,
+            &self.config,
+            overrides,
+            &quote.market,
+            &user,
+        )
+        .await?;
+        let wsol = Pubkey::from_str_const(WSOL);
+        let source = get_associated_token_address(&user, &quote.input_mint);
+        let destination = overrides
+            .and_then(|o| o.destination_token_account)
+            .unwrap_or_else(|| get_associated_token_address(&user, &quote.output_mint));
+
+        if wrap_and_unwrap_sol && quote.input_mint == wsol {
+            let max_in = if quote.amount_specified_is_input {
+                quote.amount
             } else {
-                let (amm_pool_pc_vault_amount, amm_pool_coin_vault_amount) =
-                    crate::raydium::math::Calculator::calc_total_without_take_pnl_no_orderbook(
-                        amm_pc_vault.amount,
-                        amm_coin_vault.amount,
-                        &amm,
-                    )?;
-                (amm_pool_pc_vault_amount, amm_pool_coin_vault_amount)
+                quote.other_amount_threshold
             };
-
-        let (direction, coin_to_pc) = if swap_input.input_token_mint == amm_keys.amm_coin_mint
-            && swap_input.output_token_mint == amm_keys.amm_pc_mint
+            instructions.extend(wrap_sol_instructions(&user, max_in)?);
+        }
+        if overrides
+            .and_then(|o| o.destination_token_account)
+            .is_none()
         {
-            (crate::raydium::utils::SwapDirection::Coin2PC, true)
-        } else {
-            (crate::raydium::utils::SwapDirection::PC2Coin, false)
+            instructions.push(create_associated_token_account_idempotent(
+                &user,
+                &user,
+                &quote.output_mint,
+                &spl_token::id(),
+            ));
+        }
+        instructions.push(swap_instruction(quote, &user, source, destination));
+        if wrap_and_unwrap_sol && (quote.input_mint == wsol || quote.output_mint == wsol) {
+            let wsol_account = get_associated_token_address(&user, &wsol);
+            instructions.push(spl_token::instruction::close_account(
+                &spl_token::id(),
+                &wsol_account,
+                &user,
+                &user,
+                &[],
+            )?);
+        }
+        Ok(instructions)
+    }
+
+    /// Lookup tables for a v0 swap transaction: the pool's and our own.
+    /// Empty when building legacy transactions.
+    pub async fn lookup_tables(
+        &self,
+        quote: &RaydiumAmmQuote,
+        overrides: Option<&SwapConfigOverrides>,
+    ) -> anyhow::Result<Vec<AddressLookupTableAccount>> {
+        let as_legacy_transaction = overrides
+            .and_then(|o| o.as_legacy_transaction)
+            .or(self.config.as_legacy_transaction)
+            .unwrap_or(true);
+        if as_legacy_transaction {
+            return Ok(vec![]);
+        }
+        let addresses = [quote.lookup_table, self.config.lookup_table]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        if addresses.is_empty() {
+            return Ok(vec![]);
+        }
+        fetch_lookup_tables(&self.client, &addresses).await
+    }
+
+    /// Unsigned swap transaction, the caller sets the blockhash and signs it.
+    pub async fn swap_transaction(
+        &self,
+        user: Pubkey,
+        quote: RaydiumAmmQuote,
+        overrides: Option<&SwapConfigOverrides>,
+    ) -> anyhow::Result<VersionedTransaction> {
+        let instructions = self.swap_instructions(user, &quote, overrides).await?;
+        let lookup_tables = self.lookup_tables(&quote, overrides).await?;
+        let message = compile_message(&user, &instructions, &lookup_tables, Hash::default())?;
+        Ok(VersionedTransaction {
+            signatures: vec![
+                Signature::default();
+                message.header().num_required_signatures as usize
+            ],
+            message,
+        })
+    }
+}
+
+/// Creates the user's WSOL account if needed and wraps `amount` lamports into it.
+pub fn wrap_sol_instructions(user: &Pubkey, amount: u64) -> anyhow::Result<Vec<Instruction>> {
+    let wsol = Pubkey::from_str_const(WSOL);
+    let wsol_account = get_associated_token_address(user, &wsol);
+    Ok(vec![
+        create_associated_token_account_idempotent(user, user, &wsol, &spl_token::id()),
+        system_instruction::transfer(user, &wsol_account, amount),
+        spl_token::instruction::sync_native(&spl_token::id(), &wsol_account)?,
+    ])
+}
+
+/// Raydium v4 `SwapBaseIn` (9) / `SwapBaseOut` (11) instruction for a quote.
+pub fn swap_instruction(
+    quote: &RaydiumAmmQuote,
+    user: &Pubkey,
+    source: Pubkey,
+    destination: Pubkey,
+) -> Instruction {
+    let AmmKeys {
+        amm_pool,
+        amm_authority,
+        amm_open_order,
+        amm_coin_vault,
+        amm_pc_vault,
+        market_program,
+        market,
+        ..
+    } = quote.amm_keys;
+    let MarketKeys {
+        event_queue,
+        bids,
+        asks,
+        coin_vault,
+        pc_vault,
+        vault_signer_key,
+    } = quote.market_keys;
+    let accounts = vec![
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new(amm_pool, false),
+        AccountMeta::new_readonly(amm_authority, false),
+        AccountMeta::new(amm_open_order, false),
+        AccountMeta::new(amm_coin_vault, false),
+        AccountMeta::new(amm_pc_vault, false),
+        AccountMeta::new_readonly(market_program, false),
+        AccountMeta::new(market, false),
+        AccountMeta::new(bids, false),
+        AccountMeta::new(asks, false),
+        AccountMeta::new(event_queue, false),
+        AccountMeta::new(coin_vault, false),
+        AccountMeta::new(pc_vault, false),
+        AccountMeta::new_readonly(vault_signer_key, false),
+        AccountMeta::new(source, false),
+        AccountMeta::new(destination, false),
+        AccountMeta::new_readonly(*user, true),
+    ];
+    let data = if quote.amount_specified_is_input {
+        [
+            vec![9_u8],
+            quote.amount.to_le_bytes().to_vec(),
+            quote.other_amount_threshold.to_le_bytes().to_vec(),
+        ]
+    } else {
+        [
+            vec![11_u8],
+            quote.other_amount_threshold.to_le_bytes().to_vec(),
+            quote.amount.to_le_bytes().to_vec(),
+        ]
+    }
+    .concat();
+    Instruction {
+        program_id: Pubkey::from_str_const(RAYDIUM_LIQUIDITY_POOL_V4_PROGRAM_ID),
+        accounts,
+        data,
+    }
+}
+
+/// Compute budget and priority fee instructions that go in front of a swap on `market`.
+pub(crate) async fn compute_budget_instructions(
+    client: &RpcClient,
+    config: &SwapConfig,
+    overrides: Option<&SwapConfigOverrides>,
+    market: &Pubkey,
+    user: &Pubkey,
+) -> anyhow::Result<Vec<Instruction>> {
+    let priority_fee = overrides
+        .and_then(|o| o.priority_fee)
+        .or(config.priority_fee);
+    let cu_limits = overrides
+        .and_then(|o| o.cu_limits)
+        .or(config.cu_limits)
+        .unwrap_or_default();
+
+    let mut instructions = vec![];
+    if let ComputeUnitLimits::Fixed(limit) = cu_limits {
+        instructions.push(ComputeBudgetInstruction::set_compute_unit_limit(
+            limit as u32,
+        ));
+    }
+    match priority_fee {
+        Some(PriorityFeeConfig::FixedCuPrice(price)) => {
+            instructions.push(ComputeBudgetInstruction::set_compute_unit_price(price));
+        }
+        Some(PriorityFeeConfig::DynamicMultiplier(multiplier)) => {
+            let fees = client.get_recent_prioritization_fees(&[*market]).await?;
+            let mut fees = fees
+                .into_iter()
+                .map(|fee| fee.prioritization_fee)
+                .collect::<Vec<_>>();
+            fees.sort_unstable();
+            let median = fees.get(fees.len() / 2).copied().unwrap_or_default();
+            instructions.push(ComputeBudgetInstruction::set_compute_unit_price(
+                median.max(1).saturating_mul(multiplier),
+            ));
+        }
+        Some(PriorityFeeConfig::JitoTip(tip)) => {
+            instructions.push(system_instruction::transfer(
+                user,
+                &Pubkey::from_str_const(JITO_TIP_ACCOUNT),
+                tip,
+            ));
+        }
+        None => {}
+    }
+    Ok(instructions)
+}
+
+/// Decodes a pool's accounts, in `pool_account_keys` order, into what a quote needs.
+pub(crate) fn decode_pool_state(
+    pool_id: Pubkey,
+    amm_keys: AmmKeys,
+    market_keys: MarketKeys,
+    lookup_table: Option<Pubkey>,
+    accounts: &mut [Account; 7],
+    slot: u64,
+) -> Result<PoolState, QuoteError> {
+    let [
+        amm_account,
+        amm_target_account,
+        amm_pc_vault_account,
+        amm_coin_vault_account,
+        amm_open_orders_account,
+        market_account,
+        market_event_q_account,
+    ] = accounts;
+    // the account is packed without alignment padding, borsh reads it field by field
+    let amm: RaydiumAmmInfo = LiquidityStateV4::try_from_slice(&amm_account.data)
+        .map_err(|e| QuoteError::bad_layout(&pool_id, e))?
+        .into();
+    let status = RaydiumStatus::try_from_u64(amm.status).ok_or_else(|| {
+        QuoteError::bad_layout(&pool_id, format!("unknown status {}", amm.status))
+    })?;
+
+    let _amm_target: crate::raydium::amm_types::RaydiumTargetOrders =
+        transmute_one_pedantic::<crate::raydium::amm_types::RaydiumTargetOrders>(
+            transmute_to_bytes(&amm_target_account.data),
+        )
+        .map_err(|e| QuoteError::bad_layout(&amm_keys.amm_target, e.without_src()))?;
+    let amm_pc_vault = spl_token::state::Account::unpack(&amm_pc_vault_account.data)
+        .map_err(|e| QuoteError::bad_layout(&amm_keys.amm_pc_vault, e))?;
+    let amm_coin_vault = spl_token::state::Account::unpack(&amm_coin_vault_account.data)
+        .map_err(|e| QuoteError::bad_layout(&amm_keys.amm_coin_vault, e))?;
+
+    let (pc_vault_amount, coin_vault_amount) = if status.orderbook_permission() {
+        let amm_open_orders_info = (&amm.open_orders, amm_open_orders_account).into_account_info();
+        let market_account_info = (&amm.market, market_account).into_account_info();
+        let market_event_queue_info =
+            (&(market_keys.event_queue), market_event_q_account).into_account_info();
+        let amm_authority = Pubkey::from_str_const(RAYDIUM_AUTHORITY_V4);
+        let lamports = &mut 0;
+        let data = &mut [0u8];
+        let owner = Pubkey::default();
+        let amm_authority_info = AccountInfo::new(
+            &amm_authority,
+            false,
+            false,
+            lamports,
+            data,
+            &owner,
+            false,
+            0,
+        );
+        let (market_state, open_orders) = load_serum_market_order(
+            &market_account_info,
+            &amm_open_orders_info,
+            &amm_authority_info,
+            &amm,
+            false,
+        )?;
+        crate::raydium::math::Calculator::calc_total_without_take_pnl(
+            amm_pc_vault.amount,
+            amm_coin_vault.amount,
+            &open_orders,
+            &amm,
+            &market_state,
+            &market_event_queue_info,
+            &amm_open_orders_info,
+        )?
+    } else {
+        crate::raydium::math::Calculator::calc_total_without_take_pnl_no_orderbook(
+            amm_pc_vault.amount,
+            amm_coin_vault.amount,
+            &amm,
+        )?
+    };
+
+    Ok(PoolState {
+        pool_id,
+        amm_keys,
+        market_keys,
+        lookup_table,
+        amm,
+        pc_vault_amount,
+        coin_vault_amount,
+        slot,
+    })
+}
+
+/// Which way `swap_input` trades on a pool of `coin_mint` and `pc_mint`, PoolNotFound unless
+/// the pool holds exactly its pair
+fn swap_direction(
+    swap_input: &SwapInput,
+    coin_mint: &Pubkey,
+    pc_mint: &Pubkey,
+) -> Result<SwapDirection, QuoteError> {
+    match (&swap_input.input_token_mint, &swap_input.output_token_mint) {
+        (input, output) if input == coin_mint && output == pc_mint => Ok(SwapDirection::Coin2PC),
+        (input, output) if input == pc_mint && output == coin_mint => Ok(SwapDirection::PC2Coin),
+        _ => Err(QuoteError::PoolNotFound {
+            input_mint: swap_input.input_token_mint,
+            output_mint: swap_input.output_token_mint,
+        }),
+    }
+}
+
+/// Quotes `swap_input` against decoded pool state, `clock` being the cluster's current time.
+pub(crate) fn quote_pool(
+    swap_input: &SwapInput,
+    pool: &PoolState,
+    clock: &Clock,
+) -> Result<RaydiumAmmQuote, QuoteError> {
+    let PoolState {
+        pool_id,
+        amm_keys,
+        market_keys,
+        lookup_table,
+        amm,
+        pc_vault_amount: amm_pool_pc_vault_amount,
+        coin_vault_amount: amm_pool_coin_vault_amount,
+        slot,
+    } = *pool;
+    debug!("AMM {:?}", amm);
+    // a pinned market may not hold the pair at all
+    let direction = swap_direction(swap_input, &amm_keys.amm_coin_mint, &amm_keys.amm_pc_mint)?;
+    let coin_to_pc = direction == SwapDirection::Coin2PC;
+    debug!("Direction {:?}", direction);
+    let status = RaydiumStatus::try_from_u64(amm.status).ok_or_else(|| {
+        QuoteError::bad_layout(&pool_id, format!("unknown status {}", amm.status))
+    })?;
+    check_swappable(&pool_id, &status, amm.state_data.pool_open_time, clock)?;
+
+    let amount_specified_is_input = swap_input.mode.amount_specified_is_input();
+    let (other_amount, other_amount_threshold) = swap_with_slippage(
+        amm_pool_pc_vault_amount,
+        amm_pool_coin_vault_amount,
+        amm.fees.swap_fee_numerator,
+        amm.fees.swap_fee_denominator,
+        direction,
+        swap_input.amount,
+        amount_specified_is_input,
+        swap_input.slippage_bps as u64,
+    )?;
+    log::debug!(
+        "raw quote: {}. raw other_amount_threshold: {}",
+        other_amount,
+        other_amount_threshold
+    );
+
+    let (reserve_in, reserve_out) = if coin_to_pc {
+        (amm_pool_coin_vault_amount, amm_pool_pc_vault_amount)
+    } else {
+        (amm_pool_pc_vault_amount, amm_pool_coin_vault_amount)
+    };
+    let (input_mint_decimals, output_mint_decimals) = if coin_to_pc {
+        (amm.coin_decimals as u8, amm.pc_decimals as u8)
+    } else {
+        (amm.pc_decimals as u8, amm.coin_decimals as u8)
+    };
+    let (amount_in, amount_out, minimum_out) = if amount_specified_is_input {
+        (swap_input.amount, other_amount, other_amount_threshold)
+    } else {
+        (other_amount, swap_input.amount, swap_input.amount)
+    };
+    let fee_amount = swap_fee_amount(amount_in, &amm.fees)?;
+
+    Ok(RaydiumAmmQuote {
+        market: pool_id,
+        input_mint: swap_input.input_token_mint,
+        output_mint: swap_input.output_token_mint,
+        amount: swap_input.amount,
+        other_amount,
+        other_amount_threshold,
+        amount_specified_is_input,
+        input_mint_decimals,
+        output_mint_decimals,
+        fees: amm.fees,
+        pc_vault_amount: amm_pool_pc_vault_amount,
+        coin_vault_amount: amm_pool_coin_vault_amount,
+        pool_open_time: amm.state_data.pool_open_time,
+        lookup_table,
+        spot_price: ui_price(
+            reserve_in,
+            input_mint_decimals,
+            reserve_out,
+            output_mint_decimals,
+        ),
+        execution_price: ui_price(
+            amount_in,
+            input_mint_decimals,
+            amount_out,
+            output_mint_decimals,
+        ),
+        price_impact_bps: price_impact_bps(
+            amount_in.saturating_sub(fee_amount),
+            amount_out,
+            reserve_in,
+            reserve_out,
+        ),
+        fee_amount,
+        minimum_out,
+        slot,
+        slots_behind: clock.slot.saturating_sub(slot),
+        amm_keys,
+        market_keys,
+        book: None,
+    })
+}
+
+/// Fails with the reason the program would reject a swap on this pool at `clock`.
+pub fn check_swappable(
+    pool_id: &Pubkey,
+    status: &RaydiumStatus,
+    open_time: u64,
+    clock: &Clock,
+) -> Result<(), QuoteError> {
+    match status {
+        RaydiumStatus::Disabled => return Err(QuoteError::PoolDisabled(*pool_id)),
+        RaydiumStatus::WithdrawOnly => return Err(QuoteError::WithdrawOnly(*pool_id)),
+        status if !status.swap_permission() => {
+            return Err(QuoteError::SwapNotAllowed {
+                pool: *pool_id,
+                status: status.into_u64(),
+            });
+        }
+        _ => {}
+    }
+    // a pool waiting for its open time flips to swap only on the first swap after it
+    if clock.unix_timestamp < 0 || (clock.unix_timestamp as u64) < open_time {
+        return Err(QuoteError::NotOpenYet {
+            pool: *pool_id,
+            open_time,
+            now: clock.unix_timestamp,
+        });
+    }
+    Ok(())
+}
+
+/// Exact amount on the other side of the swap, and the same amount with slippage applied.
+#[allow(clippy::too_many_arguments)]
+pub fn swap_with_slippage(
+    pc_vault_amount: u64,
+    coin_vault_amount: u64,
+    swap_fee_numerator: u64,
+    swap_fee_denominator: u64,
+    swap_direction: SwapDirection,
+    amount_specified: u64,
+    swap_base_in: bool,
+    slippage_bps: u64,
+) -> Result<(u64, u64), QuoteError> {
+    let other_amount = swap_exact_amount(
+        pc_vault_amount,
+        coin_vault_amount,
+        swap_fee_numerator,
+        swap_fee_denominator,
+        swap_direction,
+        amount_specified,
+        swap_base_in,
+    )?;
+    let other_amount_threshold = if swap_base_in {
+        amount_with_slippage(other_amount, slippage_bps, false)?
+    } else {
+        amount_with_slippage(other_amount, slippage_bps, true)?
+    };
+    Ok((other_amount, other_amount_threshold))
+}
+
+pub fn swap_exact_amount(
+    pc_vault_amount: u64,
+    coin_vault_amount: u64,
+    swap_fee_numerator: u64,
+    swap_fee_denominator: u64,
+    swap_direction: SwapDirection,
+    amount_specified: u64,
+    swap_base_in: bool,
+) -> Result<u64, QuoteError> {
+    let other_amount = if swap_base_in {
+        let swap_fee = U128::from(amount_specified)
+            .checked_mul(swap_fee_numerator.into())
+            .ok_or(QuoteError::MathOverflow)?
+            .checked_ceil_div(swap_fee_denominator.into())
+            .ok_or(QuoteError::MathOverflow)?
+            .0;
+        let swap_in_after_deduct_fee = U128::from(amount_specified)
+            .checked_sub(swap_fee)
+            .ok_or(QuoteError::MathOverflow)?;
+        crate::raydium::math::Calculator::swap_token_amount_base_in(
+            swap_in_after_deduct_fee,
+            pc_vault_amount.into(),
+            coin_vault_amount.into(),
+            swap_direction,
+        )?
+    } else {
+        let reserve_out = match swap_direction {
+            SwapDirection::Coin2PC => pc_vault_amount,
+            SwapDirection::PC2Coin => coin_vault_amount,
         };
+        if amount_specified >= reserve_out {
+            return Err(QuoteError::InsufficientLiquidity);
+        }
+        let swap_in_before_add_fee = crate::raydium::math::Calculator::swap_token_amount_base_out(
+            amount_specified.into(),
+            pc_vault_amount.into(),
+            coin_vault_amount.into(),
+            swap_direction,
+        )?;
+        swap_in_before_add_fee
+            .checked_mul(swap_fee_denominator.into())
+            .ok_or(QuoteError::MathOverflow)?
+            .checked_ceil_div(
+                swap_fee_denominator
+                    .checked_sub(swap_fee_numerator)
+                    .ok_or(QuoteError::MathOverflow)?
+                    .into(),
+            )
+            .ok_or(QuoteError::MathOverflow)?
+            .0
+    };
+    u64::try_from(other_amount).map_err(|_| QuoteError::MathOverflow)
+}
 
-        info!("Direction {:?}", direction);
-
-        // let amount_specified_is_input = swap_input.mode.amount_specified_is_input();
-        // let (other_amount, other_amount_threshold) = raydium_library::amm::swap_with_slippage(
-        //     amm_pool_pc_vault_amount,
-        //     amm_pool_coin_vault_amount,
-        //     amm.fees.swap_fee_numerator,
-        //     amm.fees.swap_fee_denominator,
-        //     direction,
-        //     swap_input.amount,
-        //     amount_specified_is_input,
-        //     swap_input.slippage_bps as u64,
-        // )?;
-        // log::debug!(
-        //     "raw quote: {}. raw other_amount_threshold: {}",
-        //     other_amount,
-        //     other_amount_threshold
-        // );
-        //
-        // Ok(RaydiumAmmQuote {
-        //     market: pool_id,
-        //     input_mint: swap_input.input_token_mint,
-        //     output_mint: swap_input.output_token_mint,
-        //     amount: swap_input.amount,
-        //     other_amount,
-        //     other_amount_threshold,
-        //     amount_specified_is_input,
-        //     input_mint_decimals: if coin_to_pc {
-        //         amm.coin_decimals
-        //     } else {
-        //         amm.pc_decimals
-        //     } as u8,
-        //     output_mint_decimals: if coin_to_pc {
-        //         amm.pc_decimals
-        //     } else {
-        //         amm.coin_decimals
-        //     } as u8,
-        //     amm_keys,
-        //     market_keys,
-        // })
-        Ok(())
+/// Swap fee taken from `amount_in`, rounded up like the program does
+pub fn swap_fee_amount(amount_in: u64, fees: &RaydiumFees) -> Result<u64, QuoteError> {
+    let fee = U128::from(amount_in)
+        .checked_mul(fees.swap_fee_numerator.into())
+        .ok_or(QuoteError::MathOverflow)?
+        .checked_ceil_div(fees.swap_fee_denominator.into())
+        .ok_or(QuoteError::MathOverflow)?
+        .0;
+    u64::try_from(fee).map_err(|_| QuoteError::MathOverflow)
+}
+
+/// Price of the input in output tokens, adjusted for decimals
+pub fn ui_price(amount_in: u64, in_decimals: u8, amount_out: u64, out_decimals: u8) -> f64 {
+    if amount_in == 0 {
+        return 0.0;
+    }
+    let amount_in = amount_in as f64 / 10f64.powi(in_decimals as i32);
+    let amount_out = amount_out as f64 / 10f64.powi(out_decimals as i32);
+    amount_out / amount_in
+}
+
+/// How far `amount_out` falls short of what `amount_in_after_fee` buys at the spot price
+pub fn price_impact_bps(
+    amount_in_after_fee: u64,
+    amount_out: u64,
+    reserve_in: u64,
+    reserve_out: u64,
+) -> u64 {
+    // output at spot price, as if the trade had no effect on the reserves
+    let spot_out = amount_in_after_fee as u128 * reserve_out as u128 / (reserve_in as u128).max(1);
+    if spot_out == 0 {
+        return 0;
+    }
+    (spot_out.saturating_sub(amount_out as u128) * 10_000 / spot_out) as u64
+}
+
+pub fn amount_with_slippage(
+    amount: u64,
+    slippage_bps: u64,
+    up_towards: bool,
+) -> Result<u64, QuoteError> {
+    let amount = amount as u128;
+    let slippage_bps = slippage_bps as u128;
+    let factor = if up_towards {
+        slippage_bps.checked_add(10_000)
+    } else {
+        10_000u128.checked_sub(slippage_bps)
+    }
+    .ok_or(QuoteError::MathOverflow)?;
+    let amount_with_slippage = amount.checked_mul(factor).ok_or(QuoteError::MathOverflow)? / 10_000;
+    u64::try_from(amount_with_slippage).map_err(|_| QuoteError::MathOverflow)
+}
+
+impl Quote for RaydiumAmmQuote {
+    fn summary(&self) -> QuoteSummary {
+        RaydiumAmmQuote::summary(self)
+    }
+
+    fn set_slippage(&mut self, slippage_bps: u64) -> anyhow::Result<()> {
+        RaydiumAmmQuote::set_slippage(self, slippage_bps)
+    }
+}
+
+#[async_trait]
+impl SwapVenue for RaydiumAmm {
+    fn name(&self) -> &'static str {
+        "raydium amm v4"
+    }
+
+    fn supports(&self, input_mint: &Pubkey, output_mint: &Pubkey) -> bool {
+        input_mint != output_mint
+    }
+
+    async fn quote(&self, swap_input: &SwapInput) -> Result<VenueQuote, QuoteError> {
+        Ok(VenueQuote::new(RaydiumAmm::quote(self, swap_input).await?))
+    }
+
+    async fn swap_instructions(
+        &self,
+        user: Pubkey,
+        quote: &VenueQuote,
+    ) -> anyhow::Result<Vec<Instruction>> {
+        let Some(quote) = quote.downcast_ref::<RaydiumAmmQuote>() else {
+            anyhow::bail!("Quote is not for a raydium amm v4 pool");
+        };
+        RaydiumAmm::swap_instructions(self, user, quote, None).await
+    }
+
+    fn required_accounts(&self, quote: &VenueQuote) -> Vec<Pubkey> {
+        let Some(quote) = quote.downcast_ref::<RaydiumAmmQuote>() else {
+            return vec![];
+        };
+        vec![
+            quote.amm_keys.amm_pool,
+            quote.amm_keys.amm_open_order,
+            quote.amm_keys.amm_coin_vault,
+            quote.amm_keys.amm_pc_vault,
+            quote.amm_keys.market,
+            quote.market_keys.bids,
+            quote.market_keys.asks,
+            quote.market_keys.event_queue,
+            quote.market_keys.coin_vault,
+            quote.market_keys.pc_vault,
+        ]
+    }
+
+    async fn lookup_tables(
+        &self,
+        quote: &VenueQuote,
+    ) -> anyhow::Result<Vec<AddressLookupTableAccount>> {
+        let Some(quote) = quote.downcast_ref::<RaydiumAmmQuote>() else {
+            anyhow::bail!("Quote is not for a raydium amm v4 pool");
+        };
+        RaydiumAmm::lookup_tables(self, quote, None).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_swappable, swap_direction};
+    use crate::raydium::amm_types::RaydiumStatus;
+    use crate::raydium::math::SwapDirection;
+    use crate::raydium::quote_error::QuoteError;
+    use crate::raydium::types::{SwapExecutionMode, SwapInput};
+    use solana_sdk::clock::Clock;
+    use solana_sdk::pubkey::Pubkey;
+
+    fn at(unix_timestamp: i64) -> Clock {
+        Clock {
+            unix_timestamp,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn swappable_only_when_the_status_allows_and_the_pool_is_open() {
+        let pool = Pubkey::new_unique();
+        assert!(check_swappable(&pool, &RaydiumStatus::SwapOnly, 100, &at(100)).is_ok());
+        // waiting pools flip to swap only on their first swap past the open time
+        assert!(check_swappable(&pool, &RaydiumStatus::WaitingTrade, 100, &at(101)).is_ok());
+        assert!(matches!(
+            check_swappable(&pool, &RaydiumStatus::Disabled, 0, &at(100)),
+            Err(QuoteError::PoolDisabled(_))
+        ));
+        assert!(matches!(
+            check_swappable(&pool, &RaydiumStatus::WithdrawOnly, 0, &at(100)),
+            Err(QuoteError::WithdrawOnly(_))
+        ));
+        assert!(matches!(
+            check_swappable(&pool, &RaydiumStatus::LiquidityOnly, 0, &at(100)),
+            Err(QuoteError::SwapNotAllowed { status: 4, .. })
+        ));
+        assert!(matches!(
+            check_swappable(&pool, &RaydiumStatus::WaitingTrade, 100, &at(99)),
+            Err(QuoteError::NotOpenYet {
+                open_time: 100,
+                now: 99,
+                ..
+            })
+        ));
+        assert!(matches!(
+            check_swappable(&pool, &RaydiumStatus::SwapOnly, 0, &at(-1)),
+            Err(QuoteError::NotOpenYet { now: -1, .. })
+        ));
+    }
+    #[test]
+    fn trades_only_the_pool_pair_either_way() {
+        let (coin, pc) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let swap = |input_token_mint, output_token_mint| SwapInput {
+            input_token_mint,
+            output_token_mint,
+            slippage_bps: 0,
+            amount: 1,
+            mode: SwapExecutionMode::ExactIn,
+            market: None,
+        };
+        assert_eq!(
+            swap_direction(&swap(coin, pc), &coin, &pc).unwrap(),
+            SwapDirection::Coin2PC
+        );
+        assert_eq!(
+            swap_direction(&swap(pc, coin), &coin, &pc).unwrap(),
+            SwapDirection::PC2Coin
+        );
+        // a pinned pool of another pair must not be traded the other way round
+        let other = Pubkey::new_unique();
+        assert!(matches!(
+            swap_direction(&swap(pc, other), &coin, &pc),
+            Err(QuoteError::PoolNotFound { .. })
+        ));
+        assert!(matches!(
+            swap_direction(&swap(other, coin), &coin, &pc),
+            Err(QuoteError::PoolNotFound { .. })
+        ));
     }
 }
@@ -226,6 +226,10 @@ impl RaydiumStatus {
         }
     }
 
+    pub fn try_from_u64(status: u64) -> Option<Self> {
+        Self::valid_status(status).then(|| Self::from_u64(status))
+    }
+
     pub fn into_u64(&self) -> u64 {
         match self {
             RaydiumStatus::Uninitialized => 0u64,
@@ -676,53 +680,54 @@ pub struct LiquidityStateV4 {
     pub padding: [u64; 3],
 }
 impl From<LiquidityStateV4> for RaydiumAmmInfo {
+    // raydium names the base token "coin" and the quote token "pc"
     fn from(value: LiquidityStateV4) -> Self {
         RaydiumAmmInfo {
             status: value.status,
             nonce: value.nonce,
             order_num: value.max_order,
             depth: value.depth,
-            coin_decimals: value.quote_decimal,
-            pc_decimals: value.base_decimal,
+            coin_decimals: value.base_decimal,
+            pc_decimals: value.quote_decimal,
             state: value.state,
             reset_flag: value.reset_flag,
             min_size: value.min_size,
             vol_max_cut_ratio: value.vol_max_cut_ratio,
             amount_wave: value.amount_wave_ratio,
-            coin_lot_size: value.quote_lot_size,
-            pc_lot_size: value.base_lot_size,
+            coin_lot_size: value.base_lot_size,
+            pc_lot_size: value.quote_lot_size,
             min_price_multiplier: value.min_price_multiplier,
             max_price_multiplier: value.max_price_multiplier,
             sys_decimal_value: value.system_decimal_value,
             fees: RaydiumFees {
-                min_separate_numerator: 0,
-                min_separate_denominator: 0,
-                trade_fee_numerator: 0,
-                trade_fee_denominator: 0,
-                pnl_numerator: 0,
-                pnl_denominator: 0,
-                swap_fee_numerator: 0,
+                min_separate_numerator: value.min_separate_numerator,
+                min_separate_denominator: value.min_separate_denominator,
+                trade_fee_numerator: value.trade_fee_numerator,
+                trade_fee_denominator: value.trade_fee_denominator,
+                pnl_numerator: value.pnl_numerator,
+                pnl_denominator: value.pnl_denominator,
+                swap_fee_numerator: value.swap_fee_numerator,
                 swap_fee_denominator: value.swap_fee_denominator,
             },
             state_data: RaydiumStateData {
-                need_take_pnl_coin: 0,
-                need_take_pnl_pc: 0,
-                total_pnl_pc: 0,
-                total_pnl_coin: 0,
-                pool_open_time: 0,
-                padding: [0, 0],
-                orderbook_to_init_time: 0,
-                swap_coin_in_amount: 0,
-                swap_pc_out_amount: 0,
-                swap_acc_pc_fee: 0,
-                swap_pc_in_amount: 0,
-                swap_coin_out_amount: 0,
-                swap_acc_coin_fee: 0,
+                need_take_pnl_coin: value.base_need_take_pnl,
+                need_take_pnl_pc: value.quote_need_take_pnl,
+                total_pnl_pc: value.quote_total_pnl,
+                total_pnl_coin: value.base_total_pnl,
+                pool_open_time: value.pool_open_time,
+                padding: [value.punish_pc_amount, value.punish_coin_amount],
+                orderbook_to_init_time: value.orderbook_to_init_time,
+                swap_coin_in_amount: value.swap_base_in_amount,
+                swap_pc_out_amount: value.swap_quote_out_amount,
+                swap_acc_pc_fee: value.swap_base2quote_fee,
+                swap_pc_in_amount: value.swap_quote_in_amount,
+                swap_coin_out_amount: value.swap_base_out_amount,
+                swap_acc_coin_fee: value.swap_quote2base_fee,
             },
-            coin_vault: value.quote_vault,
-            pc_vault: value.base_vault,
-            coin_vault_mint: value.quote_mint,
-            pc_vault_mint: value.base_mint,
+            coin_vault: value.base_vault,
+            pc_vault: value.quote_vault,
+            coin_vault_mint: value.base_mint,
+            pc_vault_mint: value.quote_mint,
             lp_mint: value.lp_mint,
             open_orders: value.open_orders,
             market: value.market_id,
@@ -731,8 +736,8 @@ impl From<LiquidityStateV4> for RaydiumAmmInfo {
             padding1: [0; 8],
             amm_owner: value.owner,
             lp_amount: value.lp_reserve,
-            client_order_id: value.max_order,
-            padding2: [0; 2],
+            client_order_id: value.padding[0],
+            padding2: [value.padding[1], value.padding[2]],
         }
     }
 }
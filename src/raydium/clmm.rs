@@ -0,0 +1,587 @@
+use crate::config::{RAYDIUM_CLMM_PROGRAM_ID, WSOL};
+use crate::lookup_table::fetch_lookup_tables;
+use crate::raydium::amm::{
+    amount_with_slippage, compute_budget_instructions, ui_price, wrap_sol_instructions,
+};
+use crate::raydium::api_v3::response::{ApiV3ClmmPool, ApiV3PoolsPage};
+use crate::raydium::api_v3::{ApiV3Client, PoolFetchParams, PoolSort, PoolSortOrder, PoolType};
+use crate::raydium::clmm_math::{simulate_swap, swap_tick_array_start_indexes};
+use crate::raydium::clmm_types::{
+    AMM_CONFIG_DISCRIMINATOR, ClmmAmmConfig, ClmmPoolState, POOL_STATE_DISCRIMINATOR,
+    SWAP_V2_DISCRIMINATOR, TICK_ARRAY_BITMAP_EXTENSION_DISCRIMINATOR, TICK_ARRAY_DISCRIMINATOR,
+    TickArrayBitmapExtension, TickArrayState, tick_array_address,
+    tick_array_bitmap_extension_address,
+};
+use crate::raydium::cpmm_types::decode_anchor_account;
+use crate::raydium::quote_error::QuoteError;
+use crate::raydium::types::{
+    ClmmQuote, QuoteSummary, RaydiumAmmExecutorOpts, SwapConfig, SwapConfigOverrides, SwapInput,
+};
+use crate::router::{Quote, SwapVenue, VenueQuote};
+use async_trait::async_trait;
+use log::info;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::address_lookup_table::AddressLookupTableAccount;
+use solana_sdk::clock::Clock;
+use solana_sdk::instruction::{AccountMeta, Instruction};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::sysvar;
+use spl_associated_token_account_client::address::get_associated_token_address_with_program_id;
+use spl_associated_token_account_client::instruction::create_associated_token_account_idempotent;
+use spl_token_2022::extension::StateWithExtensions;
+use std::sync::Arc;
+
+/// Tick arrays loaded per quote. Swaps crossing more than this many fail to quote.
+const MAX_TICK_ARRAYS: usize = 5;
+
+const MEMO_PROGRAM_ID: &str = "MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr";
+
+/// Quotes and builds swaps on Raydium concentrated liquidity pools.
+///
+/// Token-2022 transfer fees are not modelled, the slippage tolerance absorbs them.
+#[derive(Clone)]
+pub struct RaydiumClmm {
+    client: Arc<RpcClient>,
+    api: ApiV3Client,
+    config: SwapConfig,
+}
+
+impl RaydiumClmm {
+    pub fn new(client: Arc<RpcClient>, config: RaydiumAmmExecutorOpts, api: ApiV3Client) -> Self {
+        let RaydiumAmmExecutorOpts {
+            priority_fee,
+            cu_limits,
+            wrap_and_unwrap_sol,
+            as_legacy_transaction,
+            lookup_table,
+            ..
+        } = config;
+        Self {
+            client,
+            api,
+            config: SwapConfig {
+                priority_fee,
+                cu_limits,
+                wrap_and_unwrap_sol,
+                as_legacy_transaction: as_legacy_transaction.or(Some(true)),
+                lookup_table,
+            },
+        }
+    }
+
+    pub async fn quote(&self, swap_input: &SwapInput) -> Result<ClmmQuote, QuoteError> {
+        if swap_input.input_token_mint == swap_input.output_token_mint {
+            return Err(QuoteError::SameMint(swap_input.input_token_mint));
+        }
+        let pool_not_found = || QuoteError::PoolNotFound {
+            input_mint: swap_input.input_token_mint,
+            output_mint: swap_input.output_token_mint,
+        };
+
+        let pool_id = match swap_input.market {
+            Some(pool_id) => pool_id,
+            None => self
+                .find_pool(&swap_input.input_token_mint, &swap_input.output_token_mint)
+                .await?
+                .ok_or_else(pool_not_found)?,
+        };
+
+        // the pool first, everything else hangs off its keys
+        let extension_id = tick_array_bitmap_extension_address(&pool_id);
+        let accounts = crate::raydium::utils::get_multiple_account_data(
+            &self.client,
+            &[pool_id, extension_id],
+        )
+        .await?;
+        let [pool_account, extension_account] =
+            <[_; 2]>::try_from(accounts).map_err(|_| QuoteError::AccountMissing(pool_id))?;
+        let pool_account = pool_account.ok_or(QuoteError::AccountMissing(pool_id))?;
+        let pool: ClmmPoolState =
+            decode_anchor_account(&pool_account.data, &POOL_STATE_DISCRIMINATOR)
+                .map_err(|e| QuoteError::bad_layout(&pool_id, e))?;
+        // older pools never created the extension
+        let extension = extension_account
+            .map(|account| {
+                decode_anchor_account::<TickArrayBitmapExtension>(
+                    &account.data,
+                    &TICK_ARRAY_BITMAP_EXTENSION_DISCRIMINATOR,
+                )
+                .map_err(|e| QuoteError::bad_layout(&extension_id, e))
+            })
+            .transpose()?;
+        info!(
+            "CLMM pool {}: tick {}, liquidity {}",
+            pool_id, pool.tick_current, pool.liquidity
+        );
+
+        let zero_for_one = if swap_input.input_token_mint == pool.token_mint_0
+            && swap_input.output_token_mint == pool.token_mint_1
+        {
+            true
+        } else if swap_input.input_token_mint == pool.token_mint_1
+            && swap_input.output_token_mint == pool.token_mint_0
+        {
+            false
+        } else {
+            return Err(pool_not_found());
+        };
+
+        let start_indexes =
+            swap_tick_array_start_indexes(&pool, extension.as_ref(), zero_for_one, MAX_TICK_ARRAYS);
+        if start_indexes.is_empty() {
+            return Err(QuoteError::InsufficientLiquidity);
+        }
+        let tick_array_ids = start_indexes
+            .iter()
+            .map(|start_index| tick_array_address(&pool_id, *start_index))
+            .collect::<Vec<_>>();
+
+        let mut load_pubkeys = vec![
+            pool.amm_config,
+            pool.token_vault_0,
+            pool.token_vault_1,
+            pool.token_mint_0,
+            pool.token_mint_1,
+            sysvar::clock::id(),
+        ];
+        load_pubkeys.extend(&tick_array_ids);
+        let mut accounts = load_pubkeys
+            .iter()
+            .zip(
+                crate::raydium::utils::get_multiple_account_data(&self.client, &load_pubkeys)
+                    .await?,
+            )
+            .map(|(key, account)| account.ok_or(QuoteError::AccountMissing(*key)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let tick_array_accounts = accounts.split_off(6);
+        let [
+            config_account,
+            vault_0_account,
+            vault_1_account,
+            mint_0_account,
+            mint_1_account,
+            clock_account,
+        ] = <[_; 6]>::try_from(accounts).map_err(|_| QuoteError::AccountMissing(pool_id))?;
+
+        let amm_config: ClmmAmmConfig =
+            decode_anchor_account(&config_account.data, &AMM_CONFIG_DISCRIMINATOR)
+                .map_err(|e| QuoteError::bad_layout(&pool.amm_config, e))?;
+        let vault_amount = |key: &Pubkey, data: &[u8]| {
+            StateWithExtensions::<spl_token_2022::state::Account>::unpack(data)
+                .map(|vault| vault.base.amount)
+                .map_err(|e| QuoteError::bad_layout(key, e))
+        };
+        let vault_0 = vault_amount(&pool.token_vault_0, &vault_0_account.data)?;
+        let vault_1 = vault_amount(&pool.token_vault_1, &vault_1_account.data)?;
+        let clock: Clock = bincode::deserialize(&clock_account.data)
+            .map_err(|e| QuoteError::bad_layout(&sysvar::clock::id(), e))?;
+        let tick_arrays = tick_array_ids
+            .iter()
+            .zip(tick_array_accounts)
+            .map(|(key, account)| {
+                decode_anchor_account::<TickArrayState>(&account.data, &TICK_ARRAY_DISCRIMINATOR)
+                    .map_err(|e| QuoteError::bad_layout(key, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        check_swappable(&pool_id, &pool, &clock)?;
+
+        let amount_specified_is_input = swap_input.mode.amount_specified_is_input();
+        let swap = simulate_swap(
+            &pool,
+            extension.as_ref(),
+            &tick_arrays,
+            amm_config.trade_fee_rate,
+            swap_input.amount,
+            zero_for_one,
+            amount_specified_is_input,
+        )?;
+        let other_amount = if amount_specified_is_input {
+            swap.amount_out
+        } else {
+            swap.amount_in
+        };
+        let other_amount_threshold = amount_with_slippage(
+            other_amount,
+            swap_input.slippage_bps as u64,
+            !amount_specified_is_input,
+        )?;
+
+        let (
+            input_vault,
+            output_vault,
+            input_mint_decimals,
+            output_mint_decimals,
+            input_vault_amount,
+            output_vault_amount,
+            input_token_program,
+            output_token_program,
+        ) = if zero_for_one {
+            (
+                pool.token_vault_0,
+                pool.token_vault_1,
+                pool.mint_decimals_0,
+                pool.mint_decimals_1,
+                vault_0,
+                vault_1,
+                mint_0_account.owner,
+                mint_1_account.owner,
+            )
+        } else {
+            (
+                pool.token_vault_1,
+                pool.token_vault_0,
+                pool.mint_decimals_1,
+                pool.mint_decimals_0,
+                vault_1,
+                vault_0,
+                mint_1_account.owner,
+                mint_0_account.owner,
+            )
+        };
+        // raw output per raw input at the pool price
+        let price = (pool.sqrt_price_x64 as f64 / 2f64.powi(64)).powi(2);
+        let raw_spot = if zero_for_one { price } else { 1.0 / price };
+        let spot_price =
+            raw_spot * 10f64.powi(input_mint_decimals as i32 - output_mint_decimals as i32);
+
+        Ok(ClmmQuote {
+            pool: pool_id,
+            amm_config: pool.amm_config,
+            observation: pool.observation_key,
+            input_vault,
+            output_vault,
+            input_mint: swap_input.input_token_mint,
+            output_mint: swap_input.output_token_mint,
+            input_token_program,
+            output_token_program,
+            tick_array_bitmap_extension: extension.map(|_| extension_id),
+            // the price can move past what the quote crossed before the swap lands
+            tick_arrays: tick_array_ids,
+            amount: swap_input.amount,
+            other_amount,
+            other_amount_threshold,
+            amount_specified_is_input,
+            input_mint_decimals,
+            output_mint_decimals,
+            input_vault_amount,
+            output_vault_amount,
+            trade_fee_rate: amm_config.trade_fee_rate,
+            open_time: pool.open_time,
+            sqrt_price_x64: pool.sqrt_price_x64,
+            sqrt_price_after_x64: swap.sqrt_price_x64,
+            spot_price,
+            execution_price: ui_price(
+                swap.amount_in,
+                input_mint_decimals,
+                swap.amount_out,
+                output_mint_decimals,
+            ),
+            price_impact_bps: price_impact_bps(
+                swap.amount_in.saturating_sub(swap.fee_amount),
+                swap.amount_out,
+                raw_spot,
+            ),
+            fee_amount: swap.fee_amount,
+            minimum_out: if amount_specified_is_input {
+                other_amount_threshold
+            } else {
+                swap.amount_out
+            },
+            slot: clock.slot,
+        })
+    }
+
+    /// Deepest CLMM pool for the pair, per the Raydium api
+    async fn find_pool(
+        &self,
+        input_mint: &Pubkey,
+        output_mint: &Pubkey,
+    ) -> Result<Option<Pubkey>, QuoteError> {
+        let response: ApiV3PoolsPage<ApiV3ClmmPool> = self
+            .api
+            .fetch_pool_by_mints(
+                input_mint,
+                Some(output_mint),
+                &PoolFetchParams {
+                    pool_type: PoolType::Concentrated,
+                    pool_sort: PoolSort::Liquidity,
+                    sort_type: PoolSortOrder::Descending,
+                    page_size: 10,
+                    page: 1,
+                },
+            )
+            .await?;
+        Ok(response.pools.into_iter().find_map(|pool| {
+            ((pool.mint_a.address == *input_mint && pool.mint_b.address == *output_mint
+                || pool.mint_a.address == *output_mint && pool.mint_b.address == *input_mint)
+                && pool.program_id == Pubkey::from_str_const(RAYDIUM_CLMM_PROGRAM_ID))
+            .then_some(pool.id)
+        }))
+    }
+
+    pub async fn swap_instructions(
+        &self,
+        user: Pubkey,
+        quote: &ClmmQuote,
+        overrides: Option<&SwapConfigOverrides>,
+    ) -> anyhow::Result<Vec<Instruction>> {
+        let wrap_and_unwrap_sol = overrides
+            .and_then(|o| o.wrap_and_unwrap_sol)
+            .or(self.config.wrap_and_unwrap_sol)
+            .unwrap_or(true);
+
+        let mut instructions =
+            compute_budget_instructions(&self.client, &self.config, overrides, &quote.pool, &user)
+                .await?;
+
+        let wsol = Pubkey::from_str_const(WSOL);
+        let source = get_associated_token_address_with_program_id(
+            &user,
+            &quote.input_mint,
+            &quote.input_token_program,
+        );
+        let destination = overrides
+            .and_then(|o| o.destination_token_account)
+            .unwrap_or_else(|| {
+                get_associated_token_address_with_program_id(
+                    &user,
+                    &quote.output_mint,
+                    &quote.output_token_program,
+                )
+            });
+
+        if wrap_and_unwrap_sol && quote.input_mint == wsol {
+            let max_in = if quote.amount_specified_is_input {
+                quote.amount
+            } else {
+                quote.other_amount_threshold
+            };
+            instructions.extend(wrap_sol_instructions(&user, max_in)?);
+        }
+        if overrides
+            .and_then(|o| o.destination_token_account)
+            .is_none()
+        {
+            instructions.push(create_associated_token_account_idempotent(
+                &user,
+                &user,
+                &quote.output_mint,
+                &quote.output_token_program,
+            ));
+        }
+        instructions.push(swap_v2_instruction(quote, &user, source, destination));
+        if wrap_and_unwrap_sol && (quote.input_mint == wsol || quote.output_mint == wsol) {
+            let wsol_account =
+                get_associated_token_address_with_program_id(&user, &wsol, &spl_token::id());
+            instructions.push(spl_token::instruction::close_account(
+                &spl_token::id(),
+                &wsol_account,
+                &user,
+                &user,
+                &[],
+            )?);
+        }
+        Ok(instructions)
+    }
+
+    /// Our own lookup table for v0 swap transactions, CLMM pools don't come with one.
+    /// Empty when building legacy transactions.
+    pub async fn lookup_tables(
+        &self,
+        overrides: Option<&SwapConfigOverrides>,
+    ) -> anyhow::Result<Vec<AddressLookupTableAccount>> {
+        let as_legacy_transaction = overrides
+            .and_then(|o| o.as_legacy_transaction)
+            .or(self.config.as_legacy_transaction)
+            .unwrap_or(true);
+        match self.config.lookup_table {
+            Some(lookup_table) if !as_legacy_transaction => {
+                fetch_lookup_tables(&self.client, &[lookup_table]).await
+            }
+            _ => Ok(vec![]),
+        }
+    }
+}
+
+pub fn swap_v2_instruction(
+    quote: &ClmmQuote,
+    user: &Pubkey,
+    source: Pubkey,
+    destination: Pubkey,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*user, true),
+        AccountMeta::new_readonly(quote.amm_config, false),
+        AccountMeta::new(quote.pool, false),
+        AccountMeta::new(source, false),
+        AccountMeta::new(destination, false),
+        AccountMeta::new(quote.input_vault, false),
+        AccountMeta::new(quote.output_vault, false),
+        AccountMeta::new(quote.observation, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(spl_token_2022::id(), false),
+        AccountMeta::new_readonly(Pubkey::from_str_const(MEMO_PROGRAM_ID), false),
+        AccountMeta::new_readonly(quote.input_mint, false),
+        AccountMeta::new_readonly(quote.output_mint, false),
+    ];
+    // remaining accounts: the bitmap extension, then the tick arrays in swap order
+    accounts.extend(
+        quote
+            .tick_array_bitmap_extension
+            .iter()
+            .chain(&quote.tick_arrays)
+            .map(|key| AccountMeta::new(*key, false)),
+    );
+    let (amount, other_amount_threshold) = (quote.amount, quote.other_amount_threshold);
+    // no price limit, the threshold bounds the fill
+    let sqrt_price_limit_x64 = 0u128;
+    Instruction {
+        program_id: Pubkey::from_str_const(RAYDIUM_CLMM_PROGRAM_ID),
+        accounts,
+        data: [
+            SWAP_V2_DISCRIMINATOR.as_slice(),
+            &amount.to_le_bytes(),
+            &other_amount_threshold.to_le_bytes(),
+            &sqrt_price_limit_x64.to_le_bytes(),
+            &[quote.amount_specified_is_input as u8],
+        ]
+        .concat(),
+    }
+}
+
+/// Fails with the reason the program would reject a swap on this pool at `clock`
+pub fn check_swappable(
+    pool_id: &Pubkey,
+    pool: &ClmmPoolState,
+    clock: &Clock,
+) -> Result<(), QuoteError> {
+    if !pool.swap_enabled() {
+        return Err(QuoteError::SwapNotAllowed {
+            pool: *pool_id,
+            status: pool.status as u64,
+        });
+    }
+    // the program wants the block time strictly past the open time
+    if clock.unix_timestamp < 0 || clock.unix_timestamp as u64 <= pool.open_time {
+        return Err(QuoteError::NotOpenYet {
+            pool: *pool_id,
+            open_time: pool.open_time,
+            now: clock.unix_timestamp,
+        });
+    }
+    Ok(())
+}
+
+/// Shortfall of `amount_out` against the spot price, fees excluded
+fn price_impact_bps(amount_in_after_fee: u64, amount_out: u64, raw_spot: f64) -> u64 {
+    let spot_out = amount_in_after_fee as f64 * raw_spot;
+    if spot_out <= 0.0 {
+        return 0;
+    }
+    ((spot_out - amount_out as f64).max(0.0) * 10_000.0 / spot_out) as u64
+}
+
+impl Quote for ClmmQuote {
+    fn summary(&self) -> QuoteSummary {
+        ClmmQuote::summary(self)
+    }
+
+    fn set_slippage(&mut self, slippage_bps: u64) -> anyhow::Result<()> {
+        ClmmQuote::set_slippage(self, slippage_bps)
+    }
+}
+
+#[async_trait]
+impl SwapVenue for RaydiumClmm {
+    fn name(&self) -> &'static str {
+        "raydium clmm"
+    }
+
+    fn supports(&self, input_mint: &Pubkey, output_mint: &Pubkey) -> bool {
+        input_mint != output_mint
+    }
+
+    async fn quote(&self, swap_input: &SwapInput) -> Result<VenueQuote, QuoteError> {
+        Ok(VenueQuote::new(RaydiumClmm::quote(self, swap_input).await?))
+    }
+
+    async fn swap_instructions(
+        &self,
+        user: Pubkey,
+        quote: &VenueQuote,
+    ) -> anyhow::Result<Vec<Instruction>> {
+        let Some(quote) = quote.downcast_ref::<ClmmQuote>() else {
+            anyhow::bail!("Quote is not for a clmm pool");
+        };
+        RaydiumClmm::swap_instructions(self, user, quote, None).await
+    }
+
+    fn required_accounts(&self, quote: &VenueQuote) -> Vec<Pubkey> {
+        let Some(quote) = quote.downcast_ref::<ClmmQuote>() else {
+            return vec![];
+        };
+        [
+            quote.pool,
+            quote.input_vault,
+            quote.output_vault,
+            quote.observation,
+        ]
+        .into_iter()
+        .chain(quote.tick_array_bitmap_extension)
+        .chain(quote.tick_arrays.iter().copied())
+        .collect()
+    }
+
+    async fn lookup_tables(
+        &self,
+        _quote: &VenueQuote,
+    ) -> anyhow::Result<Vec<AddressLookupTableAccount>> {
+        RaydiumClmm::lookup_tables(self, None).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_swappable;
+    use crate::raydium::clmm_types::ClmmPoolState;
+    use crate::raydium::quote_error::QuoteError;
+    use solana_sdk::clock::Clock;
+    use solana_sdk::pubkey::Pubkey;
+
+    fn at(unix_timestamp: i64) -> Clock {
+        Clock {
+            unix_timestamp,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn swappable_once_open_and_not_disabled() {
+        let pool_id = Pubkey::new_unique();
+        let pool = ClmmPoolState {
+            open_time: 100,
+            ..Default::default()
+        };
+        assert!(check_swappable(&pool_id, &pool, &at(101)).is_ok());
+        // the open time itself is still too early
+        assert!(matches!(
+            check_swappable(&pool_id, &pool, &at(100)),
+            Err(QuoteError::NotOpenYet {
+                open_time: 100,
+                now: 100,
+                ..
+            })
+        ));
+        assert!(matches!(
+            check_swappable(&pool_id, &pool, &at(-1)),
+            Err(QuoteError::NotOpenYet { now: -1, .. })
+        ));
+        // the swap disabled bit
+        let disabled = ClmmPoolState { status: 16, ..pool };
+        assert!(matches!(
+            check_swappable(&pool_id, &disabled, &at(101)),
+            Err(QuoteError::SwapNotAllowed { status: 16, .. })
+        ));
+    }
+}
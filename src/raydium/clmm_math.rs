@@ -0,0 +1,704 @@
+//! Fixed point math of the CLMM program, ported with its rounding so simulated swaps match
+//! the chain to the unit.
+#![allow(clippy::manual_div_ceil)]
+
+use crate::raydium::clmm_types::{
+    ClmmPoolState, FEE_RATE_DENOMINATOR, TickArrayBitmapExtension, TickArrayState,
+};
+use crate::raydium::quote_error::QuoteError;
+use std::collections::VecDeque;
+use uint::construct_uint;
+
+construct_uint! {
+    pub struct U512(8);
+}
+construct_uint! {
+    pub struct U1024(16);
+}
+
+pub const MIN_TICK: i32 = -443636;
+pub const MAX_TICK: i32 = -MIN_TICK;
+pub const MIN_SQRT_PRICE_X64: u128 = 4295048016;
+pub const MAX_SQRT_PRICE_X64: u128 = 79226673521066979257578248091;
+/// Fractional bits of `get_tick_at_sqrt_price`'s log2 approximation
+const BIT_PRECISION: u32 = 16;
+
+/// sqrt(1.0001)^(-2^i) in Q64.64 for bit i of the tick
+const TICK_RATIOS: [u128; 19] = [
+    0xfffcb933bd6fb800,
+    0xfff97272373d4000,
+    0xfff2e50f5f657000,
+    0xffe5caca7e10f000,
+    0xffcb9843d60f7000,
+    0xff973b41fa98e800,
+    0xff2ea16466c9b000,
+    0xfe5dee046a9a3800,
+    0xfcbe86c7900bb000,
+    0xf987a7253ac65800,
+    0xf3392b0822bb6000,
+    0xe7159475a2caf000,
+    0xd097f3bdfd2f2000,
+    0xa9f746462d9f8000,
+    0x70d869a156f31c00,
+    0x31be135f97ed3200,
+    0x9aa508b5b85a500,
+    0x5d6af8dedc582c,
+    0x2216e584f5fa,
+];
+
+/// sqrt(1.0001^tick) in Q64.64
+pub fn get_sqrt_price_at_tick(tick: i32) -> Result<u128, QuoteError> {
+    let abs_tick = tick.unsigned_abs();
+    if abs_tick > MAX_TICK as u32 {
+        return Err(QuoteError::MathOverflow);
+    }
+    // 1.0 in Q64.64 when bit 0 is clear
+    let mut ratio: u128 = if abs_tick & 1 != 0 {
+        TICK_RATIOS[0]
+    } else {
+        1 << 64
+    };
+    for (bit, tick_ratio) in TICK_RATIOS.iter().enumerate().skip(1) {
+        if abs_tick & (1 << bit) != 0 {
+            // both factors are below 2^65, the product fits
+            ratio = (ratio * tick_ratio) >> 64;
+        }
+    }
+    if tick > 0 {
+        ratio = u128::MAX / ratio;
+    }
+    Ok(ratio)
+}
+
+/// Greatest tick whose sqrt price is at most `sqrt_price_x64`
+pub fn get_tick_at_sqrt_price(sqrt_price_x64: u128) -> Result<i32, QuoteError> {
+    if !(MIN_SQRT_PRICE_X64..MAX_SQRT_PRICE_X64).contains(&sqrt_price_x64) {
+        return Err(QuoteError::MathOverflow);
+    }
+    // integer part of log2 from the most significant bit
+    let msb = 128 - sqrt_price_x64.leading_zeros() - 1;
+    let log2p_integer_x32 = (msb as i128 - 64) << 32;
+
+    // fractional part, one bit per squaring, starting at 0.5 in Q64.64
+    let mut bit: i128 = 0x8000_0000_0000_0000;
+    let mut precision = 0;
+    let mut log2p_fraction_x64 = 0;
+    let mut r = if msb >= 64 {
+        sqrt_price_x64 >> (msb - 63)
+    } else {
+        sqrt_price_x64 << (63 - msb)
+    };
+    while bit > 0 && precision < BIT_PRECISION {
+        r *= r;
+        let is_r_more_than_two = (r >> 127) as u32;
+        r >>= 63 + is_r_more_than_two;
+        log2p_fraction_x64 += bit * is_r_more_than_two as i128;
+        bit >>= 1;
+        precision += 1;
+    }
+    let log2p_x32 = log2p_integer_x32 + (log2p_fraction_x64 >> 32);
+
+    // change of base, multiply by 2^16 / log2(sqrt(1.0001))
+    let log_sqrt_10001_x64 = log2p_x32 * 59543866431248i128;
+    // bounds of the approximation error
+    let tick_low = ((log_sqrt_10001_x64 - 184467440737095516i128) >> 64) as i32;
+    let tick_high = ((log_sqrt_10001_x64 + 15793534762490258745i128) >> 64) as i32;
+
+    Ok(if tick_low == tick_high {
+        tick_low
+    } else if get_sqrt_price_at_tick(tick_high)? <= sqrt_price_x64 {
+        tick_high
+    } else {
+        tick_low
+    })
+}
+
+fn mul_div_floor(a: U512, b: U512, denominator: U512) -> Option<U512> {
+    if denominator.is_zero() {
+        return None;
+    }
+    Some(a.checked_mul(b)? / denominator)
+}
+
+fn mul_div_ceil(a: U512, b: U512, denominator: U512) -> Option<U512> {
+    if denominator.is_zero() {
+        return None;
+    }
+    Some((a.checked_mul(b)? + denominator - 1) / denominator)
+}
+
+fn div_ceil(a: U512, denominator: U512) -> Option<U512> {
+    if denominator.is_zero() {
+        return None;
+    }
+    let (quotient, remainder) = a.div_mod(denominator);
+    Some(if remainder.is_zero() {
+        quotient
+    } else {
+        quotient + 1
+    })
+}
+
+fn to_u64(value: U512) -> Option<u64> {
+    (value <= U512::from(u64::MAX)).then(|| value.low_u64())
+}
+
+fn to_u128(value: U512) -> Option<u128> {
+    (value <= U512::from(u128::MAX)).then(|| value.low_u128())
+}
+
+/// Token 0 between two sqrt prices at `liquidity`. None if it doesn't fit in a u64
+pub fn get_delta_amount_0_unsigned(
+    mut sqrt_ratio_a_x64: u128,
+    mut sqrt_ratio_b_x64: u128,
+    liquidity: u128,
+    round_up: bool,
+) -> Option<u64> {
+    if sqrt_ratio_a_x64 > sqrt_ratio_b_x64 {
+        std::mem::swap(&mut sqrt_ratio_a_x64, &mut sqrt_ratio_b_x64);
+    }
+    let numerator_1 = U512::from(liquidity) << 64;
+    let numerator_2 = U512::from(sqrt_ratio_b_x64 - sqrt_ratio_a_x64);
+    let result = if round_up {
+        div_ceil(
+            mul_div_ceil(numerator_1, numerator_2, sqrt_ratio_b_x64.into())?,
+            sqrt_ratio_a_x64.into(),
+        )?
+    } else {
+        mul_div_floor(numerator_1, numerator_2, sqrt_ratio_b_x64.into())?
+            .checked_div(sqrt_ratio_a_x64.into())?
+    };
+    to_u64(result)
+}
+
+/// Token 1 between two sqrt prices at `liquidity`. None if it doesn't fit in a u64
+pub fn get_delta_amount_1_unsigned(
+    mut sqrt_ratio_a_x64: u128,
+    mut sqrt_ratio_b_x64: u128,
+    liquidity: u128,
+    round_up: bool,
+) -> Option<u64> {
+    if sqrt_ratio_a_x64 > sqrt_ratio_b_x64 {
+        std::mem::swap(&mut sqrt_ratio_a_x64, &mut sqrt_ratio_b_x64);
+    }
+    let difference = U512::from(sqrt_ratio_b_x64 - sqrt_ratio_a_x64);
+    let q64 = U512::one() << 64;
+    let result = if round_up {
+        mul_div_ceil(liquidity.into(), difference, q64)?
+    } else {
+        mul_div_floor(liquidity.into(), difference, q64)?
+    };
+    to_u64(result)
+}
+
+fn get_next_sqrt_price_from_amount_0_rounding_up(
+    sqrt_price_x64: u128,
+    liquidity: u128,
+    amount: u64,
+    add: bool,
+) -> Option<u128> {
+    if amount == 0 {
+        return Some(sqrt_price_x64);
+    }
+    let numerator_1 = U512::from(liquidity) << 64;
+    let product = U512::from(amount) * U512::from(sqrt_price_x64);
+    let denominator = if add {
+        numerator_1 + product
+    } else {
+        numerator_1.checked_sub(product)?
+    };
+    to_u128(mul_div_ceil(
+        numerator_1,
+        sqrt_price_x64.into(),
+        denominator,
+    )?)
+}
+
+fn get_next_sqrt_price_from_amount_1_rounding_down(
+    sqrt_price_x64: u128,
+    liquidity: u128,
+    amount: u64,
+    add: bool,
+) -> Option<u128> {
+    let shifted = U512::from(amount) << 64;
+    if add {
+        let quotient = to_u128(shifted.checked_div(liquidity.into())?)?;
+        sqrt_price_x64.checked_add(quotient)
+    } else {
+        let quotient = to_u128(div_ceil(shifted, liquidity.into())?)?;
+        sqrt_price_x64.checked_sub(quotient)
+    }
+}
+
+fn get_next_sqrt_price_from_input(
+    sqrt_price_x64: u128,
+    liquidity: u128,
+    amount_in: u64,
+    zero_for_one: bool,
+) -> Option<u128> {
+    if zero_for_one {
+        get_next_sqrt_price_from_amount_0_rounding_up(sqrt_price_x64, liquidity, amount_in, true)
+    } else {
+        get_next_sqrt_price_from_amount_1_rounding_down(sqrt_price_x64, liquidity, amount_in, true)
+    }
+}
+
+fn get_next_sqrt_price_from_output(
+    sqrt_price_x64: u128,
+    liquidity: u128,
+    amount_out: u64,
+    zero_for_one: bool,
+) -> Option<u128> {
+    if zero_for_one {
+        get_next_sqrt_price_from_amount_1_rounding_down(
+            sqrt_price_x64,
+            liquidity,
+            amount_out,
+            false,
+        )
+    } else {
+        get_next_sqrt_price_from_amount_0_rounding_up(sqrt_price_x64, liquidity, amount_out, false)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SwapStep {
+    pub sqrt_price_next_x64: u128,
+    pub amount_in: u64,
+    pub amount_out: u64,
+    pub fee_amount: u64,
+}
+
+/// One step of a swap within a single liquidity range, towards `sqrt_price_target_x64`
+pub fn compute_swap_step(
+    sqrt_price_current_x64: u128,
+    sqrt_price_target_x64: u128,
+    liquidity: u128,
+    amount_remaining: u64,
+    fee_rate: u32,
+    is_base_input: bool,
+    zero_for_one: bool,
+) -> Result<SwapStep, QuoteError> {
+    let mut step = SwapStep::default();
+    let fee_complement = FEE_RATE_DENOMINATOR - fee_rate;
+    if is_base_input {
+        let amount_remaining_less_fee = to_u64(
+            mul_div_floor(
+                amount_remaining.into(),
+                fee_complement.into(),
+                FEE_RATE_DENOMINATOR.into(),
+            )
+            .ok_or(QuoteError::MathOverflow)?,
+        )
+        .ok_or(QuoteError::MathOverflow)?;
+        // None when the whole range holds more than a u64
+        let amount_in = if zero_for_one {
+            get_delta_amount_0_unsigned(
+                sqrt_price_target_x64,
+                sqrt_price_current_x64,
+                liquidity,
+                true,
+            )
+        } else {
+            get_delta_amount_1_unsigned(
+                sqrt_price_current_x64,
+                sqrt_price_target_x64,
+                liquidity,
+                true,
+            )
+        };
+        if let Some(amount_in) = amount_in {
+            step.amount_in = amount_in;
+        }
+        step.sqrt_price_next_x64 = match amount_in {
+            Some(amount_in) if amount_remaining_less_fee >= amount_in => sqrt_price_target_x64,
+            _ => get_next_sqrt_price_from_input(
+                sqrt_price_current_x64,
+                liquidity,
+                amount_remaining_less_fee,
+                zero_for_one,
+            )
+            .ok_or(QuoteError::MathOverflow)?,
+        };
+    } else {
+        let amount_out = if zero_for_one {
+            get_delta_amount_1_unsigned(
+                sqrt_price_target_x64,
+                sqrt_price_current_x64,
+                liquidity,
+                false,
+            )
+        } else {
+            get_delta_amount_0_unsigned(
+                sqrt_price_current_x64,
+                sqrt_price_target_x64,
+                liquidity,
+                false,
+            )
+        };
+        if let Some(amount_out) = amount_out {
+            step.amount_out = amount_out;
+        }
+        step.sqrt_price_next_x64 = match amount_out {
+            Some(amount_out) if amount_remaining >= amount_out => sqrt_price_target_x64,
+            _ => get_next_sqrt_price_from_output(
+                sqrt_price_current_x64,
+                liquidity,
+                amount_remaining,
+                zero_for_one,
+            )
+            .ok_or(QuoteError::InsufficientLiquidity)?,
+        };
+    }
+
+    // reaching the target already fixed the specified side
+    let reached_target = sqrt_price_target_x64 == step.sqrt_price_next_x64;
+    let (next, current) = (step.sqrt_price_next_x64, sqrt_price_current_x64);
+    if zero_for_one {
+        if !reached_target || !is_base_input {
+            step.amount_in = get_delta_amount_0_unsigned(next, current, liquidity, true)
+                .ok_or(QuoteError::MathOverflow)?;
+        }
+        if !reached_target || is_base_input {
+            step.amount_out = get_delta_amount_1_unsigned(next, current, liquidity, false)
+                .ok_or(QuoteError::MathOverflow)?;
+        }
+    } else {
+        if !reached_target || !is_base_input {
+            step.amount_in = get_delta_amount_1_unsigned(current, next, liquidity, true)
+                .ok_or(QuoteError::MathOverflow)?;
+        }
+        if !reached_target || is_base_input {
+            step.amount_out = get_delta_amount_0_unsigned(current, next, liquidity, false)
+                .ok_or(QuoteError::MathOverflow)?;
+        }
+    }
+    if !is_base_input && step.amount_out > amount_remaining {
+        step.amount_out = amount_remaining;
+    }
+    step.fee_amount = if is_base_input && step.sqrt_price_next_x64 != sqrt_price_target_x64 {
+        // the range wasn't used up, whatever input is left goes to fees
+        amount_remaining - step.amount_in
+    } else {
+        to_u64(
+            mul_div_ceil(
+                step.amount_in.into(),
+                fee_rate.into(),
+                fee_complement.into(),
+            )
+            .ok_or(QuoteError::MathOverflow)?,
+        )
+        .ok_or(QuoteError::MathOverflow)?
+    };
+    Ok(step)
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SwapResult {
+    pub amount_in: u64,
+    pub amount_out: u64,
+    pub fee_amount: u64,
+    pub sqrt_price_x64: u128,
+    pub tick: i32,
+}
+
+/// Simulates a swap the way the program's `swap_internal` does, crossing initialized ticks
+/// found in `tick_arrays`. The arrays must be the pool's initialized ones in swap order.
+pub fn simulate_swap(
+    pool: &ClmmPoolState,
+    extension: Option<&TickArrayBitmapExtension>,
+    tick_arrays: &[TickArrayState],
+    trade_fee_rate: u32,
+    amount_specified: u64,
+    zero_for_one: bool,
+    is_base_input: bool,
+) -> Result<SwapResult, QuoteError> {
+    if amount_specified == 0 {
+        return Err(QuoteError::InsufficientLiquidity);
+    }
+    let sqrt_price_limit_x64 = if zero_for_one {
+        MIN_SQRT_PRICE_X64 + 1
+    } else {
+        MAX_SQRT_PRICE_X64 - 1
+    };
+    let (mut is_match_pool_current_tick_array, mut current_start_index) = pool
+        .first_initialized_tick_array(extension, zero_for_one)
+        .ok_or(QuoteError::InsufficientLiquidity)?;
+
+    let mut tick_arrays = tick_arrays.iter().collect::<VecDeque<_>>();
+    let mut tick_array_current = loop {
+        let tick_array = tick_arrays
+            .pop_front()
+            .ok_or(QuoteError::InsufficientLiquidity)?;
+        if tick_array.start_tick_index == current_start_index {
+            break tick_array;
+        }
+    };
+
+    let mut amount_remaining = amount_specified;
+    let mut amount_calculated = 0u64;
+    let mut fee_amount = 0u64;
+    let mut sqrt_price_x64 = pool.sqrt_price_x64;
+    let mut tick = pool.tick_current;
+    let mut liquidity = pool.liquidity;
+    while amount_remaining != 0
+        && sqrt_price_x64 != sqrt_price_limit_x64
+        && tick < MAX_TICK
+        && tick > MIN_TICK
+    {
+        let sqrt_price_start_x64 = sqrt_price_x64;
+        let mut next_tick = tick_array_current
+            .next_initialized_tick(tick, pool.tick_spacing, zero_for_one)
+            .copied();
+        if next_tick.is_none() && !is_match_pool_current_tick_array {
+            is_match_pool_current_tick_array = true;
+            next_tick = tick_array_current
+                .first_initialized_tick(zero_for_one)
+                .copied();
+        }
+        let next_tick = match next_tick {
+            Some(next_tick) => next_tick,
+            None => {
+                // move on to the next initialized tick array
+                let next_start_index = pool
+                    .next_initialized_tick_array_start_index(
+                        extension,
+                        current_start_index,
+                        zero_for_one,
+                    )
+                    .ok_or(QuoteError::InsufficientLiquidity)?;
+                while tick_array_current.start_tick_index != next_start_index {
+                    tick_array_current = tick_arrays
+                        .pop_front()
+                        .ok_or(QuoteError::InsufficientLiquidity)?;
+                }
+                current_start_index = next_start_index;
+                *tick_array_current
+                    .first_initialized_tick(zero_for_one)
+                    .ok_or(QuoteError::InsufficientLiquidity)?
+            }
+        };
+
+        let tick_next = next_tick.tick.clamp(MIN_TICK, MAX_TICK);
+        let sqrt_price_next_x64 = get_sqrt_price_at_tick(tick_next)?;
+        let target_price = if (zero_for_one && sqrt_price_next_x64 < sqrt_price_limit_x64)
+            || (!zero_for_one && sqrt_price_next_x64 > sqrt_price_limit_x64)
+        {
+            sqrt_price_limit_x64
+        } else {
+            sqrt_price_next_x64
+        };
+        let step = compute_swap_step(
+            sqrt_price_x64,
+            target_price,
+            liquidity,
+            amount_remaining,
+            trade_fee_rate,
+            is_base_input,
+            zero_for_one,
+        )?;
+        sqrt_price_x64 = step.sqrt_price_next_x64;
+        fee_amount = fee_amount
+            .checked_add(step.fee_amount)
+            .ok_or(QuoteError::MathOverflow)?;
+        if is_base_input {
+            amount_remaining = step
+                .amount_in
+                .checked_add(step.fee_amount)
+                .and_then(|spent| amount_remaining.checked_sub(spent))
+                .ok_or(QuoteError::MathOverflow)?;
+            amount_calculated = amount_calculated
+                .checked_add(step.amount_out)
+                .ok_or(QuoteError::MathOverflow)?;
+        } else {
+            amount_remaining = amount_remaining
+                .checked_sub(step.amount_out)
+                .ok_or(QuoteError::MathOverflow)?;
+            amount_calculated = step
+                .amount_in
+                .checked_add(step.fee_amount)
+                .and_then(|spent| amount_calculated.checked_add(spent))
+                .ok_or(QuoteError::MathOverflow)?;
+        }
+
+        if sqrt_price_x64 == sqrt_price_next_x64 {
+            // crossed into the next range
+            if next_tick.is_initialized() {
+                let liquidity_net = if zero_for_one {
+                    -next_tick.liquidity_net
+                } else {
+                    next_tick.liquidity_net
+                };
+                liquidity = add_delta(liquidity, liquidity_net)?;
+            }
+            tick = if zero_for_one {
+                tick_next - 1
+            } else {
+                tick_next
+            };
+        } else if sqrt_price_x64 != sqrt_price_start_x64 {
+            tick = get_tick_at_sqrt_price(sqrt_price_x64)?;
+        }
+    }
+
+    // partial fills are not worth copying
+    if amount_remaining != 0 {
+        return Err(QuoteError::InsufficientLiquidity);
+    }
+    let (amount_in, amount_out) = if is_base_input {
+        (amount_specified, amount_calculated)
+    } else {
+        (amount_calculated, amount_specified)
+    };
+    Ok(SwapResult {
+        amount_in,
+        amount_out,
+        fee_amount,
+        sqrt_price_x64,
+        tick,
+    })
+}
+
+fn add_delta(liquidity: u128, delta: i128) -> Result<u128, QuoteError> {
+    if delta < 0 {
+        liquidity.checked_sub(delta.unsigned_abs())
+    } else {
+        liquidity.checked_add(delta as u128)
+    }
+    .ok_or(QuoteError::MathOverflow)
+}
+
+/// Start indexes of the first `count` initialized tick arrays a swap would read
+pub fn swap_tick_array_start_indexes(
+    pool: &ClmmPoolState,
+    extension: Option<&TickArrayBitmapExtension>,
+    zero_for_one: bool,
+    count: usize,
+) -> Vec<i32> {
+    let Some((_, mut start_index)) = pool.first_initialized_tick_array(extension, zero_for_one)
+    else {
+        return vec![];
+    };
+    let mut start_indexes = vec![start_index];
+    while start_indexes.len() < count {
+        match pool.next_initialized_tick_array_start_index(extension, start_index, zero_for_one) {
+            Some(next) => {
+                start_indexes.push(next);
+                start_index = next;
+            }
+            None => break,
+        }
+    }
+    start_indexes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raydium::clmm_types::{TICK_ARRAY_SIZE, TickState};
+
+    #[test]
+    fn tick_math_matches_the_program_bounds() {
+        assert_eq!(
+            get_sqrt_price_at_tick(MIN_TICK).unwrap(),
+            MIN_SQRT_PRICE_X64
+        );
+        assert_eq!(
+            get_sqrt_price_at_tick(MAX_TICK).unwrap(),
+            MAX_SQRT_PRICE_X64
+        );
+        assert_eq!(get_sqrt_price_at_tick(0).unwrap(), 1 << 64);
+        for tick in [-443_635, -100_000, -1, 1, 7_777, 100_000, 443_635] {
+            let sqrt_price = get_sqrt_price_at_tick(tick).unwrap();
+            assert_eq!(get_tick_at_sqrt_price(sqrt_price).unwrap(), tick);
+            assert_eq!(get_tick_at_sqrt_price(sqrt_price - 1).unwrap(), tick - 1);
+        }
+    }
+
+    #[test]
+    fn swap_step_charges_the_fee_on_top_of_the_input() {
+        let liquidity = 1_000_000_000_000u128;
+        let target = get_sqrt_price_at_tick(-1_000).unwrap();
+        let step =
+            compute_swap_step(1 << 64, target, liquidity, 1_000_000, 2_500, true, true).unwrap();
+        // the range is deep enough to take the whole input
+        assert_ne!(step.sqrt_price_next_x64, target);
+        assert_eq!(step.amount_in + step.fee_amount, 1_000_000);
+        assert!(step.fee_amount >= 2_500);
+        // just under 1:1 at this price
+        assert!(step.amount_out < step.amount_in && step.amount_out > 990_000);
+
+        let exact_out = compute_swap_step(
+            1 << 64,
+            target,
+            liquidity,
+            step.amount_out,
+            2_500,
+            false,
+            true,
+        )
+        .unwrap();
+        assert_eq!(exact_out.amount_out, step.amount_out);
+        assert!(exact_out.amount_in <= step.amount_in);
+    }
+
+    fn tick_array(start_tick_index: i32, ticks: &[(i32, i128)]) -> TickArrayState {
+        let mut array = TickArrayState {
+            pool_id: Default::default(),
+            start_tick_index,
+            ticks: [TickState::default(); TICK_ARRAY_SIZE as usize],
+            initialized_tick_count: ticks.len() as u8,
+            recent_epoch: 0,
+            padding: [0; 107],
+        };
+        for (tick, liquidity_net) in ticks {
+            array.ticks[(tick - start_tick_index) as usize] = TickState {
+                tick: *tick,
+                liquidity_net: *liquidity_net,
+                liquidity_gross: liquidity_net.unsigned_abs(),
+                ..Default::default()
+            };
+        }
+        array
+    }
+
+    #[test]
+    fn swap_crosses_initialized_ticks_into_the_next_tick_array() {
+        let liquidity = 1_000_000_000_000i128;
+        // one position over [-30, 30] and one over [5, 30], the price sits at tick 10
+        let mut pool = ClmmPoolState {
+            tick_spacing: 1,
+            tick_current: 10,
+            sqrt_price_x64: get_sqrt_price_at_tick(10).unwrap(),
+            liquidity: 2 * liquidity as u128,
+            ..Default::default()
+        };
+        // arrays starting at -60 and 0
+        pool.tick_array_bitmap[7] = 1 << 63;
+        pool.tick_array_bitmap[8] = 1;
+        let tick_arrays = [
+            tick_array(0, &[(5, liquidity), (30, -2 * liquidity)]),
+            tick_array(-60, &[(-30, liquidity)]),
+        ];
+
+        let swap =
+            simulate_swap(&pool, None, &tick_arrays, 2_500, 1_000_000_000, true, true).unwrap();
+        // past tick 5 with half the liquidity, ending in the second array
+        assert!((-30..0).contains(&swap.tick));
+        assert_eq!(swap.amount_in, 1_000_000_000);
+        assert!(swap.amount_out > 990_000_000 && swap.amount_out < swap.amount_in);
+        // the first array alone runs out of liquidity
+        assert!(matches!(
+            simulate_swap(
+                &pool,
+                None,
+                &tick_arrays[..1],
+                2_500,
+                1_000_000_000,
+                true,
+                true
+            ),
+            Err(QuoteError::InsufficientLiquidity)
+        ));
+    }
+}
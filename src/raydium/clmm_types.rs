@@ -0,0 +1,556 @@
+use crate::config::RAYDIUM_CLMM_PROGRAM_ID;
+use crate::raydium::clmm_math::{MAX_TICK, MIN_TICK, U512, U1024};
+use borsh::BorshDeserialize;
+use solana_sdk::pubkey::Pubkey;
+
+/// Anchor discriminators of the CLMM accounts and instructions we read
+pub const POOL_STATE_DISCRIMINATOR: [u8; 8] = [247, 237, 227, 245, 215, 195, 222, 70];
+pub const AMM_CONFIG_DISCRIMINATOR: [u8; 8] = [218, 244, 33, 104, 203, 203, 43, 111];
+pub const TICK_ARRAY_DISCRIMINATOR: [u8; 8] = [192, 155, 85, 205, 49, 249, 129, 42];
+pub const TICK_ARRAY_BITMAP_EXTENSION_DISCRIMINATOR: [u8; 8] =
+    [60, 150, 36, 219, 97, 128, 139, 153];
+pub const SWAP_V2_DISCRIMINATOR: [u8; 8] = [43, 4, 237, 11, 26, 201, 30, 98];
+
+pub const TICK_ARRAY_SEED: &[u8] = b"tick_array";
+pub const TICK_ARRAY_BITMAP_EXTENSION_SEED: &[u8] = b"pool_tick_array_bitmap_extension";
+
+/// Ticks per tick array account
+pub const TICK_ARRAY_SIZE: i32 = 60;
+/// Tick arrays covered by one 512 bit bitmap, the pool's own or one of the extension's
+pub const TICK_ARRAY_BITMAP_SIZE: i32 = 512;
+/// Bitmaps in each direction of the extension account
+const EXTENSION_TICK_ARRAY_BITMAP_SIZE: usize = 14;
+
+/// Fee rates are parts per million
+pub const FEE_RATE_DENOMINATOR: u32 = 1_000_000;
+
+/// Bit of `ClmmPoolState::status` that disables swaps when set
+const SWAP_DISABLED_BIT: u8 = 1 << 4;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshDeserialize)]
+pub struct RewardInfo {
+    pub reward_state: u8,
+    pub open_time: u64,
+    pub end_time: u64,
+    pub last_update_time: u64,
+    pub emissions_per_second_x64: u128,
+    pub reward_total_emissioned: u64,
+    pub reward_claimed: u64,
+    pub token_mint: Pubkey,
+    pub token_vault: Pubkey,
+    pub authority: Pubkey,
+    pub reward_growth_global_x64: u128,
+}
+
+/// On-chain `PoolState` of the CLMM program, without the discriminator.
+/// The account is packed, borsh reads it field by field.
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshDeserialize)]
+pub struct ClmmPoolState {
+    pub bump: [u8; 1],
+    pub amm_config: Pubkey,
+    pub owner: Pubkey,
+    pub token_mint_0: Pubkey,
+    pub token_mint_1: Pubkey,
+    pub token_vault_0: Pubkey,
+    pub token_vault_1: Pubkey,
+    pub observation_key: Pubkey,
+    pub mint_decimals_0: u8,
+    pub mint_decimals_1: u8,
+    pub tick_spacing: u16,
+    /// Liquidity in range of the current tick
+    pub liquidity: u128,
+    /// Square root of the token 1 per token 0 price, Q64.64
+    pub sqrt_price_x64: u128,
+    pub tick_current: i32,
+    pub padding3: u16,
+    pub padding4: u16,
+    pub fee_growth_global_0_x64: u128,
+    pub fee_growth_global_1_x64: u128,
+    pub protocol_fees_token_0: u64,
+    pub protocol_fees_token_1: u64,
+    pub swap_in_amount_token_0: u128,
+    pub swap_out_amount_token_1: u128,
+    pub swap_in_amount_token_1: u128,
+    pub swap_out_amount_token_0: u128,
+    /// Bit 4 disables swaps
+    pub status: u8,
+    pub padding: [u8; 7],
+    pub reward_infos: [RewardInfo; 3],
+    /// Initialized tick arrays around tick 0, one bit each
+    pub tick_array_bitmap: [u64; 16],
+    pub total_fees_token_0: u64,
+    pub total_fees_claimed_token_0: u64,
+    pub total_fees_token_1: u64,
+    pub total_fees_claimed_token_1: u64,
+    pub fund_fees_token_0: u64,
+    pub fund_fees_token_1: u64,
+    /// Unix timestamp after which swaps are allowed
+    pub open_time: u64,
+    pub recent_epoch: u64,
+    pub padding1: [u64; 24],
+    pub padding2: [u64; 32],
+}
+
+/// On-chain `AmmConfig` of the CLMM program, without the discriminator
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshDeserialize)]
+pub struct ClmmAmmConfig {
+    pub bump: u8,
+    pub index: u16,
+    pub owner: Pubkey,
+    pub protocol_fee_rate: u32,
+    /// Charged on the input, parts per million
+    pub trade_fee_rate: u32,
+    pub tick_spacing: u16,
+    pub fund_fee_rate: u32,
+    pub padding_u32: u32,
+    pub fund_owner: Pubkey,
+    pub padding: [u64; 3],
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshDeserialize)]
+pub struct TickState {
+    pub tick: i32,
+    /// Liquidity added when crossing the tick left to right
+    pub liquidity_net: i128,
+    pub liquidity_gross: u128,
+    pub fee_growth_outside_0_x64: u128,
+    pub fee_growth_outside_1_x64: u128,
+    pub reward_growths_outside_x64: [u128; 3],
+    pub padding: [u32; 13],
+}
+
+impl TickState {
+    pub fn is_initialized(&self) -> bool {
+        self.liquidity_gross != 0
+    }
+}
+
+/// On-chain `TickArrayState` of the CLMM program, without the discriminator
+#[derive(Clone, Debug, PartialEq, BorshDeserialize)]
+pub struct TickArrayState {
+    pub pool_id: Pubkey,
+    pub start_tick_index: i32,
+    pub ticks: [TickState; TICK_ARRAY_SIZE as usize],
+    pub initialized_tick_count: u8,
+    pub recent_epoch: u64,
+    pub padding: [u8; 107],
+}
+
+impl TickArrayState {
+    /// Ticks covered by one tick array
+    pub fn tick_count(tick_spacing: u16) -> i32 {
+        TICK_ARRAY_SIZE * i32::from(tick_spacing)
+    }
+
+    /// Start of the tick array holding `tick_index`
+    pub fn get_array_start_index(tick_index: i32, tick_spacing: u16) -> i32 {
+        let ticks_in_array = Self::tick_count(tick_spacing);
+        let mut start = tick_index / ticks_in_array;
+        if tick_index < 0 && tick_index % ticks_in_array != 0 {
+            start -= 1;
+        }
+        start * ticks_in_array
+    }
+
+    /// Next initialized tick of this array from `current_tick_index` in the swap direction.
+    /// Going down the current tick itself counts.
+    pub fn next_initialized_tick(
+        &self,
+        current_tick_index: i32,
+        tick_spacing: u16,
+        zero_for_one: bool,
+    ) -> Option<&TickState> {
+        if Self::get_array_start_index(current_tick_index, tick_spacing) != self.start_tick_index {
+            return None;
+        }
+        let offset = (current_tick_index - self.start_tick_index) / i32::from(tick_spacing);
+        if zero_for_one {
+            self.ticks[..=offset as usize]
+                .iter()
+                .rev()
+                .find(|tick| tick.is_initialized())
+        } else {
+            self.ticks[offset as usize + 1..]
+                .iter()
+                .find(|tick| tick.is_initialized())
+        }
+    }
+
+    /// First initialized tick of this array in the swap direction
+    pub fn first_initialized_tick(&self, zero_for_one: bool) -> Option<&TickState> {
+        if zero_for_one {
+            self.ticks.iter().rev().find(|tick| tick.is_initialized())
+        } else {
+            self.ticks.iter().find(|tick| tick.is_initialized())
+        }
+    }
+}
+
+/// On-chain `TickArrayBitmapExtension` of the CLMM program, without the discriminator.
+/// Tracks initialized tick arrays beyond the pool's own bitmap.
+#[derive(Clone, Debug, PartialEq, BorshDeserialize)]
+pub struct TickArrayBitmapExtension {
+    pub pool_id: Pubkey,
+    pub positive_tick_array_bitmap: [[u64; 8]; EXTENSION_TICK_ARRAY_BITMAP_SIZE],
+    pub negative_tick_array_bitmap: [[u64; 8]; EXTENSION_TICK_ARRAY_BITMAP_SIZE],
+}
+
+/// Largest tick covered by one bitmap
+pub fn max_tick_in_tick_array_bitmap(tick_spacing: u16) -> i32 {
+    i32::from(tick_spacing) * TICK_ARRAY_SIZE * TICK_ARRAY_BITMAP_SIZE
+}
+
+/// Tick range of the bitmap holding `tick_array_start_index`
+fn bitmap_tick_boundary(tick_array_start_index: i32, tick_spacing: u16) -> (i32, i32) {
+    let ticks_in_one_bitmap = max_tick_in_tick_array_bitmap(tick_spacing);
+    let mut m = tick_array_start_index.abs() / ticks_in_one_bitmap;
+    if tick_array_start_index < 0 && tick_array_start_index.abs() % ticks_in_one_bitmap != 0 {
+        m += 1;
+    }
+    let min_value = ticks_in_one_bitmap * m;
+    if tick_array_start_index < 0 {
+        (-min_value, -min_value + ticks_in_one_bitmap)
+    } else {
+        (min_value, min_value + ticks_in_one_bitmap)
+    }
+}
+
+/// Bit of a tick array in the pool's 1024 bit bitmap
+fn compressed_bit(tick_array_start_index: i32, tick_spacing: u16) -> i32 {
+    let multiplier = TickArrayState::tick_count(tick_spacing);
+    let mut compressed = tick_array_start_index / multiplier + TICK_ARRAY_BITMAP_SIZE;
+    if tick_array_start_index < 0 && tick_array_start_index % multiplier != 0 {
+        compressed -= 1;
+    }
+    compressed.abs()
+}
+
+/// Next initialized tick array after `last_tick_array_start_index` in the pool's own bitmap.
+/// Returns the bitmap's edge when none is found.
+fn next_initialized_tick_array_in_pool_bitmap(
+    bitmap: U1024,
+    last_tick_array_start_index: i32,
+    tick_spacing: u16,
+    zero_for_one: bool,
+) -> (bool, i32) {
+    let tick_boundary = max_tick_in_tick_array_bitmap(tick_spacing);
+    let tick_count = TickArrayState::tick_count(tick_spacing);
+    let next_start_index = if zero_for_one {
+        last_tick_array_start_index - tick_count
+    } else {
+        last_tick_array_start_index + tick_count
+    };
+    if next_start_index < -tick_boundary || next_start_index >= tick_boundary {
+        return (false, last_tick_array_start_index);
+    }
+    let bit_pos = compressed_bit(next_start_index, tick_spacing);
+    if zero_for_one {
+        // search from higher bits to lower ones
+        let offset_bitmap = bitmap << (1024 - bit_pos - 1) as usize;
+        if offset_bitmap.is_zero() {
+            (false, -tick_boundary)
+        } else {
+            let next_bit = offset_bitmap.leading_zeros() as i32;
+            (
+                true,
+                (bit_pos - next_bit - TICK_ARRAY_BITMAP_SIZE) * tick_count,
+            )
+        }
+    } else {
+        let offset_bitmap = bitmap >> bit_pos as usize;
+        if offset_bitmap.is_zero() {
+            (false, tick_boundary - tick_count)
+        } else {
+            let next_bit = offset_bitmap.trailing_zeros() as i32;
+            (
+                true,
+                (bit_pos + next_bit - TICK_ARRAY_BITMAP_SIZE) * tick_count,
+            )
+        }
+    }
+}
+
+impl TickArrayBitmapExtension {
+    fn bitmap_offset(tick_index: i32, tick_spacing: u16) -> Option<usize> {
+        let ticks_in_one_bitmap = max_tick_in_tick_array_bitmap(tick_spacing);
+        // ticks inside the pool's own bitmap have no extension bitmap
+        if tick_index >= -ticks_in_one_bitmap && tick_index < ticks_in_one_bitmap {
+            return None;
+        }
+        let mut offset = tick_index.abs() / ticks_in_one_bitmap - 1;
+        if tick_index < 0 && tick_index.abs() % ticks_in_one_bitmap == 0 {
+            offset -= 1;
+        }
+        usize::try_from(offset)
+            .ok()
+            .filter(|offset| *offset < EXTENSION_TICK_ARRAY_BITMAP_SIZE)
+    }
+
+    fn bitmap(&self, tick_index: i32, tick_spacing: u16) -> Option<U512> {
+        let offset = Self::bitmap_offset(tick_index, tick_spacing)?;
+        Some(U512(if tick_index < 0 {
+            self.negative_tick_array_bitmap[offset]
+        } else {
+            self.positive_tick_array_bitmap[offset]
+        }))
+    }
+
+    fn tick_array_offset_in_bitmap(tick_array_start_index: i32, tick_spacing: u16) -> i32 {
+        let m = tick_array_start_index.abs() % max_tick_in_tick_array_bitmap(tick_spacing);
+        let mut offset = m / TickArrayState::tick_count(tick_spacing);
+        if tick_array_start_index < 0 && m != 0 {
+            offset = TICK_ARRAY_BITMAP_SIZE - offset;
+        }
+        offset
+    }
+
+    pub fn is_tick_array_initialized(
+        &self,
+        tick_array_start_index: i32,
+        tick_spacing: u16,
+    ) -> bool {
+        self.bitmap(tick_array_start_index, tick_spacing)
+            .is_some_and(|bitmap| {
+                bitmap.bit(
+                    Self::tick_array_offset_in_bitmap(tick_array_start_index, tick_spacing)
+                        as usize,
+                )
+            })
+    }
+
+    /// Next initialized tick array after `last_tick_array_start_index` within the extension
+    /// bitmap holding the array after it. Returns that bitmap's edge when none is found.
+    fn next_initialized_tick_array_from_one_bitmap(
+        &self,
+        last_tick_array_start_index: i32,
+        tick_spacing: u16,
+        zero_for_one: bool,
+    ) -> (bool, i32) {
+        let tick_count = TickArrayState::tick_count(tick_spacing);
+        let next_start_index = if zero_for_one {
+            last_tick_array_start_index - tick_count
+        } else {
+            last_tick_array_start_index + tick_count
+        };
+        if next_start_index < TickArrayState::get_array_start_index(MIN_TICK, tick_spacing)
+            || next_start_index > TickArrayState::get_array_start_index(MAX_TICK, tick_spacing)
+        {
+            return (false, next_start_index);
+        }
+        let Some(bitmap) = self.bitmap(next_start_index, tick_spacing) else {
+            return (false, next_start_index);
+        };
+        let (min_boundary, max_boundary) = bitmap_tick_boundary(next_start_index, tick_spacing);
+        let offset = Self::tick_array_offset_in_bitmap(next_start_index, tick_spacing);
+        if zero_for_one {
+            let offset_bitmap = bitmap << (TICK_ARRAY_BITMAP_SIZE - 1 - offset) as usize;
+            if offset_bitmap.is_zero() {
+                (false, min_boundary)
+            } else {
+                let next_bit = offset_bitmap.leading_zeros() as i32;
+                (true, next_start_index - next_bit * tick_count)
+            }
+        } else {
+            let offset_bitmap = bitmap >> offset as usize;
+            if offset_bitmap.is_zero() {
+                (false, max_boundary - tick_count)
+            } else {
+                let next_bit = offset_bitmap.trailing_zeros() as i32;
+                (true, next_start_index + next_bit * tick_count)
+            }
+        }
+    }
+}
+
+impl ClmmPoolState {
+    pub fn swap_enabled(&self) -> bool {
+        self.status & SWAP_DISABLED_BIT == 0
+    }
+
+    /// Tick array start indexes the pool's own bitmap covers, upper bound exclusive
+    fn tick_array_start_index_range(&self) -> (i32, i32) {
+        let mut max_boundary = max_tick_in_tick_array_bitmap(self.tick_spacing);
+        let mut min_boundary = -max_boundary;
+        if max_boundary > MAX_TICK {
+            max_boundary = TickArrayState::get_array_start_index(MAX_TICK, self.tick_spacing)
+                + TickArrayState::tick_count(self.tick_spacing);
+        }
+        if min_boundary < MIN_TICK {
+            min_boundary = TickArrayState::get_array_start_index(MIN_TICK, self.tick_spacing);
+        }
+        (min_boundary, max_boundary)
+    }
+
+    fn is_overflow_default_tick_array_bitmap(&self, tick_index: i32) -> bool {
+        let (min_boundary, max_boundary) = self.tick_array_start_index_range();
+        let start_index = TickArrayState::get_array_start_index(tick_index, self.tick_spacing);
+        start_index >= max_boundary || start_index < min_boundary
+    }
+
+    /// Start of the first initialized tick array a swap reads, and whether it holds the
+    /// current tick. None when there is no liquidity in that direction.
+    pub fn first_initialized_tick_array(
+        &self,
+        extension: Option<&TickArrayBitmapExtension>,
+        zero_for_one: bool,
+    ) -> Option<(bool, i32)> {
+        let start_index =
+            TickArrayState::get_array_start_index(self.tick_current, self.tick_spacing);
+        let initialized = if self.is_overflow_default_tick_array_bitmap(self.tick_current) {
+            extension?.is_tick_array_initialized(start_index, self.tick_spacing)
+        } else {
+            U1024(self.tick_array_bitmap)
+                .bit(compressed_bit(start_index, self.tick_spacing) as usize)
+        };
+        if initialized {
+            return Some((true, start_index));
+        }
+        self.next_initialized_tick_array_start_index(extension, start_index, zero_for_one)
+            .map(|start_index| (false, start_index))
+    }
+
+    /// Next initialized tick array after `last_tick_array_start_index` in the swap direction
+    pub fn next_initialized_tick_array_start_index(
+        &self,
+        extension: Option<&TickArrayBitmapExtension>,
+        last_tick_array_start_index: i32,
+        zero_for_one: bool,
+    ) -> Option<i32> {
+        let mut last_tick_array_start_index =
+            TickArrayState::get_array_start_index(last_tick_array_start_index, self.tick_spacing);
+        loop {
+            let (found, start_index) = next_initialized_tick_array_in_pool_bitmap(
+                U1024(self.tick_array_bitmap),
+                last_tick_array_start_index,
+                self.tick_spacing,
+                zero_for_one,
+            );
+            if found {
+                return Some(start_index);
+            }
+            last_tick_array_start_index = start_index;
+
+            let (found, start_index) = extension?.next_initialized_tick_array_from_one_bitmap(
+                last_tick_array_start_index,
+                self.tick_spacing,
+                zero_for_one,
+            );
+            if found {
+                return Some(start_index);
+            }
+            last_tick_array_start_index = start_index;
+            if !(MIN_TICK..=MAX_TICK).contains(&last_tick_array_start_index) {
+                return None;
+            }
+        }
+    }
+}
+
+pub fn tick_array_address(pool_id: &Pubkey, start_tick_index: i32) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            TICK_ARRAY_SEED,
+            pool_id.as_ref(),
+            &start_tick_index.to_be_bytes(),
+        ],
+        &Pubkey::from_str_const(RAYDIUM_CLMM_PROGRAM_ID),
+    )
+    .0
+}
+
+pub fn tick_array_bitmap_extension_address(pool_id: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[TICK_ARRAY_BITMAP_EXTENSION_SEED, pool_id.as_ref()],
+        &Pubkey::from_str_const(RAYDIUM_CLMM_PROGRAM_ID),
+    )
+    .0
+}
+
+/// A `swap_v2` instruction found in a transaction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClmmSwapInstruction {
+    pub pool: Pubkey,
+    pub input_mint: Pubkey,
+    pub output_mint: Pubkey,
+    /// Input for base input swaps, output for base output ones
+    pub amount: u64,
+    /// Minimum output for base input swaps, maximum input for base output ones
+    pub other_amount_threshold: u64,
+    pub base_input: bool,
+}
+
+impl ClmmSwapInstruction {
+    /// `accounts` are the instruction's accounts in order. None if this isn't a `swap_v2`
+    pub fn decode(data: &[u8], accounts: &[Pubkey]) -> Option<Self> {
+        let args = data.strip_prefix(&SWAP_V2_DISCRIMINATOR)?;
+        let (amount, rest) = args.split_first_chunk::<8>()?;
+        let (other_amount_threshold, rest) = rest.split_first_chunk::<8>()?;
+        // sqrt_price_limit_x64 then is_base_input
+        let base_input = *rest.get(16)? != 0;
+        // payer, amm_config, pool_state, input/output token accounts, input/output vaults,
+        // observation, token program, token 2022 program, memo program, input/output mints
+        if accounts.len() < 13 {
+            return None;
+        }
+        Some(Self {
+            pool: accounts[2],
+            input_mint: accounts[11],
+            output_mint: accounts[12],
+            amount: u64::from_le_bytes(*amount),
+            other_amount_threshold: u64::from_le_bytes(*other_amount_threshold),
+            base_input,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClmmPoolState, TickArrayBitmapExtension, TickArrayState};
+    use borsh::BorshDeserialize;
+
+    #[test]
+    fn layouts_match_the_account_sizes() {
+        // account sizes without the 8 byte discriminator
+        let mut pool = &[0u8; 1536][..];
+        ClmmPoolState::deserialize(&mut pool).unwrap();
+        let mut tick_array = &[0u8; 10232][..];
+        TickArrayState::deserialize(&mut tick_array).unwrap();
+        let mut extension = &[0u8; 1824][..];
+        TickArrayBitmapExtension::deserialize(&mut extension).unwrap();
+        assert!(pool.is_empty() && tick_array.is_empty() && extension.is_empty());
+    }
+
+    #[test]
+    fn walks_the_pool_bitmap_in_both_directions() {
+        assert_eq!(TickArrayState::get_array_start_index(-1, 10), -600);
+        assert_eq!(TickArrayState::get_array_start_index(600, 10), 600);
+
+        let mut pool = ClmmPoolState {
+            tick_spacing: 10,
+            tick_current: 5,
+            ..Default::default()
+        };
+        // arrays starting at -1200, 0 and 1800, bit 512 is tick array 0
+        for start_index in [-1200i32, 0, 1800] {
+            let bit = (start_index / 600 + 512) as usize;
+            pool.tick_array_bitmap[bit / 64] |= 1 << (bit % 64);
+        }
+        assert_eq!(
+            pool.first_initialized_tick_array(None, true),
+            Some((true, 0))
+        );
+        assert_eq!(
+            pool.next_initialized_tick_array_start_index(None, 0, true),
+            Some(-1200)
+        );
+        assert_eq!(
+            pool.next_initialized_tick_array_start_index(None, 0, false),
+            Some(1800)
+        );
+        // past the last array the search runs into the missing extension
+        assert_eq!(
+            pool.next_initialized_tick_array_start_index(None, 1800, false),
+            None
+        );
+    }
+}
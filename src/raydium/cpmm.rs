@@ -0,0 +1,636 @@
+use crate::config::{RAYDIUM_CPMM_PROGRAM_ID, WSOL};
+use crate::lookup_table::fetch_lookup_tables;
+use crate::raydium::amm::{
+    amount_with_slippage, compute_budget_instructions, price_impact_bps, ui_price,
+    wrap_sol_instructions,
+};
+use crate::raydium::api_v3::response::{ApiV3PoolsPage, ApiV3StandardPool};
+use crate::raydium::api_v3::{ApiV3Client, PoolFetchParams, PoolSort, PoolSortOrder, PoolType};
+use crate::raydium::cpmm_types::{
+    AMM_CONFIG_DISCRIMINATOR, AUTH_SEED, CpmmAmmConfig, CpmmPoolState, FEE_RATE_DENOMINATOR,
+    POOL_STATE_DISCRIMINATOR, SWAP_BASE_INPUT_DISCRIMINATOR, SWAP_BASE_OUTPUT_DISCRIMINATOR,
+    decode_anchor_account,
+};
+use crate::raydium::math::{CheckedCeilDiv, U128};
+use crate::raydium::quote_error::QuoteError;
+use crate::raydium::types::{
+    CpmmQuote, QuoteSummary, RaydiumAmmExecutorOpts, SwapConfig, SwapConfigOverrides, SwapInput,
+};
+use crate::router::{Quote, SwapVenue, VenueQuote};
+use async_trait::async_trait;
+use log::debug;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::address_lookup_table::AddressLookupTableAccount;
+use solana_sdk::clock::Clock;
+use solana_sdk::instruction::{AccountMeta, Instruction};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::sysvar;
+use spl_associated_token_account_client::address::get_associated_token_address_with_program_id;
+use spl_associated_token_account_client::instruction::create_associated_token_account_idempotent;
+use spl_token_2022::extension::StateWithExtensions;
+use std::sync::Arc;
+
+/// Quotes and builds swaps on Raydium CP-Swap pools.
+///
+/// Token-2022 transfer fees and creator fees are not modelled, the slippage tolerance absorbs them.
+#[derive(Clone)]
+pub struct RaydiumCpmm {
+    client: Arc<RpcClient>,
+    api: ApiV3Client,
+    config: SwapConfig,
+}
+
+impl RaydiumCpmm {
+    pub fn new(client: Arc<RpcClient>, config: RaydiumAmmExecutorOpts, api: ApiV3Client) -> Self {
+        let RaydiumAmmExecutorOpts {
+            priority_fee,
+            cu_limits,
+            wrap_and_unwrap_sol,
+            as_legacy_transaction,
+            lookup_table,
+            ..
+        } = config;
+        Self {
+            client,
+            api,
+            config: SwapConfig {
+                priority_fee,
+                cu_limits,
+                wrap_and_unwrap_sol,
+                as_legacy_transaction: as_legacy_transaction.or(Some(true)),
+                lookup_table,
+            },
+        }
+    }
+
+    pub async fn quote(&self, swap_input: &SwapInput) -> Result<CpmmQuote, QuoteError> {
+        if swap_input.input_token_mint == swap_input.output_token_mint {
+            return Err(QuoteError::SameMint(swap_input.input_token_mint));
+        }
+        let pool_not_found = || QuoteError::PoolNotFound {
+            input_mint: swap_input.input_token_mint,
+            output_mint: swap_input.output_token_mint,
+        };
+
+        let pool_id = match swap_input.market {
+            Some(pool_id) => pool_id,
+            None => self
+                .find_pool(&swap_input.input_token_mint, &swap_input.output_token_mint)
+                .await?
+                .ok_or_else(pool_not_found)?,
+        };
+
+        let mut accounts =
+            crate::raydium::utils::get_multiple_account_data(&self.client, &[pool_id]).await?;
+        let pool_account = accounts
+            .pop()
+            .flatten()
+            .ok_or(QuoteError::AccountMissing(pool_id))?;
+        let pool: CpmmPoolState =
+            decode_anchor_account(&pool_account.data, &POOL_STATE_DISCRIMINATOR)
+                .map_err(|e| QuoteError::bad_layout(&pool_id, e))?;
+        debug!("CPMM pool {:?}", pool);
+
+        let input_is_token_0 = if swap_input.input_token_mint == pool.token_0_mint
+            && swap_input.output_token_mint == pool.token_1_mint
+        {
+            true
+        } else if swap_input.input_token_mint == pool.token_1_mint
+            && swap_input.output_token_mint == pool.token_0_mint
+        {
+            false
+        } else {
+            return Err(pool_not_found());
+        };
+
+        let load_pubkeys = [
+            pool.amm_config,
+            pool.token_0_vault,
+            pool.token_1_vault,
+            sysvar::clock::id(),
+        ];
+        let accounts =
+            crate::raydium::utils::get_multiple_account_data(&self.client, &load_pubkeys).await?;
+        let [
+            config_account,
+            vault_0_account,
+            vault_1_account,
+            clock_account,
+        ] = <[_; 4]>::try_from(
+            load_pubkeys
+                .iter()
+                .zip(accounts)
+                .map(|(key, account)| account.ok_or(QuoteError::AccountMissing(*key)))
+                .collect::<Result<Vec<_>, _>>()?,
+        )
+        .map_err(|_| QuoteError::AccountMissing(pool_id))?;
+        let amm_config: CpmmAmmConfig =
+            decode_anchor_account(&config_account.data, &AMM_CONFIG_DISCRIMINATOR)
+                .map_err(|e| QuoteError::bad_layout(&pool.amm_config, e))?;
+        let vault_amount = |key: &Pubkey, data: &[u8]| {
+            StateWithExtensions::<spl_token_2022::state::Account>::unpack(data)
+                .map(|vault| vault.base.amount)
+                .map_err(|e| QuoteError::bad_layout(key, e))
+        };
+        let vault_0 = vault_amount(&pool.token_0_vault, &vault_0_account.data)?;
+        let vault_1 = vault_amount(&pool.token_1_vault, &vault_1_account.data)?;
+        let clock: Clock = bincode::deserialize(&clock_account.data)
+            .map_err(|e| QuoteError::bad_layout(&sysvar::clock::id(), e))?;
+
+        check_swappable(&pool_id, &pool, &clock)?;
+
+        let (reserve_0, reserve_1) = pool
+            .vault_amount_without_fee(vault_0, vault_1)
+            .ok_or(QuoteError::MathOverflow)?;
+        let (
+            input_vault,
+            output_vault,
+            input_token_program,
+            output_token_program,
+            input_mint_decimals,
+            output_mint_decimals,
+            input_reserve,
+            output_reserve,
+        ) = if input_is_token_0 {
+            (
+                pool.token_0_vault,
+                pool.token_1_vault,
+                pool.token_0_program,
+                pool.token_1_program,
+                pool.mint_0_decimals,
+                pool.mint_1_decimals,
+                reserve_0,
+                reserve_1,
+            )
+        } else {
+            (
+                pool.token_1_vault,
+                pool.token_0_vault,
+                pool.token_1_program,
+                pool.token_0_program,
+                pool.mint_1_decimals,
+                pool.mint_0_decimals,
+                reserve_1,
+                reserve_0,
+            )
+        };
+
+        let amount_specified_is_input = swap_input.mode.amount_specified_is_input();
+        let (amount_in, amount_out, fee_amount) = if amount_specified_is_input {
+            let (amount_out, fee) = swap_base_input(
+                swap_input.amount,
+                input_reserve,
+                output_reserve,
+                amm_config.trade_fee_rate,
+            )?;
+            (swap_input.amount, amount_out, fee)
+        } else {
+            let (amount_in, fee) = swap_base_output(
+                swap_input.amount,
+                input_reserve,
+                output_reserve,
+                amm_config.trade_fee_rate,
+            )?;
+            (amount_in, swap_input.amount, fee)
+        };
+        let other_amount = if amount_specified_is_input {
+            amount_out
+        } else {
+            amount_in
+        };
+        let other_amount_threshold = amount_with_slippage(
+            other_amount,
+            swap_input.slippage_bps as u64,
+            !amount_specified_is_input,
+        )?;
+
+        Ok(CpmmQuote {
+            pool: pool_id,
+            amm_config: pool.amm_config,
+            observation: pool.observation_key,
+            input_vault,
+            output_vault,
+            input_token_program,
+            output_token_program,
+            input_mint: swap_input.input_token_mint,
+            output_mint: swap_input.output_token_mint,
+            amount: swap_input.amount,
+            other_amount,
+            other_amount_threshold,
+            amount_specified_is_input,
+            input_mint_decimals,
+            output_mint_decimals,
+            input_reserve,
+            output_reserve,
+            trade_fee_rate: amm_config.trade_fee_rate,
+            open_time: pool.open_time,
+            spot_price: ui_price(
+                input_reserve,
+                input_mint_decimals,
+                output_reserve,
+                output_mint_decimals,
+            ),
+            execution_price: ui_price(
+                amount_in,
+                input_mint_decimals,
+                amount_out,
+                output_mint_decimals,
+            ),
+            price_impact_bps: price_impact_bps(
+                amount_in.saturating_sub(fee_amount),
+                amount_out,
+                input_reserve,
+                output_reserve,
+            ),
+            fee_amount,
+            minimum_out: if amount_specified_is_input {
+                other_amount_threshold
+            } else {
+                amount_out
+            },
+            slot: clock.slot,
+        })
+    }
+
+    /// Deepest CP-Swap pool for the pair, per the Raydium api
+    async fn find_pool(
+        &self,
+        input_mint: &Pubkey,
+        output_mint: &Pubkey,
+    ) -> Result<Option<Pubkey>, QuoteError> {
+        let response: ApiV3PoolsPage<ApiV3StandardPool> = self
+            .api
+            .fetch_pool_by_mints(
+                input_mint,
+                Some(output_mint),
+                &PoolFetchParams {
+                    pool_type: PoolType::Standard,
+                    pool_sort: PoolSort::Liquidity,
+                    sort_type: PoolSortOrder::Descending,
+                    page_size: 10,
+                    page: 1,
+                },
+            )
+            .await?;
+        Ok(response.pools.into_iter().find_map(|pool| {
+            ((pool.mint_a.address == *input_mint && pool.mint_b.address == *output_mint
+                || pool.mint_a.address == *output_mint && pool.mint_b.address == *input_mint)
+                && pool.program_id == Pubkey::from_str_const(RAYDIUM_CPMM_PROGRAM_ID))
+            .then_some(pool.id)
+        }))
+    }
+
+    pub async fn swap_instructions(
+        &self,
+        user: Pubkey,
+        quote: &CpmmQuote,
+        overrides: Option<&SwapConfigOverrides>,
+    ) -> anyhow::Result<Vec<Instruction>> {
+        let wrap_and_unwrap_sol = overrides
+            .and_then(|o| o.wrap_and_unwrap_sol)
+            .or(self.config.wrap_and_unwrap_sol)
+            .unwrap_or(true);
+
+        let mut instructions =
+            compute_budget_instructions(&self.client, &self.config, overrides, &quote.pool, &user)
+                .await?;
+
+        let wsol = Pubkey::from_str_const(WSOL);
+        let source = get_associated_token_address_with_program_id(
+            &user,
+            &quote.input_mint,
+            &quote.input_token_program,
+        );
+        let destination = overrides
+            .and_then(|o| o.destination_token_account)
+            .unwrap_or_else(|| {
+                get_associated_token_address_with_program_id(
+                    &user,
+                    &quote.output_mint,
+                    &quote.output_token_program,
+                )
+            });
+
+        if wrap_and_unwrap_sol && quote.input_mint == wsol {
+            let max_in = if quote.amount_specified_is_input {
+                quote.amount
+            } else {
+                quote.other_amount_threshold
+            };
+            instructions.extend(wrap_sol_instructions(&user, max_in)?);
+        }
+        if overrides
+            .and_then(|o| o.destination_token_account)
+            .is_none()
+        {
+            instructions.push(create_associated_token_account_idempotent(
+                &user,
+                &user,
+                &quote.output_mint,
+                &quote.output_token_program,
+            ));
+        }
+        instructions.push(swap_instruction(quote, &user, source, destination));
+        if wrap_and_unwrap_sol && (quote.input_mint == wsol || quote.output_mint == wsol) {
+            let wsol_account =
+                get_associated_token_address_with_program_id(&user, &wsol, &spl_token::id());
+            instructions.push(spl_token::instruction::close_account(
+                &spl_token::id(),
+                &wsol_account,
+                &user,
+                &user,
+                &[],
+            )?);
+        }
+        Ok(instructions)
+    }
+
+    /// Our own lookup table for v0 swap transactions, CP-Swap pools don't come with one.
+    /// Empty when building legacy transactions.
+    pub async fn lookup_tables(
+        &self,
+        overrides: Option<&SwapConfigOverrides>,
+    ) -> anyhow::Result<Vec<AddressLookupTableAccount>> {
+        let as_legacy_transaction = overrides
+            .and_then(|o| o.as_legacy_transaction)
+            .or(self.config.as_legacy_transaction)
+            .unwrap_or(true);
+        match self.config.lookup_table {
+            Some(lookup_table) if !as_legacy_transaction => {
+                fetch_lookup_tables(&self.client, &[lookup_table]).await
+            }
+            _ => Ok(vec![]),
+        }
+    }
+}
+
+/// Pda owning every pool's vaults
+pub fn authority() -> Pubkey {
+    Pubkey::find_program_address(
+        &[AUTH_SEED],
+        &Pubkey::from_str_const(RAYDIUM_CPMM_PROGRAM_ID),
+    )
+    .0
+}
+
+pub fn swap_instruction(
+    quote: &CpmmQuote,
+    user: &Pubkey,
+    source: Pubkey,
+    destination: Pubkey,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new_readonly(*user, true),
+        AccountMeta::new_readonly(authority(), false),
+        AccountMeta::new_readonly(quote.amm_config, false),
+        AccountMeta::new(quote.pool, false),
+        AccountMeta::new(source, false),
+        AccountMeta::new(destination, false),
+        AccountMeta::new(quote.input_vault, false),
+        AccountMeta::new(quote.output_vault, false),
+        AccountMeta::new_readonly(quote.input_token_program, false),
+        AccountMeta::new_readonly(quote.output_token_program, false),
+        AccountMeta::new_readonly(quote.input_mint, false),
+        AccountMeta::new_readonly(quote.output_mint, false),
+        AccountMeta::new(quote.observation, false),
+    ];
+    let (discriminator, first, second) = if quote.amount_specified_is_input {
+        (
+            SWAP_BASE_INPUT_DISCRIMINATOR,
+            quote.amount,
+            quote.other_amount_threshold,
+        )
+    } else {
+        (
+            SWAP_BASE_OUTPUT_DISCRIMINATOR,
+            quote.other_amount_threshold,
+            quote.amount,
+        )
+    };
+    Instruction {
+        program_id: Pubkey::from_str_const(RAYDIUM_CPMM_PROGRAM_ID),
+        accounts,
+        data: [
+            discriminator.to_vec(),
+            first.to_le_bytes().to_vec(),
+            second.to_le_bytes().to_vec(),
+        ]
+        .concat(),
+    }
+}
+
+/// Fails with the reason the program would reject a swap on this pool at `clock`
+pub fn check_swappable(
+    pool_id: &Pubkey,
+    pool: &CpmmPoolState,
+    clock: &Clock,
+) -> Result<(), QuoteError> {
+    if !pool.swap_enabled() {
+        return Err(QuoteError::SwapNotAllowed {
+            pool: *pool_id,
+            status: pool.status as u64,
+        });
+    }
+    // the program wants the block time strictly past the open time
+    if clock.unix_timestamp < 0 || clock.unix_timestamp as u64 <= pool.open_time {
+        return Err(QuoteError::NotOpenYet {
+            pool: *pool_id,
+            open_time: pool.open_time,
+            now: clock.unix_timestamp,
+        });
+    }
+    Ok(())
+}
+
+/// Trade fee on `amount`, rounded up
+fn trading_fee(amount: u64, trade_fee_rate: u64) -> Result<u64, QuoteError> {
+    let fee = U128::from(amount)
+        .checked_mul(trade_fee_rate.into())
+        .and_then(|fee| div_ceil(fee, FEE_RATE_DENOMINATOR.into()))
+        .ok_or(QuoteError::MathOverflow)?;
+    u64::try_from(fee).map_err(|_| QuoteError::MathOverflow)
+}
+
+/// Plain ceiling division. Unlike `CheckedCeilDiv` it never rounds a small quotient down to zero
+fn div_ceil(numerator: U128, denominator: U128) -> Option<U128> {
+    numerator
+        .checked_add(denominator)?
+        .checked_sub(U128::one())?
+        .checked_div(denominator)
+}
+
+/// Output and trade fee for `amount_in`, rounded like the program
+pub fn swap_base_input(
+    amount_in: u64,
+    input_reserve: u64,
+    output_reserve: u64,
+    trade_fee_rate: u64,
+) -> Result<(u64, u64), QuoteError> {
+    let fee = trading_fee(amount_in, trade_fee_rate)?;
+    let amount_in_less_fee = amount_in.checked_sub(fee).ok_or(QuoteError::MathOverflow)?;
+    let denominator = U128::from(input_reserve)
+        .checked_add(amount_in_less_fee.into())
+        .ok_or(QuoteError::MathOverflow)?;
+    let amount_out = U128::from(amount_in_less_fee)
+        .checked_mul(output_reserve.into())
+        .ok_or(QuoteError::MathOverflow)?
+        .checked_div(denominator)
+        .ok_or(QuoteError::MathOverflow)?;
+    Ok((
+        u64::try_from(amount_out).map_err(|_| QuoteError::MathOverflow)?,
+        fee,
+    ))
+}
+
+/// Input, trade fee included, and the trade fee needed to get `amount_out`, rounded like the program
+pub fn swap_base_output(
+    amount_out: u64,
+    input_reserve: u64,
+    output_reserve: u64,
+    trade_fee_rate: u64,
+) -> Result<(u64, u64), QuoteError> {
+    if amount_out >= output_reserve {
+        return Err(QuoteError::InsufficientLiquidity);
+    }
+    // the program uses the token-swap style ceil division here, not `div_ceil`
+    let amount_in_less_fee = U128::from(input_reserve)
+        .checked_mul(amount_out.into())
+        .ok_or(QuoteError::MathOverflow)?
+        .checked_ceil_div((output_reserve - amount_out).into())
+        .ok_or(QuoteError::MathOverflow)?
+        .0;
+    let amount_in = if trade_fee_rate == 0 {
+        amount_in_less_fee
+    } else {
+        let denominator = FEE_RATE_DENOMINATOR
+            .checked_sub(trade_fee_rate)
+            .ok_or(QuoteError::MathOverflow)?;
+        amount_in_less_fee
+            .checked_mul(FEE_RATE_DENOMINATOR.into())
+            .and_then(|amount| div_ceil(amount, denominator.into()))
+            .ok_or(QuoteError::MathOverflow)?
+    };
+    let amount_in = u64::try_from(amount_in).map_err(|_| QuoteError::MathOverflow)?;
+    Ok((amount_in, trading_fee(amount_in, trade_fee_rate)?))
+}
+
+impl Quote for CpmmQuote {
+    fn summary(&self) -> QuoteSummary {
+        CpmmQuote::summary(self)
+    }
+
+    fn set_slippage(&mut self, slippage_bps: u64) -> anyhow::Result<()> {
+        CpmmQuote::set_slippage(self, slippage_bps)
+    }
+}
+
+#[async_trait]
+impl SwapVenue for RaydiumCpmm {
+    fn name(&self) -> &'static str {
+        "raydium cp-swap"
+    }
+
+    fn supports(&self, input_mint: &Pubkey, output_mint: &Pubkey) -> bool {
+        input_mint != output_mint
+    }
+
+    async fn quote(&self, swap_input: &SwapInput) -> Result<VenueQuote, QuoteError> {
+        Ok(VenueQuote::new(RaydiumCpmm::quote(self, swap_input).await?))
+    }
+
+    async fn swap_instructions(
+        &self,
+        user: Pubkey,
+        quote: &VenueQuote,
+    ) -> anyhow::Result<Vec<Instruction>> {
+        let Some(quote) = quote.downcast_ref::<CpmmQuote>() else {
+            anyhow::bail!("Quote is not for a cp-swap pool");
+        };
+        RaydiumCpmm::swap_instructions(self, user, quote, None).await
+    }
+
+    fn required_accounts(&self, quote: &VenueQuote) -> Vec<Pubkey> {
+        let Some(quote) = quote.downcast_ref::<CpmmQuote>() else {
+            return vec![];
+        };
+        vec![
+            quote.pool,
+            quote.input_vault,
+            quote.output_vault,
+            quote.observation,
+        ]
+    }
+
+    async fn lookup_tables(
+        &self,
+        _quote: &VenueQuote,
+    ) -> anyhow::Result<Vec<AddressLookupTableAccount>> {
+        RaydiumCpmm::lookup_tables(self, None).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_swappable, swap_base_input, swap_base_output};
+    use crate::raydium::cpmm_types::CpmmPoolState;
+    use crate::raydium::quote_error::QuoteError;
+    use solana_sdk::clock::Clock;
+    use solana_sdk::pubkey::Pubkey;
+
+    fn at(unix_timestamp: i64) -> Clock {
+        Clock {
+            unix_timestamp,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn base_input_takes_the_fee_before_the_curve() {
+        // 0.25% fee: 1_000 in, 3 fee rounded up, 997 swapped into a 1:2 pool
+        let (out, fee) = swap_base_input(1_000, 1_000_000, 2_000_000, 2_500).unwrap();
+        assert_eq!(fee, 3);
+        assert_eq!(out, 997 * 2_000_000 / (1_000_000 + 997));
+        // any nonzero fee rounds up to at least one unit
+        assert_eq!(
+            swap_base_input(10, 1_000_000, 2_000_000, 2_500).unwrap().1,
+            1
+        );
+    }
+
+    #[test]
+    fn base_output_covers_the_requested_amount() {
+        let (amount_in, _) = swap_base_output(1_990, 1_000_000, 2_000_000, 2_500).unwrap();
+        let (out, _) = swap_base_input(amount_in, 1_000_000, 2_000_000, 2_500).unwrap();
+        assert!(out >= 1_990);
+        assert!(swap_base_output(2_000_000, 1_000_000, 2_000_000, 2_500).is_err());
+    }
+
+    #[test]
+    fn swappable_once_open_and_not_disabled() {
+        let pool_id = Pubkey::new_unique();
+        let pool = CpmmPoolState {
+            open_time: 100,
+            ..Default::default()
+        };
+        assert!(check_swappable(&pool_id, &pool, &at(101)).is_ok());
+        // the open time itself is still too early
+        assert!(matches!(
+            check_swappable(&pool_id, &pool, &at(100)),
+            Err(QuoteError::NotOpenYet {
+                open_time: 100,
+                now: 100,
+                ..
+            })
+        ));
+        assert!(matches!(
+            check_swappable(&pool_id, &pool, &at(-1)),
+            Err(QuoteError::NotOpenYet { now: -1, .. })
+        ));
+        // the swap disabled bit
+        let disabled = CpmmPoolState { status: 4, ..pool };
+        assert!(matches!(
+            check_swappable(&pool_id, &disabled, &at(101)),
+            Err(QuoteError::SwapNotAllowed { status: 4, .. })
+        ));
+    }
+}
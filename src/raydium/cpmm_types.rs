@@ -0,0 +1,156 @@
+use borsh::BorshDeserialize;
+use solana_sdk::pubkey::Pubkey;
+
+/// Anchor discriminators of the CP-Swap accounts and instructions we read
+pub const POOL_STATE_DISCRIMINATOR: [u8; 8] = [247, 237, 227, 245, 215, 195, 222, 70];
+pub const AMM_CONFIG_DISCRIMINATOR: [u8; 8] = [218, 244, 33, 104, 203, 203, 43, 111];
+pub const SWAP_BASE_INPUT_DISCRIMINATOR: [u8; 8] = [143, 190, 90, 218, 196, 30, 51, 222];
+pub const SWAP_BASE_OUTPUT_DISCRIMINATOR: [u8; 8] = [55, 217, 98, 86, 163, 74, 180, 173];
+
+/// Seed of the pda owning every pool's vaults and lp mint
+pub const AUTH_SEED: &[u8] = b"vault_and_lp_mint_auth_seed";
+
+/// Fee rates are parts per million
+pub const FEE_RATE_DENOMINATOR: u64 = 1_000_000;
+
+/// Bit of `CpmmPoolState::status` that disables swaps when set
+const SWAP_DISABLED_BIT: u8 = 1 << 2;
+
+/// On-chain `PoolState` of the CP-Swap program, without the discriminator.
+/// The account is packed, borsh reads it field by field.
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshDeserialize)]
+pub struct CpmmPoolState {
+    pub amm_config: Pubkey,
+    pub pool_creator: Pubkey,
+    pub token_0_vault: Pubkey,
+    pub token_1_vault: Pubkey,
+    pub lp_mint: Pubkey,
+    pub token_0_mint: Pubkey,
+    pub token_1_mint: Pubkey,
+    pub token_0_program: Pubkey,
+    pub token_1_program: Pubkey,
+    pub observation_key: Pubkey,
+    pub auth_bump: u8,
+    /// Bit 0 disables deposits, bit 1 withdrawals, bit 2 swaps
+    pub status: u8,
+    pub lp_mint_decimals: u8,
+    pub mint_0_decimals: u8,
+    pub mint_1_decimals: u8,
+    pub lp_supply: u64,
+    /// Fees owed to the protocol, still held by the vaults
+    pub protocol_fees_token_0: u64,
+    pub protocol_fees_token_1: u64,
+    /// Fees owed to the fund, still held by the vaults
+    pub fund_fees_token_0: u64,
+    pub fund_fees_token_1: u64,
+    /// Unix timestamp after which swaps are allowed
+    pub open_time: u64,
+    pub recent_epoch: u64,
+    pub creator_fee_on: u8,
+    pub enable_creator_fee: bool,
+    pub padding1: [u8; 6],
+    /// Fees owed to the pool creator, still held by the vaults
+    pub creator_fees_token_0: u64,
+    pub creator_fees_token_1: u64,
+    pub padding: [u64; 28],
+}
+
+impl CpmmPoolState {
+    pub fn swap_enabled(&self) -> bool {
+        self.status & SWAP_DISABLED_BIT == 0
+    }
+
+    /// Vault balances minus the fees they hold for others
+    pub fn vault_amount_without_fee(&self, vault_0: u64, vault_1: u64) -> Option<(u64, u64)> {
+        Some((
+            vault_0
+                .checked_sub(self.protocol_fees_token_0)?
+                .checked_sub(self.fund_fees_token_0)?
+                .checked_sub(self.creator_fees_token_0)?,
+            vault_1
+                .checked_sub(self.protocol_fees_token_1)?
+                .checked_sub(self.fund_fees_token_1)?
+                .checked_sub(self.creator_fees_token_1)?,
+        ))
+    }
+}
+
+/// On-chain `AmmConfig` of the CP-Swap program, without the discriminator
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshDeserialize)]
+pub struct CpmmAmmConfig {
+    pub bump: u8,
+    pub disable_create_pool: bool,
+    pub index: u16,
+    /// Charged on the input, parts per million
+    pub trade_fee_rate: u64,
+    /// Share of the trade fee going to the protocol, parts per million
+    pub protocol_fee_rate: u64,
+    /// Share of the trade fee going to the fund, parts per million
+    pub fund_fee_rate: u64,
+    pub create_pool_fee: u64,
+    pub protocol_owner: Pubkey,
+    pub fund_owner: Pubkey,
+    pub creator_fee_rate: u64,
+    pub padding: [u64; 15],
+}
+
+/// Reads an anchor account, checking its discriminator
+pub fn decode_anchor_account<T: BorshDeserialize>(
+    data: &[u8],
+    discriminator: &[u8; 8],
+) -> std::io::Result<T> {
+    let Some(body) = data.strip_prefix(discriminator) else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "unexpected account discriminator",
+        ));
+    };
+    // trailing bytes are allowed, accounts are often allocated larger than their layout
+    T::deserialize(&mut &body[..])
+}
+
+/// A `swap_base_input` or `swap_base_output` instruction found in a transaction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CpmmSwapInstruction {
+    pub pool: Pubkey,
+    pub input_mint: Pubkey,
+    pub output_mint: Pubkey,
+    /// `amount_in` for base input swaps, `amount_out` for base output ones
+    pub amount: u64,
+    /// `minimum_amount_out` for base input swaps, `max_amount_in` for base output ones
+    pub other_amount_threshold: u64,
+    pub base_input: bool,
+}
+
+impl CpmmSwapInstruction {
+    /// `accounts` are the instruction's accounts in order. None if this isn't a swap
+    pub fn decode(data: &[u8], accounts: &[Pubkey]) -> Option<Self> {
+        let (discriminator, args) = data.split_first_chunk::<8>()?;
+        let base_input = match *discriminator {
+            SWAP_BASE_INPUT_DISCRIMINATOR => true,
+            SWAP_BASE_OUTPUT_DISCRIMINATOR => false,
+            _ => return None,
+        };
+        let (first, rest) = args.split_first_chunk::<8>()?;
+        let (second, _) = rest.split_first_chunk::<8>()?;
+        let (first, second) = (u64::from_le_bytes(*first), u64::from_le_bytes(*second));
+        // payer, authority, amm_config, pool_state, input/output token accounts, input/output
+        // vaults, input/output token programs, input/output mints, observation state
+        if accounts.len() < 13 {
+            return None;
+        }
+        let (amount, other_amount_threshold) = if base_input {
+            (first, second)
+        } else {
+            (second, first)
+        };
+        Some(Self {
+            pool: accounts[3],
+            input_mint: accounts[10],
+            output_mint: accounts[11],
+            amount,
+            other_amount_threshold,
+            base_input,
+        })
+    }
+}
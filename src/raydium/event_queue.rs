@@ -0,0 +1,304 @@
+use crate::client::{AccountFilter, AccountSubscription, AccountSubscriptions};
+use crate::raydium::serum_types::{
+    ACCOUNT_HEAD_PADDING, ACCOUNT_TAIL_PADDING, Event, EventQueueHeader, EventView, QueueHeader,
+    Side,
+};
+use log::warn;
+use solana_sdk::account::Account;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use thiserror::Error;
+use tokio::sync::mpsc;
+
+#[derive(Debug, Error)]
+pub enum EventQueueError {
+    #[error("Account is too short for an event queue")]
+    TooShort,
+    #[error("Event at {0} has unknown flags or fee tier")]
+    BadEvent(u64),
+}
+
+/// A fill or out from a market's event queue
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MarketEvent {
+    Fill {
+        seq_num: u64,
+        /// Open orders account of the filled order
+        owner: Pubkey,
+        side: Side,
+        maker: bool,
+        /// Quote lots per base lot, from the order id
+        price_lots: u64,
+        /// Coin bought or sold, native units
+        native_coin: u64,
+        /// Pc paid or received, native units
+        native_pc: u64,
+        /// Fee for takers, rebate for makers
+        native_fee_or_rebate: u64,
+        order_id: u128,
+    },
+    Out {
+        seq_num: u64,
+        owner: Pubkey,
+        side: Side,
+        /// Funds the cancelled or fully filled order released, native units of the paying side
+        native_qty_unlocked: u64,
+        order_id: u128,
+    },
+}
+
+impl MarketEvent {
+    pub fn seq_num(&self) -> u64 {
+        match self {
+            MarketEvent::Fill { seq_num, .. } | MarketEvent::Out { seq_num, .. } => *seq_num,
+        }
+    }
+
+    fn from_view(seq_num: u64, view: EventView) -> Self {
+        match view {
+            EventView::Fill {
+                side,
+                maker,
+                native_qty_paid,
+                native_qty_received,
+                native_fee_or_rebate,
+                order_id,
+                owner,
+                ..
+            } => {
+                // bids pay pc for coin, asks the other way around
+                let (native_coin, native_pc) = match side {
+                    Side::Bid => (native_qty_received, native_qty_paid),
+                    Side::Ask => (native_qty_paid, native_qty_received),
+                };
+                MarketEvent::Fill {
+                    seq_num,
+                    owner: Pubkey::new_from_array(bytemuck::cast(owner)),
+                    side,
+                    maker,
+                    price_lots: (order_id >> 64) as u64,
+                    native_coin,
+                    native_pc,
+                    native_fee_or_rebate,
+                    order_id,
+                }
+            }
+            EventView::Out {
+                side,
+                native_qty_unlocked,
+                order_id,
+                owner,
+                ..
+            } => MarketEvent::Out {
+                seq_num,
+                owner: Pubkey::new_from_array(bytemuck::cast(owner)),
+                side,
+                native_qty_unlocked,
+                order_id,
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for MarketEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MarketEvent::Fill {
+                seq_num,
+                owner,
+                side,
+                maker,
+                price_lots,
+                native_coin,
+                native_pc,
+                native_fee_or_rebate,
+                ..
+            } => write!(
+                f,
+                "#{} fill {:?} {} coin for {} pc at {} lots by {} ({}, fee {})",
+                seq_num,
+                side,
+                native_coin,
+                native_pc,
+                price_lots,
+                owner,
+                if *maker { "maker" } else { "taker" },
+                native_fee_or_rebate
+            ),
+            MarketEvent::Out {
+                seq_num,
+                owner,
+                side,
+                native_qty_unlocked,
+                ..
+            } => write!(
+                f,
+                "#{} out {:?} unlocking {} by {}",
+                seq_num, side, native_qty_unlocked, owner
+            ),
+        }
+    }
+}
+
+/// Events still in a queue, oldest first
+#[derive(Clone, Debug, Default)]
+pub struct EventQueueSnapshot {
+    /// Sequence number the next pushed event gets
+    pub next_seq_num: u64,
+    pub events: Vec<MarketEvent>,
+}
+
+/// Reads the events an event queue account holds, numbered by sequence.
+pub fn decode_event_queue(data: &[u8]) -> Result<EventQueueSnapshot, EventQueueError> {
+    let body = data
+        .strip_prefix(ACCOUNT_HEAD_PADDING.as_slice())
+        .and_then(|body| body.strip_suffix(ACCOUNT_TAIL_PADDING.as_slice()))
+        .filter(|body| body.len() >= size_of::<EventQueueHeader>())
+        .ok_or(EventQueueError::TooShort)?;
+    let (header, buf) = body.split_at(size_of::<EventQueueHeader>());
+    let header: EventQueueHeader = bytemuck::pod_read_unaligned(header);
+    let capacity = (buf.len() / size_of::<Event>()) as u64;
+    if header.count() > capacity || capacity == 0 && header.count() > 0 {
+        return Err(EventQueueError::TooShort);
+    }
+
+    // the queue is a ring buffer, the oldest event sits at `head`
+    let first_seq_num = header.seq_num().saturating_sub(header.count());
+    let events = (0..header.count())
+        .map(|offset| {
+            let index = ((header.head() + offset) % capacity) as usize;
+            let event: Event = bytemuck::pod_read_unaligned(
+                &buf[index * size_of::<Event>()..(index + 1) * size_of::<Event>()],
+            );
+            let seq_num = first_seq_num + offset;
+            if !event.is_valid() {
+                return Err(EventQueueError::BadEvent(seq_num));
+            }
+            let view = event
+                .as_view()
+                .map_err(|_| EventQueueError::BadEvent(seq_num))?;
+            Ok(MarketEvent::from_view(seq_num, view))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(EventQueueSnapshot {
+        next_seq_num: header.seq_num(),
+        events,
+    })
+}
+
+/// Remembers how far into a queue's sequence we've read, so each event is seen once.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EventCursor {
+    next_seq_num: Option<u64>,
+}
+
+impl EventCursor {
+    /// Events of `snapshot` not returned before, and how many were cranked out of the queue
+    /// before we could see them. The first snapshot only sets the starting point.
+    pub fn advance(&mut self, snapshot: &EventQueueSnapshot) -> (Vec<MarketEvent>, u64) {
+        let Some(next_seq_num) = self.next_seq_num else {
+            self.next_seq_num = Some(snapshot.next_seq_num);
+            return (vec![], 0);
+        };
+        // updates can arrive out of order, an older snapshot has nothing new
+        if snapshot.next_seq_num <= next_seq_num {
+            return (vec![], 0);
+        }
+        let oldest = snapshot
+            .events
+            .first()
+            .map_or(snapshot.next_seq_num, MarketEvent::seq_num);
+        self.next_seq_num = Some(snapshot.next_seq_num);
+        let events = snapshot
+            .events
+            .iter()
+            .filter(|event| event.seq_num() >= next_seq_num)
+            .copied()
+            .collect();
+        (events, oldest.saturating_sub(next_seq_num))
+    }
+}
+
+/// New events of serum / OpenBook event queues as geyser reports account updates.
+pub struct FillStream {
+    events: mpsc::UnboundedReceiver<(Pubkey, MarketEvent)>,
+    _subscription: AccountSubscription,
+}
+
+impl FillStream {
+    /// Streams events of `event_queues` from now on, tagged with their queue. Events cranked
+    /// out while the stream behind `accounts` is disconnected are missed.
+    pub fn start(accounts: &AccountSubscriptions, event_queues: &[Pubkey]) -> Self {
+        let (events_tx, events) = mpsc::unbounded_channel();
+        let cursors: Mutex<HashMap<Pubkey, EventCursor>> = Mutex::new(HashMap::new());
+        let subscription = accounts.subscribe_all(
+            event_queues.iter().copied().map(AccountFilter::Account),
+            move |key: Pubkey, account: &Account, _slot: u64| {
+                let snapshot = match decode_event_queue(&account.data) {
+                    Ok(snapshot) => snapshot,
+                    Err(e) => {
+                        warn!("Failed to decode event queue {}: {}", key, e);
+                        return;
+                    }
+                };
+                let (new_events, missed) = cursors
+                    .lock()
+                    .unwrap()
+                    .entry(key)
+                    .or_default()
+                    .advance(&snapshot);
+                if missed > 0 {
+                    warn!("Missed {} events of {}", missed, key);
+                }
+                for event in new_events {
+                    let _ = events_tx.send((key, event));
+                }
+            },
+        );
+        Self {
+            events,
+            _subscription: subscription,
+        }
+    }
+
+    /// Next event of any of the queues
+    pub async fn recv(&mut self) -> Option<(Pubkey, MarketEvent)> {
+        self.events.recv().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EventCursor, EventQueueSnapshot, MarketEvent};
+    use crate::raydium::serum_types::Side;
+    use solana_sdk::pubkey::Pubkey;
+
+    fn out(seq_num: u64) -> MarketEvent {
+        MarketEvent::Out {
+            seq_num,
+            owner: Pubkey::default(),
+            side: Side::Bid,
+            native_qty_unlocked: 0,
+            order_id: 0,
+        }
+    }
+
+    fn snapshot(seq_nums: std::ops::Range<u64>) -> EventQueueSnapshot {
+        EventQueueSnapshot {
+            next_seq_num: seq_nums.end,
+            events: seq_nums.map(out).collect(),
+        }
+    }
+
+    #[test]
+    fn cursor_emits_each_event_once() {
+        let mut cursor = EventCursor::default();
+        assert_eq!(cursor.advance(&snapshot(0..3)), (vec![], 0));
+        assert_eq!(cursor.advance(&snapshot(1..5)), (vec![out(3), out(4)], 0));
+        // a stale update
+        assert_eq!(cursor.advance(&snapshot(2..4)), (vec![], 0));
+        // 5 and 6 were cranked before we saw them
+        assert_eq!(cursor.advance(&snapshot(7..8)), (vec![out(7)], 2));
+    }
+}
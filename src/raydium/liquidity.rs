@@ -0,0 +1,350 @@
+//! Pool creation and liquidity changes on Raydium amm v4, read from transactions.
+
+use crate::config::RAYDIUM_LIQUIDITY_POOL_V4_PROGRAM_ID;
+use crate::raydium::types::AmmKeys;
+use solana_sdk::pubkey::Pubkey;
+use yellowstone_grpc_proto::prelude::{Message, TransactionStatusMeta};
+
+/// Instruction tags of the amm v4 program
+pub const INITIALIZE2_TAG: u8 = 1;
+pub const DEPOSIT_TAG: u8 = 3;
+pub const WITHDRAW_TAG: u8 = 4;
+
+/// An `initialize2`, `deposit` or `withdraw` instruction found in a transaction.
+/// Account fields are indices into the transaction's account keys.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AmmLiquidityInstruction {
+    Initialize2 {
+        amm_keys: Box<AmmKeys>,
+        open_time: u64,
+        init_coin_amount: u64,
+        init_pc_amount: u64,
+    },
+    Deposit {
+        pool: Pubkey,
+        coin_vault: u8,
+        pc_vault: u8,
+        user_lp: u8,
+    },
+    Withdraw {
+        pool: Pubkey,
+        coin_vault: u8,
+        pc_vault: u8,
+        lp_amount: u64,
+    },
+}
+
+impl AmmLiquidityInstruction {
+    /// `accounts` are the instruction's account indices, `keys` the transaction's account keys.
+    /// None if this isn't one of the three
+    pub fn decode(data: &[u8], accounts: &[u8], keys: &[Pubkey]) -> Option<Self> {
+        let key = |position: usize| keys.get(*accounts.get(position)? as usize).copied();
+        let (tag, args) = data.split_first()?;
+        match *tag {
+            INITIALIZE2_TAG => {
+                let (nonce, args) = args.split_first()?;
+                let (open_time, args) = args.split_first_chunk::<8>()?;
+                let (init_pc_amount, args) = args.split_first_chunk::<8>()?;
+                let (init_coin_amount, _) = args.split_first_chunk::<8>()?;
+                // token, associated token, system and rent programs, amm, authority, open
+                // orders, lp mint, coin mint, pc mint, coin vault, pc vault, target orders,
+                // config, fee destination, market program, market, ...
+                Some(Self::Initialize2 {
+                    amm_keys: Box::new(AmmKeys {
+                        amm_pool: key(4)?,
+                        amm_authority: key(5)?,
+                        amm_open_order: key(6)?,
+                        amm_lp_mint: key(7)?,
+                        amm_coin_mint: key(8)?,
+                        amm_pc_mint: key(9)?,
+                        amm_coin_vault: key(10)?,
+                        amm_pc_vault: key(11)?,
+                        amm_target: key(12)?,
+                        market_program: key(15)?,
+                        market: key(16)?,
+                        nonce: *nonce,
+                    }),
+                    open_time: u64::from_le_bytes(*open_time),
+                    init_coin_amount: u64::from_le_bytes(*init_coin_amount),
+                    init_pc_amount: u64::from_le_bytes(*init_pc_amount),
+                })
+            }
+            // token program, amm, authority, open orders, target orders, lp mint, coin vault,
+            // pc vault, market, user coin, user pc, user lp, ...
+            DEPOSIT_TAG if accounts.len() >= 12 => Some(Self::Deposit {
+                pool: key(1)?,
+                coin_vault: accounts[6],
+                pc_vault: accounts[7],
+                user_lp: accounts[11],
+            }),
+            // token program, amm, authority, open orders, target orders, lp mint, coin vault,
+            // pc vault, ...
+            WITHDRAW_TAG if accounts.len() >= 8 => {
+                let (lp_amount, _) = args.split_first_chunk::<8>()?;
+                Some(Self::Withdraw {
+                    pool: key(1)?,
+                    coin_vault: accounts[6],
+                    pc_vault: accounts[7],
+                    lp_amount: u64::from_le_bytes(*lp_amount),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A new amm v4 pool and the liquidity it starts with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PoolCreated {
+    pub amm_keys: AmmKeys,
+    pub open_time: u64,
+    pub coin_amount: u64,
+    pub pc_amount: u64,
+    pub slot: u64,
+}
+
+/// Liquidity added to or removed from a pool, native units
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LiquidityChange {
+    pub pool: Pubkey,
+    pub coin_mint: Pubkey,
+    pub pc_mint: Pubkey,
+    pub coin_amount: u64,
+    pub pc_amount: u64,
+    /// Lp tokens minted or burned
+    pub lp_amount: u64,
+    /// Vault balances once the transaction went through
+    pub coin_reserve: u64,
+    pub pc_reserve: u64,
+    pub slot: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LiquidityEvent {
+    PoolCreated(Box<PoolCreated>),
+    Added(LiquidityChange),
+    Removed(LiquidityChange),
+}
+
+impl LiquidityEvent {
+    pub fn pool(&self) -> Pubkey {
+        match self {
+            LiquidityEvent::PoolCreated(created) => created.amm_keys.amm_pool,
+            LiquidityEvent::Added(change) | LiquidityEvent::Removed(change) => change.pool,
+        }
+    }
+}
+
+impl std::fmt::Display for LiquidityEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LiquidityEvent::PoolCreated(created) => write!(
+                f,
+                "pool {} created for {} / {} with {} coin and {} pc, opens at {}",
+                created.amm_keys.amm_pool,
+                created.amm_keys.amm_coin_mint,
+                created.amm_keys.amm_pc_mint,
+                created.coin_amount,
+                created.pc_amount,
+                created.open_time
+            ),
+            LiquidityEvent::Added(change) | LiquidityEvent::Removed(change) => write!(
+                f,
+                "{} {} coin and {} pc ({} lp) {} pool {}, reserves now {} coin and {} pc",
+                if matches!(self, LiquidityEvent::Added(_)) {
+                    "added"
+                } else {
+                    "removed"
+                },
+                change.coin_amount,
+                change.pc_amount,
+                change.lp_amount,
+                if matches!(self, LiquidityEvent::Added(_)) {
+                    "to"
+                } else {
+                    "from"
+                },
+                change.pool,
+                change.coin_reserve,
+                change.pc_reserve
+            ),
+        }
+    }
+}
+
+/// Mint and native balances before and after the transaction of a token account
+fn token_balance(meta: &TransactionStatusMeta, account_index: u8) -> Option<(Pubkey, u64, u64)> {
+    let amount = |balances: &[yellowstone_grpc_proto::prelude::TokenBalance]| {
+        balances
+            .iter()
+            .find(|balance| balance.account_index == account_index as u32)
+            .map(|balance| {
+                let amount = balance
+                    .ui_token_amount
+                    .as_ref()
+                    .and_then(|ui| ui.amount.parse().ok())
+                    .unwrap_or(0);
+                (balance.mint.parse().ok(), amount)
+            })
+    };
+    let (pre_mint, pre) = amount(&meta.pre_token_balances).unwrap_or((None, 0));
+    let (post_mint, post) = amount(&meta.post_token_balances)?;
+    Some((post_mint.or(pre_mint)?, pre, post))
+}
+
+/// Pool creations and liquidity changes of amm v4 in a transaction, top level or inner.
+/// Amounts come from the vault balances, two changes to one pool in a transaction each see
+/// the sum of both.
+pub fn liquidity_events(
+    message: &Message,
+    meta: &TransactionStatusMeta,
+    slot: u64,
+) -> Vec<LiquidityEvent> {
+    if meta.err.is_some() {
+        return vec![];
+    }
+    // static keys first, then the ones loaded from lookup tables
+    let Some(keys) = message
+        .account_keys
+        .iter()
+        .chain(&meta.loaded_writable_addresses)
+        .chain(&meta.loaded_readonly_addresses)
+        .map(|key| Pubkey::try_from(key.as_slice()).ok())
+        .collect::<Option<Vec<_>>>()
+    else {
+        return vec![];
+    };
+    let program_id = Pubkey::from_str_const(RAYDIUM_LIQUIDITY_POOL_V4_PROGRAM_ID);
+    let instructions = message
+        .instructions
+        .iter()
+        .map(|ix| (ix.program_id_index, &ix.accounts, &ix.data))
+        .chain(
+            meta.inner_instructions
+                .iter()
+                .flat_map(|inner| &inner.instructions)
+                .map(|ix| (ix.program_id_index, &ix.accounts, &ix.data)),
+        )
+        .filter(|(program_id_index, _, _)| {
+            keys.get(*program_id_index as usize) == Some(&program_id)
+        })
+        .filter_map(|(_, accounts, data)| AmmLiquidityInstruction::decode(data, accounts, &keys));
+
+    instructions
+        .filter_map(|instruction| match instruction {
+            AmmLiquidityInstruction::Initialize2 {
+                amm_keys,
+                open_time,
+                init_coin_amount,
+                init_pc_amount,
+            } => Some(LiquidityEvent::PoolCreated(Box::new(PoolCreated {
+                amm_keys: *amm_keys,
+                open_time,
+                coin_amount: init_coin_amount,
+                pc_amount: init_pc_amount,
+                slot,
+            }))),
+            AmmLiquidityInstruction::Deposit {
+                pool,
+                coin_vault,
+                pc_vault,
+                user_lp,
+            } => {
+                let (coin_mint, coin_pre, coin_post) = token_balance(meta, coin_vault)?;
+                let (pc_mint, pc_pre, pc_post) = token_balance(meta, pc_vault)?;
+                let lp_amount = token_balance(meta, user_lp)
+                    .map_or(0, |(_, pre, post)| post.saturating_sub(pre));
+                Some(LiquidityEvent::Added(LiquidityChange {
+                    pool,
+                    coin_mint,
+                    pc_mint,
+                    coin_amount: coin_post.saturating_sub(coin_pre),
+                    pc_amount: pc_post.saturating_sub(pc_pre),
+                    lp_amount,
+                    coin_reserve: coin_post,
+                    pc_reserve: pc_post,
+                    slot,
+                }))
+            }
+            AmmLiquidityInstruction::Withdraw {
+                pool,
+                coin_vault,
+                pc_vault,
+                lp_amount,
+            } => {
+                let (coin_mint, coin_pre, coin_post) = token_balance(meta, coin_vault)?;
+                let (pc_mint, pc_pre, pc_post) = token_balance(meta, pc_vault)?;
+                Some(LiquidityEvent::Removed(LiquidityChange {
+                    pool,
+                    coin_mint,
+                    pc_mint,
+                    coin_amount: coin_pre.saturating_sub(coin_post),
+                    pc_amount: pc_pre.saturating_sub(pc_post),
+                    lp_amount,
+                    coin_reserve: coin_post,
+                    pc_reserve: pc_post,
+                    slot,
+                }))
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LiquidityChange, LiquidityEvent, WITHDRAW_TAG, liquidity_events};
+    use crate::config::RAYDIUM_LIQUIDITY_POOL_V4_PROGRAM_ID;
+    use solana_sdk::pubkey::Pubkey;
+    use yellowstone_grpc_proto::prelude::{
+        CompiledInstruction, Message, TokenBalance, TransactionStatusMeta, UiTokenAmount,
+    };
+
+    #[test]
+    fn reads_withdrawn_amounts_from_the_vaults() {
+        let program_id = Pubkey::from_str_const(RAYDIUM_LIQUIDITY_POOL_V4_PROGRAM_ID);
+        let keys = (0..8).map(|_| Pubkey::new_unique()).collect::<Vec<_>>();
+        let (pool, coin_mint, pc_mint) = (keys[1], Pubkey::new_unique(), Pubkey::new_unique());
+        let message = Message {
+            account_keys: keys
+                .iter()
+                .chain([&program_id])
+                .map(|key| key.to_bytes().to_vec())
+                .collect(),
+            instructions: vec![CompiledInstruction {
+                program_id_index: 8,
+                accounts: (0..8).collect(),
+                data: [[WITHDRAW_TAG].as_slice(), &500u64.to_le_bytes()].concat(),
+            }],
+            ..Default::default()
+        };
+        let balance = |account_index, mint: Pubkey, amount: u64| TokenBalance {
+            account_index,
+            mint: mint.to_string(),
+            ui_token_amount: Some(UiTokenAmount {
+                amount: amount.to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let meta = TransactionStatusMeta {
+            pre_token_balances: vec![balance(6, coin_mint, 1_000), balance(7, pc_mint, 4_000)],
+            post_token_balances: vec![balance(6, coin_mint, 100), balance(7, pc_mint, 400)],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            liquidity_events(&message, &meta, 42),
+            [LiquidityEvent::Removed(LiquidityChange {
+                pool,
+                coin_mint,
+                pc_mint,
+                coin_amount: 900,
+                pc_amount: 3_600,
+                lp_amount: 500,
+                coin_reserve: 100,
+                pc_reserve: 400,
+                slot: 42,
+            })]
+        );
+    }
+}
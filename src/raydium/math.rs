@@ -5,6 +5,7 @@
 #![allow(clippy::manual_range_contains)]
 
 use crate::raydium::amm_types::{AmmError, RaydiumAmmInfo};
+use crate::raydium::quote_error::QuoteError;
 use crate::raydium::serum_error::DexResult;
 use crate::raydium::serum_types::{EventView, MarketState, OpenOrders, Side, ToAlignedBytes};
 use num_traits::CheckedDiv;
@@ -244,8 +245,10 @@ impl Calculator {
         market_state: &'a Box<MarketState>,
         event_q_account: &'a AccountInfo,
         amm_open_account: &'a AccountInfo,
-    ) -> Result<(u64, u64), AmmError> {
-        let event_q = market_state.load_event_queue_mut(event_q_account).unwrap();
+    ) -> Result<(u64, u64), QuoteError> {
+        let event_q = market_state
+            .load_event_queue_mut(event_q_account)
+            .map_err(|e| QuoteError::bad_layout(event_q_account.key, e))?;
         let mut native_pc_total = open_orders.native_pc_total;
         let mut native_coin_total = open_orders.native_coin_total;
         msg!("calc_exact len:{}", event_q.len());
@@ -255,7 +258,10 @@ impl Calculator {
                 continue;
             }
             // msg!("{:?}", event.as_view().unwrap());
-            match event.as_view().unwrap() {
+            match event
+                .as_view()
+                .map_err(|e| QuoteError::bad_layout(event_q_account.key, e))?
+            {
                 EventView::Fill {
                     side,
                     maker,
@@ -270,12 +276,20 @@ impl Calculator {
                 } => {
                     match side {
                         Side::Bid if maker => {
-                            native_pc_total -= native_qty_paid;
-                            native_coin_total += native_qty_received;
+                            native_pc_total = native_pc_total
+                                .checked_sub(native_qty_paid)
+                                .ok_or(QuoteError::MathOverflow)?;
+                            native_coin_total = native_coin_total
+                                .checked_add(native_qty_received)
+                                .ok_or(QuoteError::MathOverflow)?;
                         }
                         Side::Ask if maker => {
-                            native_coin_total -= native_qty_paid;
-                            native_pc_total += native_qty_received;
+                            native_coin_total = native_coin_total
+                                .checked_sub(native_qty_paid)
+                                .ok_or(QuoteError::MathOverflow)?;
+                            native_pc_total = native_pc_total
+                                .checked_add(native_qty_received)
+                                .ok_or(QuoteError::MathOverflow)?;
                         }
                         _ => (),
                     };
@@ -297,7 +311,7 @@ impl Calculator {
         market_state: &'a Box<MarketState>,
         event_q_account: &'a AccountInfo,
         amm_open_account: &'a AccountInfo,
-    ) -> Result<(u64, u64), AmmError> {
+    ) -> Result<(u64, u64), QuoteError> {
         let (pc_total_in_serum, coin_total_in_serum) = Self::calc_exact_vault_in_serum(
             open_orders,
             market_state,
@@ -374,7 +388,7 @@ impl Calculator {
         total_pc_without_take_pnl: U128,
         total_coin_without_take_pnl: U128,
         swap_direction: SwapDirection,
-    ) -> U128 {
+    ) -> Result<U128, AmmError> {
         let amount_out;
         match swap_direction {
             SwapDirection::Coin2PC => {
@@ -383,12 +397,14 @@ impl Calculator {
                 // => amount_out = pc - coin * pc / (coin + amount_in)
                 // => amount_out = ((pc * coin + pc * amount_in) - coin * pc) / (coin + amount_in)
                 // => amount_out =  pc * amount_in / (coin + amount_in)
-                let denominator = total_coin_without_take_pnl.checked_add(amount_in).unwrap();
+                let denominator = total_coin_without_take_pnl
+                    .checked_add(amount_in)
+                    .ok_or(AmmError::CheckedAddOverflow)?;
                 amount_out = total_pc_without_take_pnl
                     .checked_mul(amount_in)
-                    .unwrap()
+                    .ok_or(AmmError::CheckedMulOverflow)?
                     .checked_div(denominator)
-                    .unwrap();
+                    .ok_or(AmmError::CheckedDivOverflow)?;
             }
             SwapDirection::PC2Coin => {
                 // (x + delta_x) * (y + delta_y) = x * y
@@ -396,15 +412,17 @@ impl Calculator {
                 // => amount_out = coin - coin * pc / (pc + amount_in)
                 // => amount_out = (coin * pc + coin * amount_in - coin * pc) / (pc + amount_in)
                 // => amount_out = coin * amount_in / (pc + amount_in)
-                let denominator = total_pc_without_take_pnl.checked_add(amount_in).unwrap();
+                let denominator = total_pc_without_take_pnl
+                    .checked_add(amount_in)
+                    .ok_or(AmmError::CheckedAddOverflow)?;
                 amount_out = total_coin_without_take_pnl
                     .checked_mul(amount_in)
-                    .unwrap()
+                    .ok_or(AmmError::CheckedMulOverflow)?
                     .checked_div(denominator)
-                    .unwrap();
+                    .ok_or(AmmError::CheckedDivOverflow)?;
             }
         }
-        return amount_out;
+        Ok(amount_out)
     }
 
     pub fn swap_token_amount_base_out(
@@ -412,7 +430,7 @@ impl Calculator {
         total_pc_without_take_pnl: U128,
         total_coin_without_take_pnl: U128,
         swap_direction: SwapDirection,
-    ) -> U128 {
+    ) -> Result<U128, AmmError> {
         let amount_in;
         match swap_direction {
             SwapDirection::Coin2PC => {
@@ -421,12 +439,14 @@ impl Calculator {
                 // => amount_in = coin * pc / (pc - amount_out) - coin
                 // => amount_in = (coin * pc - pc * coin + amount_out * coin) / (pc - amount_out)
                 // => amount_in = (amount_out * coin) / (pc - amount_out)
-                let denominator = total_pc_without_take_pnl.checked_sub(amount_out).unwrap();
+                let denominator = total_pc_without_take_pnl
+                    .checked_sub(amount_out)
+                    .ok_or(AmmError::CheckedSubOverflow)?;
                 amount_in = total_coin_without_take_pnl
                     .checked_mul(amount_out)
-                    .unwrap()
+                    .ok_or(AmmError::CheckedMulOverflow)?
                     .checked_ceil_div(denominator)
-                    .unwrap()
+                    .ok_or(AmmError::CheckedDivOverflow)?
                     .0;
             }
             SwapDirection::PC2Coin => {
@@ -439,16 +459,18 @@ impl Calculator {
                 // => amount_in = coin * pc / (coin - amount_out) - pc
                 // => amount_in = (coin * pc - pc * coin + pc * amount_out) / (coin - amount_out)
                 // => amount_in = (pc * amount_out) / (coin - amount_out)
-                let denominator = total_coin_without_take_pnl.checked_sub(amount_out).unwrap();
+                let denominator = total_coin_without_take_pnl
+                    .checked_sub(amount_out)
+                    .ok_or(AmmError::CheckedSubOverflow)?;
                 amount_in = total_pc_without_take_pnl
                     .checked_mul(amount_out)
-                    .unwrap()
+                    .ok_or(AmmError::CheckedMulOverflow)?
                     .checked_ceil_div(denominator)
-                    .unwrap()
+                    .ok_or(AmmError::CheckedDivOverflow)?
                     .0;
             }
         }
-        return amount_in;
+        Ok(amount_in)
     }
 }
 
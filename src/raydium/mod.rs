@@ -1,54 +1,311 @@
 pub(crate) mod types;
-use crate::config::WSOL;
+use crate::config::{WSOL, env_or};
+use crate::copy_rules::{CopyRuleSet, CopyRules};
+use crate::gen_engine::Engine;
+use crate::honeypot::{HoneypotConfig, simulate_round_trip};
+use crate::pump_fun::amm::PumpAmm;
+use crate::pump_fun::{PumpFun, bonding_curve_address};
 use crate::raydium::amm::RaydiumAmm;
 use crate::raydium::api_v3::ApiV3Client;
+use crate::raydium::clmm::RaydiumClmm;
+use crate::raydium::cpmm::RaydiumCpmm;
 use crate::raydium::types::{RaydiumAmmExecutorOpts, SwapExecutionMode, SwapInput};
-use crate::trade_info::TradeInfoFromToken;
-use log::info;
-use solana_client::nonblocking::rpc_client::RpcClient;
+use crate::router::{PinnedVenue, Route, Router, SwapVenue, VenueQuote};
+use crate::sender::SendOutcome;
+use crate::slippage::SlippageConfig;
+use crate::token_safety::{TokenSafety, TokenSafetyConfig};
+use crate::trade_info::{TradeInfoFromToken, TradeVenue};
+use log::{info, warn};
 use solana_sdk::pubkey::Pubkey;
 use solana_sdk::signer::Signer;
-use solana_sdk::transaction::VersionedTransaction;
 use std::env;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 pub mod amm;
 mod amm_types;
 pub mod api_v3;
+pub mod clmm;
+mod clmm_math;
+pub mod clmm_types;
+pub mod cpmm;
+pub mod cpmm_types;
+pub mod event_queue;
+pub mod liquidity;
 mod math;
+pub mod orderbook;
+pub mod pool_cache;
+pub mod quote_error;
 mod serum;
 mod serum_error;
 mod serum_types;
-mod utils;
-
-pub async fn swap_in(trade_info_from_token: TradeInfoFromToken) {
-    let rpc_link = env::var("RPC_SOLANA").unwrap();
-    let client = Arc::new(RpcClient::new(rpc_link));
-    let executor = RaydiumAmm::new(
-        Arc::clone(&client),
-        RaydiumAmmExecutorOpts::default(),
-        ApiV3Client::new(None),
-    );
+pub(crate) mod utils;
+
+/// Copies a target's buy. A failure is journaled as the decision on the target's trade, unless
+/// one was made before it.
+pub async fn swap_in(
+    engine: &Engine,
+    trade_info_from_token: TradeInfoFromToken,
+) -> anyhow::Result<()> {
+    let mut decided = false;
+    let result = copy_buy(engine, &trade_info_from_token, &mut decided).await;
+    if let Err(e) = &result
+        && !decided
+    {
+        engine.journal.record_decision(
+            &trade_info_from_token.signature,
+            &trade_info_from_token.mint,
+            Some(&format!("error: {}", e)),
+        );
+    }
+    result
+}
+
+async fn copy_buy(
+    engine: &Engine,
+    trade_info_from_token: &TradeInfoFromToken,
+    decided: &mut bool,
+) -> anyhow::Result<()> {
+    let client = &engine.client;
+    let user = engine.keypair.pubkey();
+    let journal = &engine.journal;
+    let target_signature = &trade_info_from_token.signature;
+    let output_token_mint = Pubkey::from_str(&trade_info_from_token.mint)?;
+
+    let lookup_table = env::var("LOOKUP_TABLE")
+        .ok()
+        .map(|lookup_table| Pubkey::from_str(&lookup_table))
+        .transpose()?;
+    let opts = || RaydiumAmmExecutorOpts {
+        as_legacy_transaction: Some(env_or("LEGACY_TRANSACTIONS", true)),
+        lookup_table,
+        ..Default::default()
+    };
+    let amm = || {
+        RaydiumAmm::new(Arc::clone(client), opts(), ApiV3Client::new(None))
+            .with_pool_cache(engine.pools.clone())
+            .with_order_book(env_or("AMM_ORDER_BOOK", false))
+    };
+    let cpmm = || RaydiumCpmm::new(Arc::clone(client), opts(), ApiV3Client::new(None));
+    let clmm = || RaydiumClmm::new(Arc::clone(client), opts(), ApiV3Client::new(None));
+    let pump_fun = || PumpFun::new(Arc::clone(client), opts());
+    let pump_amm = || PumpAmm::new(Arc::clone(client), opts());
+    // every venue that may hold the pair is quoted, the one the target traded on only on the
+    // target's pool. A Jupiter route is copied on whichever single pool gives the most
+    let (cpmm_pool, clmm_pool, bonding_curve) = match &trade_info_from_token.venue {
+        TradeVenue::RaydiumV4 => (None, None, None),
+        TradeVenue::Cpmm { pool } => (Some(*pool), None, None),
+        TradeVenue::Clmm { pool } => (None, Some(*pool), None),
+        TradeVenue::PumpFun { bonding_curve } => (None, None, Some(*bonding_curve)),
+        TradeVenue::Jupiter { trade } => {
+            info!(
+                "Target routed {} {} -> {} {} through jupiter",
+                trade.input_amount, trade.input_mint, trade.output_amount, trade.output_mint
+            );
+            (None, None, None)
+        }
+    };
+    let pinned = |venue: Box<dyn SwapVenue>, market: Option<Pubkey>| -> Box<dyn SwapVenue> {
+        match market {
+            Some(market) => Box::new(PinnedVenue::new(venue, market)),
+            None => venue,
+        }
+    };
+    let router = Router::new(vec![
+        Box::new(amm()),
+        pinned(Box::new(cpmm()), cpmm_pool),
+        pinned(Box::new(clmm()), clmm_pool),
+        pinned(Box::new(pump_fun()), bonding_curve),
+        Box::new(pump_amm()),
+    ]);
     let base_token = Pubkey::from_str_const(WSOL);
+    let checks = CopyChecks::new(engine, trade_info_from_token);
     let swap_input = SwapInput {
         input_token_mint: base_token,
-        output_token_mint: Pubkey::from_str(&trade_info_from_token.mint).unwrap(),
-        slippage_bps: 1000, // 10%
-        amount: 1_000_000,  // 0.001 SOL
+        output_token_mint,
+        slippage_bps: checks.config.slippage.default_slippage_bps.min(10_000) as u16,
+        amount: 1_000_000, // 0.001 SOL
         mode: SwapExecutionMode::ExactIn,
         market: None,
     };
 
-    let quote = executor.quote(&swap_input).await;
-    // log::info!("Quote: {:#?}", quote);
-    //
-    // let keypair = Keypair::new();
-    // let mut transaction = executor
-    //     .swap_transaction(keypair.pubkey(), quote, None)
-    //     .await?;
-    // let blockhash = client.get_latest_blockhash()?;
-    // transaction.message.set_recent_blockhash(blockhash);
-    // let _final_tx = VersionedTransaction::try_new(transaction.message, &[&keypair])?;
-    // info!("{:?}", _final_tx);
+    let route = router.best_quote(&swap_input).await?;
+    // a sold out curve refuses the quote once it migrated, a position on it follows the token
+    // onto its pool. A live curve beaten on price keeps its positions
+    if route.curve_completed(&output_token_mint)
+        && let Some(position) = engine.positions.graduate(
+            &output_token_mint,
+            &bonding_curve_address(&output_token_mint),
+            route.quote.summary().market,
+        )
+    {
+        info!("Position graduated: {}", position);
+    }
+    let Route {
+        venue, mut quote, ..
+    } = route;
+    log::debug!("Quote from {}: {:#?}", venue.name(), quote);
+
+    // journaled once the checks set its slippage, so the minimum out is the one sent
+    let skip = checks.run(trade_info_from_token, &mut quote, venue).await?;
+    journal.record_quote(target_signature, &quote.summary());
+    if let Some(reason) = skip {
+        warn!(
+            "Skipping buy of {}: {}. Quote {}",
+            output_token_mint, reason, quote
+        );
+        journal.record_decision(target_signature, &trade_info_from_token.mint, Some(&reason));
+        *decided = true;
+        return Ok(());
+    }
+    info!(
+        "Copying buy of {} on {}. Quote {}",
+        output_token_mint,
+        venue.name(),
+        quote
+    );
+    log::debug!("Writable accounts: {:?}", venue.required_accounts(&quote));
+    journal.record_decision(target_signature, &trade_info_from_token.mint, None);
+    *decided = true;
+
+    let mut requotes = 0;
+    loop {
+        let instructions = venue.swap_instructions(user, &quote).await?;
+        let lookup_tables = venue.lookup_tables(&quote).await?;
+        let outcome = engine
+            .sender
+            .send(instructions, &lookup_tables, &engine.keypair)
+            .await?;
+        info!("Buy of {}: {}", output_token_mint, outcome);
+        journal.record_send(target_signature, &quote.summary(), &outcome);
+        let expired = matches!(outcome, SendOutcome::Expired { .. });
+        let position = engine.positions.record_buy(
+            &quote.summary(),
+            &trade_info_from_token.signature,
+            outcome,
+        );
+        info!("Position {}", position);
+        if !expired || requotes >= engine.sender.config().max_requotes {
+            break;
+        }
+
+        // a new quote goes through every check again, the pool or the token may have changed
+        requotes += 1;
+        quote = venue
+            .quote(&SwapInput {
+                market: Some(quote.summary().market),
+                ..swap_input
+            })
+            .await?;
+        let skip = checks.run(trade_info_from_token, &mut quote, venue).await?;
+        journal.record_quote(target_signature, &quote.summary());
+        if let Some(reason) = skip {
+            warn!(
+                "Giving up on buy of {}: {}. Quote {}",
+                output_token_mint, reason, quote
+            );
+            break;
+        }
+        info!("Requoted buy of {}. Quote {}", output_token_mint, quote);
+    }
+    Ok(())
+}
+
+/// Settings every copy is checked against, read once at startup
+#[derive(Clone, Debug)]
+pub struct CopyConfig {
+    pub safety: TokenSafetyConfig,
+    pub slippage: SlippageConfig,
+    pub rules: CopyRuleSet,
+    pub honeypot: HoneypotConfig,
+}
+
+impl CopyConfig {
+    /// Fails on a mistyped safety check mode or an unreadable rules file, rather than copying
+    /// with a check off
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(Self {
+            safety: TokenSafetyConfig::from_env()?,
+            slippage: SlippageConfig::from_env(),
+            rules: CopyRuleSet::new(&env_or("COPY_RULES_PATH", "copy_rules.json".to_string()))?,
+            honeypot: HoneypotConfig::from_env(),
+        })
+    }
+}
+
+/// Everything a quote has to pass before a copy is sent on it
+struct CopyChecks<'a> {
+    engine: &'a Engine,
+    config: &'a CopyConfig,
+    safety: TokenSafety,
+    rules: CopyRules,
+}
+
+impl<'a> CopyChecks<'a> {
+    fn new(engine: &'a Engine, trade_info: &TradeInfoFromToken) -> Self {
+        Self {
+            engine,
+            config: &engine.copy,
+            safety: TokenSafety::new(
+                Arc::clone(&engine.client),
+                ApiV3Client::new(None),
+                engine.copy.safety,
+            ),
+            rules: engine.copy.rules.for_target(&trade_info.target),
+        }
+    }
+
+    /// Runs the token safety checks, sets the quote's slippage from the target's fill, applies
+    /// the target's copy rules and simulates the round trip on `venue`. Why the copy is skipped,
+    /// None when it goes ahead.
+    async fn run(
+        &self,
+        trade_info: &TradeInfoFromToken,
+        quote: &mut VenueQuote,
+        venue: &dyn SwapVenue,
+    ) -> anyhow::Result<Option<String>> {
+        let mint = quote.summary().output_mint;
+        let report = self.safety.check(&mint).await?;
+        if !report.flagged.is_empty() {
+            warn!(
+                "Token {} flagged by safety checks: {:?}",
+                report.mint, report.flagged
+            );
+        }
+        if !report.is_safe() {
+            return Ok(Some(format!(
+                "rejected by safety checks: {:?}",
+                report.rejected
+            )));
+        }
+
+        // don't chase a price the target's own buy already moved
+        match self
+            .config
+            .slippage
+            .slippage_bps(trade_info.fill_price(), quote.summary().execution_price)
+        {
+            Ok(slippage_bps) => quote.set_slippage(slippage_bps)?,
+            Err(behind) => return Ok(Some(behind.to_string())),
+        }
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        if let Err(reason) = self.rules.evaluate(&quote.summary(), now) {
+            return Ok(Some(reason.to_string()));
+        }
+
+        if self.config.honeypot.enabled {
+            let user = self.engine.keypair.pubkey();
+            let round_trip = simulate_round_trip(&self.engine.client, venue, user, quote).await?;
+            if round_trip.is_honeypot(&self.config.honeypot) {
+                return Ok(Some(format!(
+                    "round trip simulation failed: {}",
+                    round_trip
+                )));
+            }
+            info!("Round trip for {}: {}", mint, round_trip);
+        }
+        Ok(None)
+    }
 }
@@ -0,0 +1,344 @@
+use crate::raydium::serum_types::{ACCOUNT_HEAD_PADDING, ACCOUNT_TAIL_PADDING, MarketState, Side};
+use crate::raydium::types::MarketKeys;
+use solana_sdk::pubkey::Pubkey;
+use thiserror::Error;
+
+/// Account flags, then the slab header: bump index, free list length, free list head, root node
+/// and leaf count
+const SLAB_HEADER_LEN: usize = 8 + 8 + 8 + 4 + 4 + 8;
+const NODE_LEN: usize = 72;
+
+const INNER_NODE_TAG: u32 = 1;
+const LEAF_NODE_TAG: u32 = 2;
+
+#[derive(Debug, Error)]
+pub enum SlabError {
+    #[error("Account is too short for a slab")]
+    TooShort,
+    #[error("Slab node {0} is out of bounds")]
+    NodeOutOfBounds(u32),
+    #[error("Slab node {index} has unexpected tag {tag}")]
+    UnexpectedTag { index: u32, tag: u32 },
+    #[error("Slab tree holds more nodes than its leaf count allows")]
+    Cycle,
+}
+
+/// A resting order, in lots
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Order {
+    pub order_id: u128,
+    /// Quote lots per base lot
+    pub price_lots: u64,
+    pub quantity_lots: u64,
+    /// Open orders account the order belongs to
+    pub owner: Pubkey,
+}
+
+impl Order {
+    fn from_leaf(node: &[u8]) -> Self {
+        // tag, owner slot, fee tier, padding, key, owner, quantity, client order id
+        let order_id = u128::from_le_bytes(node[8..24].try_into().unwrap());
+        Self {
+            order_id,
+            price_lots: (order_id >> 64) as u64,
+            quantity_lots: u64::from_le_bytes(node[56..64].try_into().unwrap()),
+            owner: Pubkey::try_from(&node[24..56]).unwrap(),
+        }
+    }
+}
+
+/// Orders of a bids or asks account, best price first.
+///
+/// The account is a critbit tree keyed by price then sequence number, walked in key order.
+pub fn decode_slab(data: &[u8], side: Side) -> Result<Vec<Order>, SlabError> {
+    let body = data
+        .strip_prefix(ACCOUNT_HEAD_PADDING.as_slice())
+        .and_then(|body| body.strip_suffix(ACCOUNT_TAIL_PADDING.as_slice()))
+        .filter(|body| body.len() >= SLAB_HEADER_LEN)
+        .ok_or(SlabError::TooShort)?;
+    let (header, nodes) = body.split_at(SLAB_HEADER_LEN);
+    let root = u32::from_le_bytes(header[28..32].try_into().unwrap());
+    let leaf_count = u64::from_le_bytes(header[32..40].try_into().unwrap());
+    if leaf_count == 0 {
+        return Ok(vec![]);
+    }
+
+    let node = |index: u32| {
+        nodes
+            .get(index as usize * NODE_LEN..(index as usize + 1) * NODE_LEN)
+            .ok_or(SlabError::NodeOutOfBounds(index))
+    };
+    // the count comes from the account, the slab can't hold more leaves than nodes
+    let mut orders = Vec::with_capacity((leaf_count as usize).min(nodes.len() / NODE_LEN));
+    let mut stack = vec![root];
+    while let Some(index) = stack.pop() {
+        // a well formed tree has one fewer inner node than leaves
+        if orders.len() as u64 + stack.len() as u64 > leaf_count.saturating_mul(2) {
+            return Err(SlabError::Cycle);
+        }
+        let node = node(index)?;
+        match u32::from_le_bytes(node[0..4].try_into().unwrap()) {
+            INNER_NODE_TAG => {
+                let low = u32::from_le_bytes(node[24..28].try_into().unwrap());
+                let high = u32::from_le_bytes(node[28..32].try_into().unwrap());
+                // the child popped first comes first: asks lowest price first, bids highest
+                match side {
+                    Side::Ask => stack.extend([high, low]),
+                    Side::Bid => stack.extend([low, high]),
+                }
+            }
+            LEAF_NODE_TAG => {
+                orders.push(Order::from_leaf(node));
+                if orders.len() as u64 > leaf_count {
+                    return Err(SlabError::Cycle);
+                }
+            }
+            tag => return Err(SlabError::UnexpectedTag { index, tag }),
+        }
+    }
+    Ok(orders)
+}
+
+/// Lot sizes of a serum / OpenBook market account. None if the account is too short
+pub fn market_lot_sizes(data: &[u8]) -> Option<(u64, u64)> {
+    let state = data
+        .strip_prefix(ACCOUNT_HEAD_PADDING.as_slice())?
+        .get(..size_of::<MarketState>())?;
+    let state: MarketState = bytemuck::pod_read_unaligned(state);
+    Some((state.coin_lot_size, state.pc_lot_size))
+}
+
+/// Accounts of a serum / OpenBook market owned by `market_program`, read from its account.
+/// None if the account is too short or its vault signer doesn't derive
+pub fn market_keys(data: &[u8], market: &Pubkey, market_program: &Pubkey) -> Option<MarketKeys> {
+    let state = data
+        .strip_prefix(ACCOUNT_HEAD_PADDING.as_slice())?
+        .get(..size_of::<MarketState>())?;
+    let state: MarketState = bytemuck::pod_read_unaligned(state);
+    let key = |words: [u64; 4]| Pubkey::new_from_array(bytemuck::cast(words));
+    let vault_signer_key = Pubkey::create_program_address(
+        &[market.as_ref(), &state.vault_signer_nonce.to_le_bytes()],
+        market_program,
+    )
+    .ok()?;
+    Some(MarketKeys {
+        event_queue: key(state.event_q),
+        bids: key(state.bids),
+        asks: key(state.asks),
+        coin_vault: key(state.coin_vault),
+        pc_vault: key(state.pc_vault),
+        vault_signer_key,
+    })
+}
+
+/// Both sides of a market's book, best price first on each
+#[derive(Clone, Debug, Default)]
+pub struct OrderBook {
+    pub bids: Vec<Order>,
+    pub asks: Vec<Order>,
+    pub coin_lot_size: u64,
+    pub pc_lot_size: u64,
+}
+
+impl OrderBook {
+    /// Native pc per native coin at `price_lots`
+    fn native_price(&self, price_lots: u64) -> f64 {
+        price_lots as f64 * self.pc_lot_size as f64 / self.coin_lot_size.max(1) as f64
+    }
+
+    pub fn best_bid(&self) -> Option<f64> {
+        self.bids
+            .first()
+            .map(|order| self.native_price(order.price_lots))
+    }
+
+    pub fn best_ask(&self) -> Option<f64> {
+        self.asks
+            .first()
+            .map(|order| self.native_price(order.price_lots))
+    }
+
+    /// Coin resting on a side, in native units
+    pub fn depth(&self, side: Side) -> u64 {
+        let orders = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+        orders
+            .iter()
+            .map(|order| order.quantity_lots.saturating_mul(self.coin_lot_size))
+            .fold(0, u64::saturating_add)
+    }
+
+    /// Output of taking `amount_in` from the book, and how much of it found a match.
+    /// Coin in sells into the bids, pc in buys from the asks. Taker fees are not modelled.
+    pub fn fill(&self, coin_to_pc: bool, amount_in: u64) -> (u64, u64) {
+        let (coin_lot, pc_lot) = (self.coin_lot_size as u128, self.pc_lot_size as u128);
+        if coin_lot == 0 || pc_lot == 0 {
+            return (0, 0);
+        }
+        let mut remaining = amount_in as u128;
+        let mut out = 0u128;
+        let orders = if coin_to_pc { &self.bids } else { &self.asks };
+        for order in orders {
+            // what one coin lot costs at this level, in native pc
+            let lot_price = order.price_lots as u128 * pc_lot;
+            let lots = if coin_to_pc {
+                remaining / coin_lot
+            } else {
+                remaining / lot_price.max(1)
+            }
+            .min(order.quantity_lots as u128);
+            if lots == 0 {
+                break;
+            }
+            if coin_to_pc {
+                remaining -= lots * coin_lot;
+                out += lots * lot_price;
+            } else {
+                remaining -= lots * lot_price;
+                out += lots * coin_lot;
+            }
+        }
+        (
+            u64::try_from(out).unwrap_or(u64::MAX),
+            amount_in - remaining as u64,
+        )
+    }
+}
+
+/// The market's book next to an amm v4 quote
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BookSummary {
+    /// Best prices in pc per coin, ui units
+    pub best_bid: Option<f64>,
+    pub best_ask: Option<f64>,
+    /// Coin resting on each side, native units
+    pub bid_depth: u64,
+    pub ask_depth: u64,
+    /// What the quoted input would get taking from the book instead, exact in quotes only
+    pub book_out: Option<u64>,
+    /// How far the amm's spot price sits from the book's mid price
+    pub divergence_bps: Option<u64>,
+}
+
+impl BookSummary {
+    /// `amm_price` is the pool's pc per coin in ui units
+    pub fn new(
+        book: &OrderBook,
+        coin_decimals: u8,
+        pc_decimals: u8,
+        amm_price: f64,
+        coin_to_pc: bool,
+        exact_in: Option<u64>,
+    ) -> Self {
+        let ui = |price: f64| price * 10f64.powi(coin_decimals as i32 - pc_decimals as i32);
+        let best_bid = book.best_bid().map(ui);
+        let best_ask = book.best_ask().map(ui);
+        let divergence_bps = match (best_bid, best_ask) {
+            (Some(bid), Some(ask)) if bid + ask > 0.0 => {
+                let mid = (bid + ask) / 2.0;
+                Some(((amm_price - mid).abs() / mid * 10_000.0) as u64)
+            }
+            _ => None,
+        };
+        Self {
+            best_bid,
+            best_ask,
+            bid_depth: book.depth(Side::Bid),
+            ask_depth: book.depth(Side::Ask),
+            book_out: exact_in.map(|amount_in| book.fill(coin_to_pc, amount_in).0),
+            divergence_bps,
+        }
+    }
+}
+
+impl std::fmt::Display for BookSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "bid {:?} ({} deep), ask {:?} ({} deep), book out {:?}, amm {:?} bps off mid",
+            self.best_bid,
+            self.bid_depth,
+            self.best_ask,
+            self.ask_depth,
+            self.book_out,
+            self.divergence_bps
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NODE_LEN, OrderBook, SLAB_HEADER_LEN, decode_slab};
+    use crate::raydium::serum_types::{ACCOUNT_HEAD_PADDING, ACCOUNT_TAIL_PADDING, Side};
+
+    fn leaf(price_lots: u64, sequence: u64, quantity_lots: u64) -> Vec<u8> {
+        let mut node = vec![0u8; NODE_LEN];
+        node[0..4].copy_from_slice(&2u32.to_le_bytes());
+        let key = ((price_lots as u128) << 64) | sequence as u128;
+        node[8..24].copy_from_slice(&key.to_le_bytes());
+        node[56..64].copy_from_slice(&quantity_lots.to_le_bytes());
+        node
+    }
+
+    fn inner(low: u32, high: u32) -> Vec<u8> {
+        let mut node = vec![0u8; NODE_LEN];
+        node[0..4].copy_from_slice(&1u32.to_le_bytes());
+        node[24..28].copy_from_slice(&low.to_le_bytes());
+        node[28..32].copy_from_slice(&high.to_le_bytes());
+        node
+    }
+
+    #[test]
+    fn walks_the_tree_best_price_first() {
+        // root 0 splits into leaf 1 (price 10) and inner 2, which splits into prices 20 and 30
+        let mut header = vec![0u8; SLAB_HEADER_LEN];
+        header[28..32].copy_from_slice(&0u32.to_le_bytes());
+        header[32..40].copy_from_slice(&3u64.to_le_bytes());
+        let data = [
+            ACCOUNT_HEAD_PADDING.to_vec(),
+            header,
+            inner(1, 2),
+            leaf(10, 1, 5),
+            inner(3, 4),
+            leaf(20, 2, 6),
+            leaf(30, 3, 7),
+            ACCOUNT_TAIL_PADDING.to_vec(),
+        ]
+        .concat();
+
+        let prices = |side| {
+            decode_slab(&data, side)
+                .unwrap()
+                .iter()
+                .map(|order| order.price_lots)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(prices(Side::Ask), [10, 20, 30]);
+        assert_eq!(prices(Side::Bid), [30, 20, 10]);
+
+        let book = OrderBook {
+            asks: decode_slab(&data, Side::Ask).unwrap(),
+            coin_lot_size: 100,
+            pc_lot_size: 1,
+            ..Default::default()
+        };
+        // 5 lots at 10 then 2 of the 6 at 20, the rest of the input is too little for a lot
+        assert_eq!(book.fill(false, 95), (700, 90));
+    }
+
+    #[test]
+    fn survives_a_corrupt_leaf_count() {
+        let mut header = vec![0u8; SLAB_HEADER_LEN];
+        header[32..40].copy_from_slice(&u64::MAX.to_le_bytes());
+        let data = [
+            ACCOUNT_HEAD_PADDING.to_vec(),
+            header,
+            leaf(10, 1, 5),
+            ACCOUNT_TAIL_PADDING.to_vec(),
+        ]
+        .concat();
+        assert_eq!(decode_slab(&data, Side::Ask).unwrap().len(), 1);
+    }
+}
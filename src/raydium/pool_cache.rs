@@ -0,0 +1,407 @@
+use crate::client::{AccountFilter, AccountHandler, AccountSubscription, AccountSubscriptions};
+use crate::config::env_or;
+use crate::raydium::amm::decode_pool_state;
+use crate::raydium::amm_types::RaydiumAmmInfo;
+use crate::raydium::types::{AmmKeys, MarketKeys};
+use log::{debug, info, warn};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::account::Account;
+use solana_sdk::clock::Clock;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::sysvar;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::time::{Duration, interval};
+
+/// Everything a quote needs from one pool, decoded.
+#[derive(Clone, Copy, Debug)]
+pub struct PoolState {
+    pub pool_id: Pubkey,
+    pub amm_keys: AmmKeys,
+    pub market_keys: MarketKeys,
+    pub lookup_table: Option<Pubkey>,
+    pub amm: RaydiumAmmInfo,
+    /// Pc reserve without pending pnl
+    pub pc_vault_amount: u64,
+    /// Coin reserve without pending pnl
+    pub coin_vault_amount: u64,
+    /// Slot of the newest account update the state was decoded from
+    pub slot: u64,
+}
+
+impl PoolState {
+    /// Reserve of `mint`, if the pool trades it
+    pub fn reserve_of(&self, mint: &Pubkey) -> Option<u64> {
+        if *mint == self.amm_keys.amm_coin_mint {
+            Some(self.coin_vault_amount)
+        } else if *mint == self.amm_keys.amm_pc_mint {
+            Some(self.pc_vault_amount)
+        } else {
+            None
+        }
+    }
+}
+
+/// Accounts a pool's state is decoded from, in the order `decode_pool_state` expects.
+pub fn pool_account_keys(
+    pool_id: &Pubkey,
+    amm_keys: &AmmKeys,
+    market_keys: &MarketKeys,
+) -> [Pubkey; 7] {
+    [
+        *pool_id,
+        amm_keys.amm_target,
+        amm_keys.amm_pc_vault,
+        amm_keys.amm_coin_vault,
+        amm_keys.amm_open_order,
+        amm_keys.market,
+        market_keys.event_queue,
+    ]
+}
+
+struct TrackedPool {
+    pool_id: Pubkey,
+    amm_keys: AmmKeys,
+    market_keys: MarketKeys,
+    lookup_table: Option<Pubkey>,
+    keys: [Pubkey; 7],
+    accounts: [Account; 7],
+    slots: [u64; 7],
+    /// `None` while the accounts don't decode
+    state: Option<PoolState>,
+    /// Tracked on creation rather than for a quote
+    warmed: bool,
+    /// Use count of the cache when the pool was last tracked or read
+    last_used: AtomicU64,
+}
+
+#[derive(Default)]
+struct Pools {
+    pools: HashMap<Pubkey, TrackedPool>,
+    /// Pools each subscribed account belongs to
+    pools_by_account: HashMap<Pubkey, Vec<Pubkey>>,
+    pools_by_mints: HashMap<(Pubkey, Pubkey), Vec<Pubkey>>,
+    clock: Option<Clock>,
+    /// Bumped on every use, orders pools by how recently they were used
+    uses: AtomicU64,
+}
+
+impl Pools {
+    fn touch(&self, pool: &TrackedPool) {
+        let use_count = self.uses.fetch_add(1, Ordering::Relaxed);
+        pool.last_used.store(use_count, Ordering::Relaxed);
+    }
+
+    /// Tracked pool holding the most `input_mint` among those trading it against `output_mint`.
+    /// Warmed pools only when no pool a quote loaded trades the pair.
+    fn pool_for(&self, input_mint: &Pubkey, output_mint: &Pubkey) -> Option<Pubkey> {
+        self.pools_by_mints
+            .get(&(*input_mint, *output_mint))?
+            .iter()
+            .filter_map(|pool_id| self.pools.get(pool_id))
+            .filter_map(|pool| Some((!pool.warmed, pool.state?)))
+            .max_by_key(|(quoted, state)| (*quoted, state.reserve_of(input_mint)))
+            .map(|(_, state)| state.pool_id)
+    }
+
+    fn insert(&mut self, pool: TrackedPool) {
+        let (pool_id, keys) = (pool.pool_id, pool.keys);
+        let (coin_mint, pc_mint) = (pool.amm_keys.amm_coin_mint, pool.amm_keys.amm_pc_mint);
+        self.touch(&pool);
+        self.pools.insert(pool_id, pool);
+        for key in keys {
+            self.pools_by_account.entry(key).or_default().push(pool_id);
+        }
+        for pair in [(coin_mint, pc_mint), (pc_mint, coin_mint)] {
+            self.pools_by_mints.entry(pair).or_default().push(pool_id);
+        }
+    }
+
+    /// Stops tracking the pool used least recently
+    fn evict(&mut self) -> Option<Pubkey> {
+        let pool_id = *self
+            .pools
+            .iter()
+            .min_by_key(|(_, pool)| pool.last_used.load(Ordering::Relaxed))?
+            .0;
+        let pool = self.pools.remove(&pool_id)?;
+        let (coin_mint, pc_mint) = (pool.amm_keys.amm_coin_mint, pool.amm_keys.amm_pc_mint);
+        for key in pool.keys {
+            remove_pool(&mut self.pools_by_account, key, &pool_id);
+        }
+        for pair in [(coin_mint, pc_mint), (pc_mint, coin_mint)] {
+            remove_pool(&mut self.pools_by_mints, pair, &pool_id);
+        }
+        Some(pool_id)
+    }
+
+    fn apply(&mut self, key: Pubkey, account: Account, slot: u64) {
+        if key == sysvar::clock::id() {
+            match bincode::deserialize::<Clock>(&account.data) {
+                Ok(clock) => self.clock = Some(clock),
+                Err(e) => warn!("Failed to decode clock: {}", e),
+            }
+            return;
+        }
+        let Some(pool_ids) = self.pools_by_account.get(&key) else {
+            return;
+        };
+        for pool_id in pool_ids {
+            let Some(pool) = self.pools.get_mut(pool_id) else {
+                continue;
+            };
+            let Some(index) = pool.keys.iter().position(|k| *k == key) else {
+                continue;
+            };
+            if slot < pool.slots[index] {
+                continue;
+            }
+            pool.accounts[index] = account.clone();
+            pool.slots[index] = slot;
+            pool.refresh();
+        }
+    }
+}
+
+fn remove_pool<K: std::hash::Hash + Eq>(
+    index: &mut HashMap<K, Vec<Pubkey>>,
+    key: K,
+    pool_id: &Pubkey,
+) {
+    if let Some(pool_ids) = index.get_mut(&key) {
+        pool_ids.retain(|id| id != pool_id);
+        if pool_ids.is_empty() {
+            index.remove(&key);
+        }
+    }
+}
+
+impl TrackedPool {
+    fn refresh(&mut self) {
+        let mut accounts = self.accounts.clone();
+        let slot = self.slots.iter().copied().max().unwrap_or_default();
+        match decode_pool_state(
+            self.pool_id,
+            self.amm_keys,
+            self.market_keys,
+            self.lookup_table,
+            &mut accounts,
+            slot,
+        ) {
+            Ok(state) => self.state = Some(state),
+            Err(e) => {
+                debug!("Dropping cached state of {}: {}", self.pool_id, e);
+                self.state = None;
+            }
+        }
+    }
+}
+
+/// Decoded pool states kept fresh by geyser account subscriptions, so quoting a tracked pool
+/// needs no rpc.
+#[derive(Clone)]
+pub struct PoolStateCache {
+    pools: Arc<RwLock<Pools>>,
+    accounts: AccountSubscriptions,
+    /// Keeps each tracked pool's accounts subscribed, dropped when the pool is evicted
+    subscriptions: Arc<Mutex<HashMap<Pubkey, AccountSubscription>>>,
+    /// Processed slot of the rpc, polled apart from the stream so a stalled stream shows up as
+    /// slots behind
+    rpc_slot: Arc<AtomicU64>,
+    /// Pools tracked at most, the least recently used one is dropped for a new one
+    max_pools: usize,
+}
+
+impl PoolStateCache {
+    /// Follows pools on the stream behind `accounts`
+    pub fn start(accounts: &AccountSubscriptions, client: Arc<RpcClient>) -> Self {
+        let pools = Arc::new(RwLock::new(Pools::default()));
+        let clock = accounts.subscribe(
+            AccountFilter::Account(sysvar::clock::id()),
+            handler(Arc::clone(&pools)),
+        );
+        let cache = Self {
+            pools,
+            accounts: accounts.clone(),
+            subscriptions: Arc::new(Mutex::new(HashMap::from([(sysvar::clock::id(), clock)]))),
+            rpc_slot: Arc::new(AtomicU64::new(0)),
+            max_pools: env_or("POOL_CACHE_MAX_POOLS", 200).max(1),
+        };
+
+        let rpc_slot = Arc::clone(&cache.rpc_slot);
+        tokio::spawn(async move {
+            let mut timer = interval(Duration::from_millis(env_or("SLOT_POLL_MS", 400)));
+            loop {
+                timer.tick().await;
+                match client
+                    .get_slot_with_commitment(CommitmentConfig::processed())
+                    .await
+                {
+                    Ok(slot) => {
+                        rpc_slot.fetch_max(slot, Ordering::Relaxed);
+                    }
+                    Err(e) => debug!("Failed to poll slot: {}", e),
+                }
+            }
+        });
+        cache
+    }
+
+    /// Decoded state of `pool_id` and the cluster clock, if both are known. The clock's slot is
+    /// at least the rpc's, the clock account arrives on the same stream as the pool.
+    pub fn get(&self, pool_id: &Pubkey) -> Option<(PoolState, Clock)> {
+        let pools = self.pools.read().unwrap();
+        let pool = pools.pools.get(pool_id)?;
+        pools.touch(pool);
+        let state = pool.state?;
+        let mut clock = pools.clock.clone()?;
+        clock.slot = clock.slot.max(self.rpc_slot.load(Ordering::Relaxed));
+        Some((state, clock))
+    }
+
+    /// Tracked pool holding the most `input_mint` among those trading it against `output_mint`.
+    /// A pool warmed on creation never takes the place of one a quote loaded.
+    pub fn pool_for(&self, input_mint: &Pubkey, output_mint: &Pubkey) -> Option<Pubkey> {
+        self.pools.read().unwrap().pool_for(input_mint, output_mint)
+    }
+
+    /// Starts following a pool from state loaded over rpc, `warmed` if no quote asked for it.
+    /// A pool already followed only takes the accounts the stream hasn't delivered something
+    /// newer for.
+    pub fn track(&self, state: PoolState, accounts: [Account; 7], warmed: bool) {
+        let keys = pool_account_keys(&state.pool_id, &state.amm_keys, &state.market_keys);
+        let mut pools = self.pools.write().unwrap();
+        if let Some(pool) = pools.pools.get_mut(&state.pool_id) {
+            for (index, account) in accounts.into_iter().enumerate() {
+                if state.slot > pool.slots[index] {
+                    pool.accounts[index] = account;
+                    pool.slots[index] = state.slot;
+                }
+            }
+            pool.lookup_table = pool.lookup_table.or(state.lookup_table);
+            pool.warmed &= warmed;
+            pool.refresh();
+            return;
+        }
+        while pools.pools.len() >= self.max_pools {
+            let Some(evicted) = pools.evict() else {
+                break;
+            };
+            self.subscriptions.lock().unwrap().remove(&evicted);
+            info!("Stopped tracking pool {}", evicted);
+        }
+        pools.insert(TrackedPool {
+            pool_id: state.pool_id,
+            amm_keys: state.amm_keys,
+            market_keys: state.market_keys,
+            lookup_table: state.lookup_table,
+            keys,
+            accounts,
+            slots: [state.slot; 7],
+            state: Some(state),
+            warmed,
+            last_used: AtomicU64::new(0),
+        });
+        drop(pools);
+        let subscription = self.accounts.subscribe_all(
+            keys.map(AccountFilter::Account),
+            handler(Arc::clone(&self.pools)),
+        );
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .insert(state.pool_id, subscription);
+        info!("Tracking pool {}", state.pool_id);
+    }
+}
+
+/// Applies updates of pool accounts and the clock to `pools`
+fn handler(pools: Arc<RwLock<Pools>>) -> impl AccountHandler {
+    move |key: Pubkey, account: &Account, slot: u64| {
+        pools.write().unwrap().apply(key, account.clone(), slot);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PoolState, Pools, TrackedPool, pool_account_keys};
+    use crate::raydium::amm_types::RaydiumAmmInfo;
+    use crate::raydium::types::{AmmKeys, MarketKeys};
+    use solana_sdk::pubkey::Pubkey;
+    use std::sync::atomic::AtomicU64;
+
+    fn pool(coin_mint: Pubkey, pc_mint: Pubkey, pc_vault_amount: u64, warmed: bool) -> TrackedPool {
+        let key = Pubkey::new_unique;
+        let pool_id = key();
+        let amm_keys = AmmKeys {
+            amm_pool: pool_id,
+            amm_coin_mint: coin_mint,
+            amm_pc_mint: pc_mint,
+            amm_authority: key(),
+            amm_target: key(),
+            amm_coin_vault: key(),
+            amm_pc_vault: key(),
+            amm_lp_mint: key(),
+            amm_open_order: key(),
+            market_program: key(),
+            market: key(),
+            nonce: 0,
+        };
+        let market_keys = MarketKeys {
+            event_queue: key(),
+            bids: key(),
+            asks: key(),
+            coin_vault: key(),
+            pc_vault: key(),
+            vault_signer_key: key(),
+        };
+        TrackedPool {
+            pool_id,
+            amm_keys,
+            market_keys,
+            lookup_table: None,
+            keys: pool_account_keys(&pool_id, &amm_keys, &market_keys),
+            accounts: Default::default(),
+            slots: [0; 7],
+            state: Some(PoolState {
+                pool_id,
+                amm_keys,
+                market_keys,
+                lookup_table: None,
+                amm: RaydiumAmmInfo::default(),
+                pc_vault_amount,
+                coin_vault_amount: 0,
+                slot: 0,
+            }),
+            warmed,
+            last_used: AtomicU64::new(0),
+        }
+    }
+
+    #[test]
+    fn warmed_pools_stand_in_only_for_missing_ones_and_the_least_used_goes_first() {
+        let (token, sol) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut pools = Pools::default();
+        let quoted = pool(token, sol, 100, false);
+        let quoted_id = quoted.pool_id;
+        let warmed = pool(token, sol, 1_000, true);
+        let warmed_id = warmed.pool_id;
+
+        pools.insert(warmed);
+        assert_eq!(pools.pool_for(&sol, &token), Some(warmed_id));
+        // deeper, but only warmed
+        pools.insert(quoted);
+        assert_eq!(pools.pool_for(&sol, &token), Some(quoted_id));
+
+        pools.touch(&pools.pools[&warmed_id]);
+        assert_eq!(pools.evict(), Some(quoted_id));
+        assert!(!pools.pools_by_account.contains_key(&quoted_id));
+        assert_eq!(pools.pool_for(&sol, &token), Some(warmed_id));
+        assert_eq!(pools.evict(), Some(warmed_id));
+        assert!(pools.pools_by_account.is_empty());
+        assert!(pools.pools_by_mints.is_empty());
+        assert_eq!(pools.evict(), None);
+    }
+}
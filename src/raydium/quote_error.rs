@@ -0,0 +1,74 @@
+use crate::raydium::amm_types::AmmError;
+use solana_client::client_error::ClientError;
+use solana_sdk::pubkey::Pubkey;
+use thiserror::Error;
+
+/// Why a pool could not be quoted.
+#[derive(Debug, Error)]
+pub enum QuoteError {
+    #[error("Input token cannot equal output token {0}")]
+    SameMint(Pubkey),
+    #[error("No raydium pool for {input_mint} -> {output_mint}")]
+    PoolNotFound {
+        input_mint: Pubkey,
+        output_mint: Pubkey,
+    },
+    #[error("No pool keys for raydium v4 pool {0}")]
+    PoolKeysNotFound(Pubkey),
+    #[error("Account {0} does not exist")]
+    AccountMissing(Pubkey),
+    #[error("Account {account} has an unexpected layout: {reason}")]
+    BadLayout { account: Pubkey, reason: String },
+    #[error("Pool {0} is disabled")]
+    PoolDisabled(Pubkey),
+    #[error("Pool {0} is withdraw only")]
+    WithdrawOnly(Pubkey),
+    #[error("Pool {pool} does not allow swaps in status {status}")]
+    SwapNotAllowed { pool: Pubkey, status: u64 },
+    #[error("Pool {pool} opens at {open_time}, cluster time is {now}")]
+    NotOpenYet {
+        pool: Pubkey,
+        open_time: u64,
+        now: i64,
+    },
+    #[error("Math overflow")]
+    MathOverflow,
+    #[error("Bonding curve of {0} is complete, the token migrated off it")]
+    CurveComplete(Pubkey),
+    #[error("Pool does not hold enough to fill the swap")]
+    InsufficientLiquidity,
+    #[error("Amm error: {0}")]
+    Amm(AmmError),
+    #[error(transparent)]
+    Rpc(#[from] Box<ClientError>),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl QuoteError {
+    pub fn bad_layout(account: &Pubkey, reason: impl ToString) -> Self {
+        QuoteError::BadLayout {
+            account: *account,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl From<ClientError> for QuoteError {
+    fn from(e: ClientError) -> Self {
+        QuoteError::Rpc(Box::new(e))
+    }
+}
+
+impl From<AmmError> for QuoteError {
+    fn from(e: AmmError) -> Self {
+        match e {
+            AmmError::CheckedAddOverflow
+            | AmmError::CheckedSubOverflow
+            | AmmError::CheckedMulOverflow
+            | AmmError::CheckedDivOverflow
+            | AmmError::ConversionFailure => QuoteError::MathOverflow,
+            e => QuoteError::Amm(e),
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use crate::raydium::amm_types::{AmmError, RaydiumAmmInfo};
+use crate::raydium::quote_error::QuoteError;
 use crate::raydium::serum_types::{Market, MarketState, OpenOrders, ToAlignedBytes};
 use solana_sdk::account_info::AccountInfo;
-use solana_sdk::program_error::ProgramError;
 use std::convert::identity;
 use std::ops::Deref;
 
@@ -12,8 +12,9 @@ pub fn load_serum_market_order<'a>(
     amm: &RaydiumAmmInfo,
     // Allow for the market flag to be set to AccountFlag::Disabled
     allow_disabled: bool,
-) -> Result<(Box<MarketState>, Box<OpenOrders>), ProgramError> {
-    let market_state = Market::load(market_acc, &amm.market_program, allow_disabled).unwrap();
+) -> Result<(Box<MarketState>, Box<OpenOrders>), QuoteError> {
+    let market_state = Market::load(market_acc, &amm.market_program, allow_disabled)
+        .map_err(|e| QuoteError::bad_layout(market_acc.key, e))?;
     let open_orders = market_state
         .load_orders_mut(
             open_orders_acc,
@@ -22,7 +23,7 @@ pub fn load_serum_market_order<'a>(
             None,
             None,
         )
-        .unwrap();
+        .map_err(|e| QuoteError::bad_layout(open_orders_acc.key, e))?;
     if identity(open_orders.market) != market_acc.key.to_aligned_bytes() {
         return Err(AmmError::InvalidMarket.into());
     }
@@ -19,7 +19,7 @@ use std::ops::{Deref, DerefMut};
 
 pub const ACCOUNT_TAIL_PADDING: &[u8; 7] = b"padding";
 pub const ACCOUNT_HEAD_PADDING: &[u8; 5] = b"serum";
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Side {
     Bid = 0,
     Ask = 1,
@@ -223,6 +223,14 @@ pub struct EventQueueHeader {
     seq_num: u64,
 }
 unsafe impl Zeroable for EventQueueHeader {}
+
+impl EventQueueHeader {
+    /// Sequence number the next pushed event gets
+    pub fn seq_num(&self) -> u64 {
+        self.seq_num
+    }
+}
+
 unsafe impl Pod for EventQueueHeader {}
 
 unsafe impl TriviallyTransmutable for EventQueueHeader {}
@@ -354,6 +362,12 @@ impl Event {
         }
     }
 
+    /// Whether `as_view` can read the event without panicking on unknown flags or fee tier
+    pub fn is_valid(&self) -> bool {
+        BitFlags::<EventFlag>::from_bits(self.event_flags).is_ok()
+            && FeeTier::try_from(self.fee_tier).is_ok()
+    }
+
     #[inline(always)]
     pub fn as_view(&self) -> DexResult<EventView> {
         let flags = BitFlags::from_bits(self.event_flags).unwrap();
@@ -1,5 +1,6 @@
 use crate::raydium::amm_types::Loadable;
 use crate::raydium::amm_types::{RaydiumAmmInfo, RaydiumFees, RaydiumStateData};
+use crate::raydium::orderbook::BookSummary;
 use anyhow::Context;
 use bytemuck::{Pod, Zeroable};
 use safe_transmute::trivial::TriviallyTransmutable;
@@ -34,6 +35,8 @@ pub struct SwapConfig {
     pub cu_limits: Option<ComputeUnitLimits>,
     pub wrap_and_unwrap_sol: Option<bool>,
     pub as_legacy_transaction: Option<bool>,
+    /// Our own lookup table, used on top of the pool's for v0 transactions
+    pub lookup_table: Option<Pubkey>,
 }
 
 #[derive(Clone, Debug, Default)]
@@ -72,9 +75,378 @@ pub struct RaydiumAmmExecutorOpts {
     pub cu_limits: Option<ComputeUnitLimits>,
     pub wrap_and_unwrap_sol: Option<bool>,
     pub load_keys_by_api: Option<bool>,
+    pub as_legacy_transaction: Option<bool>,
+    pub lookup_table: Option<Pubkey>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RaydiumAmmQuote {
+    /// The address of the amm pool
+    pub market: Pubkey,
+    /// The input mint
+    pub input_mint: Pubkey,
+    /// The output mint,
+    pub output_mint: Pubkey,
+    /// The amount specified
+    pub amount: u64,
+    /// The other amount
+    pub other_amount: u64,
+    /// The other amount with slippage
+    pub other_amount_threshold: u64,
+    /// Whether the amount specified is in terms of the input token
+    pub amount_specified_is_input: bool,
+    /// The input mint decimals
+    pub input_mint_decimals: u8,
+    /// The output mint decimals
+    pub output_mint_decimals: u8,
+    /// Pool fees at the time of the quote
+    pub fees: RaydiumFees,
+    /// Pc reserve the quote was computed from, without pending pnl
+    pub pc_vault_amount: u64,
+    /// Coin reserve the quote was computed from, without pending pnl
+    pub coin_vault_amount: u64,
+    /// Unix timestamp from which the pool accepts swaps
+    pub pool_open_time: u64,
+    /// The pool's own address lookup table
+    pub lookup_table: Option<Pubkey>,
+    /// Output per input before the trade, in ui units
+    pub spot_price: f64,
+    /// Output per input we actually get, fees included, in ui units
+    pub execution_price: f64,
+    /// How much worse our fill is than the spot price, fees excluded
+    pub price_impact_bps: u64,
+    /// Swap fee paid, in input token units
+    pub fee_amount: u64,
+    /// Least output we accept once slippage is applied
+    pub minimum_out: u64,
+    /// Slot of the newest pool account the quote was computed from
+    pub slot: u64,
+    /// How many slots the cluster had moved past `slot` when quoting
+    pub slots_behind: u64,
+    /// Amm keys
+    pub amm_keys: AmmKeys,
+    /// Market keys
+    pub market_keys: MarketKeys,
+    /// The market's book, for pools sharing liquidity with it
+    pub book: Option<BookSummary>,
+}
+
+impl RaydiumAmmQuote {
+    /// Re-applies `slippage_bps` to the other side of the quote
+    pub fn set_slippage(&mut self, slippage_bps: u64) -> anyhow::Result<()> {
+        self.other_amount_threshold = crate::raydium::amm::amount_with_slippage(
+            self.other_amount,
+            slippage_bps,
+            !self.amount_specified_is_input,
+        )?;
+        if self.amount_specified_is_input {
+            self.minimum_out = self.other_amount_threshold;
+        }
+        Ok(())
+    }
+
+    /// Reserve of `mint`, if the pool holds it
+    pub fn reserve_of(&self, mint: &Pubkey) -> Option<u64> {
+        if *mint == self.amm_keys.amm_coin_mint {
+            Some(self.coin_vault_amount)
+        } else if *mint == self.amm_keys.amm_pc_mint {
+            Some(self.pc_vault_amount)
+        } else {
+            None
+        }
+    }
 }
 
+impl std::fmt::Display for RaydiumAmmQuote {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} -> {} on {}: spot {:.12}, execution {:.12}, impact {} bps, fee {}, min out {}, slot {} ({} behind)",
+            self.input_mint,
+            self.output_mint,
+            self.market,
+            self.spot_price,
+            self.execution_price,
+            self.price_impact_bps,
+            self.fee_amount,
+            self.minimum_out,
+            self.slot,
+            self.slots_behind
+        )?;
+        if let Some(book) = &self.book {
+            write!(f, ", {}", book)?;
+        }
+        Ok(())
+    }
+}
+
+/// Venue independent view of a quote, for journaling, positions and copy rules
 #[derive(Clone, Copy, Debug)]
+pub struct QuoteSummary {
+    pub market: Pubkey,
+    pub input_mint: Pubkey,
+    pub output_mint: Pubkey,
+    pub amount: u64,
+    pub other_amount: u64,
+    pub amount_specified_is_input: bool,
+    pub input_mint_decimals: u8,
+    pub output_mint_decimals: u8,
+    pub spot_price: f64,
+    pub execution_price: f64,
+    pub price_impact_bps: u64,
+    pub fee_amount: u64,
+    pub minimum_out: u64,
+    /// Unix timestamp from which the pool accepts swaps
+    pub pool_open_time: u64,
+    /// WSOL held by the pool, if it trades against WSOL
+    pub sol_reserve: Option<u64>,
+    pub slot: u64,
+    pub slots_behind: u64,
+}
+
+impl RaydiumAmmQuote {
+    pub fn summary(&self) -> QuoteSummary {
+        QuoteSummary {
+            market: self.market,
+            input_mint: self.input_mint,
+            output_mint: self.output_mint,
+            amount: self.amount,
+            other_amount: self.other_amount,
+            amount_specified_is_input: self.amount_specified_is_input,
+            input_mint_decimals: self.input_mint_decimals,
+            output_mint_decimals: self.output_mint_decimals,
+            spot_price: self.spot_price,
+            execution_price: self.execution_price,
+            price_impact_bps: self.price_impact_bps,
+            fee_amount: self.fee_amount,
+            minimum_out: self.minimum_out,
+            pool_open_time: self.pool_open_time,
+            sol_reserve: self.reserve_of(&Pubkey::from_str_const(crate::config::WSOL)),
+            slot: self.slot,
+            slots_behind: self.slots_behind,
+        }
+    }
+}
+
+/// Quote against a Raydium CP-Swap pool, with the accounts its swap instruction needs
+#[derive(Clone, Copy, Debug)]
+pub struct CpmmQuote {
+    pub pool: Pubkey,
+    pub amm_config: Pubkey,
+    pub observation: Pubkey,
+    pub input_vault: Pubkey,
+    pub output_vault: Pubkey,
+    pub input_token_program: Pubkey,
+    pub output_token_program: Pubkey,
+    pub input_mint: Pubkey,
+    pub output_mint: Pubkey,
+    /// The amount specified
+    pub amount: u64,
+    /// The other amount
+    pub other_amount: u64,
+    /// The other amount with slippage
+    pub other_amount_threshold: u64,
+    pub amount_specified_is_input: bool,
+    pub input_mint_decimals: u8,
+    pub output_mint_decimals: u8,
+    /// Input side reserve, fees owed to others excluded
+    pub input_reserve: u64,
+    /// Output side reserve, fees owed to others excluded
+    pub output_reserve: u64,
+    /// Trade fee rate in parts per million
+    pub trade_fee_rate: u64,
+    /// Unix timestamp after which the pool accepts swaps
+    pub open_time: u64,
+    /// Output per input before the trade, in ui units
+    pub spot_price: f64,
+    /// Output per input we actually get, fees included, in ui units
+    pub execution_price: f64,
+    /// How much worse our fill is than the spot price, fees excluded
+    pub price_impact_bps: u64,
+    /// Trade fee paid, in input token units
+    pub fee_amount: u64,
+    /// Least output we accept once slippage is applied
+    pub minimum_out: u64,
+    /// Slot the pool accounts were read at
+    pub slot: u64,
+}
+
+impl CpmmQuote {
+    /// Re-applies `slippage_bps` to the other side of the quote
+    pub fn set_slippage(&mut self, slippage_bps: u64) -> anyhow::Result<()> {
+        self.other_amount_threshold = crate::raydium::amm::amount_with_slippage(
+            self.other_amount,
+            slippage_bps,
+            !self.amount_specified_is_input,
+        )?;
+        if self.amount_specified_is_input {
+            self.minimum_out = self.other_amount_threshold;
+        }
+        Ok(())
+    }
+
+    pub fn summary(&self) -> QuoteSummary {
+        let wsol = Pubkey::from_str_const(crate::config::WSOL);
+        QuoteSummary {
+            market: self.pool,
+            input_mint: self.input_mint,
+            output_mint: self.output_mint,
+            amount: self.amount,
+            other_amount: self.other_amount,
+            amount_specified_is_input: self.amount_specified_is_input,
+            input_mint_decimals: self.input_mint_decimals,
+            output_mint_decimals: self.output_mint_decimals,
+            spot_price: self.spot_price,
+            execution_price: self.execution_price,
+            price_impact_bps: self.price_impact_bps,
+            fee_amount: self.fee_amount,
+            minimum_out: self.minimum_out,
+            pool_open_time: self.open_time,
+            sol_reserve: if self.input_mint == wsol {
+                Some(self.input_reserve)
+            } else if self.output_mint == wsol {
+                Some(self.output_reserve)
+            } else {
+                None
+            },
+            slot: self.slot,
+            slots_behind: 0,
+        }
+    }
+}
+
+impl std::fmt::Display for CpmmQuote {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} -> {} on cpmm {}: spot {:.12}, execution {:.12}, impact {} bps, fee {}, min out {}, slot {}",
+            self.input_mint,
+            self.output_mint,
+            self.pool,
+            self.spot_price,
+            self.execution_price,
+            self.price_impact_bps,
+            self.fee_amount,
+            self.minimum_out,
+            self.slot
+        )
+    }
+}
+
+/// Quote against a Raydium CLMM pool, with the accounts its `swap_v2` instruction needs
+#[derive(Clone, Debug)]
+pub struct ClmmQuote {
+    pub pool: Pubkey,
+    pub amm_config: Pubkey,
+    pub observation: Pubkey,
+    pub input_vault: Pubkey,
+    pub output_vault: Pubkey,
+    pub input_mint: Pubkey,
+    pub output_mint: Pubkey,
+    /// Owner of the input mint, spl token or token 2022
+    pub input_token_program: Pubkey,
+    pub output_token_program: Pubkey,
+    /// Passed ahead of the tick arrays when the pool has one
+    pub tick_array_bitmap_extension: Option<Pubkey>,
+    /// Tick arrays the swap crosses, in order
+    pub tick_arrays: Vec<Pubkey>,
+    /// The amount specified
+    pub amount: u64,
+    /// The other amount
+    pub other_amount: u64,
+    /// The other amount with slippage
+    pub other_amount_threshold: u64,
+    pub amount_specified_is_input: bool,
+    pub input_mint_decimals: u8,
+    pub output_mint_decimals: u8,
+    /// Vault balances, fees owed to others included
+    pub input_vault_amount: u64,
+    pub output_vault_amount: u64,
+    /// Trade fee rate in parts per million
+    pub trade_fee_rate: u32,
+    /// Unix timestamp after which the pool accepts swaps
+    pub open_time: u64,
+    /// Pool price before and after the swap, Q64.64
+    pub sqrt_price_x64: u128,
+    pub sqrt_price_after_x64: u128,
+    /// Output per input before the trade, in ui units
+    pub spot_price: f64,
+    /// Output per input we actually get, fees included, in ui units
+    pub execution_price: f64,
+    /// How much worse our fill is than the spot price, fees excluded
+    pub price_impact_bps: u64,
+    /// Trade fee paid, in input token units
+    pub fee_amount: u64,
+    /// Least output we accept once slippage is applied
+    pub minimum_out: u64,
+    /// Slot the pool accounts were read at
+    pub slot: u64,
+}
+
+impl ClmmQuote {
+    /// Re-applies `slippage_bps` to the other side of the quote
+    pub fn set_slippage(&mut self, slippage_bps: u64) -> anyhow::Result<()> {
+        self.other_amount_threshold = crate::raydium::amm::amount_with_slippage(
+            self.other_amount,
+            slippage_bps,
+            !self.amount_specified_is_input,
+        )?;
+        if self.amount_specified_is_input {
+            self.minimum_out = self.other_amount_threshold;
+        }
+        Ok(())
+    }
+
+    pub fn summary(&self) -> QuoteSummary {
+        let wsol = Pubkey::from_str_const(crate::config::WSOL);
+        QuoteSummary {
+            market: self.pool,
+            input_mint: self.input_mint,
+            output_mint: self.output_mint,
+            amount: self.amount,
+            other_amount: self.other_amount,
+            amount_specified_is_input: self.amount_specified_is_input,
+            input_mint_decimals: self.input_mint_decimals,
+            output_mint_decimals: self.output_mint_decimals,
+            spot_price: self.spot_price,
+            execution_price: self.execution_price,
+            price_impact_bps: self.price_impact_bps,
+            fee_amount: self.fee_amount,
+            minimum_out: self.minimum_out,
+            pool_open_time: self.open_time,
+            sol_reserve: if self.input_mint == wsol {
+                Some(self.input_vault_amount)
+            } else if self.output_mint == wsol {
+                Some(self.output_vault_amount)
+            } else {
+                None
+            },
+            slot: self.slot,
+            slots_behind: 0,
+        }
+    }
+}
+
+impl std::fmt::Display for ClmmQuote {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} -> {} on clmm {}: spot {:.12}, execution {:.12}, impact {} bps, fee {}, min out {}, {} tick arrays, slot {}",
+            self.input_mint,
+            self.output_mint,
+            self.pool,
+            self.spot_price,
+            self.execution_price,
+            self.price_impact_bps,
+            self.fee_amount,
+            self.minimum_out,
+            self.tick_arrays.len(),
+            self.slot
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct AmmKeys {
     pub amm_pool: Pubkey,
     pub amm_coin_mint: Pubkey,
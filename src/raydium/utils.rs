@@ -35,10 +35,3 @@ pub async fn get_multiple_account_data(
     }
     Ok(accounts_vec)
 }
-#[derive(Debug)]
-pub enum SwapDirection {
-    /// Input token pc, output token coin
-    PC2Coin,
-    /// Input token coin, output token pc
-    Coin2PC,
-}
@@ -0,0 +1,373 @@
+use crate::config::WSOL;
+use crate::journal::Journal;
+use serde::Serialize;
+use solana_sdk::native_token::LAMPORTS_PER_SOL;
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReportFormat {
+    Table,
+    Csv,
+    Json,
+}
+
+impl FromStr for ReportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(ReportFormat::Table),
+            "csv" => Ok(ReportFormat::Csv),
+            "json" => Ok(ReportFormat::Json),
+            _ => anyhow::bail!("Unknown report format {}, expected table, csv or json", s),
+        }
+    }
+}
+
+/// Performance of the copies grouped under one target or one mint.
+///
+/// Open positions are marked at the last journaled quote for their mint.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct CopyStats {
+    pub key: String,
+    /// Buys that landed without error
+    pub copies: u64,
+    /// Copies that returned more than they cost, from what their sells realized and the rest of
+    /// their tokens at the mark
+    pub wins: u64,
+    pub win_rate: Option<f64>,
+    pub invested_sol: f64,
+    pub realized_pnl_sol: f64,
+    pub unrealized_pnl_sol: f64,
+    /// How much worse our fill was than the target's, in bps
+    pub avg_slippage_bps: Option<f64>,
+    /// Slots between the target's trade and our buy landing
+    pub avg_latency_slots: Option<f64>,
+    #[serde(skip)]
+    slippage_bps_sum: f64,
+    #[serde(skip)]
+    slippage_samples: u64,
+    #[serde(skip)]
+    latency_slots_sum: u64,
+    #[serde(skip)]
+    latency_samples: u64,
+}
+
+impl CopyStats {
+    fn add(&mut self, other: &CopyStats) {
+        self.copies += other.copies;
+        self.wins += other.wins;
+        self.invested_sol += other.invested_sol;
+        self.realized_pnl_sol += other.realized_pnl_sol;
+        self.unrealized_pnl_sol += other.unrealized_pnl_sol;
+        self.slippage_bps_sum += other.slippage_bps_sum;
+        self.slippage_samples += other.slippage_samples;
+        self.latency_slots_sum += other.latency_slots_sum;
+        self.latency_samples += other.latency_samples;
+        self.avg_slippage_bps = (self.slippage_samples > 0)
+            .then(|| self.slippage_bps_sum / self.slippage_samples as f64);
+        self.avg_latency_slots = (self.latency_samples > 0)
+            .then(|| self.latency_slots_sum as f64 / self.latency_samples as f64);
+        self.win_rate = (self.copies > 0).then(|| self.wins as f64 / self.copies as f64);
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    pub targets: Vec<CopyStats>,
+    pub mints: Vec<CopyStats>,
+}
+
+/// A landed swap of ours joined with the target trade it copied, in the amounts it landed with
+struct Fill {
+    target: String,
+    mint: String,
+    buy: bool,
+    sol: f64,
+    tokens: f64,
+    target_slot: u64,
+    landed_slot: u64,
+    /// Tokens per SOL the target got
+    target_price: Option<f64>,
+}
+
+/// Holdings and results of following one target on one mint
+#[derive(Default)]
+struct Ledger {
+    stats: CopyStats,
+    tokens_held: f64,
+    cost_held: f64,
+    buys: Vec<Lot>,
+}
+
+/// One copy's share of a ledger
+struct Lot {
+    /// SOL spent on the buy
+    sol: f64,
+    /// Tokens of the buy not sold yet
+    tokens: f64,
+    /// SOL its share of the sells returned
+    proceeds: f64,
+}
+
+impl Report {
+    pub fn from_journal(journal: &Journal) -> anyhow::Result<Self> {
+        let connection = journal.connection();
+        let mut statement = connection.prepare(
+            "SELECT t.target, s.input_mint, s.output_mint, COALESCE(s.landed_amount_in, s.amount_in),
+                COALESCE(s.landed_amount_out, s.amount_out), t.decimals,
+                t.slot, s.slot, t.token_pre_amount, t.token_post_amount, t.sol_pre_amount,
+                t.sol_post_amount
+             FROM sends s JOIN target_trades t ON t.signature = s.target_signature
+             WHERE s.landed = 1 AND s.err IS NULL
+             ORDER BY s.id",
+        )?;
+        let fills = statement
+            .query_map([], |row| {
+                let input_mint: String = row.get(1)?;
+                let output_mint: String = row.get(2)?;
+                let (amount_in, amount_out): (u64, u64) = (row.get(3)?, row.get(4)?);
+                let decimals: u32 = row.get(5)?;
+                let buy = input_mint == WSOL;
+                let (lamports, raw_tokens, mint) = if buy {
+                    (amount_in, amount_out, output_mint)
+                } else {
+                    (amount_out, amount_in, input_mint)
+                };
+                let target_tokens = row.get::<_, f64>(9)? - row.get::<_, f64>(8)?;
+                let target_sol = row.get::<_, f64>(10)? - row.get::<_, f64>(11)?;
+                Ok(Fill {
+                    target: row.get(0)?,
+                    mint,
+                    buy,
+                    sol: lamports as f64 / LAMPORTS_PER_SOL as f64,
+                    tokens: raw_tokens as f64 / 10f64.powi(decimals as i32),
+                    target_slot: row.get(6)?,
+                    landed_slot: row.get(7)?,
+                    target_price: (target_tokens > 0.0 && target_sol > 0.0)
+                        .then(|| target_tokens / target_sol),
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // tokens per SOL from the newest quote of every mint we bought
+        let mut statement = connection.prepare(
+            "SELECT output_mint, spot_price FROM quotes
+             WHERE id IN (SELECT MAX(id) FROM quotes WHERE input_mint = ?1 GROUP BY output_mint)",
+        )?;
+        let marks = statement
+            .query_map([WSOL], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<HashMap<String, f64>, _>>()?;
+
+        Ok(Self::from_fills(fills, &marks))
+    }
+
+    fn from_fills(fills: Vec<Fill>, marks: &HashMap<String, f64>) -> Self {
+        let mut ledgers: BTreeMap<(String, String), Ledger> = BTreeMap::new();
+        for fill in fills {
+            let ledger = ledgers
+                .entry((fill.target.clone(), fill.mint.clone()))
+                .or_default();
+            if fill.buy {
+                ledger.stats.copies += 1;
+                ledger.stats.invested_sol += fill.sol;
+                ledger.tokens_held += fill.tokens;
+                ledger.cost_held += fill.sol;
+                ledger.buys.push(Lot {
+                    sol: fill.sol,
+                    tokens: fill.tokens,
+                    proceeds: 0.0,
+                });
+                if let Some(target_price) = fill.target_price
+                    && fill.sol > 0.0
+                {
+                    let price = fill.tokens / fill.sol;
+                    ledger.stats.slippage_bps_sum += (target_price - price) / target_price * 1e4;
+                    ledger.stats.slippage_samples += 1;
+                }
+                ledger.stats.latency_slots_sum += fill.landed_slot.saturating_sub(fill.target_slot);
+                ledger.stats.latency_samples += 1;
+            } else if ledger.tokens_held > 0.0 {
+                // sells realize against the average cost of what is held
+                let sold = fill.tokens.min(ledger.tokens_held);
+                let cost = ledger.cost_held * sold / ledger.tokens_held;
+                ledger.stats.realized_pnl_sol += fill.sol - cost;
+                for lot in &mut ledger.buys {
+                    lot.proceeds += fill.sol * lot.tokens / ledger.tokens_held;
+                    lot.tokens -= lot.tokens * sold / ledger.tokens_held;
+                }
+                ledger.tokens_held -= sold;
+                ledger.cost_held -= cost;
+            }
+        }
+
+        let mut targets: BTreeMap<String, CopyStats> = BTreeMap::new();
+        let mut mints: BTreeMap<String, CopyStats> = BTreeMap::new();
+        for ((target, mint), mut ledger) in ledgers {
+            let mark = marks.get(&mint).filter(|mark| **mark > 0.0);
+            if let Some(mark) = mark {
+                ledger.stats.unrealized_pnl_sol = ledger.tokens_held / mark - ledger.cost_held;
+            }
+            // tokens still held count for nothing without a mark
+            ledger.stats.wins = ledger
+                .buys
+                .iter()
+                .filter(|lot| lot.proceeds + mark.map_or(0.0, |mark| lot.tokens / mark) > lot.sol)
+                .count() as u64;
+            targets
+                .entry(target.clone())
+                .or_insert_with(|| CopyStats {
+                    key: target,
+                    ..Default::default()
+                })
+                .add(&ledger.stats);
+            mints
+                .entry(mint.clone())
+                .or_insert_with(|| CopyStats {
+                    key: mint,
+                    ..Default::default()
+                })
+                .add(&ledger.stats);
+        }
+        Self {
+            targets: targets.into_values().collect(),
+            mints: mints.into_values().collect(),
+        }
+    }
+
+    pub fn render(&self, format: ReportFormat) -> anyhow::Result<String> {
+        Ok(match format {
+            ReportFormat::Table => format!(
+                "Targets\n{}\nMints\n{}",
+                table(&self.targets),
+                table(&self.mints)
+            ),
+            ReportFormat::Csv => {
+                let mut csv = "group,key,copies,win_rate,invested_sol,realized_pnl_sol,\
+                    unrealized_pnl_sol,avg_slippage_bps,avg_latency_slots\n"
+                    .to_string();
+                for (group, stats) in [("target", &self.targets), ("mint", &self.mints)] {
+                    for stats in stats {
+                        csv.push_str(&format!(
+                            "{},{},{},{},{},{},{},{},{}\n",
+                            group,
+                            stats.key,
+                            stats.copies,
+                            optional(stats.win_rate),
+                            stats.invested_sol,
+                            stats.realized_pnl_sol,
+                            stats.unrealized_pnl_sol,
+                            optional(stats.avg_slippage_bps),
+                            optional(stats.avg_latency_slots)
+                        ));
+                    }
+                }
+                csv
+            }
+            ReportFormat::Json => serde_json::to_string_pretty(self)?,
+        })
+    }
+}
+
+fn optional(value: Option<f64>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+fn table(stats: &[CopyStats]) -> String {
+    let mut table = format!(
+        "{:<44} {:>6} {:>8} {:>12} {:>12} {:>12} {:>10} {:>8}\n",
+        "", "copies", "win rate", "invested", "realized", "unrealized", "slippage", "latency"
+    );
+    for stats in stats {
+        table.push_str(&format!(
+            "{:<44} {:>6} {:>8} {:>12.6} {:>12.6} {:>12.6} {:>10} {:>8}\n",
+            stats.key,
+            stats.copies,
+            stats
+                .win_rate
+                .map(|rate| format!("{:.1}%", rate * 100.0))
+                .unwrap_or_default(),
+            stats.invested_sol,
+            stats.realized_pnl_sol,
+            stats.unrealized_pnl_sol,
+            stats
+                .avg_slippage_bps
+                .map(|bps| format!("{:.0} bps", bps))
+                .unwrap_or_default(),
+            stats
+                .avg_latency_slots
+                .map(|slots| format!("{:.1}", slots))
+                .unwrap_or_default(),
+        ));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Fill, Report, ReportFormat};
+    use std::collections::HashMap;
+
+    fn buy(target: &str, sol: f64, tokens: f64) -> Fill {
+        Fill {
+            target: target.to_string(),
+            mint: "mint".to_string(),
+            buy: true,
+            sol,
+            tokens,
+            target_slot: 100,
+            landed_slot: 102,
+            target_price: Some(1000.0),
+        }
+    }
+
+    #[test]
+    fn marks_open_positions_and_realizes_sells() {
+        let fills = vec![
+            buy("a", 1.0, 1000.0),
+            buy("a", 1.0, 500.0),
+            Fill {
+                buy: false,
+                sol: 1.0,
+                tokens: 750.0,
+                ..buy("a", 0.0, 0.0)
+            },
+        ];
+        // 800 tokens per SOL now
+        let marks = HashMap::from([("mint".to_string(), 800.0)]);
+        let report = Report::from_fills(fills, &marks);
+        let stats = &report.targets[0];
+        assert_eq!((stats.copies, stats.wins), (2, 1));
+        assert_eq!(stats.invested_sol, 2.0);
+        // half the tokens sold for 1 SOL against 1 SOL of cost
+        assert_eq!(stats.realized_pnl_sol, 0.0);
+        assert_eq!(stats.unrealized_pnl_sol, 750.0 / 800.0 - 1.0);
+        // second buy got half the target's price
+        assert_eq!(stats.avg_slippage_bps, Some(2500.0));
+        assert_eq!(stats.avg_latency_slots, Some(2.0));
+        assert_eq!(report.mints[0].copies, 2);
+    }
+
+    #[test]
+    fn exited_copies_win_on_what_they_realized() {
+        let fills = vec![
+            buy("a", 1.0, 1000.0),
+            buy("a", 1.0, 1000.0),
+            Fill {
+                buy: false,
+                sol: 3.0,
+                tokens: 2000.0,
+                ..buy("a", 0.0, 0.0)
+            },
+        ];
+        // no quote for the mint since we left it
+        let report = Report::from_fills(fills, &HashMap::new());
+        let stats = &report.targets[0];
+        assert_eq!((stats.copies, stats.wins), (2, 2));
+        assert_eq!(stats.win_rate, Some(1.0));
+        assert_eq!(stats.realized_pnl_sol, 1.0);
+        let json = report.render(ReportFormat::Json).unwrap();
+        assert!(json.contains("\"win_rate\": 1.0"));
+    }
+}
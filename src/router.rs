@@ -0,0 +1,415 @@
+use crate::raydium::quote_error::QuoteError;
+use crate::raydium::types::{QuoteSummary, SwapInput};
+use async_trait::async_trait;
+use futures::future::join_all;
+use log::debug;
+use solana_sdk::address_lookup_table::AddressLookupTableAccount;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::pubkey::Pubkey;
+use std::any::Any;
+
+/// Somewhere a mint pair can be swapped. Implemented per pool type so the router and the engine
+/// don't care which one a copy trades on.
+#[async_trait]
+pub trait SwapVenue: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Whether the venue can trade the pair at all, decided without going to the network
+    fn supports(&self, input_mint: &Pubkey, output_mint: &Pubkey) -> bool;
+
+    async fn quote(&self, swap_input: &SwapInput) -> Result<VenueQuote, QuoteError>;
+
+    /// Fails if `quote` came from another venue
+    async fn swap_instructions(
+        &self,
+        user: Pubkey,
+        quote: &VenueQuote,
+    ) -> anyhow::Result<Vec<Instruction>>;
+
+    /// Accounts the swap built from `quote` writes to
+    fn required_accounts(&self, quote: &VenueQuote) -> Vec<Pubkey>;
+
+    async fn lookup_tables(
+        &self,
+        quote: &VenueQuote,
+    ) -> anyhow::Result<Vec<AddressLookupTableAccount>>;
+}
+
+/// What the router and the engine need of a venue's own quote type. Venues get theirs back
+/// with [`VenueQuote::downcast_ref`].
+pub trait Quote: Any + std::fmt::Debug + std::fmt::Display + Send + Sync {
+    fn summary(&self) -> QuoteSummary;
+
+    fn set_slippage(&mut self, slippage_bps: u64) -> anyhow::Result<()>;
+}
+
+/// A quote from any venue
+#[derive(Debug)]
+pub struct VenueQuote(Box<dyn Quote>);
+
+impl VenueQuote {
+    pub fn new(quote: impl Quote) -> Self {
+        Self(Box::new(quote))
+    }
+
+    /// The venue's own quote. None if this one came from another venue
+    pub fn downcast_ref<T: Quote>(&self) -> Option<&T> {
+        (self.0.as_ref() as &dyn Any).downcast_ref()
+    }
+
+    pub fn summary(&self) -> QuoteSummary {
+        self.0.summary()
+    }
+
+    pub fn set_slippage(&mut self, slippage_bps: u64) -> anyhow::Result<()> {
+        self.0.set_slippage(slippage_bps)
+    }
+
+    /// Higher is better: the output of an exact in quote, the negated input of an exact out one.
+    /// Fees are already taken out of both.
+    fn score(&self) -> i128 {
+        let summary = self.summary();
+        if summary.amount_specified_is_input {
+            summary.other_amount as i128
+        } else {
+            -(summary.other_amount as i128)
+        }
+    }
+}
+
+impl std::fmt::Display for VenueQuote {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// A venue that only quotes `market`, the pool a target traded on, whatever market the swap
+/// input names
+pub struct PinnedVenue {
+    venue: Box<dyn SwapVenue>,
+    market: Pubkey,
+}
+
+impl PinnedVenue {
+    pub fn new(venue: Box<dyn SwapVenue>, market: Pubkey) -> Self {
+        Self { venue, market }
+    }
+}
+
+#[async_trait]
+impl SwapVenue for PinnedVenue {
+    fn name(&self) -> &'static str {
+        self.venue.name()
+    }
+
+    fn supports(&self, input_mint: &Pubkey, output_mint: &Pubkey) -> bool {
+        self.venue.supports(input_mint, output_mint)
+    }
+
+    async fn quote(&self, swap_input: &SwapInput) -> Result<VenueQuote, QuoteError> {
+        let swap_input = SwapInput {
+            market: Some(self.market),
+            ..*swap_input
+        };
+        self.venue.quote(&swap_input).await
+    }
+
+    async fn swap_instructions(
+        &self,
+        user: Pubkey,
+        quote: &VenueQuote,
+    ) -> anyhow::Result<Vec<Instruction>> {
+        self.venue.swap_instructions(user, quote).await
+    }
+
+    fn required_accounts(&self, quote: &VenueQuote) -> Vec<Pubkey> {
+        self.venue.required_accounts(quote)
+    }
+
+    async fn lookup_tables(
+        &self,
+        quote: &VenueQuote,
+    ) -> anyhow::Result<Vec<AddressLookupTableAccount>> {
+        self.venue.lookup_tables(quote).await
+    }
+}
+
+/// The venue a swap goes through and its quote
+pub struct Route<'a> {
+    pub venue: &'a dyn SwapVenue,
+    pub quote: VenueQuote,
+    /// Why the venues that didn't quote failed
+    pub errors: Vec<QuoteError>,
+}
+
+impl Route<'_> {
+    /// Whether a venue refused to quote because `mint`'s bonding curve completed. Losing on
+    /// price doesn't count, the curve may still be trading
+    pub fn curve_completed(&self, mint: &Pubkey) -> bool {
+        self.errors
+            .iter()
+            .any(|e| matches!(e, QuoteError::CurveComplete(completed) if completed == mint))
+    }
+}
+
+/// Picks the venue giving the best output for a swap.
+pub struct Router {
+    venues: Vec<Box<dyn SwapVenue>>,
+}
+
+impl Router {
+    pub fn new(venues: Vec<Box<dyn SwapVenue>>) -> Self {
+        Self { venues }
+    }
+
+    /// Quotes every venue supporting the pair at once and keeps the best output after fees.
+    /// Fails with the last venue's error when none can quote.
+    pub async fn best_quote(&self, swap_input: &SwapInput) -> Result<Route<'_>, QuoteError> {
+        let venues = self
+            .venues
+            .iter()
+            .filter(|venue| {
+                venue.supports(&swap_input.input_token_mint, &swap_input.output_token_mint)
+            })
+            .collect::<Vec<_>>();
+        let quotes = join_all(venues.iter().map(|venue| venue.quote(swap_input))).await;
+
+        let mut best: Option<(&dyn SwapVenue, VenueQuote)> = None;
+        let mut errors = vec![];
+        for (venue, quote) in venues.into_iter().zip(quotes) {
+            match quote {
+                Ok(quote) => {
+                    if best
+                        .as_ref()
+                        .is_none_or(|(_, best)| quote.score() > best.score())
+                    {
+                        best = Some((venue.as_ref(), quote));
+                    }
+                }
+                Err(e) => {
+                    debug!("No quote from {}: {}", venue.name(), e);
+                    errors.push(e);
+                }
+            }
+        }
+        match best {
+            Some((venue, quote)) => Ok(Route {
+                venue,
+                quote,
+                errors,
+            }),
+            None => Err(errors.pop().unwrap_or(QuoteError::PoolNotFound {
+                input_mint: swap_input.input_token_mint,
+                output_mint: swap_input.output_token_mint,
+            })),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Quote, Router, SwapVenue, VenueQuote};
+    use crate::raydium::quote_error::QuoteError;
+    use crate::raydium::types::{QuoteSummary, SwapExecutionMode, SwapInput};
+    use async_trait::async_trait;
+    use solana_sdk::address_lookup_table::AddressLookupTableAccount;
+    use solana_sdk::instruction::Instruction;
+    use solana_sdk::pubkey::Pubkey;
+
+    #[derive(Debug)]
+    struct StubQuote(QuoteSummary);
+
+    impl std::fmt::Display for StubQuote {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}", self.0.other_amount)
+        }
+    }
+
+    impl Quote for StubQuote {
+        fn summary(&self) -> QuoteSummary {
+            self.0
+        }
+
+        fn set_slippage(&mut self, _slippage_bps: u64) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Quotes `other_amount` for whatever it's asked, or fails when that is None. A completed
+    /// curve fails the way pump.fun does
+    struct StubVenue {
+        name: &'static str,
+        other_amount: Option<u64>,
+        supported: bool,
+        curve_complete: bool,
+    }
+
+    fn venue(name: &'static str, other_amount: Option<u64>) -> Box<dyn SwapVenue> {
+        Box::new(StubVenue {
+            name,
+            other_amount,
+            supported: true,
+            curve_complete: false,
+        })
+    }
+
+    fn unsupported(other_amount: u64) -> Box<dyn SwapVenue> {
+        Box::new(StubVenue {
+            name: "unsupported",
+            other_amount: Some(other_amount),
+            supported: false,
+            curve_complete: false,
+        })
+    }
+
+    fn completed_curve() -> Box<dyn SwapVenue> {
+        Box::new(StubVenue {
+            name: "completed curve",
+            other_amount: None,
+            supported: true,
+            curve_complete: true,
+        })
+    }
+
+    #[async_trait]
+    impl SwapVenue for StubVenue {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn supports(&self, _input_mint: &Pubkey, _output_mint: &Pubkey) -> bool {
+            self.supported
+        }
+
+        async fn quote(&self, swap_input: &SwapInput) -> Result<VenueQuote, QuoteError> {
+            if self.curve_complete {
+                return Err(QuoteError::CurveComplete(swap_input.output_token_mint));
+            }
+            let other_amount = self
+                .other_amount
+                .ok_or(QuoteError::AccountMissing(Pubkey::default()))?;
+            Ok(VenueQuote::new(StubQuote(QuoteSummary {
+                market: Pubkey::default(),
+                input_mint: swap_input.input_token_mint,
+                output_mint: swap_input.output_token_mint,
+                amount: swap_input.amount,
+                other_amount,
+                amount_specified_is_input: swap_input.mode.amount_specified_is_input(),
+                input_mint_decimals: 9,
+                output_mint_decimals: 6,
+                spot_price: 0.,
+                execution_price: 0.,
+                price_impact_bps: 0,
+                fee_amount: 0,
+                minimum_out: 0,
+                pool_open_time: 0,
+                sol_reserve: None,
+                slot: 0,
+                slots_behind: 0,
+            })))
+        }
+
+        async fn swap_instructions(
+            &self,
+            _user: Pubkey,
+            _quote: &VenueQuote,
+        ) -> anyhow::Result<Vec<Instruction>> {
+            Ok(vec![])
+        }
+
+        fn required_accounts(&self, _quote: &VenueQuote) -> Vec<Pubkey> {
+            vec![]
+        }
+
+        async fn lookup_tables(
+            &self,
+            _quote: &VenueQuote,
+        ) -> anyhow::Result<Vec<AddressLookupTableAccount>> {
+            Ok(vec![])
+        }
+    }
+
+    fn swap_input(mode: SwapExecutionMode) -> SwapInput {
+        SwapInput {
+            input_token_mint: Pubkey::new_unique(),
+            output_token_mint: Pubkey::new_unique(),
+            slippage_bps: 100,
+            amount: 1_000,
+            mode,
+            market: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn picks_the_most_output_and_skips_failing_venues() {
+        let router = Router::new(vec![
+            venue("low", Some(100)),
+            venue("failing", None),
+            venue("high", Some(300)),
+            unsupported(1_000_000),
+            venue("middle", Some(200)),
+        ]);
+        let route = router
+            .best_quote(&swap_input(SwapExecutionMode::ExactIn))
+            .await
+            .unwrap();
+        assert_eq!(route.venue.name(), "high");
+        assert_eq!(route.quote.summary().other_amount, 300);
+        assert!(route.quote.downcast_ref::<StubQuote>().is_some());
+
+        // nothing quotes, the error is passed on
+        let router = Router::new(vec![venue("failing", None)]);
+        assert!(matches!(
+            router
+                .best_quote(&swap_input(SwapExecutionMode::ExactIn))
+                .await,
+            Err(QuoteError::AccountMissing(_))
+        ));
+        let router = Router::new(vec![unsupported(1)]);
+        assert!(matches!(
+            router
+                .best_quote(&swap_input(SwapExecutionMode::ExactIn))
+                .await,
+            Err(QuoteError::PoolNotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn exact_out_quotes_prefer_the_least_input() {
+        let router = Router::new(vec![
+            venue("expensive", Some(300)),
+            venue("cheap", Some(100)),
+            venue("failing", None),
+        ]);
+        let route = router
+            .best_quote(&swap_input(SwapExecutionMode::ExactOut))
+            .await
+            .unwrap();
+        assert_eq!(route.venue.name(), "cheap");
+    }
+    #[tokio::test]
+    async fn graduates_only_off_completed_curves() {
+        let swap_input = swap_input(SwapExecutionMode::ExactIn);
+        let mint = swap_input.output_token_mint;
+        // a live curve losing on price to a pool is still trading
+        let router = Router::new(vec![venue("curve", Some(100)), venue("pool", Some(300))]);
+        let route = router.best_quote(&swap_input).await.unwrap();
+        assert_eq!(route.venue.name(), "pool");
+        assert!(!route.curve_completed(&mint));
+        // failing for another reason isn't a migration either
+        let router = Router::new(vec![venue("curve", None), venue("pool", Some(300))]);
+        assert!(
+            !router
+                .best_quote(&swap_input)
+                .await
+                .unwrap()
+                .curve_completed(&mint)
+        );
+
+        let router = Router::new(vec![completed_curve(), venue("pool", Some(300))]);
+        let route = router.best_quote(&swap_input).await.unwrap();
+        assert_eq!(route.venue.name(), "pool");
+        assert!(route.curve_completed(&mint));
+        assert!(!route.curve_completed(&Pubkey::new_unique()));
+    }
+}
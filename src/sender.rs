@@ -0,0 +1,629 @@
+use crate::config::{WSOL, env_or};
+use crate::lookup_table::compile_message;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use futures::stream::{FuturesUnordered, StreamExt};
+use log::{debug, warn};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::nonce_utils::nonblocking as nonce_utils;
+use solana_client::rpc_config::{RpcSendTransactionConfig, RpcTransactionConfig};
+use solana_sdk::address_lookup_table::AddressLookupTableAccount;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::hash::Hash;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signature, Signer};
+use solana_sdk::system_instruction;
+use solana_sdk::transaction::{TransactionError, VersionedTransaction};
+use solana_transaction_status_client_types::{
+    TransactionStatus, UiTransactionEncoding, UiTransactionStatusMeta, UiTransactionTokenBalance,
+};
+use std::collections::HashMap;
+use std::env;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{Duration, interval, sleep, timeout};
+
+#[derive(Clone, Debug)]
+pub struct SenderConfig {
+    /// Rpc urls every transaction is broadcast to on the rpc path. Empty means the primary rpc
+    pub send_endpoints: Vec<String>,
+    pub send_path: SendPath,
+    pub skip_preflight: bool,
+    /// How often the same signed transaction is sent again while it isn't confirmed
+    pub rebroadcast_interval_ms: u64,
+    /// How long a single send endpoint gets to accept a transaction
+    pub send_timeout_ms: u64,
+    /// How many times a buy is quoted and built again after its blockhash expired
+    pub max_requotes: u32,
+    pub blockhash_refresh_ms: u64,
+    /// Durable nonce accounts, owned by our wallet, used instead of recent blockhashes. Each
+    /// send holds one of them until it is done, so there are at most this many in flight
+    pub nonce_accounts: Vec<Pubkey>,
+}
+
+impl SenderConfig {
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(Self {
+            send_endpoints: env::var("SEND_ENDPOINTS")
+                .map(|endpoints| {
+                    endpoints
+                        .split(',')
+                        .map(|endpoint| endpoint.trim().to_string())
+                        .filter(|endpoint| !endpoint.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            send_path: env_or("SEND_PATH", SendPath::Rpc),
+            skip_preflight: env_or("SEND_SKIP_PREFLIGHT", true),
+            rebroadcast_interval_ms: env_or("SEND_REBROADCAST_MS", 400),
+            send_timeout_ms: env_or("SEND_TIMEOUT_MS", 1000),
+            max_requotes: env_or("SEND_MAX_REQUOTES", 2),
+            blockhash_refresh_ms: env_or("BLOCKHASH_REFRESH_MS", 1000),
+            nonce_accounts: env::var("NONCE_ACCOUNTS")
+                .or_else(|_| env::var("NONCE_ACCOUNT"))
+                .map(|accounts| {
+                    accounts
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|account| !account.is_empty())
+                        .map(Pubkey::from_str)
+                        .collect::<Result<Vec<_>, _>>()
+                })
+                .unwrap_or(Ok(vec![]))?,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct CachedBlockhash {
+    blockhash: Hash,
+    last_valid_block_height: u64,
+}
+
+/// Latest blockhash, kept fresh by a background task so sending never waits on it.
+#[derive(Clone)]
+pub struct BlockhashCache {
+    latest: Arc<RwLock<CachedBlockhash>>,
+}
+
+impl BlockhashCache {
+    pub async fn start(client: Arc<RpcClient>, refresh_ms: u64) -> anyhow::Result<Self> {
+        let (blockhash, last_valid_block_height) = client
+            .get_latest_blockhash_with_commitment(CommitmentConfig::confirmed())
+            .await?;
+        let cache = Self {
+            latest: Arc::new(RwLock::new(CachedBlockhash {
+                blockhash,
+                last_valid_block_height,
+            })),
+        };
+
+        let latest = Arc::clone(&cache.latest);
+        tokio::spawn(async move {
+            let mut timer = interval(Duration::from_millis(refresh_ms));
+            loop {
+                timer.tick().await;
+                match client
+                    .get_latest_blockhash_with_commitment(CommitmentConfig::confirmed())
+                    .await
+                {
+                    Ok((blockhash, last_valid_block_height)) => {
+                        *latest.write().unwrap() = CachedBlockhash {
+                            blockhash,
+                            last_valid_block_height,
+                        };
+                    }
+                    Err(e) => warn!("Failed to refresh blockhash: {}", e),
+                }
+            }
+        });
+        Ok(cache)
+    }
+
+    /// The blockhash and the last block height it is valid for
+    pub fn latest(&self) -> (Hash, u64) {
+        let latest = self.latest.read().unwrap();
+        (latest.blockhash, latest.last_valid_block_height)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum SendOutcome {
+    /// Confirmed on chain, possibly with an error
+    Landed {
+        signature: Signature,
+        /// Endpoint that first accepted the broadcast the transaction landed from
+        endpoint: Option<String>,
+        slot: u64,
+        err: Option<TransactionError>,
+        compute_units: Option<u64>,
+        fee: Option<u64>,
+        /// What the transaction moved in and out of the signer's accounts
+        balance_changes: Option<BalanceChanges>,
+    },
+    /// The blockhash expired, or the nonce advanced, before the transaction was confirmed
+    Expired { signature: Signature },
+}
+
+impl SendOutcome {
+    pub fn is_success(&self) -> bool {
+        matches!(self, SendOutcome::Landed { err: None, .. })
+    }
+}
+
+/// Balances a landed transaction changed for its fee payer
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BalanceChanges {
+    /// Lamports gained, with the fee added back
+    pub lamports: i128,
+    /// Tokens gained in accounts the fee payer owns, by mint
+    pub tokens: HashMap<Pubkey, i128>,
+}
+
+impl BalanceChanges {
+    fn from_meta(meta: &UiTransactionStatusMeta, payer: &Pubkey) -> Option<Self> {
+        let lamports = *meta.post_balances.first()? as i128 - *meta.pre_balances.first()? as i128
+            + meta.fee as i128;
+        let mut tokens = HashMap::new();
+        let owned = |balances: &Option<Vec<UiTransactionTokenBalance>>| {
+            balances
+                .iter()
+                .flatten()
+                .filter(|balance| {
+                    Option::<&String>::from(balance.owner.as_ref())
+                        .is_some_and(|owner| *owner == payer.to_string())
+                })
+                .filter_map(|balance| {
+                    Some((
+                        Pubkey::from_str(&balance.mint).ok()?,
+                        balance.ui_token_amount.amount.parse::<i128>().ok()?,
+                    ))
+                })
+                .collect::<Vec<_>>()
+        };
+        for (mint, amount) in owned(&meta.post_token_balances.clone().into()) {
+            *tokens.entry(mint).or_default() += amount;
+        }
+        for (mint, amount) in owned(&meta.pre_token_balances.clone().into()) {
+            *tokens.entry(mint).or_default() -= amount;
+        }
+        Some(Self { lamports, tokens })
+    }
+
+    /// Net amount of `mint` gained. For wsol that is the SOL gained, since it is wrapped and
+    /// unwrapped within the swap, which includes rent of token accounts opened or closed.
+    pub fn of(&self, mint: &Pubkey) -> i128 {
+        let tokens = self.tokens.get(mint).copied().unwrap_or(0);
+        if *mint == Pubkey::from_str_const(WSOL) {
+            tokens + self.lamports
+        } else {
+            tokens
+        }
+    }
+}
+
+impl std::fmt::Display for SendOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SendOutcome::Landed {
+                signature,
+                endpoint,
+                slot,
+                err,
+                compute_units,
+                fee,
+                ..
+            } => {
+                write!(f, "{} landed in slot {}", signature, slot)?;
+                if let Some(endpoint) = endpoint {
+                    write!(f, " via {}", endpoint)?;
+                }
+                if let Some(err) = err {
+                    write!(f, " with error {}", err)?;
+                }
+                write!(f, ", cu {:?}, fee {:?}", compute_units, fee)
+            }
+            SendOutcome::Expired { signature } => write!(f, "{} expired", signature),
+        }
+    }
+}
+
+/// Hands a signed transaction to the network. Implemented for rpc fan-out and direct TPU
+/// submission so the send loop doesn't care which route is used.
+#[async_trait]
+pub trait TransactionSubmitter: Send + Sync {
+    /// Returns the route that accepted the transaction first, if any did
+    async fn submit(&self, transaction: &VersionedTransaction) -> anyhow::Result<Option<String>>;
+}
+
+/// Where signed transactions are submitted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SendPath {
+    #[default]
+    Rpc,
+    Tpu,
+}
+
+impl FromStr for SendPath {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "rpc" => Ok(SendPath::Rpc),
+            "tpu" => Ok(SendPath::Tpu),
+            x => Err(anyhow!("Unknown send path {}", x)),
+        }
+    }
+}
+
+/// Sends to every configured rpc endpoint at once.
+pub struct RpcSubmitter {
+    endpoints: Vec<(String, Arc<RpcClient>)>,
+    config: RpcSendTransactionConfig,
+    timeout: Duration,
+}
+
+impl RpcSubmitter {
+    /// Falls back to `client` when no send endpoints are configured
+    pub fn new(client: &Arc<RpcClient>, config: &SenderConfig) -> Self {
+        let endpoints = if config.send_endpoints.is_empty() {
+            vec![(client.url(), Arc::clone(client))]
+        } else {
+            config
+                .send_endpoints
+                .iter()
+                .map(|url| (url.clone(), Arc::new(RpcClient::new(url.clone()))))
+                .collect()
+        };
+        Self {
+            endpoints,
+            config: RpcSendTransactionConfig {
+                skip_preflight: config.skip_preflight,
+                max_retries: Some(0),
+                ..Default::default()
+            },
+            timeout: Duration::from_millis(config.send_timeout_ms),
+        }
+    }
+}
+
+#[async_trait]
+impl TransactionSubmitter for RpcSubmitter {
+    /// Returns as soon as one endpoint accepts, the others keep sending in the background so a
+    /// slow endpoint never holds up the send loop
+    async fn submit(&self, transaction: &VersionedTransaction) -> anyhow::Result<Option<String>> {
+        let mut sends = self
+            .endpoints
+            .iter()
+            .map(|(url, client)| {
+                let (url, client, transaction) =
+                    (url.clone(), Arc::clone(client), transaction.clone());
+                let (config, send_timeout) = (self.config, self.timeout);
+                tokio::spawn(async move {
+                    let result = match timeout(
+                        send_timeout,
+                        client.send_transaction_with_config(&transaction, config),
+                    )
+                    .await
+                    {
+                        Ok(result) => result.map_err(anyhow::Error::from),
+                        Err(_) => Err(anyhow!("timed out after {:?}", send_timeout)),
+                    };
+                    if let Err(e) = &result {
+                        debug!("Send to {} failed: {}", url, e);
+                    }
+                    (url, result)
+                })
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        let mut last_err = None;
+        while let Some(send) = sends.next().await {
+            match send? {
+                (url, Ok(_)) => return Ok(Some(url)),
+                (_, Err(e)) => last_err = Some(e),
+            }
+        }
+        // with preflight on, every endpoint refusing means the transaction would fail
+        match last_err {
+            Some(e) if !self.config.skip_preflight => Err(e),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Nonce accounts not used by a send right now
+#[derive(Clone)]
+struct NoncePool {
+    free: Arc<Mutex<Vec<Pubkey>>>,
+    permits: Arc<Semaphore>,
+}
+
+impl NoncePool {
+    fn new(accounts: &[Pubkey]) -> Self {
+        Self {
+            free: Arc::new(Mutex::new(accounts.to_vec())),
+            permits: Arc::new(Semaphore::new(accounts.len())),
+        }
+    }
+
+    /// Waits until an account is free
+    async fn acquire(&self) -> anyhow::Result<NonceLease> {
+        let permit = Arc::clone(&self.permits).acquire_owned().await?;
+        let account = self
+            .free
+            .lock()
+            .unwrap()
+            .pop()
+            .ok_or_else(|| anyhow!("No nonce account left despite a permit"))?;
+        Ok(NonceLease {
+            account,
+            pool: self.clone(),
+            _permit: permit,
+        })
+    }
+}
+
+/// A nonce account held by one send, handed back when dropped
+struct NonceLease {
+    account: Pubkey,
+    pool: NoncePool,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Drop for NonceLease {
+    fn drop(&mut self) {
+        self.pool.free.lock().unwrap().push(self.account);
+    }
+}
+
+#[derive(Clone)]
+pub struct TransactionSender {
+    /// Reads (statuses, block height, landed transactions) only go here
+    client: Arc<RpcClient>,
+    submitter: Arc<dyn TransactionSubmitter>,
+    blockhashes: BlockhashCache,
+    nonces: Option<NoncePool>,
+    config: SenderConfig,
+}
+
+impl TransactionSender {
+    pub fn new(
+        client: Arc<RpcClient>,
+        submitter: Arc<dyn TransactionSubmitter>,
+        blockhashes: BlockhashCache,
+        config: SenderConfig,
+    ) -> Self {
+        Self {
+            client,
+            submitter,
+            blockhashes,
+            nonces: (!config.nonce_accounts.is_empty())
+                .then(|| NoncePool::new(&config.nonce_accounts)),
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &SenderConfig {
+        &self.config
+    }
+
+    /// Builds and signs a transaction from `instructions`, v0 when `lookup_tables` are given,
+    /// then sends it until it is confirmed
+    /// or can no longer land.
+    ///
+    /// With nonce accounts configured the transaction uses the nonce stored in one held for the
+    /// whole send instead of a recent blockhash. It is given up on once a recent blockhash would
+    /// have expired, and the nonce is advanced then so it can't land any later.
+    pub async fn send(
+        &self,
+        mut instructions: Vec<Instruction>,
+        lookup_tables: &[AddressLookupTableAccount],
+        signer: &Keypair,
+    ) -> anyhow::Result<SendOutcome> {
+        let nonce = match &self.nonces {
+            Some(nonces) => Some(nonces.acquire().await?),
+            None => None,
+        };
+        let (mut blockhash, last_valid_block_height) = self.blockhashes.latest();
+        if let Some(nonce) = &nonce {
+            instructions.insert(
+                0,
+                system_instruction::advance_nonce_account(&nonce.account, &signer.pubkey()),
+            );
+            blockhash = self.nonce_blockhash(&nonce.account).await?;
+        }
+        let message = compile_message(&signer.pubkey(), &instructions, lookup_tables, blockhash)?;
+        let transaction = VersionedTransaction::try_new(message, &[signer])?;
+        let signature = *transaction
+            .signatures
+            .first()
+            .ok_or_else(|| anyhow!("Transaction has no signature"))?;
+
+        // every broadcast with the slot seen before it and the endpoint that accepted it first
+        let mut broadcasts = vec![];
+        let mut slot = 0;
+        loop {
+            // leaders dedup by signature, sending again only helps it reach one
+            let accepted_by = self.submitter.submit(&transaction).await?;
+            broadcasts.push((slot, accepted_by));
+            sleep(Duration::from_millis(self.config.rebroadcast_interval_ms)).await;
+
+            let status;
+            (slot, status) = self.confirmed_status(&signature).await?;
+            if let Some(status) = status {
+                let endpoint = landed_from(&broadcasts, status.slot);
+                return Ok(self.landed(signature, endpoint, status, signer).await);
+            }
+            let mut expired = self.client.get_block_height().await? > last_valid_block_height;
+            if let Some(nonce) = &nonce {
+                // the nonce moved on, either we landed or something else used it
+                if self.nonce_blockhash(&nonce.account).await? != blockhash {
+                    expired = true;
+                } else if expired {
+                    self.retire_nonce(&nonce.account, signer).await?;
+                }
+            }
+            if expired {
+                // it may have been confirmed right before the check
+                return Ok(match self.confirmed_status(&signature).await?.1 {
+                    Some(status) => {
+                        let endpoint = landed_from(&broadcasts, status.slot);
+                        self.landed(signature, endpoint, status, signer).await
+                    }
+                    None => SendOutcome::Expired { signature },
+                });
+            }
+        }
+    }
+
+    /// The rpc's current slot and the status of `signature` once it is confirmed
+    async fn confirmed_status(
+        &self,
+        signature: &Signature,
+    ) -> anyhow::Result<(u64, Option<TransactionStatus>)> {
+        let response = self.client.get_signature_statuses(&[*signature]).await?;
+        let status = response.value.into_iter().next().flatten();
+        Ok((
+            response.context.slot,
+            status.filter(|status| status.satisfies_commitment(CommitmentConfig::confirmed())),
+        ))
+    }
+
+    async fn landed(
+        &self,
+        signature: Signature,
+        endpoint: Option<String>,
+        status: TransactionStatus,
+        signer: &Keypair,
+    ) -> SendOutcome {
+        let meta = self.meta_of(&signature).await;
+        SendOutcome::Landed {
+            signature,
+            endpoint,
+            slot: status.slot,
+            err: status.err,
+            compute_units: meta
+                .as_ref()
+                .and_then(|meta| meta.compute_units_consumed.clone().into()),
+            fee: meta.as_ref().map(|meta| meta.fee),
+            balance_changes: meta
+                .as_ref()
+                .and_then(|meta| BalanceChanges::from_meta(meta, &signer.pubkey())),
+        }
+    }
+
+    /// Advances a nonce account with a recent blockhash, so nothing built on its current nonce
+    /// can land anymore
+    async fn retire_nonce(&self, nonce_account: &Pubkey, signer: &Keypair) -> anyhow::Result<()> {
+        let (blockhash, last_valid_block_height) = self.blockhashes.latest();
+        let instructions = [system_instruction::advance_nonce_account(
+            nonce_account,
+            &signer.pubkey(),
+        )];
+        let message = compile_message(&signer.pubkey(), &instructions, &[], blockhash)?;
+        let transaction = VersionedTransaction::try_new(message, &[signer])?;
+        loop {
+            self.submitter.submit(&transaction).await?;
+            sleep(Duration::from_millis(self.config.rebroadcast_interval_ms)).await;
+            if self
+                .confirmed_status(&transaction.signatures[0])
+                .await?
+                .1
+                .is_some()
+            {
+                return Ok(());
+            }
+            if self.client.get_block_height().await? > last_valid_block_height {
+                return Err(anyhow!("Failed to advance nonce account {}", nonce_account));
+            }
+        }
+    }
+
+    /// Blockhash currently stored in a durable nonce account
+    async fn nonce_blockhash(&self, nonce_account: &Pubkey) -> anyhow::Result<Hash> {
+        let account = nonce_utils::get_account_with_commitment(
+            &self.client,
+            nonce_account,
+            CommitmentConfig::confirmed(),
+        )
+        .await?;
+        Ok(nonce_utils::data_from_account(&account)?.blockhash())
+    }
+
+    /// Status meta of a confirmed transaction
+    async fn meta_of(&self, signature: &Signature) -> Option<UiTransactionStatusMeta> {
+        let config = RpcTransactionConfig {
+            encoding: Some(UiTransactionEncoding::Base64),
+            commitment: Some(CommitmentConfig::confirmed()),
+            max_supported_transaction_version: Some(0),
+        };
+        match self
+            .client
+            .get_transaction_with_config(signature, config)
+            .await
+        {
+            Ok(transaction) => transaction.transaction.meta,
+            Err(e) => {
+                debug!("Failed to fetch {}: {}", signature, e);
+                None
+            }
+        }
+    }
+}
+
+/// Endpoint of the last accepted broadcast sent before `landed_slot`. The signature is the same
+/// every time, but an earlier broadcast reaching a leader would have landed in an earlier slot.
+fn landed_from(broadcasts: &[(u64, Option<String>)], landed_slot: u64) -> Option<String> {
+    let mut accepted = broadcasts
+        .iter()
+        .filter_map(|(slot, endpoint)| Some((*slot, endpoint.as_ref()?)));
+    let first = accepted.clone().next();
+    accepted
+        .rfind(|(slot, _)| *slot < landed_slot)
+        .or(first)
+        .map(|(_, endpoint)| endpoint.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NoncePool, landed_from};
+    use solana_sdk::pubkey::Pubkey;
+    use tokio::time::{Duration, timeout};
+
+    #[tokio::test]
+    async fn each_send_holds_its_own_nonce_account() {
+        let accounts = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let pool = NoncePool::new(&accounts);
+        let first = pool.acquire().await.unwrap();
+        let second = pool.acquire().await.unwrap();
+        assert_ne!(first.account, second.account);
+        // a third send waits for one to be handed back
+        assert!(
+            timeout(Duration::from_millis(10), pool.acquire())
+                .await
+                .is_err()
+        );
+        let released = first.account;
+        drop(first);
+        assert_eq!(pool.acquire().await.unwrap().account, released);
+    }
+
+    #[test]
+    fn attributes_the_landing_to_the_last_broadcast_before_its_slot() {
+        let broadcasts = [
+            (0, Some("a".to_string())),
+            (100, None),
+            (101, Some("b".to_string())),
+            (103, Some("c".to_string())),
+        ];
+        assert_eq!(landed_from(&broadcasts, 102).as_deref(), Some("b"));
+        assert_eq!(landed_from(&broadcasts, 101).as_deref(), Some("a"));
+        assert_eq!(landed_from(&broadcasts, 200).as_deref(), Some("c"));
+        // the rpc's slot can run ahead of the one the transaction landed in
+        assert_eq!(landed_from(&broadcasts[1..], 50).as_deref(), Some("b"));
+        assert_eq!(landed_from(&[(0, None)], 50), None);
+    }
+}
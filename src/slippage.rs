@@ -0,0 +1,105 @@
+use crate::config::env_or;
+
+#[derive(Clone, Copy, Debug)]
+pub struct SlippageConfig {
+    /// How far below the target's fill price we still accept our own fill
+    pub tolerance_bps: u64,
+    /// Refuse the copy when our quote is already this much worse than the target's fill
+    pub max_behind_target_bps: u64,
+    /// Lower bound on the slippage we send, so a quote sitting right at the limit can still land
+    pub min_slippage_bps: u64,
+    /// Used when the target's fill can't be read from its balances
+    pub default_slippage_bps: u64,
+}
+
+impl SlippageConfig {
+    pub fn from_env() -> Self {
+        Self {
+            tolerance_bps: env_or("COPY_SLIPPAGE_TOLERANCE_BPS", 200),
+            max_behind_target_bps: env_or("COPY_MAX_BEHIND_TARGET_BPS", 500),
+            min_slippage_bps: env_or("COPY_MIN_SLIPPAGE_BPS", 50),
+            default_slippage_bps: env_or("COPY_DEFAULT_SLIPPAGE_BPS", 1000),
+        }
+    }
+
+    /// Slippage for a quote at `quote_price`, given the target filled at `target_price`.
+    /// Both prices are output per input. Errors with how far behind the target we are
+    /// when that is beyond `max_behind_target_bps`.
+    pub fn slippage_bps(
+        &self,
+        target_price: Option<f64>,
+        quote_price: f64,
+    ) -> Result<u64, BehindTarget> {
+        let Some(target_price) = target_price else {
+            return Ok(self.default_slippage_bps);
+        };
+        let behind_bps = worse_by_bps(target_price, quote_price);
+        if behind_bps > self.max_behind_target_bps {
+            return Err(BehindTarget {
+                behind_bps,
+                max: self.max_behind_target_bps,
+            });
+        }
+        let floor_price =
+            target_price * (10_000 - self.tolerance_bps.min(10_000)) as f64 / 10_000.0;
+        Ok(worse_by_bps(quote_price, floor_price).max(self.min_slippage_bps))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BehindTarget {
+    pub behind_bps: u64,
+    pub max: u64,
+}
+
+impl std::fmt::Display for BehindTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "quote is {} bps worse than the target's fill, above {} bps",
+            self.behind_bps, self.max
+        )
+    }
+}
+
+/// How much lower `price` is than `reference`, in bps. Zero when it isn't lower.
+pub fn worse_by_bps(reference: f64, price: f64) -> u64 {
+    if reference <= 0.0 || price >= reference {
+        return 0;
+    }
+    ((reference - price) / reference * 10_000.0).round() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BehindTarget, SlippageConfig};
+
+    const CONFIG: SlippageConfig = SlippageConfig {
+        tolerance_bps: 200,
+        max_behind_target_bps: 500,
+        min_slippage_bps: 50,
+        default_slippage_bps: 1000,
+    };
+
+    #[test]
+    fn slippage_reaches_down_to_target_minus_tolerance() {
+        // our quote matches the target, so all the tolerance is ours
+        assert_eq!(CONFIG.slippage_bps(Some(100.0), 100.0), Ok(200));
+        // price moved 1% after the target, 1% of tolerance is left
+        assert_eq!(CONFIG.slippage_bps(Some(100.0), 99.0), Ok(101));
+        // beyond the tolerance but within the limit, keep a minimum
+        assert_eq!(CONFIG.slippage_bps(Some(100.0), 97.0), Ok(50));
+        assert_eq!(CONFIG.slippage_bps(None, 97.0), Ok(1000));
+    }
+
+    #[test]
+    fn refuses_quotes_far_behind_the_target() {
+        assert_eq!(
+            CONFIG.slippage_bps(Some(100.0), 90.0),
+            Err(BehindTarget {
+                behind_bps: 1000,
+                max: 500
+            })
+        );
+    }
+}
@@ -0,0 +1,288 @@
+use crate::config::{env_or, env_parse};
+use crate::raydium::api_v3::ApiV3Client;
+use crate::raydium::api_v3::response::token::ApiV3TokenTag;
+use anyhow::anyhow;
+use log::debug;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use spl_token_2022::extension::permanent_delegate::PermanentDelegate;
+use spl_token_2022::extension::transfer_hook::TransferHook;
+use spl_token_2022::extension::{BaseStateWithExtensions, ExtensionType, StateWithExtensions};
+use spl_token_2022::state::Mint;
+use std::str::FromStr;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SafetyCheck {
+    /// Mint authority is still set, supply can be inflated
+    MintAuthority,
+    /// Freeze authority is set, our token account can be frozen
+    FreezeAuthority,
+    /// Token-2022 transfer fee extension
+    TransferFee,
+    /// Token-2022 transfer hook pointing at a program
+    TransferHook,
+    /// Token-2022 permanent delegate that can move or burn our tokens
+    PermanentDelegate,
+    /// Token-2022 non-transferable (soulbound) mint
+    NonTransferable,
+}
+
+/// What to do when a single check trips.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CheckMode {
+    Off,
+    Warn,
+    #[default]
+    Reject,
+}
+
+impl FromStr for CheckMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(CheckMode::Off),
+            "warn" => Ok(CheckMode::Warn),
+            "reject" => Ok(CheckMode::Reject),
+            x => Err(anyhow!("Unknown check mode {}", x)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TokenSafetyConfig {
+    pub mint_authority: CheckMode,
+    pub freeze_authority: CheckMode,
+    pub transfer_fee: CheckMode,
+    pub transfer_hook: CheckMode,
+    pub permanent_delegate: CheckMode,
+    pub non_transferable: CheckMode,
+    /// Trust Raydium API tags to reject known tokens before hitting the RPC
+    pub use_api_tags: bool,
+}
+
+impl Default for TokenSafetyConfig {
+    fn default() -> Self {
+        Self {
+            mint_authority: CheckMode::Reject,
+            freeze_authority: CheckMode::Reject,
+            transfer_fee: CheckMode::Reject,
+            transfer_hook: CheckMode::Reject,
+            permanent_delegate: CheckMode::Reject,
+            non_transferable: CheckMode::Reject,
+            use_api_tags: true,
+        }
+    }
+}
+
+impl TokenSafetyConfig {
+    /// Fails on a check mode that cannot be parsed rather than falling back, a typo must not
+    /// turn a check off or on silently
+    pub fn from_env() -> anyhow::Result<Self> {
+        let default = Self::default();
+        let mode = |key, default| Ok::<_, anyhow::Error>(env_parse(key)?.unwrap_or(default));
+        Ok(Self {
+            mint_authority: mode("SAFETY_MINT_AUTHORITY", default.mint_authority)?,
+            freeze_authority: mode("SAFETY_FREEZE_AUTHORITY", default.freeze_authority)?,
+            transfer_fee: mode("SAFETY_TRANSFER_FEE", default.transfer_fee)?,
+            transfer_hook: mode("SAFETY_TRANSFER_HOOK", default.transfer_hook)?,
+            permanent_delegate: mode("SAFETY_PERMANENT_DELEGATE", default.permanent_delegate)?,
+            non_transferable: mode("SAFETY_NON_TRANSFERABLE", default.non_transferable)?,
+            use_api_tags: env_or("SAFETY_USE_API_TAGS", default.use_api_tags),
+        })
+    }
+
+    pub fn mode(&self, check: SafetyCheck) -> CheckMode {
+        match check {
+            SafetyCheck::MintAuthority => self.mint_authority,
+            SafetyCheck::FreezeAuthority => self.freeze_authority,
+            SafetyCheck::TransferFee => self.transfer_fee,
+            SafetyCheck::TransferHook => self.transfer_hook,
+            SafetyCheck::PermanentDelegate => self.permanent_delegate,
+            SafetyCheck::NonTransferable => self.non_transferable,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TokenSafetyReport {
+    pub mint: Pubkey,
+    /// Checks that tripped in `Warn` mode
+    pub flagged: Vec<SafetyCheck>,
+    /// Checks that tripped in `Reject` mode
+    pub rejected: Vec<SafetyCheck>,
+}
+
+impl TokenSafetyReport {
+    fn new(mint: Pubkey) -> Self {
+        Self {
+            mint,
+            flagged: vec![],
+            rejected: vec![],
+        }
+    }
+
+    fn record(&mut self, check: SafetyCheck, mode: CheckMode) {
+        let list = match mode {
+            CheckMode::Off => return,
+            CheckMode::Warn => &mut self.flagged,
+            CheckMode::Reject => &mut self.rejected,
+        };
+        if !list.contains(&check) {
+            list.push(check);
+        }
+    }
+
+    pub fn is_safe(&self) -> bool {
+        self.rejected.is_empty()
+    }
+}
+
+#[derive(Clone)]
+pub struct TokenSafety {
+    client: Arc<RpcClient>,
+    api: ApiV3Client,
+    config: TokenSafetyConfig,
+}
+
+impl TokenSafety {
+    pub fn new(client: Arc<RpcClient>, api: ApiV3Client, config: TokenSafetyConfig) -> Self {
+        Self {
+            client,
+            api,
+            config,
+        }
+    }
+
+    pub async fn check(&self, mint: &Pubkey) -> anyhow::Result<TokenSafetyReport> {
+        let mut report = TokenSafetyReport::new(*mint);
+
+        if self.config.use_api_tags {
+            // unknown tokens make the api fail to deserialize, fall through to the mint account
+            match self.api.get_token_info(vec![mint.to_string()]).await {
+                Ok(tokens) => {
+                    for tag in tokens.iter().flat_map(|token| token.tags.iter()) {
+                        let check = match tag {
+                            ApiV3TokenTag::HasFreeze => SafetyCheck::FreezeAuthority,
+                            ApiV3TokenTag::HasTransferFee => SafetyCheck::TransferFee,
+                            _ => continue,
+                        };
+                        report.record(check, self.config.mode(check));
+                    }
+                }
+                Err(e) => debug!("Token {} is not known to the api: {}", mint, e),
+            }
+            if !report.is_safe() {
+                return Ok(report);
+            }
+        }
+
+        let account = self.client.get_account(mint).await?;
+        for check in inspect_mint(&account.owner, &account.data)? {
+            report.record(check, self.config.mode(check));
+        }
+        Ok(report)
+    }
+}
+
+/// Lists every risky property of a raw spl-token or Token-2022 mint account.
+pub fn inspect_mint(owner: &Pubkey, data: &[u8]) -> anyhow::Result<Vec<SafetyCheck>> {
+    if *owner != spl_token::id() && *owner != spl_token_2022::id() {
+        return Err(anyhow!(
+            "Account is not owned by a token program: {}",
+            owner
+        ));
+    }
+    let mint = StateWithExtensions::<Mint>::unpack(data)?;
+
+    let mut checks = vec![];
+    if mint.base.mint_authority.is_some() {
+        checks.push(SafetyCheck::MintAuthority);
+    }
+    if mint.base.freeze_authority.is_some() {
+        checks.push(SafetyCheck::FreezeAuthority);
+    }
+    for extension in mint.get_extension_types()? {
+        match extension {
+            ExtensionType::TransferFeeConfig => checks.push(SafetyCheck::TransferFee),
+            ExtensionType::NonTransferable => checks.push(SafetyCheck::NonTransferable),
+            ExtensionType::TransferHook => {
+                let hook = mint.get_extension::<TransferHook>()?;
+                if Option::<Pubkey>::from(hook.program_id).is_some() {
+                    checks.push(SafetyCheck::TransferHook);
+                }
+            }
+            ExtensionType::PermanentDelegate => {
+                let delegate = mint.get_extension::<PermanentDelegate>()?;
+                if Option::<Pubkey>::from(delegate.delegate).is_some() {
+                    checks.push(SafetyCheck::PermanentDelegate);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(checks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SafetyCheck, inspect_mint};
+    use solana_sdk::program_option::COption;
+    use solana_sdk::program_pack::Pack;
+    use solana_sdk::pubkey::Pubkey;
+    use spl_token_2022::extension::{AccountType, ExtensionType};
+    use spl_token_2022::state::Mint;
+
+    fn packed_mint(mint_authority: COption<Pubkey>, freeze_authority: COption<Pubkey>) -> Vec<u8> {
+        let mint = Mint {
+            mint_authority,
+            supply: 1_000_000,
+            decimals: 6,
+            is_initialized: true,
+            freeze_authority,
+        };
+        let mut data = vec![0u8; Mint::LEN];
+        Mint::pack(mint, &mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn renounced_legacy_mint_is_clean() {
+        let data = packed_mint(COption::None, COption::None);
+        let checks = inspect_mint(&spl_token::id(), &data).unwrap();
+        assert!(checks.is_empty());
+    }
+
+    #[test]
+    fn legacy_mint_with_authorities() {
+        let data = packed_mint(
+            COption::Some(Pubkey::new_unique()),
+            COption::Some(Pubkey::new_unique()),
+        );
+        let checks = inspect_mint(&spl_token::id(), &data).unwrap();
+        assert_eq!(
+            checks,
+            vec![SafetyCheck::MintAuthority, SafetyCheck::FreezeAuthority]
+        );
+    }
+
+    #[test]
+    fn token_2022_non_transferable_mint() {
+        // base mint, padded to the account size, then the account type and a single empty tlv
+        let mut data = packed_mint(COption::None, COption::None);
+        data.resize(spl_token_2022::state::Account::LEN, 0);
+        data.push(AccountType::Mint as u8);
+        data.extend_from_slice(&u16::from(ExtensionType::NonTransferable).to_le_bytes());
+        data.extend_from_slice(&0u16.to_le_bytes());
+
+        let checks = inspect_mint(&spl_token_2022::id(), &data).unwrap();
+        assert_eq!(checks, vec![SafetyCheck::NonTransferable]);
+    }
+
+    #[test]
+    fn rejects_foreign_owner() {
+        let data = packed_mint(COption::None, COption::None);
+        assert!(inspect_mint(&Pubkey::new_unique(), &data).is_err());
+    }
+}
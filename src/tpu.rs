@@ -0,0 +1,48 @@
+use crate::config::env_or;
+use crate::sender::TransactionSubmitter;
+use async_trait::async_trait;
+use log::debug;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::nonblocking::tpu_client::TpuClient;
+use solana_client::tpu_client::TpuClientConfig;
+use solana_quic_client::{QuicConfig, QuicConnectionManager, QuicPool};
+use solana_sdk::transaction::VersionedTransaction;
+use std::sync::Arc;
+
+/// Sends transactions straight to the QUIC TPU ports of the current and upcoming leaders.
+///
+/// Leaders are tracked from the leader schedule and cluster nodes over rpc, slot updates come
+/// from the websocket.
+pub struct TpuSubmitter {
+    client: TpuClient<QuicPool, QuicConnectionManager, QuicConfig>,
+}
+
+impl TpuSubmitter {
+    pub async fn new(client: Arc<RpcClient>, ws_link: &str) -> anyhow::Result<Self> {
+        let config = TpuClientConfig {
+            fanout_slots: env_or("TPU_FANOUT_SLOTS", TpuClientConfig::default().fanout_slots),
+        };
+        Ok(Self {
+            client: TpuClient::new("copy-trade-tpu", client, ws_link, config).await?,
+        })
+    }
+}
+
+#[async_trait]
+impl TransactionSubmitter for TpuSubmitter {
+    async fn submit(&self, transaction: &VersionedTransaction) -> anyhow::Result<Option<String>> {
+        let wire_transaction = bincode::serialize(transaction)?;
+        match self
+            .client
+            .try_send_wire_transaction(wire_transaction)
+            .await
+        {
+            Ok(()) => Ok(Some("tpu".to_string())),
+            // no leader took it this round, the send loop tries again
+            Err(e) => {
+                debug!("Tpu send failed: {}", e);
+                Ok(None)
+            }
+        }
+    }
+}
@@ -1,10 +1,19 @@
-use crate::config::WSOL;
+use crate::config::{
+    JUPITER_V6_PROGRAM_ID, PUMP_FUN_PROGRAM_ID, RAYDIUM_CLMM_PROGRAM_ID, RAYDIUM_CPMM_PROGRAM_ID,
+    WSOL,
+};
+use crate::jupiter::{JupiterRouteInstruction, NetTrade, SwapEvent};
+use crate::pump_fun::types::PumpFunTradeInstruction;
+use crate::raydium::clmm_types::ClmmSwapInstruction;
+use crate::raydium::cpmm_types::CpmmSwapInstruction;
 use anyhow::anyhow;
 use log::info;
 use solana_sdk::hash::Hash;
+use solana_sdk::native_token::LAMPORTS_PER_SOL;
 use solana_sdk::pubkey::Pubkey;
 use solana_sdk::signature::Signature;
 use yellowstone_grpc_proto::geyser::SubscribeUpdateTransaction;
+use yellowstone_grpc_proto::prelude::{Message, TransactionStatusMeta};
 
 #[derive(Clone, Debug)]
 pub struct TokenAmountList {
@@ -25,6 +34,31 @@ pub enum TradeType {
     Unknown,
 }
 
+impl std::fmt::Display for TradeType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TradeType::Buy => write!(f, "buy"),
+            TradeType::Sell => write!(f, "sell"),
+            TradeType::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// Where the target's trade executed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TradeVenue {
+    /// Raydium amm v4
+    RaydiumV4,
+    /// Raydium CP-Swap
+    Cpmm { pool: Pubkey },
+    /// Raydium concentrated liquidity
+    Clmm { pool: Pubkey },
+    /// pump.fun bonding curve, before the token migrates
+    PumpFun { bonding_curve: Pubkey },
+    /// Jupiter v6 route, possibly over several pools
+    Jupiter { trade: NetTrade },
+}
+
 #[derive(Clone, Debug)]
 pub struct TradeInfoFromToken {
     pub slot: u64,
@@ -37,6 +71,7 @@ pub struct TradeInfoFromToken {
     pub pool: String,
     pub decimal: u32,
     pub trade_type: TradeType,
+    pub venue: TradeVenue,
 }
 
 impl TradeInfoFromToken {
@@ -52,6 +87,7 @@ impl TradeInfoFromToken {
             bonding_curve,
             mint_decimal,
             trade_type,
+            venue,
         ) = if let Some(transaction) = txn.transaction {
             let signature = match Signature::try_from(transaction.signature.clone()) {
                 Ok(signature) => format!("{:?}", signature),
@@ -89,6 +125,15 @@ impl TradeInfoFromToken {
                 .map_err(|_| anyhow::anyhow!("Failed to parse target pubkey"))?
                 .to_string();
 
+            let venue = match (&transaction.transaction, &transaction.meta) {
+                (Some(tx), Some(meta)) => tx
+                    .message
+                    .as_ref()
+                    .and_then(|message| find_venue(message, meta))
+                    .unwrap_or(TradeVenue::RaydiumV4),
+                _ => TradeVenue::RaydiumV4,
+            };
+
             if let Some(meta) = transaction.meta.clone() {
                 if let Some(error) = meta.err {
                     return Err(anyhow!("Error in transaction"));
@@ -114,6 +159,18 @@ impl TradeInfoFromToken {
                         }
                     }
                 }
+                // hops through other tokens leave their balances behind, the route names the mint
+                if let TradeVenue::Jupiter { trade } = venue {
+                    let Some((traded_mint, _)) = trade.sol_trade() else {
+                        return Err(anyhow!(
+                            "signature[{}]: route {} -> {} isn't quoted in SOL",
+                            signature,
+                            trade.input_mint,
+                            trade.output_mint
+                        ));
+                    };
+                    mint = traded_mint.to_string();
+                }
 
                 if mint.is_empty() {
                     return Err(anyhow::anyhow!(format!(
@@ -168,6 +225,18 @@ impl TradeInfoFromToken {
                         }
                     }
                 }
+
+                // no wsol account, the target paid in native sol. Add the fee and the rent of
+                // token accounts it opened back so only the swap itself is counted
+                if sol_pre_amount == 0.0
+                    && sol_post_amount == 0.0
+                    && let (Some(pre), Some(post)) =
+                        (meta.pre_balances.first(), meta.post_balances.first())
+                {
+                    sol_pre_amount = *pre as f64 / LAMPORTS_PER_SOL as f64;
+                    sol_post_amount = (*post + meta.fee + created_token_account_rent(&meta)) as f64
+                        / LAMPORTS_PER_SOL as f64;
+                }
             } else {
                 return Err(anyhow::anyhow!("Transaction meta is None"));
             }
@@ -187,8 +256,14 @@ impl TradeInfoFromToken {
                 // info!("Sol {:?}", transaction.meta);
             }
 
-            // Determine trade type
-            let trade_type = if token_post_amount > token_pre_amount {
+            // Determine trade type. A route's comes from its net direction, the target's
+            // balances can be skewed by the hops it took
+            let trade_type = if let TradeVenue::Jupiter { trade } = venue {
+                match trade.sol_trade() {
+                    Some((_, true)) => TradeType::Buy,
+                    _ => TradeType::Sell,
+                }
+            } else if token_post_amount > token_pre_amount {
                 TradeType::Buy
             } else if token_pre_amount > token_post_amount {
                 TradeType::Sell
@@ -206,6 +281,7 @@ impl TradeInfoFromToken {
                 bonding_curve,
                 mint_decimal,
                 trade_type,
+                venue,
             )
         } else {
             return Err(anyhow::anyhow!("Transaction is None"));
@@ -222,6 +298,146 @@ impl TradeInfoFromToken {
             pool: bonding_curve,
             decimal: mint_decimal,
             trade_type,
+            venue,
+        })
+    }
+}
+
+/// The first Jupiter route in the transaction, else the pool of the first CP-Swap, CLMM or
+/// pump.fun trade, top level or inner. None when none shows up, amm v4 is assumed then.
+fn find_venue(message: &Message, meta: &TransactionStatusMeta) -> Option<TradeVenue> {
+    // static keys first, then the ones loaded from lookup tables
+    let account_keys = message
+        .account_keys
+        .iter()
+        .chain(&meta.loaded_writable_addresses)
+        .chain(&meta.loaded_readonly_addresses)
+        .map(|key| Pubkey::try_from(key.as_slice()).ok())
+        .collect::<Option<Vec<_>>>()?;
+
+    // a route hides its hops in inner instructions, it is copied as a whole
+    let jupiter_program_id = Pubkey::from_str_const(JUPITER_V6_PROGRAM_ID);
+    let is_jupiter = |program_id_index: u32| {
+        account_keys.get(program_id_index as usize) == Some(&jupiter_program_id)
+    };
+    let inner_instructions = || {
+        meta.inner_instructions
+            .iter()
+            .flat_map(|inner| &inner.instructions)
+    };
+    let route = message
+        .instructions
+        .iter()
+        .map(|ix| (ix.program_id_index, &ix.accounts, &ix.data))
+        .chain(inner_instructions().map(|ix| (ix.program_id_index, &ix.accounts, &ix.data)))
+        .filter(|(program_id_index, _, _)| is_jupiter(*program_id_index))
+        .find_map(|(_, accounts, data)| {
+            let accounts = accounts
+                .iter()
+                .map(|index| account_keys.get(*index as usize).copied())
+                .collect::<Option<Vec<_>>>()?;
+            JupiterRouteInstruction::decode(data, &accounts)
+        });
+    if let Some(route) = route {
+        let events = inner_instructions()
+            .filter(|ix| is_jupiter(ix.program_id_index))
+            .filter_map(|ix| SwapEvent::decode(&ix.data))
+            .collect::<Vec<_>>();
+        if let Some(trade) = NetTrade::from_route(&route, &events) {
+            return Some(TradeVenue::Jupiter { trade });
+        }
+    }
+
+    let cpmm_program_id = Pubkey::from_str_const(RAYDIUM_CPMM_PROGRAM_ID);
+    let clmm_program_id = Pubkey::from_str_const(RAYDIUM_CLMM_PROGRAM_ID);
+    let pump_fun_program_id = Pubkey::from_str_const(PUMP_FUN_PROGRAM_ID);
+    let decode = |program_id_index: u32, accounts: &[u8], data: &[u8]| {
+        let program_id = account_keys.get(program_id_index as usize)?;
+        if ![cpmm_program_id, clmm_program_id, pump_fun_program_id].contains(program_id) {
+            return None;
+        }
+        let accounts = accounts
+            .iter()
+            .map(|index| account_keys.get(*index as usize).copied())
+            .collect::<Option<Vec<_>>>()?;
+        if *program_id == cpmm_program_id {
+            CpmmSwapInstruction::decode(data, &accounts)
+                .map(|swap| TradeVenue::Cpmm { pool: swap.pool })
+        } else if *program_id == clmm_program_id {
+            ClmmSwapInstruction::decode(data, &accounts)
+                .map(|swap| TradeVenue::Clmm { pool: swap.pool })
+        } else {
+            PumpFunTradeInstruction::decode(data, &accounts).map(|trade| TradeVenue::PumpFun {
+                bonding_curve: trade.bonding_curve,
+            })
+        }
+    };
+    message
+        .instructions
+        .iter()
+        .find_map(|ix| decode(ix.program_id_index, &ix.accounts, &ix.data))
+        .or_else(|| {
+            meta.inner_instructions
+                .iter()
+                .flat_map(|inner| &inner.instructions)
+                .find_map(|ix| decode(ix.program_id_index, &ix.accounts, &ix.data))
+        })
+}
+
+/// Lamports deposited as rent into token accounts `meta`'s transaction opened
+fn created_token_account_rent(meta: &TransactionStatusMeta) -> u64 {
+    meta.post_token_balances
+        .iter()
+        .filter(|post| {
+            !meta
+                .pre_token_balances
+                .iter()
+                .any(|pre| pre.account_index == post.account_index)
         })
+        .filter_map(|post| {
+            let index = post.account_index as usize;
+            match (meta.pre_balances.get(index), meta.post_balances.get(index)) {
+                (Some(0), Some(rent)) => Some(*rent),
+                _ => None,
+            }
+        })
+        .sum()
+}
+
+impl TradeInfoFromToken {
+    /// Tokens the target got per SOL spent, in ui units. None unless this is a buy
+    /// with both balance changes visible
+    pub fn fill_price(&self) -> Option<f64> {
+        let tokens =
+            self.token_amount_list.token_post_amount - self.token_amount_list.token_pre_amount;
+        let sol = self.sol_amount_list.sol_pre_amount - self.sol_amount_list.sol_post_amount;
+        if tokens > 0.0 && sol > 0.0 {
+            Some(tokens / sol)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::created_token_account_rent;
+    use yellowstone_grpc_proto::prelude::{TokenBalance, TransactionStatusMeta};
+
+    #[test]
+    fn counts_rent_of_opened_token_accounts_only() {
+        let balance = |account_index| TokenBalance {
+            account_index,
+            ..Default::default()
+        };
+        let meta = TransactionStatusMeta {
+            // the target, an existing vault and the target's new token account
+            pre_balances: vec![1_000_000_000, 5_000_000, 0],
+            post_balances: vec![897_956_720, 105_000_000, 2_039_280],
+            pre_token_balances: vec![balance(1)],
+            post_token_balances: vec![balance(1), balance(2)],
+            ..Default::default()
+        };
+        assert_eq!(created_token_account_rent(&meta), 2_039_280);
     }
 }